@@ -85,7 +85,7 @@ fn main() {
           "This example is a showcase implementation of Todo-Backend project (http://todobackend.com/), \
           visit http://localhost:{0}/ to try it or run reference test suite by pointing \
           your browser to http://todobackend.com/specs/index.html?http://localhost:{0}",
-          server.socket.port()
+          server.local_addr().port()
         );
       },
       Err(e) => error!("could not run the server: {}", e)
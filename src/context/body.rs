@@ -6,63 +6,234 @@ use rustc_serialize::json;
 use rustc_serialize::Decodable;
 
 #[cfg(feature = "multipart")]
-use multipart::server::{HttpRequest, Multipart};
+use multipart::server::{HttpRequest, Multipart, MultipartField};
 
 use std::io::{self, Read};
+use std::fmt;
+use std::error::Error;
+use std::time::Instant;
+
+#[cfg(feature = "compression")]
+use flate2::read::{GzDecoder, ZlibDecoder};
+
+use unicase::UniCase;
 
 use hyper::buffer::BufReader;
 use hyper::http::h1::HttpReader;
 use hyper::net::NetworkStream;
 
 use context::Parameters;
-use header::Headers;
+use context::chunked::ChunkedReader;
+use utils::Charset;
+use header::{Headers, ContentEncoding, Encoding};
+use StatusCode;
 
 ///A reader for a request body.
 pub struct BodyReader<'a, 'b: 'a> {
-    reader: HttpReader<&'a mut BufReader<&'b mut NetworkStream>>,
+    reader: Reader<'a, 'b>,
+    max_size: Option<u64>,
+    max_decoded_size: Option<u64>,
+    max_parameters: Option<usize>,
+    deadline: Option<Instant>,
+    encodings: Vec<Encoding>,
 
     #[cfg(feature = "multipart")]
-    multipart_boundary: Option<String>
+    multipart_boundary: Option<String>,
+
+    #[cfg(feature = "multipart")]
+    is_multipart: bool
 }
 
 impl<'a, 'b> BodyReader<'a, 'b> {
     #[doc(hidden)]
     #[cfg(feature = "multipart")]
     ///Internal and may change without warning.
-    pub fn from_reader(reader: HttpReader<&'a mut BufReader<&'b mut NetworkStream>>, headers: &Headers) -> BodyReader<'a, 'b> {
+    pub fn from_reader(reader: HttpReader<&'a mut BufReader<&'b mut NetworkStream>>, headers: &Headers, max_size: Option<u64>, max_decoded_size: Option<u64>, max_parameters: Option<usize>, deadline: Option<Instant>) -> BodyReader<'a, 'b> {
         use header::ContentType;
         use mime::{Mime, TopLevel, SubLevel, Attr, Value};
 
-        let boundary = match headers.get() {
+        let (is_multipart, boundary) = match headers.get() {
             Some(&ContentType(Mime(TopLevel::Multipart, SubLevel::FormData, ref attrs))) => {
-                attrs.iter()
+                let boundary = attrs.iter()
                     .find(|&&(ref attr, _)| attr == &Attr::Boundary)
                     .and_then(|&(_, ref val)| if let Value::Ext(ref boundary) = *val {
                         Some(boundary.clone())
                     } else {
                         None
-                    })
+                    });
+                (true, boundary)
             },
-            _ => None
+            _ => (false, None)
         };
 
         BodyReader {
-            reader: reader,
-            multipart_boundary: boundary
+            reader: Reader::new(reader),
+            max_size: max_size,
+            max_decoded_size: max_decoded_size,
+            max_parameters: max_parameters,
+            deadline: deadline,
+            encodings: content_encodings(headers),
+            multipart_boundary: boundary,
+            is_multipart: is_multipart
         }
     }
 
     #[doc(hidden)]
     #[cfg(not(feature = "multipart"))]
     ///Internal and may change without warning.
-    pub fn from_reader(reader: HttpReader<&'a mut BufReader<&'b mut NetworkStream>>, _headers: &Headers) -> BodyReader<'a, 'b> {
+    pub fn from_reader(reader: HttpReader<&'a mut BufReader<&'b mut NetworkStream>>, headers: &Headers, max_size: Option<u64>, max_decoded_size: Option<u64>, max_parameters: Option<usize>, deadline: Option<Instant>) -> BodyReader<'a, 'b> {
         BodyReader {
-            reader: reader
+            reader: Reader::new(reader),
+            max_size: max_size,
+            max_decoded_size: max_decoded_size,
+            max_parameters: max_parameters,
+            deadline: deadline,
+            encodings: content_encodings(headers)
         }
     }
 }
 
 impl<'a, 'b> BodyReader<'a, 'b> {
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn raw_stream(&mut self) -> &mut BufReader<&'b mut NetworkStream> {
+        &mut **self.reader.get_mut()
+    }
+}
+
+impl<'a, 'b> BodyReader<'a, 'b> {
+    ///The maximum number of bytes that will be read by `read_body` and the
+    ///other buffering methods, if limited.
+    pub fn max_size(&self) -> Option<u64> {
+        self.max_size
+    }
+
+    ///The maximum size of a decoded body, in bytes, if limited. The decoded
+    ///body is also limited by `max_size`.
+    pub fn max_decoded_size(&self) -> Option<u64> {
+        self.max_decoded_size
+    }
+
+    ///The maximum number of parameters that will be parsed by
+    ///`read_query_body` and `Context::parse_form`, if limited.
+    pub fn max_parameters(&self) -> Option<usize> {
+        self.max_parameters
+    }
+
+    ///Get the trailer fields that were sent after the body.
+    ///
+    ///Only bodies with `Transfer-Encoding: chunked` can have trailers, and
+    ///they are only available after the whole body has been read. This
+    ///method returns `None` for other bodies, such as those with a
+    ///`Content-Length`, and for chunked bodies that haven't been read to the
+    ///end. A chunked body without trailers results in an empty set of
+    ///headers.
+    ///
+    ///```
+    ///use rustful::{Context, Response, StatusCode};
+    ///
+    ///fn my_handler(mut context: Context, mut response: Response) {
+    ///    let body = match context.body.read_body() {
+    ///        Ok(body) => body,
+    ///        Err(e) => return response.set_status(e.into())
+    ///    };
+    ///
+    ///    let checksum = context.body.trailers().and_then(|trailers| trailers.get_raw("Checksum"));
+    ///    match checksum {
+    ///        Some(checksum) => response.send(format!("received {} bytes with the checksum {:?}", body.len(), checksum)),
+    ///        None => response.set_status(StatusCode::BadRequest)
+    ///    }
+    ///}
+    ///```
+    pub fn trailers(&self) -> Option<&Headers> {
+        match self.reader {
+            Reader::Chunked(ref reader) if reader.is_finished() => Some(reader.trailers()),
+            Reader::Chunked(_) | Reader::Http(_) => None
+        }
+    }
+
+    ///Read the whole request body into a byte vector. The reading will stop
+    ///with `BodyError::TooLarge` if the body is larger than the server's
+    ///`max_body_size`.
+    ///
+    ///A body with a `Content-Encoding` is decoded, and the decoded body is
+    ///limited by both `max_body_size` and `max_decoded_body_size`. Decoding
+    ///stops with `BodyError::TooLarge` as soon as the limit is reached, so a
+    ///small body that expands to a huge amount of data is never fully
+    ///inflated. The `gzip` and `deflate` encodings are supported, and any
+    ///other encoding, except `identity`, results in
+    ///`BodyError::UnsupportedEncoding`. This requires the `compression`
+    ///feature. The body is returned as it is, without being decoded or
+    ///rejected, when the feature is disabled. The other
+    ///buffering methods are based on this method and will also decode the
+    ///body, while reading from the `BodyReader` directly gives the raw bytes.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///fn my_handler(mut context: Context, mut response: Response) {
+    ///    match context.body.read_body() {
+    ///        Ok(body) => response.send(format!("received {} bytes", body.len())),
+    ///        Err(e) => response.set_status(e.into())
+    ///    }
+    ///}
+    ///```
+    pub fn read_body(&mut self) -> Result<Vec<u8>, BodyError> {
+        let mut buf = Vec::new();
+
+        if let Some(max_size) = self.max_size {
+            try!(self.by_ref().take(max_size + 1).read_to_end(&mut buf));
+            if buf.len() as u64 > max_size {
+                return Err(BodyError::TooLarge);
+            }
+        } else {
+            try!(self.read_to_end(&mut buf));
+        }
+
+        let max_decoded_size = match (self.max_size, self.max_decoded_size) {
+            (Some(max_size), Some(max_decoded_size)) => Some(::std::cmp::min(max_size, max_decoded_size)),
+            (max_size, None) => max_size,
+            (None, max_decoded_size) => max_decoded_size
+        };
+
+        decode_body(buf, &self.encodings, max_decoded_size)
+    }
+
+    ///Read the body as text in `charset`, which is UTF-8 if it's `None`,
+    ///and convert it to a `String`. UTF-8 and US-ASCII are always
+    ///supported, while other charsets, such as `iso-8859-1`, require the
+    ///`charsets` feature. `decoding` decides if invalid text is rejected or
+    ///replaced. The body is read using `read_body`.
+    ///
+    ///`Context::read_text` uses the `charset` from the `Content-Type` header.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///use rustful::context::body::TextDecoding;
+    ///
+    ///fn my_handler(mut context: Context, mut response: Response) {
+    ///    match context.body.read_text(Some("utf-8"), TextDecoding::Strict) {
+    ///        Ok(text) => response.send(format!("received {} characters", text.chars().count())),
+    ///        Err(e) => response.set_status(e.into())
+    ///    }
+    ///}
+    ///```
+    pub fn read_text(&mut self, charset: Option<&str>, decoding: TextDecoding) -> Result<String, TextError> {
+        let charset = match Charset::from_label(charset.unwrap_or("utf-8")) {
+            Some(charset) => charset,
+            None => return Err(TextError::Charset)
+        };
+
+        let body = try!(self.read_body());
+        charset.decode(body, decoding).ok_or(TextError::InvalidText)
+    }
+
     ///Try to create a `multipart/form-data` reader from the request body.
     ///
     ///```
@@ -112,8 +283,71 @@ impl<'a, 'b> BodyReader<'a, 'b> {
         )
     }
 
+    ///Create a `multipart/form-data` reader from the request body, that reads
+    ///one part at the time. This is similar to `as_multipart`, but it
+    ///reports why the body couldn't be read.
+    ///
+    ///```
+    ///# extern crate rustful;
+    ///# extern crate multipart;
+    ///use std::fs::File;
+    ///use std::io;
+    ///use std::path::Path;
+    ///use rustful::{Context, Response};
+    ///use rustful::file::check_path;
+    ///use multipart::server::MultipartData;
+    ///
+    ///fn my_handler(mut context: Context, mut response: Response) {
+    ///    let mut parts = match context.body.multipart_parts() {
+    ///        Ok(parts) => parts,
+    ///        Err(e) => return response.set_status(e.into())
+    ///    };
+    ///
+    ///    loop {
+    ///        match parts.next_part() {
+    ///            Ok(Some(mut part)) => if let MultipartData::File(ref mut file) = part.data {
+    ///                //Save each uploaded file in the "uploads" directory
+    ///                let path = file.filename().map(|name| Path::new("uploads").join(name));
+    ///                if let Some(path) = path.and_then(|p| if check_path(&p).is_ok() { Some(p) } else { None }) {
+    ///                    let saved = File::create(path).and_then(|mut f| io::copy(file, &mut f));
+    ///                    if saved.is_err() {
+    ///                        return response.set_status(rustful::StatusCode::InternalServerError);
+    ///                    }
+    ///                }
+    ///            },
+    ///            Ok(None) => break,
+    ///            Err(e) => return response.set_status(e.into())
+    ///        }
+    ///    }
+    ///
+    ///    response.send("the files were saved");
+    ///}
+    ///# fn main() {}
+    ///```
+    #[cfg(feature = "multipart")]
+    pub fn multipart_parts<'r>(&'r mut self) -> Result<MultipartParts<'r, 'a, 'b>, MultipartError> {
+        if !self.is_multipart {
+            return Err(MultipartError::NotMultipart);
+        }
+
+        let reader = &mut self.reader;
+        let boundary = match self.multipart_boundary {
+            Some(ref boundary) if boundary.len() > 0 && boundary.len() <= 70 => boundary,
+            _ => return Err(MultipartError::MalformedBoundary)
+        };
+
+        Multipart::from_request(MultipartRequest {
+            boundary: boundary,
+            reader: reader
+        }).map(|multipart| MultipartParts {
+            multipart: multipart
+        }).map_err(|_| MultipartError::MalformedBoundary)
+    }
+
     ///Read and parse the request body as a query string. The body will be
     ///decoded as UTF-8 and plain '+' characters will be replaced with spaces.
+    ///An error of the kind `InvalidData` is returned if there are more
+    ///parameters than the server's `max_parameters`.
     ///
     ///A simplified example of how to parse `a=number&b=number`:
     ///
@@ -133,9 +367,10 @@ impl<'a, 'b> BodyReader<'a, 'b> {
     ///```
     #[inline]
     pub fn read_query_body(&mut self) -> io::Result<Parameters> {
-        let mut buf = Vec::new();
-        try!(self.read_to_end(&mut buf));
-        Ok(::utils::parse_parameters(&buf))
+        let buf = try!(self.read_body());
+        ::utils::parse_parameters(&buf, self.max_parameters).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "too many parameters")
+        })
     }
 
     ///Read the request body into a generic JSON structure. This structure can
@@ -159,7 +394,8 @@ impl<'a, 'b> BodyReader<'a, 'b> {
     ///```
     #[cfg(feature = "rustc_json_body")]
     pub fn read_json_body(&mut self) -> Result<json::Json, json::BuilderError> {
-        json::Json::from_reader(self)
+        let buf = try!(self.read_body().map_err(|e| json::ParserError::IoError(e.into())));
+        json::Json::from_reader(&mut &buf[..])
     }
 
     ///Read and decode a request body as a type `T`. The target type must
@@ -189,9 +425,10 @@ impl<'a, 'b> BodyReader<'a, 'b> {
     ///```
     #[cfg(feature = "rustc_json_body")]
     pub fn decode_json_body<T: Decodable>(&mut self) -> json::DecodeResult<T> {
-        let mut buf = String::new();
-        try!(self.read_to_string(&mut buf).map_err(|e| {
-            let parse_err = json::ParserError::IoError(e);
+        let buf = try!(self.read_body().and_then(|buf| {
+            String::from_utf8(buf).map_err(|e| BodyError::Io(io::Error::new(io::ErrorKind::InvalidData, e)))
+        }).map_err(|e| {
+            let parse_err = json::ParserError::IoError(e.into());
             json::DecoderError::ParseError(parse_err)
         }));
         json::decode(&buf)
@@ -199,17 +436,549 @@ impl<'a, 'b> BodyReader<'a, 'b> {
 }
 
 impl<'a, 'b> Read for BodyReader<'a, 'b> {
-    ///Read the request body.
+    ///Read the request body. An error of the kind `TimedOut` is returned if
+    ///the server's `read_timeout` has passed.
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(deadline) = self.deadline {
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "the request body took too long to read"));
+            }
+        }
+
         self.reader.read(buf)
     }
 }
 
+//Chunked bodies are decoded by `ChunkedReader` instead of hyper, to make
+//sure that they end at the last chunk, even if it's followed by trailers.
+enum Reader<'a, 'b: 'a> {
+    Http(HttpReader<&'a mut BufReader<&'b mut NetworkStream>>),
+    Chunked(ChunkedReader<&'a mut BufReader<&'b mut NetworkStream>>)
+}
+
+impl<'a, 'b> Reader<'a, 'b> {
+    fn new(reader: HttpReader<&'a mut BufReader<&'b mut NetworkStream>>) -> Reader<'a, 'b> {
+        match reader {
+            HttpReader::ChunkedReader(stream, None) => Reader::Chunked(ChunkedReader::new(stream)),
+            reader => Reader::Http(reader)
+        }
+    }
+
+    fn get_mut(&mut self) -> &mut &'a mut BufReader<&'b mut NetworkStream> {
+        match *self {
+            Reader::Http(ref mut reader) => reader.get_mut(),
+            Reader::Chunked(ref mut reader) => reader.get_mut()
+        }
+    }
+}
+
+impl<'a, 'b> Read for Reader<'a, 'b> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Reader::Http(ref mut reader) => reader.read(buf),
+            Reader::Chunked(ref mut reader) => reader.read(buf)
+        }
+    }
+}
+
+//The encodings from `Content-Encoding`, in the order they were applied.
+fn content_encodings(headers: &Headers) -> Vec<Encoding> {
+    match headers.get::<ContentEncoding>() {
+        Some(&ContentEncoding(ref encodings)) => encodings.iter().filter(|&e| !is_identity(e)).cloned().collect(),
+        None => vec![]
+    }
+}
+
+fn is_identity(encoding: &Encoding) -> bool {
+    match *encoding {
+        Encoding::Identity => true,
+        Encoding::EncodingExt(ref name) => UniCase(&**name) == UniCase("identity"),
+        _ => false
+    }
+}
+
+//Undo the encodings, starting with the last one that was applied.
+#[cfg(feature = "compression")]
+fn decode_body(mut body: Vec<u8>, encodings: &[Encoding], max_size: Option<u64>) -> Result<Vec<u8>, BodyError> {
+    for encoding in encodings.iter().rev() {
+        body = try!(decode(&body, encoding, max_size));
+    }
+
+    Ok(body)
+}
+
+#[cfg(feature = "compression")]
+fn decode(body: &[u8], encoding: &Encoding, max_size: Option<u64>) -> Result<Vec<u8>, BodyError> {
+    match *encoding {
+        Encoding::Gzip => decompress(try!(GzDecoder::new(body)), max_size),
+        Encoding::EncodingExt(ref name) if UniCase(&**name) == UniCase("x-gzip") => decompress(try!(GzDecoder::new(body)), max_size),
+        Encoding::Deflate => decompress(ZlibDecoder::new(body), max_size),
+        _ => Err(BodyError::UnsupportedEncoding)
+    }
+}
+
+//The body is passed on as it is without the `compression` feature, and
+//it's up to the handler to decode it.
+#[cfg(not(feature = "compression"))]
+fn decode_body(body: Vec<u8>, _encodings: &[Encoding], _max_size: Option<u64>) -> Result<Vec<u8>, BodyError> {
+    Ok(body)
+}
+
+//Decompressed bodies are limited as well, to protect against small bodies
+//that expand to huge amounts of data.
+#[cfg(feature = "compression")]
+fn decompress<R: Read>(decoder: R, max_size: Option<u64>) -> Result<Vec<u8>, BodyError> {
+    let mut buf = Vec::new();
+
+    if let Some(max_size) = max_size {
+        try!(decoder.take(max_size + 1).read_to_end(&mut buf));
+        if buf.len() as u64 > max_size {
+            return Err(BodyError::TooLarge);
+        }
+    } else {
+        let mut decoder = decoder;
+        try!(decoder.read_to_end(&mut buf));
+    }
+
+    Ok(buf)
+}
+
+///Error that may occur while buffering a request body.
+#[derive(Debug)]
+pub enum BodyError {
+    ///The body is larger than the maximum allowed size.
+    TooLarge,
+
+    ///The body took too long to read.
+    TimedOut,
+
+    ///The body has a `Content-Encoding` that can't be decoded.
+    UnsupportedEncoding,
+
+    ///There was an IO error.
+    Io(io::Error)
+}
+
+impl From<io::Error> for BodyError {
+    fn from(err: io::Error) -> BodyError {
+        //Socket read timeouts are reported as `WouldBlock` on some platforms
+        match err.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => BodyError::TimedOut,
+            _ => BodyError::Io(err)
+        }
+    }
+}
+
+impl From<BodyError> for StatusCode {
+    fn from(err: BodyError) -> StatusCode {
+        match err {
+            BodyError::TooLarge => StatusCode::PayloadTooLarge,
+            BodyError::TimedOut => StatusCode::RequestTimeout,
+            BodyError::UnsupportedEncoding => StatusCode::UnsupportedMediaType,
+            BodyError::Io(_) => StatusCode::BadRequest
+        }
+    }
+}
+
+impl From<BodyError> for io::Error {
+    fn from(err: BodyError) -> io::Error {
+        match err {
+            BodyError::TimedOut => io::Error::new(io::ErrorKind::TimedOut, err),
+            BodyError::Io(e) => e,
+            err => io::Error::new(io::ErrorKind::InvalidData, err)
+        }
+    }
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BodyError::TooLarge => write!(f, "the request body is too large"),
+            BodyError::TimedOut => write!(f, "the request body took too long to read"),
+            BodyError::UnsupportedEncoding => write!(f, "the request body has an unsupported encoding"),
+            BodyError::Io(ref e) => write!(f, "io error: {}", e)
+        }
+    }
+}
+
+impl Error for BodyError {
+    fn description(&self) -> &str {
+        match *self {
+            BodyError::TooLarge => "the request body is too large",
+            BodyError::TimedOut => "the request body took too long to read",
+            BodyError::UnsupportedEncoding => "the request body has an unsupported encoding",
+            BodyError::Io(ref e) => e.description()
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            BodyError::TooLarge | BodyError::TimedOut | BodyError::UnsupportedEncoding => None,
+            BodyError::Io(ref e) => Some(e)
+        }
+    }
+}
+
+///A `multipart/form-data` reader that reads one part at the time.
+#[cfg(feature = "multipart")]
+pub struct MultipartParts<'r, 'a: 'r, 'b: 'a> {
+    multipart: Multipart<MultipartRequest<'r, 'a, 'b>>
+}
+
+#[cfg(feature = "multipart")]
+impl<'r, 'a, 'b> MultipartParts<'r, 'a, 'b> {
+    ///Read the next part, if any. Each part has a name and either text data
+    ///or a file, which can be streamed through its `Read` implementation.
+    pub fn next_part(&mut self) -> Result<Option<MultipartField<MultipartRequest<'r, 'a, 'b>>>, MultipartError> {
+        match self.multipart.read_entry() {
+            Ok(part) => Ok(part),
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(MultipartError::Truncated),
+            Err(e) => Err(MultipartError::Io(e))
+        }
+    }
+
+    ///Access the underlying `Multipart` reader.
+    pub fn as_multipart(&mut self) -> &mut Multipart<MultipartRequest<'r, 'a, 'b>> {
+        &mut self.multipart
+    }
+}
+
+///Error that may occur while reading a `multipart/form-data` request body.
+#[cfg(feature = "multipart")]
+#[derive(Debug)]
+pub enum MultipartError {
+    ///The `Content-Type` header is missing or is not `multipart/form-data`.
+    NotMultipart,
+
+    ///The boundary is missing or invalid.
+    MalformedBoundary,
+
+    ///The body ended in the middle of a part.
+    Truncated,
+
+    ///There was an IO error.
+    Io(io::Error)
+}
+
+#[cfg(feature = "multipart")]
+impl From<MultipartError> for StatusCode {
+    fn from(err: MultipartError) -> StatusCode {
+        match err {
+            MultipartError::NotMultipart => StatusCode::UnsupportedMediaType,
+            _ => StatusCode::BadRequest
+        }
+    }
+}
+
+#[cfg(feature = "multipart")]
+impl fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MultipartError::Io(ref e) => write!(f, "io error: {}", e),
+            _ => f.write_str(self.description())
+        }
+    }
+}
+
+#[cfg(feature = "multipart")]
+impl Error for MultipartError {
+    fn description(&self) -> &str {
+        match *self {
+            MultipartError::NotMultipart => "the request body is not multipart/form-data",
+            MultipartError::MalformedBoundary => "the multipart boundary is missing or malformed",
+            MultipartError::Truncated => "the multipart body ended unexpectedly",
+            MultipartError::Io(ref e) => e.description()
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            MultipartError::Io(ref e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+///How text that is invalid in its charset is handled.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TextDecoding {
+    ///Reject the text.
+    Strict,
+
+    ///Replace the invalid parts with `U+FFFD REPLACEMENT CHARACTER`.
+    Lossy
+}
+
+///Error that may occur while reading a text request body.
+#[derive(Debug)]
+pub enum TextError {
+    ///The charset is not supported. Charsets other than UTF-8 and US-ASCII
+    ///require the `charsets` feature.
+    Charset,
+
+    ///The body is not valid text in its charset.
+    InvalidText,
+
+    ///The body is larger than the maximum allowed size.
+    TooLarge,
+
+    ///The body took too long to read.
+    TimedOut,
+
+    ///The body has a `Content-Encoding` that can't be decoded.
+    UnsupportedEncoding,
+
+    ///There was an IO error.
+    Io(io::Error)
+}
+
+impl From<BodyError> for TextError {
+    fn from(err: BodyError) -> TextError {
+        match err {
+            BodyError::TooLarge => TextError::TooLarge,
+            BodyError::TimedOut => TextError::TimedOut,
+            BodyError::UnsupportedEncoding => TextError::UnsupportedEncoding,
+            BodyError::Io(e) => TextError::Io(e)
+        }
+    }
+}
+
+impl From<TextError> for StatusCode {
+    fn from(err: TextError) -> StatusCode {
+        match err {
+            TextError::Charset => StatusCode::UnsupportedMediaType,
+            TextError::InvalidText => StatusCode::BadRequest,
+            TextError::TooLarge => StatusCode::PayloadTooLarge,
+            TextError::TimedOut => StatusCode::RequestTimeout,
+            TextError::UnsupportedEncoding => StatusCode::UnsupportedMediaType,
+            TextError::Io(_) => StatusCode::BadRequest
+        }
+    }
+}
+
+impl fmt::Display for TextError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TextError::Charset => write!(f, "the request body has an unsupported charset"),
+            TextError::InvalidText => write!(f, "the request body is not valid text in its charset"),
+            TextError::TooLarge => write!(f, "the request body is too large"),
+            TextError::TimedOut => write!(f, "the request body took too long to read"),
+            TextError::UnsupportedEncoding => write!(f, "the request body has an unsupported encoding"),
+            TextError::Io(ref e) => write!(f, "io error: {}", e)
+        }
+    }
+}
+
+impl Error for TextError {
+    fn description(&self) -> &str {
+        match *self {
+            TextError::Charset => "the request body has an unsupported charset",
+            TextError::InvalidText => "the request body is not valid text in its charset",
+            TextError::TooLarge => "the request body is too large",
+            TextError::TimedOut => "the request body took too long to read",
+            TextError::UnsupportedEncoding => "the request body has an unsupported encoding",
+            TextError::Io(ref e) => e.description()
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            TextError::Charset | TextError::InvalidText | TextError::TooLarge |
+            TextError::TimedOut | TextError::UnsupportedEncoding => None,
+            TextError::Io(ref e) => Some(e)
+        }
+    }
+}
+
+///Error that may occur while parsing a form request body.
+#[derive(Debug)]
+pub enum FormError {
+    ///The `Content-Type` header is missing or is not
+    ///`application/x-www-form-urlencoded`.
+    ContentType,
+
+    ///The `Content-Type` header has an unsupported `charset`.
+    Charset,
+
+    ///The body is larger than the maximum allowed size.
+    TooLarge,
+
+    ///The body took too long to read.
+    TimedOut,
+
+    ///The form has more parameters than the server's `max_parameters`.
+    TooManyParameters,
+
+    ///The body has a `Content-Encoding` that can't be decoded.
+    UnsupportedEncoding,
+
+    ///There was an IO error.
+    Io(io::Error)
+}
+
+impl From<BodyError> for FormError {
+    fn from(err: BodyError) -> FormError {
+        match err {
+            BodyError::TooLarge => FormError::TooLarge,
+            BodyError::TimedOut => FormError::TimedOut,
+            BodyError::UnsupportedEncoding => FormError::UnsupportedEncoding,
+            BodyError::Io(e) => FormError::Io(e)
+        }
+    }
+}
+
+impl From<FormError> for StatusCode {
+    fn from(err: FormError) -> StatusCode {
+        match err {
+            FormError::ContentType => StatusCode::UnsupportedMediaType,
+            FormError::Charset => StatusCode::UnsupportedMediaType,
+            FormError::TooLarge => StatusCode::PayloadTooLarge,
+            FormError::TimedOut => StatusCode::RequestTimeout,
+            FormError::TooManyParameters => StatusCode::BadRequest,
+            FormError::UnsupportedEncoding => StatusCode::UnsupportedMediaType,
+            FormError::Io(_) => StatusCode::BadRequest
+        }
+    }
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FormError::ContentType => write!(f, "the request body is not a URL encoded form"),
+            FormError::Charset => write!(f, "the request body has an unsupported charset"),
+            FormError::TooLarge => write!(f, "the request body is too large"),
+            FormError::TimedOut => write!(f, "the request body took too long to read"),
+            FormError::TooManyParameters => write!(f, "the form has too many parameters"),
+            FormError::UnsupportedEncoding => write!(f, "the request body has an unsupported encoding"),
+            FormError::Io(ref e) => write!(f, "io error: {}", e)
+        }
+    }
+}
+
+impl Error for FormError {
+    fn description(&self) -> &str {
+        match *self {
+            FormError::ContentType => "the request body is not a URL encoded form",
+            FormError::Charset => "the request body has an unsupported charset",
+            FormError::TooLarge => "the request body is too large",
+            FormError::TimedOut => "the request body took too long to read",
+            FormError::TooManyParameters => "the form has too many parameters",
+            FormError::UnsupportedEncoding => "the request body has an unsupported encoding",
+            FormError::Io(ref e) => e.description()
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            FormError::ContentType | FormError::Charset | FormError::TooLarge | FormError::TimedOut |
+            FormError::TooManyParameters | FormError::UnsupportedEncoding => None,
+            FormError::Io(ref e) => Some(e)
+        }
+    }
+}
+
+///Error that may occur while parsing a JSON request body.
+#[cfg(feature = "rustc_json_body")]
+#[derive(Debug)]
+pub enum JsonError {
+    ///The `Content-Type` header is missing or is not `application/json`.
+    ContentType,
+
+    ///The `Content-Type` header has a `charset` other than `utf-8`.
+    Charset,
+
+    ///The body is larger than the maximum allowed size.
+    TooLarge,
+
+    ///The body took too long to read.
+    TimedOut,
+
+    ///The body has a `Content-Encoding` that can't be decoded.
+    UnsupportedEncoding,
+
+    ///There was an IO error.
+    Io(io::Error),
+
+    ///The body is not valid JSON or it doesn't match the target type.
+    Decode(json::DecoderError)
+}
+
+#[cfg(feature = "rustc_json_body")]
+impl From<BodyError> for JsonError {
+    fn from(err: BodyError) -> JsonError {
+        match err {
+            BodyError::TooLarge => JsonError::TooLarge,
+            BodyError::TimedOut => JsonError::TimedOut,
+            BodyError::UnsupportedEncoding => JsonError::UnsupportedEncoding,
+            BodyError::Io(e) => JsonError::Io(e)
+        }
+    }
+}
+
+#[cfg(feature = "rustc_json_body")]
+impl From<json::DecoderError> for JsonError {
+    fn from(err: json::DecoderError) -> JsonError {
+        JsonError::Decode(err)
+    }
+}
+
+#[cfg(feature = "rustc_json_body")]
+impl From<JsonError> for StatusCode {
+    fn from(err: JsonError) -> StatusCode {
+        match err {
+            JsonError::ContentType => StatusCode::UnsupportedMediaType,
+            JsonError::Charset => StatusCode::UnsupportedMediaType,
+            JsonError::TooLarge => StatusCode::PayloadTooLarge,
+            JsonError::TimedOut => StatusCode::RequestTimeout,
+            JsonError::UnsupportedEncoding => StatusCode::UnsupportedMediaType,
+            JsonError::Io(_) | JsonError::Decode(_) => StatusCode::BadRequest
+        }
+    }
+}
+
+#[cfg(feature = "rustc_json_body")]
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            JsonError::ContentType => write!(f, "the request body is not JSON"),
+            JsonError::Charset => write!(f, "the request body has an unsupported charset"),
+            JsonError::TooLarge => write!(f, "the request body is too large"),
+            JsonError::TimedOut => write!(f, "the request body took too long to read"),
+            JsonError::UnsupportedEncoding => write!(f, "the request body has an unsupported encoding"),
+            JsonError::Io(ref e) => write!(f, "io error: {}", e),
+            JsonError::Decode(ref e) => write!(f, "decoding error: {}", e)
+        }
+    }
+}
+
+#[cfg(feature = "rustc_json_body")]
+impl Error for JsonError {
+    fn description(&self) -> &str {
+        match *self {
+            JsonError::ContentType => "the request body is not JSON",
+            JsonError::Charset => "the request body has an unsupported charset",
+            JsonError::TooLarge => "the request body is too large",
+            JsonError::TimedOut => "the request body took too long to read",
+            JsonError::UnsupportedEncoding => "the request body has an unsupported encoding",
+            JsonError::Io(ref e) => e.description(),
+            JsonError::Decode(ref e) => e.description()
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            JsonError::ContentType | JsonError::Charset | JsonError::TooLarge | JsonError::TimedOut | JsonError::UnsupportedEncoding => None,
+            JsonError::Io(ref e) => Some(e),
+            JsonError::Decode(ref e) => Some(e)
+        }
+    }
+}
+
 ///A specialized request representation for the multipart interface.
 #[cfg(feature = "multipart")]
 pub struct MultipartRequest<'r, 'a: 'r, 'b: 'a> {
     boundary: &'r str,
-    reader: &'r mut HttpReader<&'a mut BufReader<&'b mut NetworkStream>>
+    reader: &'r mut Reader<'a, 'b>
 }
 
 #[cfg(feature = "multipart")]
@@ -231,4 +1000,97 @@ impl<'r, 'a, 'b> Read for MultipartRequest<'r, 'a, 'b> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         self.reader.read(buf)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use header::{Headers, Encoding};
+    use StatusCode;
+    use super::{decode_body, content_encodings, BodyError};
+
+    #[cfg(feature = "compression")]
+    fn compress(body: &[u8], encoding: &Encoding) -> Vec<u8> {
+        use std::io::Write;
+        use flate2::Compression;
+        use flate2::write::{GzEncoder, ZlibEncoder};
+
+        match *encoding {
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(vec![], Compression::Default);
+                encoder.write_all(body).unwrap();
+                encoder.finish().unwrap()
+            },
+            Encoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(vec![], Compression::Default);
+                encoder.write_all(body).unwrap();
+                encoder.finish().unwrap()
+            },
+            _ => panic!("unexpected encoding: {}", encoding)
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn decode_gzip_and_deflate() {
+        let body = b"hello, hello, hello, hello";
+
+        let gzip = compress(body, &Encoding::Gzip);
+        assert_eq!(decode_body(gzip, &[Encoding::Gzip], None).unwrap(), &body[..]);
+
+        let deflate = compress(body, &Encoding::Deflate);
+        assert_eq!(decode_body(deflate, &[Encoding::Deflate], Some(100)).unwrap(), &body[..]);
+
+        let both = compress(&compress(body, &Encoding::Deflate), &Encoding::Gzip);
+        assert_eq!(decode_body(both, &[Encoding::Deflate, Encoding::Gzip], None).unwrap(), &body[..]);
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn limit_decoded_size() {
+        //A small body that expands to a lot of data
+        let body = vec![0; 1024 * 1024];
+        let gzip = compress(&body, &Encoding::Gzip);
+        assert!(gzip.len() < 10 * 1024);
+
+        match decode_body(gzip.clone(), &[Encoding::Gzip], Some(64 * 1024)) {
+            Err(BodyError::TooLarge) => {},
+            other => panic!("expected TooLarge, got {:?}", other.map(|body| body.len()))
+        }
+        assert_eq!(decode_body(gzip, &[Encoding::Gzip], Some(1024 * 1024)).unwrap().len(), body.len());
+
+        let deflate = compress(&body, &Encoding::Deflate);
+        match decode_body(deflate, &[Encoding::Deflate], Some(1024 * 1024 - 1)) {
+            Err(BodyError::TooLarge) => {},
+            other => panic!("expected TooLarge, got {:?}", other.map(|body| body.len()))
+        }
+    }
+
+    #[test]
+    fn ignore_identity() {
+        let mut headers = Headers::new();
+        headers.set_raw("Content-Encoding", vec![b"identity, Identity".to_vec()]);
+        assert!(content_encodings(&headers).is_empty());
+        assert!(content_encodings(&Headers::new()).is_empty());
+
+        assert_eq!(decode_body(b"hello".to_vec(), &[], Some(5)).unwrap(), b"hello");
+    }
+
+    #[test]
+    #[cfg(not(feature = "compression"))]
+    fn pass_through_without_compression() {
+        let encoding = Encoding::EncodingExt("unknown".into());
+        assert_eq!(decode_body(b"hello".to_vec(), &[Encoding::Gzip, encoding], None).unwrap(), b"hello");
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn reject_unknown_encodings() {
+        let encoding = Encoding::EncodingExt("unknown".into());
+        match decode_body(b"hello".to_vec(), &[encoding], None) {
+            Err(e @ BodyError::UnsupportedEncoding) => assert_eq!(StatusCode::from(e), StatusCode::UnsupportedMediaType),
+            other => panic!("expected UnsupportedEncoding, got {:?}", other)
+        }
+
+        assert_eq!(decode_body(b"hello".to_vec(), &[], Some(1)).unwrap(), b"hello");
+    }
+}
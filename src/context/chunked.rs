@@ -0,0 +1,257 @@
+use std::io::{self, Read, BufRead};
+use std::cmp::min;
+
+use header::Headers;
+
+//Protects against endless chunk size and trailer lines.
+const MAX_LINE_LENGTH: u64 = 4096;
+const MAX_TRAILERS: usize = 100;
+
+enum State {
+    Size,
+    Data(u64),
+    End
+}
+
+//Decoder for request bodies with `Transfer-Encoding: chunked`. It stops at
+//the final zero-length chunk and keeps the trailer fields that follow it,
+//leaving the rest of the stream untouched.
+pub struct ChunkedReader<R> {
+    reader: R,
+    state: State,
+    trailers: Headers
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    pub fn new(reader: R) -> ChunkedReader<R> {
+        ChunkedReader {
+            reader: reader,
+            state: State::Size,
+            trailers: Headers::new()
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    pub fn is_finished(&self) -> bool {
+        match self.state {
+            State::End => true,
+            State::Size | State::Data(_) => false
+        }
+    }
+
+    pub fn trailers(&self) -> &Headers {
+        &self.trailers
+    }
+
+    //Reads a line and strips the line ending.
+    fn read_line(&mut self) -> io::Result<Vec<u8>> {
+        let mut line = Vec::new();
+        try!((&mut self.reader).take(MAX_LINE_LENGTH + 1).read_until(b'\n', &mut line));
+
+        if line.last() != Some(&b'\n') {
+            return if line.len() as u64 > MAX_LINE_LENGTH {
+                Err(io::Error::new(io::ErrorKind::InvalidData, "too long line in chunked body"))
+            } else {
+                Err(io::Error::new(io::ErrorKind::UnexpectedEof, "unexpected end of chunked body"))
+            };
+        }
+
+        line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+
+        Ok(line)
+    }
+
+    fn read_trailers(&mut self) -> io::Result<()> {
+        let mut count = 0;
+
+        loop {
+            let line = try!(self.read_line());
+            if line.is_empty() {
+                return Ok(());
+            }
+
+            count += 1;
+            if count > MAX_TRAILERS {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "too many trailer fields"));
+            }
+
+            let (name, value) = try!(parse_trailer(&line));
+            let mut values = self.trailers.get_raw(&name).map(|values| values.to_vec()).unwrap_or_else(Vec::new);
+            values.push(value);
+            self.trailers.set_raw(name, values);
+        }
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.state {
+                State::Size => {
+                    let line = try!(self.read_line());
+                    let size = try!(parse_size(&line));
+
+                    if size == 0 {
+                        try!(self.read_trailers());
+                        self.state = State::End;
+                    } else {
+                        self.state = State::Data(size);
+                    }
+                },
+                State::Data(remaining) => {
+                    if buf.is_empty() {
+                        return Ok(0);
+                    }
+
+                    let length = min(remaining, buf.len() as u64) as usize;
+                    let read = try!(self.reader.read(&mut buf[..length]));
+                    if read == 0 {
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "unexpected end of chunked body"));
+                    }
+
+                    let remaining = remaining - read as u64;
+                    if remaining == 0 {
+                        if !try!(self.read_line()).is_empty() {
+                            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid end of chunk"));
+                        }
+                        self.state = State::Size;
+                    } else {
+                        self.state = State::Data(remaining);
+                    }
+
+                    return Ok(read);
+                },
+                State::End => return Ok(0)
+            }
+        }
+    }
+}
+
+//Parses `size[;extensions]`, where the extensions are ignored.
+fn parse_size(line: &[u8]) -> io::Result<u64> {
+    let size = line.split(|&b| b == b';').next().unwrap_or(line);
+    let size = trim(size);
+
+    if size.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "missing chunk size"));
+    }
+
+    let mut result: u64 = 0;
+    for &b in size {
+        let digit = match b {
+            b'0'...b'9' => b - b'0',
+            b'a'...b'f' => b - b'a' + 10,
+            b'A'...b'F' => b - b'A' + 10,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid chunk size"))
+        };
+
+        result = match result.checked_mul(16) {
+            Some(result) => result + digit as u64,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData, "too large chunk size"))
+        };
+    }
+
+    Ok(result)
+}
+
+fn parse_trailer(line: &[u8]) -> io::Result<(String, Vec<u8>)> {
+    let colon = match line.iter().position(|&b| b == b':') {
+        Some(colon) => colon,
+        None => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid trailer field"))
+    };
+
+    let name = &line[..colon];
+    if name.is_empty() || name.iter().any(|&b| b <= b' ' || b >= 0x7f) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid trailer field name"));
+    }
+
+    let name = String::from_utf8_lossy(name).into_owned();
+    Ok((name, trim(&line[colon + 1..]).to_vec()))
+}
+
+fn trim(mut bytes: &[u8]) -> &[u8] {
+    while let Some((&first, rest)) = bytes.split_first() {
+        if first != b' ' && first != b'\t' {
+            break;
+        }
+        bytes = rest;
+    }
+
+    while let Some((&last, rest)) = bytes.split_last() {
+        if last != b' ' && last != b'\t' {
+            break;
+        }
+        bytes = rest;
+    }
+
+    bytes
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, ErrorKind};
+    use super::ChunkedReader;
+
+    fn decode(input: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut reader = ChunkedReader::new(input);
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body).unwrap();
+        assert!(reader.is_finished());
+
+        let mut rest = Vec::new();
+        reader.get_mut().read_to_end(&mut rest).unwrap();
+        (body, rest)
+    }
+
+    #[test]
+    fn stop_at_last_chunk() {
+        let (body, rest) = decode(b"5\r\nhello\r\n7\r\n, world\r\n0\r\n\r\nGET / HTTP/1.1\r\n");
+        assert_eq!(body, b"hello, world");
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n");
+    }
+
+    #[test]
+    fn ignore_extensions() {
+        let (body, _) = decode(b"A;name=value\r\n0123456789\r\n0 ; last\r\n\r\n");
+        assert_eq!(body, b"0123456789");
+    }
+
+    #[test]
+    fn read_trailers() {
+        let mut reader = ChunkedReader::new(&b"3\r\nabc\r\n0\r\nDigest: sha-256=abc\r\nX-Extra:  a \r\nX-Extra: b\r\n\r\n"[..]);
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body).unwrap();
+
+        assert_eq!(body, b"abc");
+        assert_eq!(reader.trailers().get_raw("Digest"), Some(&[b"sha-256=abc".to_vec()][..]));
+        assert_eq!(reader.trailers().get_raw("X-Extra"), Some(&[b"a".to_vec(), b"b".to_vec()][..]));
+    }
+
+    #[test]
+    fn reject_broken_bodies() {
+        let inputs: &[&[u8]] = &[
+            b"5\r\nhel",
+            b"5\r\nhello",
+            b"5\r\nhelloX\r\n0\r\n\r\n",
+            b"x\r\nhello\r\n0\r\n\r\n",
+            b"11111111111111111\r\n",
+            b"0\r\ninvalid\r\n\r\n"
+        ];
+
+        for input in inputs {
+            let mut body = Vec::new();
+            let result = ChunkedReader::new(*input).read_to_end(&mut body);
+            assert!(result.is_err(), "{:?} was accepted", String::from_utf8_lossy(input));
+        }
+
+        let mut body = Vec::new();
+        let error = ChunkedReader::new(&b"5\r\nhel"[..]).read_to_end(&mut body).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,386 @@
+use std::fmt;
+use std::error::Error;
+use std::str::FromStr;
+
+use rustc_serialize::{Decodable, Decoder};
+
+use context::{Parameters, MaybeUtf8Owned};
+use StatusCode;
+
+///Error from `Parameters::decode`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    ///The named parameter is missing.
+    Missing(String),
+
+    ///The named parameter could not be parsed. The second value describes
+    ///what went wrong.
+    Invalid(String, String),
+
+    ///The target type has a structure that can't be decoded from
+    ///parameters, such as nested structs.
+    Unsupported(String)
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::Missing(ref name) => write!(f, "missing parameter '{}'", name),
+            DecodeError::Invalid(ref name, ref e) => write!(f, "invalid parameter '{}': {}", name, e),
+            DecodeError::Unsupported(ref e) => write!(f, "unsupported type: {}", e)
+        }
+    }
+}
+
+impl Error for DecodeError {
+    fn description(&self) -> &str {
+        match *self {
+            DecodeError::Missing(_) => "missing parameter",
+            DecodeError::Invalid(_, _) => "invalid parameter",
+            DecodeError::Unsupported(_) => "unsupported type"
+        }
+    }
+}
+
+impl From<DecodeError> for StatusCode {
+    ///Missing and invalid parameters are the client's fault and translates
+    ///to `400 Bad Request`, while unsupported types translates to `500
+    ///Internal Server Error`.
+    fn from(err: DecodeError) -> StatusCode {
+        match err {
+            DecodeError::Missing(_) | DecodeError::Invalid(_, _) => StatusCode::BadRequest,
+            DecodeError::Unsupported(_) => StatusCode::InternalServerError
+        }
+    }
+}
+
+pub fn decode<T: Decodable>(parameters: &Parameters) -> Result<T, DecodeError> {
+    let mut decoder = ParameterDecoder {
+        parameters: parameters,
+        field: None,
+        value: None,
+        entries: vec![],
+        sequence: vec![]
+    };
+
+    T::decode(&mut decoder)
+}
+
+//Decodes a struct, or a map, from parameters. The fields are decoded from
+//their string representations.
+struct ParameterDecoder<'a> {
+    parameters: &'a Parameters,
+    field: Option<String>,
+    value: Option<&'a MaybeUtf8Owned>,
+    entries: Vec<(&'a MaybeUtf8Owned, &'a MaybeUtf8Owned)>,
+    sequence: Vec<&'a MaybeUtf8Owned>
+}
+
+impl<'a> ParameterDecoder<'a> {
+    fn field_name(&self) -> String {
+        self.field.clone().unwrap_or_else(String::new)
+    }
+
+    fn value(&self) -> Result<&'a MaybeUtf8Owned, DecodeError> {
+        if self.field.is_none() {
+            return Err(DecodeError::Unsupported("only structs and maps can be decoded from parameters".to_owned()));
+        }
+
+        self.value.ok_or_else(|| DecodeError::Missing(self.field_name()))
+    }
+
+    fn parse<T: FromStr>(&mut self) -> Result<T, DecodeError> where T::Err: fmt::Display {
+        let value = try!(self.value());
+        value.as_utf8_lossy().parse().map_err(|e: T::Err| DecodeError::Invalid(self.field_name(), e.to_string()))
+    }
+
+    fn unsupported<T>(&self, what: &str) -> Result<T, DecodeError> {
+        Err(DecodeError::Unsupported(format!("{} can't be decoded from parameters", what)))
+    }
+}
+
+impl<'a> Decoder for ParameterDecoder<'a> {
+    type Error = DecodeError;
+
+    fn read_nil(&mut self) -> Result<(), DecodeError> {
+        Ok(())
+    }
+
+    fn read_usize(&mut self) -> Result<usize, DecodeError> {
+        self.parse()
+    }
+
+    fn read_u64(&mut self) -> Result<u64, DecodeError> {
+        self.parse()
+    }
+
+    fn read_u32(&mut self) -> Result<u32, DecodeError> {
+        self.parse()
+    }
+
+    fn read_u16(&mut self) -> Result<u16, DecodeError> {
+        self.parse()
+    }
+
+    fn read_u8(&mut self) -> Result<u8, DecodeError> {
+        self.parse()
+    }
+
+    fn read_isize(&mut self) -> Result<isize, DecodeError> {
+        self.parse()
+    }
+
+    fn read_i64(&mut self) -> Result<i64, DecodeError> {
+        self.parse()
+    }
+
+    fn read_i32(&mut self) -> Result<i32, DecodeError> {
+        self.parse()
+    }
+
+    fn read_i16(&mut self) -> Result<i16, DecodeError> {
+        self.parse()
+    }
+
+    fn read_i8(&mut self) -> Result<i8, DecodeError> {
+        self.parse()
+    }
+
+    fn read_bool(&mut self) -> Result<bool, DecodeError> {
+        self.parse()
+    }
+
+    fn read_f64(&mut self) -> Result<f64, DecodeError> {
+        self.parse()
+    }
+
+    fn read_f32(&mut self) -> Result<f32, DecodeError> {
+        self.parse()
+    }
+
+    fn read_char(&mut self) -> Result<char, DecodeError> {
+        self.parse()
+    }
+
+    fn read_str(&mut self) -> Result<String, DecodeError> {
+        let value = try!(self.value());
+        Ok(value.as_utf8_lossy().into_owned())
+    }
+
+    fn read_enum<T, F>(&mut self, _name: &str, f: F) -> Result<T, DecodeError> where
+        F: FnOnce(&mut ParameterDecoder<'a>) -> Result<T, DecodeError>
+    {
+        f(self)
+    }
+
+    fn read_enum_variant<T, F>(&mut self, names: &[&str], mut f: F) -> Result<T, DecodeError> where
+        F: FnMut(&mut ParameterDecoder<'a>, usize) -> Result<T, DecodeError>
+    {
+        let value = try!(self.value());
+        let index = names.iter().position(|&name| name == value.as_utf8_lossy());
+        match index {
+            Some(index) => f(self, index),
+            None => Err(DecodeError::Invalid(self.field_name(), format!("expected one of {}", names.join(", "))))
+        }
+    }
+
+    fn read_enum_variant_arg<T, F>(&mut self, _a_idx: usize, _f: F) -> Result<T, DecodeError> where
+        F: FnOnce(&mut ParameterDecoder<'a>) -> Result<T, DecodeError>
+    {
+        self.unsupported("enum variants with values")
+    }
+
+    fn read_enum_struct_variant<T, F>(&mut self, names: &[&str], f: F) -> Result<T, DecodeError> where
+        F: FnMut(&mut ParameterDecoder<'a>, usize) -> Result<T, DecodeError>
+    {
+        self.read_enum_variant(names, f)
+    }
+
+    fn read_enum_struct_variant_field<T, F>(&mut self, _f_name: &str, _f_idx: usize, _f: F) -> Result<T, DecodeError> where
+        F: FnOnce(&mut ParameterDecoder<'a>) -> Result<T, DecodeError>
+    {
+        self.unsupported("enum variants with values")
+    }
+
+    fn read_struct<T, F>(&mut self, _s_name: &str, _len: usize, f: F) -> Result<T, DecodeError> where
+        F: FnOnce(&mut ParameterDecoder<'a>) -> Result<T, DecodeError>
+    {
+        if self.field.is_some() {
+            self.unsupported("nested structs")
+        } else {
+            f(self)
+        }
+    }
+
+    fn read_struct_field<T, F>(&mut self, f_name: &str, _f_idx: usize, f: F) -> Result<T, DecodeError> where
+        F: FnOnce(&mut ParameterDecoder<'a>) -> Result<T, DecodeError>
+    {
+        self.field = Some(f_name.to_owned());
+        self.value = self.parameters.get_raw(f_name);
+        let result = f(self);
+        self.field = None;
+        self.value = None;
+        result
+    }
+
+    fn read_tuple<T, F>(&mut self, _len: usize, _f: F) -> Result<T, DecodeError> where
+        F: FnOnce(&mut ParameterDecoder<'a>) -> Result<T, DecodeError>
+    {
+        self.unsupported("tuples")
+    }
+
+    fn read_tuple_arg<T, F>(&mut self, _a_idx: usize, _f: F) -> Result<T, DecodeError> where
+        F: FnOnce(&mut ParameterDecoder<'a>) -> Result<T, DecodeError>
+    {
+        self.unsupported("tuples")
+    }
+
+    fn read_tuple_struct<T, F>(&mut self, _s_name: &str, _len: usize, _f: F) -> Result<T, DecodeError> where
+        F: FnOnce(&mut ParameterDecoder<'a>) -> Result<T, DecodeError>
+    {
+        self.unsupported("tuple structs")
+    }
+
+    fn read_tuple_struct_arg<T, F>(&mut self, _a_idx: usize, _f: F) -> Result<T, DecodeError> where
+        F: FnOnce(&mut ParameterDecoder<'a>) -> Result<T, DecodeError>
+    {
+        self.unsupported("tuple structs")
+    }
+
+    fn read_option<T, F>(&mut self, mut f: F) -> Result<T, DecodeError> where
+        F: FnMut(&mut ParameterDecoder<'a>, bool) -> Result<T, DecodeError>
+    {
+        let is_some = self.value.is_some();
+        f(self, is_some)
+    }
+
+    fn read_seq<T, F>(&mut self, f: F) -> Result<T, DecodeError> where
+        F: FnOnce(&mut ParameterDecoder<'a>, usize) -> Result<T, DecodeError>
+    {
+        //A sequence is made from every value of a repeated parameter.
+        let sequence = match self.field {
+            Some(ref field) if self.sequence.is_empty() => self.parameters.get_all(field),
+            _ => return self.unsupported("nested sequences")
+        };
+
+        self.sequence = sequence;
+        let len = self.sequence.len();
+        let result = f(self, len);
+        self.sequence = vec![];
+        result
+    }
+
+    fn read_seq_elt<T, F>(&mut self, idx: usize, f: F) -> Result<T, DecodeError> where
+        F: FnOnce(&mut ParameterDecoder<'a>) -> Result<T, DecodeError>
+    {
+        let value = self.value;
+        self.value = Some(self.sequence[idx]);
+        let result = f(self);
+        self.value = value;
+        result
+    }
+
+    fn read_map<T, F>(&mut self, f: F) -> Result<T, DecodeError> where
+        F: FnOnce(&mut ParameterDecoder<'a>, usize) -> Result<T, DecodeError>
+    {
+        if self.field.is_some() {
+            return self.unsupported("nested maps");
+        }
+
+        self.entries = self.parameters.iter().collect();
+        let len = self.entries.len();
+        f(self, len)
+    }
+
+    fn read_map_elt_key<T, F>(&mut self, idx: usize, f: F) -> Result<T, DecodeError> where
+        F: FnOnce(&mut ParameterDecoder<'a>) -> Result<T, DecodeError>
+    {
+        let (key, _) = self.entries[idx];
+        self.field = Some(key.as_utf8_lossy().into_owned());
+        self.value = Some(key);
+        let result = f(self);
+        self.field = None;
+        self.value = None;
+        result
+    }
+
+    fn read_map_elt_val<T, F>(&mut self, idx: usize, f: F) -> Result<T, DecodeError> where
+        F: FnOnce(&mut ParameterDecoder<'a>) -> Result<T, DecodeError>
+    {
+        let (key, value) = self.entries[idx];
+        self.field = Some(key.as_utf8_lossy().into_owned());
+        self.value = Some(value);
+        let result = f(self);
+        self.field = None;
+        self.value = None;
+        result
+    }
+
+    fn error(&mut self, err: &str) -> DecodeError {
+        DecodeError::Invalid(self.field_name(), err.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use context::Parameters;
+    use super::DecodeError;
+
+    #[derive(RustcDecodable, Debug, PartialEq)]
+    enum Order {
+        Asc,
+        Desc
+    }
+
+    #[derive(RustcDecodable, Debug, PartialEq)]
+    struct UserPath {
+        id: u32,
+        slug: String,
+        order: Option<Order>,
+        tags: Vec<String>
+    }
+
+    #[test]
+    fn decode_struct() {
+        let mut parameters = Parameters::new();
+        parameters.insert("id", "42");
+        parameters.insert("slug", "hello-world");
+
+        let path: UserPath = parameters.decode().unwrap();
+        assert_eq!(path, UserPath { id: 42, slug: "hello-world".to_owned(), order: None, tags: vec![] });
+
+        parameters.append("tags", "a");
+        parameters.append("tags", "b");
+        let path: UserPath = parameters.decode().unwrap();
+        assert_eq!(path.tags, vec!["a".to_owned(), "b".to_owned()]);
+
+        parameters.insert("order", "Desc");
+        let path: UserPath = parameters.decode().unwrap();
+        assert_eq!(path.order, Some(Order::Desc));
+
+        parameters.insert("order", "sideways");
+        assert_eq!(parameters.decode::<UserPath>().unwrap_err(), DecodeError::Invalid("order".to_owned(), "expected one of Asc, Desc".to_owned()));
+
+        parameters.insert("id", "forty-two");
+        match parameters.decode::<UserPath>() {
+            Err(DecodeError::Invalid(name, _)) => assert_eq!(name, "id"),
+            other => panic!("unexpected result: {:?}", other)
+        }
+
+        parameters.remove("id");
+        assert_eq!(parameters.decode::<UserPath>().unwrap_err(), DecodeError::Missing("id".to_owned()));
+    }
+
+    #[test]
+    fn decode_map() {
+        let mut parameters = Parameters::new();
+        parameters.insert("a", "1");
+        parameters.insert("b", "2");
+
+        let map: HashMap<String, u8> = parameters.decode().unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get("a"), Some(&1));
+        assert_eq!(map.get("b"), Some(&2));
+    }
+}
@@ -4,6 +4,7 @@ use std::fmt;
 use Method;
 use Handler;
 use context::MaybeUtf8Slice;
+use utils;
 
 ///A hyperlink.
 #[derive(Clone)]
@@ -17,6 +18,82 @@ pub struct Link<'a> {
     pub handler: Option<&'a Handler>,
 }
 
+impl<'a> Link<'a> {
+    ///Resolve the link into a `LinkTarget`, where `base` is the path of the
+    ///current location, such as the requested path.
+    ///
+    ///Variable segments are written as `{label}` in the path template. The
+    ///router may leave their labels empty, in which case they are written as
+    ///`{segment}` or `{path}`, for single segments and sequences. The
+    ///relation name is taken from the last segment, or `"self"` if the path
+    ///is empty.
+    ///
+    ///```
+    ///use rustful::context::MaybeUtf8Slice;
+    ///use rustful::context::hypermedia::{Link, LinkSegment, SegmentType};
+    ///
+    ///let link = Link {
+    ///    method: None,
+    ///    path: vec![
+    ///        LinkSegment { label: "posts".into(), ty: SegmentType::Static },
+    ///        LinkSegment { label: MaybeUtf8Slice::new(), ty: SegmentType::VariableSegment }
+    ///    ],
+    ///    handler: None
+    ///};
+    ///
+    ///let target = link.target(b"/users/12");
+    ///assert_eq!(target.href, "/users/12/posts/{segment}");
+    ///assert_eq!(target.relation, "segment");
+    ///assert!(target.templated);
+    ///```
+    pub fn target(&self, base: &[u8]) -> LinkTarget {
+        let mut href = utils::percent_encode_path(base);
+        while href.ends_with('/') {
+            href.pop();
+        }
+
+        let mut relation = "self".to_owned();
+        let mut templated = false;
+
+        for segment in &self.path {
+            href.push('/');
+
+            relation = match segment.ty {
+                SegmentType::Static => {
+                    href.push_str(&utils::percent_encode_path(segment.label.as_bytes()));
+                    segment.label.as_utf8_lossy().into_owned()
+                },
+                SegmentType::VariableSegment | SegmentType::VariableSequence => {
+                    let name = if !segment.label.is_empty() {
+                        segment.label.as_utf8_lossy().into_owned()
+                    } else if segment.ty == SegmentType::VariableSegment {
+                        "segment".to_owned()
+                    } else {
+                        "path".to_owned()
+                    };
+
+                    templated = true;
+                    href.push('{');
+                    href.push_str(&name);
+                    href.push('}');
+                    name
+                }
+            };
+        }
+
+        if href.is_empty() {
+            href.push('/');
+        }
+
+        LinkTarget {
+            relation: relation,
+            method: self.method.clone(),
+            href: href,
+            templated: templated
+        }
+    }
+}
+
 impl<'a> fmt::Debug for Link<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "method: {:?}, path: {:?}, handler present: {}", self.method, self.path, self.handler.is_some())
@@ -44,4 +121,142 @@ pub enum SegmentType {
     ///A dynamic sequence of segments. This works like a variable segment, but
     ///will match one or more segments until the rest of the pattern matches.
     VariableSequence,
-}
\ No newline at end of file
+}
+///A resolved hyperlink, with a complete path template.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LinkTarget {
+    ///The name of the relation between the current resource and the target.
+    pub relation: String,
+    ///The HTTP method for which the target is available, if it matters.
+    pub method: Option<Method>,
+    ///The path to the target, where variable segments are written as
+    ///`{label}`.
+    pub href: String,
+    ///`true` if `href` contains variable segments.
+    pub templated: bool
+}
+
+///A builder for a `_links` object, as used in JSON based hypermedia formats,
+///such as HAL.
+///
+///It's formatted as a JSON object, where each relation is a key. A relation
+///with more than one target is formatted as an array.
+///
+///```
+///use rustful::{Context, Response};
+///use rustful::context::hypermedia::Links;
+///
+///fn my_handler(context: Context, response: Response) {
+///    let links = Links::from(context.links())
+///        .link("home", "/");
+///
+///    response.send(format!("{{\"_links\": {}}}", links));
+///}
+///```
+#[derive(Clone, Debug, Default)]
+pub struct Links {
+    targets: Vec<LinkTarget>
+}
+
+impl Links {
+    ///Create an empty `_links` object.
+    pub fn new() -> Links {
+        Links {
+            targets: vec![]
+        }
+    }
+
+    ///Add a target.
+    pub fn add(mut self, target: LinkTarget) -> Links {
+        self.targets.push(target);
+        self
+    }
+
+    ///Add a fixed link to `href`, for any method.
+    pub fn link<R: Into<String>, H: Into<String>>(self, relation: R, href: H) -> Links {
+        self.add(LinkTarget {
+            relation: relation.into(),
+            method: None,
+            href: href.into(),
+            templated: false
+        })
+    }
+
+    ///Get the added targets.
+    pub fn targets(&self) -> &[LinkTarget] {
+        &self.targets
+    }
+}
+
+impl From<Vec<LinkTarget>> for Links {
+    fn from(targets: Vec<LinkTarget>) -> Links {
+        Links {
+            targets: targets
+        }
+    }
+}
+
+impl fmt::Display for Links {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        //Group the targets by relation, in order of appearance.
+        let mut relations: Vec<(&str, Vec<&LinkTarget>)> = vec![];
+        for target in &self.targets {
+            match relations.iter().position(|&(relation, _)| relation == target.relation) {
+                Some(index) => relations[index].1.push(target),
+                None => relations.push((&target.relation[..], vec![target]))
+            }
+        }
+
+        try!(f.write_str("{"));
+        for (i, &(relation, ref targets)) in relations.iter().enumerate() {
+            if i > 0 {
+                try!(f.write_str(","));
+            }
+
+            try!(write_json_string(f, relation));
+            try!(f.write_str(":"));
+
+            if targets.len() > 1 {
+                try!(f.write_str("["));
+            }
+
+            for (j, target) in targets.iter().enumerate() {
+                if j > 0 {
+                    try!(f.write_str(","));
+                }
+
+                try!(f.write_str("{\"href\":"));
+                try!(write_json_string(f, &target.href));
+                if target.templated {
+                    try!(f.write_str(",\"templated\":true"));
+                }
+                if let Some(ref method) = target.method {
+                    try!(f.write_str(",\"method\":"));
+                    try!(write_json_string(f, &method.to_string()));
+                }
+                try!(f.write_str("}"));
+            }
+
+            if targets.len() > 1 {
+                try!(f.write_str("]"));
+            }
+        }
+        f.write_str("}")
+    }
+}
+
+fn write_json_string(f: &mut fmt::Formatter, string: &str) -> fmt::Result {
+    try!(f.write_str("\""));
+    for c in string.chars() {
+        match c {
+            '"' => try!(f.write_str("\\\"")),
+            '\\' => try!(f.write_str("\\\\")),
+            '\n' => try!(f.write_str("\\n")),
+            '\r' => try!(f.write_str("\\r")),
+            '\t' => try!(f.write_str("\\t")),
+            c if (c as u32) < 0x20 => try!(write!(f, "\\u{:04x}", c as u32)),
+            c => try!(write!(f, "{}", c))
+        }
+    }
+    f.write_str("\"")
+}
@@ -55,14 +55,18 @@
 //! * The fragment (`http://example.com#foo`) is also parsed and can be
 //!accessed through `fragment` as an optional `String`.
 //!
+//! * Cookies from the `Cookie` headers are available through the `cookies`
+//!field, which parses them on first access, and single values can be
+//!retrieved using `get_cookie`.
+//!
 //!##Global Data
 //!
 //!There is also infrastructure for globally accessible data, that can be
-//!accessed through the `global` field. This is meant to provide a place for
-//!things like database connections or cached data that should be available to
-//!all handlers. The storage space itself is immutable when the server has
-//!started, so the only way to change it is through some kind of inner
-//!mutability.
+//!accessed through the `global` field, or by type using `get_global`. This
+//!is meant to provide a place for things like database connections or cached
+//!data that should be available to all handlers. The storage space itself is
+//!immutable when the server has started, so the only way to change it is
+//!through some kind of inner mutability.
 //!
 //!```
 //!# #[macro_use] extern crate rustful;
@@ -71,7 +75,7 @@
 //!use rustful::StatusCode::InternalServerError;
 //!
 //!fn my_handler(context: Context, mut response: Response) {
-//!    if let Some(some_wise_words) = context.global.get::<&str>() {
+//!    if let Some(some_wise_words) = context.get_global::<&str>() {
 //!        response.send(format!("food for thought: {}", some_wise_words));
 //!    } else {
 //!        error!("there should be a string literal in `global`");
@@ -82,6 +86,37 @@
 //!# fn main() {}
 //!```
 //!
+//!##Request Extensions
+//!
+//!Context filters can attach typed values to the request, such as an
+//!authenticated user or a parsed session, through the `extensions` field.
+//!Each type can be stored once and the handler can then get it back by its
+//!type.
+//!
+//!```
+//!use rustful::{Context, Response};
+//!use rustful::filter::{FilterContext, ContextFilter, ContextAction};
+//!
+//!struct CurrentUser(String);
+//!
+//!struct Authenticate;
+//!
+//!impl ContextFilter for Authenticate {
+//!    fn modify(&self, _filter_context: FilterContext, context: &mut Context) -> ContextAction {
+//!        context.extensions.insert(CurrentUser("alice".into()));
+//!        ContextAction::next()
+//!    }
+//!}
+//!
+//!fn my_handler(context: Context, response: Response) {
+//!    if let Some(&CurrentUser(ref name)) = context.extensions.get::<CurrentUser>() {
+//!        response.send(format!("hello, {}", name));
+//!    } else {
+//!        response.send("hello, stranger");
+//!    }
+//!}
+//!```
+//!
 //!##Request Body
 //!
 //!The body will not be read in advance, unlike the other parts of the
@@ -109,44 +144,115 @@
 //![log]: ../log/index.html
 //![body_reader]: body/struct.BodyReader.html
 
-use std::net::SocketAddr;
+use std::net::{SocketAddr, IpAddr};
 use std::fmt;
 use std::borrow::Cow;
+use std::time::Instant;
+use std::str;
+use std::sync::{Arc, Mutex};
+
+use anymap::AnyMap;
+use anymap::any::Any;
+
+use hyper::net::NetworkStream;
+
+use unicase::UniCase;
+
+#[cfg(feature = "rustc_json_body")]
+use rustc_serialize::{json, Decodable};
 
 use HttpVersion;
 use Method;
+use LanguageTag;
 use header::Headers;
-use server::Global;
+use cookie::Cookies;
+use mime::{Mime, TopLevel, SubLevel};
+use server::{Global, WorkerState};
 
 use self::body::BodyReader;
-use self::hypermedia::Link;
+use self::hypermedia::{Link, LinkTarget};
 
 pub mod body;
 pub mod hypermedia;
 
+mod chunked;
+
 mod maybe_utf8;
 pub use self::maybe_utf8::{MaybeUtf8, MaybeUtf8Owned, MaybeUtf8Slice, Buffer};
 
 mod parameters;
-pub use self::parameters::Parameters;
+pub use self::parameters::{Parameters, ParameterError};
+
+#[cfg(feature = "rustc_json_body")]
+mod decode;
+#[cfg(feature = "rustc_json_body")]
+pub use self::decode::DecodeError;
 
 ///A container for handler input, like request data and utilities.
 pub struct Context<'a, 'b: 'a, 's> {
-    ///Headers from the HTTP request.
+    ///Headers from the HTTP request. Any trailer fields that are sent after
+    ///a chunked body are available through `body.trailers()`. Context
+    ///filters may change them, as described in the `ContextFilter`
+    ///documentation.
     pub headers: Headers,
 
-    ///The HTTP version used in the request.
+    ///The HTTP version used in the request. HTTP/1.0 connections are closed
+    ///after each response, unless the client asks for `keep-alive`, and the
+    ///`Response` will report it through `will_close_connection`.
     pub http_version: HttpVersion,
 
     ///The client address
     pub address: SocketAddr,
 
+    ///The client IP address, as reported by trusted proxies in the
+    ///`Forwarded` or `X-Forwarded-For` headers. This is `None` if the
+    ///request didn't come from a trusted proxy.
+    pub forwarded_for: Option<IpAddr>,
+
+    ///The request scheme, in lower case. This is usually `"http"` or
+    ///`"https"`, depending on the server, but it may be overridden by
+    ///`X-Forwarded-Proto` if the server is configured to use it.
+    pub scheme: String,
+
+    ///The scheme of the listener that received the request, which is
+    ///`"http"` or `"https"`. Unlike `scheme`, it's never taken from
+    ///`X-Forwarded-Proto`, so it tells if the connection to this server is
+    ///encrypted, even when the server has more than one listener.
+    pub listener_scheme: &'static str,
+
+    ///The requested host name or IP address, from the `Host` header. It's
+    ///in lower case and IPv6 addresses are kept within brackets, like
+    ///`[2001:db8::1]`, so it can be used together with `scheme` and `port`
+    ///to build absolute URLs. It's `None` if the header is missing or empty.
+    ///Requests with malformed `Host` headers are rejected with `400 Bad
+    ///Request`.
+    pub host: Option<String>,
+
+    ///The requested port. It's taken from the `Host` header, or
+    ///`X-Forwarded-Port` if the server is configured to use it, and falls
+    ///back to the default port for `scheme`.
+    pub port: u16,
+
     ///The HTTP method.
     pub method: Method,
 
     ///The requested URI.
     pub uri: Uri,
 
+    ///The scheme, host and port from the request target, if it was sent in
+    ///absolute form, as in `GET http://example.com/path HTTP/1.1`. This is
+    ///how clients talk to forward proxies. The host and port replace the
+    ///`Host` header, so they also decide which of the server's
+    ///`host_handlers` will handle the request.
+    pub absolute_target: Option<AbsoluteTarget>,
+
+    ///The pattern of the matched route, such as `/users/:id`, if the router
+    ///keeps track of it. It's `None` for the fallback handler and in the
+    ///global context filters, since they run before the routing. This is
+    ///useful for things like metrics, where the concrete path would produce
+    ///too many different values.
+    pub route: Option<MaybeUtf8Slice<'s>>,
+
     ///Hyperlinks from the current endpoint.
     pub hyperlinks: Vec<Link<'s>>,
 
@@ -156,16 +262,553 @@ pub struct Context<'a, 'b: 'a, 's> {
     ///Query variables from the path.
     pub query: Parameters,
 
+    ///The original query string, without the leading `?`, exactly as it was
+    ///received. This may be useful for verifying signatures or forwarding the
+    ///query to other servers.
+    pub raw_query: Option<MaybeUtf8Owned>,
+
     ///The fragment part of the URL (after #), if provided.
     pub fragment: Option<MaybeUtf8Owned>,
 
+    ///Cookies from the `Cookie` headers. They are parsed when a cookie is
+    ///first accessed.
+    pub cookies: Cookies,
+
+    ///Typed values that were attached to the request by the context
+    ///filters. It's empty by default.
+    pub extensions: AnyMap,
+
     ///Globally accessible data.
     pub global: &'s Global,
 
+    ///The request headers that have been used to choose the response. The
+    ///negotiation methods, such as `negotiate`, add the headers they read,
+    ///and handlers may add their own.
+    pub negotiated: NegotiatedHeaders,
+
+    ///The time when the server began to handle the request, right after its
+    ///head was parsed. The time that has passed since then is available through
+    ///`Response::elapsed`.
+    pub received_at: Instant,
+
     ///A reader for the request body.
     pub body: BodyReader<'a, 'b>,
 }
 
+impl<'a, 'b, 's> Context<'a, 'b, 's> {
+    ///Get the address of the directly connected peer. This may be a proxy.
+    pub fn client_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    ///Get the IP address of the client, as reported by trusted proxies.
+    ///
+    ///The addresses in `Forwarded` (or `X-Forwarded-For`, if `Forwarded` is
+    ///missing) are walked from right to left, as long as they are trusted.
+    ///The first untrusted address is the client. Forwarded addresses are
+    ///ignored if the peer itself is not trusted, to prevent spoofing. The
+    ///trusted proxies are set in the server's `trusted_proxies` list.
+    pub fn real_ip(&self) -> IpAddr {
+        self.forwarded_for.unwrap_or_else(|| self.address.ip())
+    }
+
+    ///Resolve the hyperlinks from the current endpoint into `LinkTarget`s,
+    ///relative to the requested path. See `Link::target` for more details,
+    ///and `Links` for a way to include them in a JSON response.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///fn my_handler(context: Context, response: Response) {
+    ///    let next: Vec<_> = context.links().into_iter()
+    ///        .map(|link| format!("{} {}", link.relation, link.href))
+    ///        .collect();
+    ///
+    ///    response.send(next.join("\n"));
+    ///}
+    ///```
+    pub fn links(&self) -> Vec<LinkTarget> {
+        let base = match self.uri {
+            Uri::Path(ref path) => path.as_bytes(),
+            Uri::Asterisk => &b"/"[..]
+        };
+        self.hyperlinks.iter().map(|link| link.target(base)).collect()
+    }
+
+    ///Get a cookie value as a UTF-8 string. A lossy conversion will be
+    ///performed if it's not encoded as UTF-8. The raw values are available
+    ///through `cookies`.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///fn my_handler(context: Context, response: Response) {
+    ///    if let Some(theme) = context.get_cookie("theme") {
+    ///        response.send(format!("using the {} theme", theme));
+    ///    } else {
+    ///        response.send("using the default theme");
+    ///    }
+    ///}
+    ///```
+    pub fn get_cookie(&self, name: &str) -> Option<String> {
+        self.cookies.get(name)
+    }
+
+    ///Get the username and password from an `Authorization` header with the
+    ///`Basic` scheme. `None` is returned if the header is missing, uses an
+    ///other scheme, or is malformed. The credentials are expected to be
+    ///UTF-8 encoded.
+    ///
+    ///```
+    ///use rustful::{Context, Response, StatusCode};
+    ///
+    ///fn my_handler(context: Context, mut response: Response) {
+    ///    match context.basic_auth() {
+    ///        Some((ref username, ref password)) if username == "admin" && password == "secret" => {
+    ///            response.send("welcome");
+    ///        },
+    ///        _ => {
+    ///            response.set_status(StatusCode::Unauthorized);
+    ///            response.headers_mut().set_raw("WWW-Authenticate", vec![b"Basic realm=\"admin\"".to_vec()]);
+    ///        }
+    ///    }
+    ///}
+    ///```
+    pub fn basic_auth(&self) -> Option<(String, String)> {
+        let credentials = match self.authorization("Basic").and_then(::utils::base64_decode) {
+            Some(credentials) => credentials,
+            None => return None
+        };
+
+        let credentials = match String::from_utf8(credentials) {
+            Ok(credentials) => credentials,
+            Err(_) => return None
+        };
+
+        credentials.find(':').map(|colon| (credentials[..colon].to_owned(), credentials[colon + 1..].to_owned()))
+    }
+
+    ///Get the token from an `Authorization` header with the `Bearer`
+    ///scheme. `None` is returned if the header is missing, uses an other
+    ///scheme, or is malformed.
+    ///
+    ///```
+    ///use rustful::{Context, Response, StatusCode};
+    ///
+    ///fn my_handler(context: Context, mut response: Response) {
+    ///    match context.bearer_token() {
+    ///        Some(token) => response.send(format!("got the token {}", token)),
+    ///        None => response.set_status(StatusCode::Unauthorized)
+    ///    }
+    ///}
+    ///```
+    pub fn bearer_token(&self) -> Option<String> {
+        self.authorization("Bearer")
+            .and_then(|token| str::from_utf8(token).ok())
+            .map(|token| token.to_owned())
+    }
+
+    //The credentials from the `Authorization` header, if it uses `scheme`.
+    fn authorization(&self, scheme: &str) -> Option<&[u8]> {
+        let value = match self.headers.get_raw("Authorization") {
+            Some(values) if values.len() == 1 => &values[0][..],
+            _ => return None
+        };
+
+        let space = match value.iter().position(|&b| b == b' ') {
+            Some(space) => space,
+            None => return None
+        };
+
+        let same_scheme = str::from_utf8(&value[..space]).ok().map_or(false, |name| UniCase(name) == UniCase(scheme));
+        let credentials = value[space..].iter().position(|&b| b != b' ').map(|start| &value[space + start..]);
+
+        match credentials {
+            Some(credentials) if same_scheme && !credentials.contains(&b' ') => Some(credentials),
+            _ => None
+        }
+    }
+
+    ///Borrow a value of type `T` from the global data, if there is one. This
+    ///is the same as `context.global.get()`, but the value isn't tied to the
+    ///lifetime of the `Context`.
+    ///
+    ///```
+    ///use std::sync::Mutex;
+    ///use rustful::{Context, Response};
+    ///
+    ///struct Counter(Mutex<u64>);
+    ///
+    ///fn my_handler(context: Context, response: Response) {
+    ///    if let Some(&Counter(ref count)) = context.get_global() {
+    ///        let mut count = count.lock().unwrap();
+    ///        *count += 1;
+    ///        response.send(format!("visitor number {}", *count));
+    ///    }
+    ///}
+    ///```
+    pub fn get_global<T: Any + Send + Sync>(&self) -> Option<&'s T> {
+        self.global.get()
+    }
+
+    ///Run `f` with the current worker thread's value of type `T`, from the
+    ///server's `worker_init`, and return its result. `None` is returned if
+    ///there is no such value. See `WorkerState` for more details.
+    ///
+    ///The value is borrowed for the duration of `f`, so calling this again
+    ///for the same type from within `f` will panic.
+    pub fn with_worker_state<T: 'static, R, F: FnOnce(&mut T) -> R>(&self, f: F) -> Option<R> {
+        WorkerState::with(f)
+    }
+
+    ///Get the network stream of the connection, if it's a `T`. This gives
+    ///access to connection level details, such as socket options or the TLS
+    ///session. The stream is `server::HttpStream` for plain HTTP,
+    ///`server::TlsStream` with the `rustls` feature, and OpenSSL's
+    ///`SslStream<HttpStream>` with the `ssl` feature.
+    ///
+    ///The stream must not be read from or written to. That's still possible
+    ///through the shared reference, for streams such as `HttpStream`, but it
+    ///would bypass the request body and the response and break the HTTP
+    ///protocol for the rest of the connection. The stream is shared with the
+    ///whole connection, so any changes to the socket will also affect the
+    ///following requests.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///use rustful::server::HttpStream;
+    ///
+    ///fn my_handler(mut context: Context, response: Response) {
+    ///    let local_addr = context.transport::<HttpStream>().and_then(|stream| stream.0.local_addr().ok());
+    ///
+    ///    match local_addr {
+    ///        Some(address) => response.send(format!("received by {}", address)),
+    ///        None => response.send("received over TLS")
+    ///    }
+    ///}
+    ///```
+    pub fn transport<T: NetworkStream>(&mut self) -> Option<&T> {
+        self.body.raw_stream().get_ref().downcast_ref()
+    }
+
+    ///Get the `Last-Event-ID` header as a UTF-8 string. It's sent by
+    ///Server-Sent Events clients when they reconnect, to resume from the last
+    ///event they received. A lossy conversion will be performed if it's not
+    ///encoded as UTF-8.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///use rustful::sse::Event;
+    ///
+    ///fn my_handler(context: Context, response: Response) {
+    ///    let next = context.last_event_id()
+    ///        .and_then(|id| id.parse().ok())
+    ///        .map_or(0, |id: u64| id + 1);
+    ///
+    ///    let mut events = response.into_event_stream();
+    ///    events.send(Event::new("resumed").id(next.to_string())).unwrap_or(());
+    ///}
+    ///```
+    pub fn last_event_id(&self) -> Option<Cow<str>> {
+        self.headers.get_raw("Last-Event-ID")
+            .and_then(|values| values.first())
+            .map(|value| String::from_utf8_lossy(value))
+    }
+
+    ///Choose the most acceptable media type from `offers`, based on the
+    ///`Accept` header and its quality values. Wildcards, such as `*/*` and
+    ///`text/*`, are supported and the most specific match decides the quality
+    ///of each offer. Ties are won by the earliest offer.
+    ///
+    ///The first offer is returned if there is no `Accept` header, and `None`
+    ///is returned if none of the offers are acceptable. The handler may then
+    ///respond with `406 Not Acceptable`.
+    ///
+    ///```
+    ///use rustful::{Context, Response, StatusCode};
+    ///use rustful::header::ContentType;
+    ///
+    ///fn my_handler(context: Context, mut response: Response) {
+    ///    let offers = [
+    ///        "application/json".parse().unwrap(),
+    ///        "text/plain".parse().unwrap()
+    ///    ];
+    ///
+    ///    match context.negotiate(&offers) {
+    ///        Some(mime) => {
+    ///            let body = if mime == offers[0] { "{\"hello\": \"world\"}" } else { "hello world" };
+    ///            response.headers_mut().set(ContentType(mime));
+    ///            response.send(body);
+    ///        },
+    ///        None => response.set_status(StatusCode::NotAcceptable)
+    ///    }
+    ///}
+    ///```
+    pub fn negotiate(&self, offers: &[Mime]) -> Option<Mime> {
+        use header::Accept;
+
+        self.negotiated.add("Accept");
+
+        match self.headers.get() {
+            Some(&Accept(ref accept)) => ::utils::negotiate_media_type(accept, offers).map(|index| offers[index].clone()),
+            None => offers.first().cloned()
+        }
+    }
+
+    ///Choose the most acceptable language from `offers`, based on the
+    ///`Accept-Language` header and its quality values. A range such as `en`
+    ///matches `en-US`, while an offered `en` is used as a fallback for
+    ///`en-US`, unless `en-US` is also offered. The `*` range matches any
+    ///language, and the most specific match decides the quality of each
+    ///offer. Ties are won by the earliest offer.
+    ///
+    ///The first offer is returned if there is no `Accept-Language` header,
+    ///and `None` is returned if none of the offers are acceptable. The
+    ///handler may then use a default language. `Accept-Language` is added to
+    ///`negotiated`.
+    ///
+    ///```
+    ///use rustful::{Context, Response, LanguageTag};
+    ///use rustful::header::{ContentLanguage, qitem};
+    ///
+    ///fn my_handler(context: Context, mut response: Response) {
+    ///    let offers: Vec<LanguageTag> = vec!["en".parse().unwrap(), "sv".parse().unwrap()];
+    ///    let language = context.negotiate_language(&offers).unwrap_or_else(|| offers[0].clone());
+    ///
+    ///    let greeting = if language == offers[1] { "hej" } else { "hello" };
+    ///    response.headers_mut().set(ContentLanguage(vec![qitem(language)]));
+    ///    response.send(greeting);
+    ///}
+    ///```
+    pub fn negotiate_language(&self, offers: &[LanguageTag]) -> Option<LanguageTag> {
+        self.negotiated.add("Accept-Language");
+
+        match self.headers.get_raw("Accept-Language") {
+            Some(values) => {
+                let accept = ::utils::parse_accept_language(values);
+                ::utils::negotiate_language(&accept, offers).map(|index| offers[index].clone())
+            },
+            None => offers.first().cloned()
+        }
+    }
+
+    ///Get the media type of the request body, from the `Content-Type`
+    ///header.
+    pub fn content_type(&self) -> Option<Mime> {
+        use header::ContentType;
+
+        self.headers.get().map(|&ContentType(ref mime)| mime.clone())
+    }
+
+    ///Check if the request body has the media type `top_level/sub_level`,
+    ///regardless of any parameters, such as `charset`.
+    ///
+    ///```
+    ///use rustful::{Context, Response, StatusCode};
+    ///use rustful::mime::{TopLevel, SubLevel};
+    ///
+    ///fn my_handler(context: Context, mut response: Response) {
+    ///    if !context.has_content_type(TopLevel::Text, SubLevel::Plain) {
+    ///        response.set_status(StatusCode::UnsupportedMediaType);
+    ///        return;
+    ///    }
+    ///
+    ///    match context.charset() {
+    ///        Some(charset) => response.send(format!("the text is encoded as {}", charset)),
+    ///        None => response.send("the text has no charset")
+    ///    }
+    ///}
+    ///```
+    pub fn has_content_type(&self, top_level: TopLevel, sub_level: SubLevel) -> bool {
+        use header::ContentType;
+
+        match self.headers.get() {
+            Some(&ContentType(Mime(ref top, ref sub, _))) => *top == top_level && *sub == sub_level,
+            None => false
+        }
+    }
+
+    ///Get the `charset` parameter of the `Content-Type` header, in lower
+    ///case, such as `utf-8` or `iso-8859-1`.
+    pub fn charset(&self) -> Option<String> {
+        self.content_type().and_then(|mime| ::utils::mime_charset(&mime))
+    }
+
+    //Check that the body is UTF-8, or that it doesn't say anything else.
+    fn has_utf8_charset(&self) -> bool {
+        self.charset().map_or(true, |charset| ::utils::is_utf8_charset(&charset))
+    }
+
+    ///Read and parse an `application/x-www-form-urlencoded` request body. The
+    ///form is parsed in the same way as `query`, and the size of the body and
+    ///the number of parameters are limited by the server's `max_body_size`
+    ///and `max_parameters`. The form is expected to be UTF-8, unless the
+    ///`Content-Type` has an other `charset`. The names and values are then
+    ///converted to UTF-8 if the `charsets` feature is enabled, and the form
+    ///is otherwise rejected. The error can be converted into a suitable
+    ///status code.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///fn my_handler(mut context: Context, mut response: Response) {
+    ///    match context.parse_form() {
+    ///        Ok(form) => {
+    ///            let name = form.get("name").unwrap_or("stranger".into());
+    ///            response.send(format!("hello, {}", name));
+    ///        },
+    ///        Err(e) => response.set_status(e.into())
+    ///    }
+    ///}
+    ///```
+    pub fn parse_form(&mut self) -> Result<Parameters, body::FormError> {
+        if !self.has_content_type(TopLevel::Application, SubLevel::WwwFormUrlEncoded) {
+            return Err(body::FormError::ContentType);
+        }
+
+        let charset = match ::utils::Charset::from_label(&self.charset().unwrap_or_else(|| "utf-8".to_owned())) {
+            Some(charset) => charset,
+            None => return Err(body::FormError::Charset)
+        };
+
+        let buf = try!(self.body.read_body());
+        let form = try!(::utils::parse_parameters(&buf, self.body.max_parameters()).ok_or(body::FormError::TooManyParameters));
+        Ok(charset.decode_parameters(form))
+    }
+
+    ///Read the request body as text, in the `charset` from the
+    ///`Content-Type` header, or UTF-8 if there is none, and convert it to a
+    ///`String`. See `BodyReader::read_text` for more details.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///use rustful::context::body::TextDecoding;
+    ///
+    ///fn my_handler(mut context: Context, mut response: Response) {
+    ///    match context.read_text(TextDecoding::Lossy) {
+    ///        Ok(text) => response.send(text.to_uppercase()),
+    ///        Err(e) => response.set_status(e.into())
+    ///    }
+    ///}
+    ///```
+    pub fn read_text(&mut self, decoding: body::TextDecoding) -> Result<String, body::TextError> {
+        let charset = self.charset();
+        self.body.read_text(charset.as_ref().map(|charset| &**charset), decoding)
+    }
+
+    ///Read and decode a JSON request body as a type `T`. The request must
+    ///have the content type `application/json`, without any `charset` other
+    ///than `utf-8`, and the size of the body is limited by the server's
+    ///`max_body_size`. The error can be converted into a suitable status
+    ///code.
+    ///
+    ///```
+    ///extern crate rustful;
+    ///extern crate rustc_serialize;
+    ///
+    ///use rustful::{Context, Response};
+    ///
+    ///#[derive(RustcDecodable)]
+    ///struct Foo {
+    ///    a: f64,
+    ///    b: f64
+    ///}
+    ///
+    ///fn my_handler(mut context: Context, mut response: Response) {
+    ///    match context.parse_json::<Foo>() {
+    ///        Ok(foo) => response.send(format!("{} + {} = {}", foo.a, foo.b, foo.a + foo.b)),
+    ///        Err(e) => response.set_status(e.into())
+    ///    }
+    ///}
+    ///# fn main() {}
+    ///```
+    #[cfg(feature = "rustc_json_body")]
+    pub fn parse_json<T: Decodable>(&mut self) -> Result<T, body::JsonError> {
+        if !self.has_content_type(TopLevel::Application, SubLevel::Json) {
+            return Err(body::JsonError::ContentType);
+        }
+
+        if !self.has_utf8_charset() {
+            return Err(body::JsonError::Charset);
+        }
+
+        self.parse_json_lenient()
+    }
+
+    ///Read and decode a JSON request body as a type `T`, without checking
+    ///the content type. It's otherwise the same as `parse_json`.
+    #[cfg(feature = "rustc_json_body")]
+    pub fn parse_json_lenient<T: Decodable>(&mut self) -> Result<T, body::JsonError> {
+        let buf = try!(self.body.read_body());
+        let json = try!(json::Json::from_reader(&mut &buf[..]).map_err(json::DecoderError::ParseError));
+        let mut decoder = json::Decoder::new(json);
+        Decodable::decode(&mut decoder).map_err(body::JsonError::Decode)
+    }
+}
+
+///A list of request headers that have been used to choose the response.
+///
+///The same list is shared between the `Context` and the filter storage,
+///where it's read by response filters, such as `filter::VaryNegotiated`, to
+///produce a matching `Vary` header. The names are case insensitive and each
+///name is only listed once.
+///
+///```
+///use rustful::context::NegotiatedHeaders;
+///
+///let negotiated = NegotiatedHeaders::new();
+///negotiated.add("Accept");
+///negotiated.add("User-Agent");
+///negotiated.add("accept");
+///
+///assert_eq!(negotiated.names(), vec!["Accept".to_owned(), "User-Agent".to_owned()]);
+///```
+#[derive(Clone, Default, Debug)]
+pub struct NegotiatedHeaders {
+    names: Arc<Mutex<Vec<UniCase<String>>>>
+}
+
+impl NegotiatedHeaders {
+    ///Create an empty list.
+    pub fn new() -> NegotiatedHeaders {
+        NegotiatedHeaders::default()
+    }
+
+    ///Add a header name, unless it's already in the list.
+    pub fn add(&self, name: &str) {
+        let name = UniCase(name.to_owned());
+        let mut names = match self.names.lock() {
+            Ok(names) => names,
+            Err(poisoned) => poisoned.into_inner()
+        };
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    ///Get the header names, in the order they were added.
+    pub fn names(&self) -> Vec<String> {
+        let names = match self.names.lock() {
+            Ok(names) => names,
+            Err(poisoned) => poisoned.into_inner()
+        };
+        names.iter().map(|name| name.0.clone()).collect()
+    }
+}
+
+///The authority and scheme of an absolute request target.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AbsoluteTarget {
+    ///The URI scheme, such as `http`, in lower case.
+    pub scheme: String,
+
+    ///The host name or IP address.
+    pub host: String,
+
+    ///The port, if it was specified.
+    pub port: Option<u16>
+}
+
 ///A URI that can be a path or an asterisk (`*`).
 ///
 ///The URI may be an invalid UTF-8 path and it is therefore represented as a
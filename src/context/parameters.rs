@@ -1,25 +1,52 @@
 use std::collections::hash_map::{HashMap, Entry};
 use std::iter::FromIterator;
 use std::fmt;
+use std::error::Error;
 use std::ops::{Deref, DerefMut};
 use std::str::FromStr;
 use std::hash::Hash;
 use std::borrow::Cow;
 
+#[cfg(feature = "rustc_json_body")]
+use rustc_serialize::Decodable;
+
 use context::MaybeUtf8Owned;
+#[cfg(feature = "rustc_json_body")]
+use context::decode::{self, DecodeError};
 
 ///An extended `HashMap` with extra functionality for value parsing.
 ///
 ///Some of the methods from `HashMap` has been wrapped to provide a more
 ///ergonomic API, where anything that can be represented as a byte slice can
 ///be used as a key.
+///
+///A key may appear more than once, such as in `?a=1&a=2`. The last value is
+///the one that is returned by `get` and the other single value methods,
+///while `get_all` returns every value in the order they were added. This is
+///true for query parameters, form bodies and anything else that is added
+///using `append`, collected from an iterator or added using `extend`.
+///Modifying the values in place, through `get_mut`, `entry` or the mutable
+///`HashMap`, keeps only the last value of the affected keys.
+///
+///The keys are hashed with the standard `RandomState`, which is seeded
+///randomly for each map. A client can therefore not predict the hash values
+///and craft keys that collide, and the server's `max_parameters` limit puts
+///a bound on how many keys it can send.
 #[derive(Clone)]
-pub struct Parameters(HashMap<MaybeUtf8Owned, MaybeUtf8Owned>);
+pub struct Parameters {
+    values: HashMap<MaybeUtf8Owned, MaybeUtf8Owned>,
+
+    //Every value, in order, for keys that were appended more than once.
+    repeated: HashMap<MaybeUtf8Owned, Vec<MaybeUtf8Owned>>
+}
 
 impl Parameters {
     ///Create an empty `Parameters`.
     pub fn new() -> Parameters {
-        Parameters(HashMap::new())
+        Parameters {
+            values: HashMap::new(),
+            repeated: HashMap::new()
+        }
     }
 
     ///Get a parameter as a UTF-8 string. A lossy conversion will be performed
@@ -27,48 +54,93 @@ impl Parameters {
     pub fn get<'a, K: ?Sized>(&'a self, key: &K) -> Option<Cow<'a, str>> where
         K: Hash + Eq + AsRef<[u8]>
     {
-        self.0.get(key.as_ref()).map(|v| v.as_utf8_lossy())
+        self.values.get(key.as_ref()).map(|v| v.as_utf8_lossy())
     }
 
     ///Get a parameter that may or may not be a UTF-8 string.
     pub fn get_raw<'a, K: ?Sized>(&'a self, key: &K) -> Option<&'a MaybeUtf8Owned> where
         K: Hash + Eq + AsRef<[u8]>
     {
-        self.0.get(key.as_ref())
+        self.values.get(key.as_ref())
     }
 
-    ///Get a mutable parameter that may or may not be a UTF-8 string.
+    ///Get a mutable parameter that may or may not be a UTF-8 string. Only
+    ///the last value is kept if the parameter has more than one.
     pub fn get_mut<'a, K: ?Sized>(&'a mut self, key: &K) -> Option<&'a mut MaybeUtf8Owned> where
         K: Hash + Eq + AsRef<[u8]>
     {
-        self.0.get_mut(key.as_ref())
+        self.repeated.remove(key.as_ref());
+        self.values.get_mut(key.as_ref())
     }
 
     ///Returns true if a parameter with the given key exists.
     pub fn contains_key<K: ?Sized>(&self, key: &K) -> bool where
         K: Hash + Eq + AsRef<[u8]>
     {
-        self.0.contains_key(key.as_ref())
+        self.values.contains_key(key.as_ref())
     }
 
-    ///Insert a parameter.
+    ///Get every value for a parameter, in the order they were added. It's
+    ///empty if the parameter doesn't exist.
+    ///
+    ///```
+    ///# use rustful::{Context, Response};
+    ///fn my_handler(context: Context, response: Response) {
+    ///    //Checkboxes, as in `?color=red&color=blue`
+    ///    let colors: Vec<_> = context.query.get_all("color").into_iter()
+    ///        .map(|color| color.as_utf8_lossy().into_owned())
+    ///        .collect();
+    ///
+    ///    response.send(format!("selected colors: {}", colors.join(", ")));
+    ///}
+    ///```
+    pub fn get_all<'a, K: ?Sized>(&'a self, key: &K) -> Vec<&'a MaybeUtf8Owned> where
+        K: Hash + Eq + AsRef<[u8]>
+    {
+        match self.repeated.get(key.as_ref()) {
+            Some(values) => values.iter().collect(),
+            None => self.values.get(key.as_ref()).into_iter().collect()
+        }
+    }
+
+    ///Insert a parameter, replacing every previous value.
     pub fn insert<K, V>(&mut self, key: K, value: V) -> Option<MaybeUtf8Owned> where
         K: Into<MaybeUtf8Owned>, V: Into<MaybeUtf8Owned>
     {
-        self.0.insert(key.into(), value.into())
+        let key = key.into();
+        self.repeated.remove(&key);
+        self.values.insert(key, value.into())
+    }
+
+    ///Add a value to a parameter, while keeping the previous values. The
+    ///new value becomes the one that is returned by `get`, and every value is
+    ///returned by `get_all`.
+    pub fn append<K, V>(&mut self, key: K, value: V) where
+        K: Into<MaybeUtf8Owned>, V: Into<MaybeUtf8Owned>
+    {
+        let key = key.into();
+        let value = value.into();
+
+        if let Some(previous) = self.values.insert(key.clone(), value.clone()) {
+            self.repeated.entry(key).or_insert_with(|| vec![previous]).push(value);
+        }
     }
 
-    ///Remove a parameter and return it.
+    ///Remove a parameter and return its last value.
     pub fn remove<K: ?Sized>(&mut self, key: &K) -> Option<MaybeUtf8Owned> where
         K: Hash + Eq + AsRef<[u8]>
     {
-        self.0.remove(key.as_ref())
+        self.repeated.remove(key.as_ref());
+        self.values.remove(key.as_ref())
     }
 
     ///Gets the given key's corresponding parameter in the map for in-place
-    ///manipulation.
+    ///manipulation. Only the last value is kept if the parameter has more
+    ///than one.
     pub fn entry<K>(&mut self, key: K) -> Entry<MaybeUtf8Owned, MaybeUtf8Owned> where K: Into<MaybeUtf8Owned> {
-        self.0.entry(key.into())
+        let key = key.into();
+        self.repeated.remove(&key);
+        self.values.entry(key)
     }
 
     ///Try to parse an entry as `T`, if it exists. The error will be `None` if
@@ -90,7 +162,7 @@ impl Parameters {
         K: Hash + Eq + AsRef<[u8]>,
         T: FromStr
     {
-        if let Some(val) = self.0.get(key.as_ref()) {
+        if let Some(val) = self.values.get(key.as_ref()) {
             val.as_utf8_lossy().parse().map_err(Some)
         } else {
             Err(None)
@@ -133,49 +205,198 @@ impl Parameters {
     {
         self.parse(key).unwrap_or_else(or_else)
     }
+
+    ///Try to parse a required entry as `T`. The error tells if the entry is
+    ///missing or if it couldn't be parsed, and it includes the name of the
+    ///entry.
+    ///
+    ///```
+    ///# use rustful::{Context, Response, StatusCode};
+    ///fn my_handler(context: Context, mut response: Response) {
+    ///    match context.query.parse_required::<_, u32>("page") {
+    ///        Ok(page) => response.send(format!("current page: {}", page)),
+    ///        Err(e) => {
+    ///            response.set_status(StatusCode::BadRequest);
+    ///            response.send(e.to_string());
+    ///        }
+    ///    }
+    ///}
+    ///```
+    pub fn parse_required<K: ?Sized, T>(&self, key: &K) -> Result<T, ParameterError<T::Err>> where
+        K: Hash + Eq + AsRef<[u8]>,
+        T: FromStr
+    {
+        self.parse(key).map_err(|e| {
+            let name = String::from_utf8_lossy(key.as_ref()).into_owned();
+            match e {
+                Some(e) => ParameterError::Invalid(name, e),
+                None => ParameterError::Missing(name)
+            }
+        })
+    }
+
+    ///Try to parse an optional entry as `T`. A missing entry is not an
+    ///error, but an unparsable one is.
+    ///
+    ///```
+    ///# use rustful::{Context, Response, StatusCode};
+    ///fn my_handler(context: Context, mut response: Response) {
+    ///    match context.query.parse_optional("limit") {
+    ///        Ok(limit) => {
+    ///            let limit: u32 = limit.unwrap_or(20);
+    ///            response.send(format!("showing {} items", limit));
+    ///        },
+    ///        Err(_) => response.set_status(StatusCode::BadRequest)
+    ///    }
+    ///}
+    ///```
+    pub fn parse_optional<K: ?Sized, T>(&self, key: &K) -> Result<Option<T>, T::Err> where
+        K: Hash + Eq + AsRef<[u8]>,
+        T: FromStr
+    {
+        match self.parse(key) {
+            Ok(value) => Ok(Some(value)),
+            Err(Some(e)) => Err(e),
+            Err(None) => Ok(None)
+        }
+    }
+
+    ///Decode every parameter into a struct, or a map, where each field is
+    ///parsed from the parameter with the same name. Fields may be numbers,
+    ///booleans, characters, strings, unit-only enums, where the variant names
+    ///are matched, or `Option`s of those. A `Vec` field gets every value of a
+    ///repeated parameter, as from `get_all`. A missing parameter is only an
+    ///error if its field is not an `Option` or a `Vec`.
+    ///
+    ///The error can be turned into `400 Bad Request`, if a parameter is
+    ///missing or invalid, or `500 Internal Server Error` if the type can't be
+    ///decoded from parameters.
+    ///
+    ///```
+    ///extern crate rustful;
+    ///extern crate rustc_serialize;
+    ///
+    ///use rustful::{Context, Response};
+    ///
+    ///#[derive(RustcDecodable)]
+    ///struct UserPath {
+    ///    id: u64,
+    ///    slug: String
+    ///}
+    ///
+    ///#[derive(RustcDecodable)]
+    ///struct Paging {
+    ///    page: Option<u32>
+    ///}
+    ///
+    ///fn my_handler(context: Context, mut response: Response) {
+    ///    let parsed = context.variables.decode::<UserPath>()
+    ///        .and_then(|path| context.query.decode::<Paging>().map(|paging| (path, paging)));
+    ///
+    ///    match parsed {
+    ///        Ok((path, paging)) => response.send(format!(
+    ///            "user {} ({}), page {}",
+    ///            path.id,
+    ///            path.slug,
+    ///            paging.page.unwrap_or(1)
+    ///        )),
+    ///        Err(e) => {
+    ///            response.set_status(e.clone().into());
+    ///            response.send(e.to_string());
+    ///        }
+    ///    }
+    ///}
+    ///# fn main() {}
+    ///```
+    #[cfg(feature = "rustc_json_body")]
+    pub fn decode<T: Decodable>(&self) -> Result<T, DecodeError> {
+        decode::decode(self)
+    }
+}
+
+///Error from `Parameters::parse_required`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParameterError<E> {
+    ///The named parameter is missing.
+    Missing(String),
+
+    ///The named parameter could not be parsed.
+    Invalid(String, E)
+}
+
+impl<E: fmt::Display> fmt::Display for ParameterError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParameterError::Missing(ref name) => write!(f, "missing parameter '{}'", name),
+            ParameterError::Invalid(ref name, ref e) => write!(f, "invalid parameter '{}': {}", name, e)
+        }
+    }
+}
+
+impl<E: Error> Error for ParameterError<E> {
+    fn description(&self) -> &str {
+        match *self {
+            ParameterError::Missing(_) => "missing parameter",
+            ParameterError::Invalid(_, _) => "invalid parameter"
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            ParameterError::Missing(_) => None,
+            ParameterError::Invalid(_, ref e) => Some(e)
+        }
+    }
 }
 
 impl Deref for Parameters {
     type Target = HashMap<MaybeUtf8Owned, MaybeUtf8Owned>;
 
     fn deref(&self) -> &HashMap<MaybeUtf8Owned, MaybeUtf8Owned> {
-        &self.0
+        &self.values
     }
 }
 
+//Any value may be changed or removed through the map, so the repeated values
+//can't be trusted afterwards.
 impl DerefMut for Parameters {
     fn deref_mut(&mut self) -> &mut HashMap<MaybeUtf8Owned, MaybeUtf8Owned> {
-        &mut self.0
+        self.repeated.clear();
+        &mut self.values
     }
 }
 
 impl AsRef<HashMap<MaybeUtf8Owned, MaybeUtf8Owned>> for Parameters {
     fn as_ref(&self) -> &HashMap<MaybeUtf8Owned, MaybeUtf8Owned> {
-        &self.0
+        &self.values
     }
 }
 
 impl AsMut<HashMap<MaybeUtf8Owned, MaybeUtf8Owned>> for Parameters {
     fn as_mut(&mut self) -> &mut HashMap<MaybeUtf8Owned, MaybeUtf8Owned> {
-        &mut self.0
+        self.repeated.clear();
+        &mut self.values
     }
 }
 
 impl Into<HashMap<MaybeUtf8Owned, MaybeUtf8Owned>> for Parameters {
     fn into(self) -> HashMap<MaybeUtf8Owned, MaybeUtf8Owned> {
-        self.0
+        self.values
     }
 }
 
 impl From<HashMap<MaybeUtf8Owned, MaybeUtf8Owned>> for Parameters {
     fn from(map: HashMap<MaybeUtf8Owned, MaybeUtf8Owned>) -> Parameters {
-        Parameters(map)
+        Parameters {
+            values: map,
+            repeated: HashMap::new()
+        }
     }
 }
 
 impl PartialEq for Parameters {
     fn eq(&self, other: &Parameters) -> bool {
-        self.0.eq(&other.0)
+        self.values.eq(&other.values) && self.repeated.eq(&other.repeated)
     }
 }
 
@@ -183,7 +404,7 @@ impl Eq for Parameters {}
 
 impl fmt::Debug for Parameters {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.0.fmt(f)
+        self.values.fmt(f)
     }
 }
 
@@ -198,7 +419,7 @@ impl IntoIterator for Parameters {
     type Item = (MaybeUtf8Owned, MaybeUtf8Owned);
 
     fn into_iter(self) -> Self::IntoIter {
-        self.0.into_iter()
+        self.values.into_iter()
     }
 }
 
@@ -207,7 +428,7 @@ impl<'a> IntoIterator for &'a Parameters {
     type Item = (&'a MaybeUtf8Owned, &'a MaybeUtf8Owned);
 
     fn into_iter(self) -> Self::IntoIter {
-        (&self.0).into_iter()
+        (&self.values).into_iter()
     }
 }
 
@@ -216,18 +437,64 @@ impl<'a> IntoIterator for &'a mut Parameters {
     type Item = (&'a MaybeUtf8Owned, &'a mut MaybeUtf8Owned);
 
     fn into_iter(self) -> Self::IntoIter {
-        (&mut self.0).into_iter()
+        self.repeated.clear();
+        (&mut self.values).into_iter()
     }
 }
 
 impl<K: Into<MaybeUtf8Owned>, V: Into<MaybeUtf8Owned>> FromIterator<(K, V)> for Parameters {
     fn from_iter<T: IntoIterator<Item=(K, V)>>(iterable: T) -> Parameters {
-        HashMap::from_iter(iterable.into_iter().map(|(k, v)| (k.into(), v.into()))).into()
+        let mut parameters = Parameters::new();
+        for (key, value) in iterable {
+            parameters.append(key, value);
+        }
+        parameters
     }
 }
 
 impl<K: Into<MaybeUtf8Owned>, V: Into<MaybeUtf8Owned>> Extend<(K, V)> for Parameters {
     fn extend<T: IntoIterator<Item=(K, V)>>(&mut self, iter: T) {
-        self.0.extend(iter.into_iter().map(|(k, v)| (k.into(), v.into())))
+        for (key, value) in iter {
+            self.append(key, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Parameters;
+
+    fn values(parameters: &Parameters, key: &str) -> Vec<String> {
+        parameters.get_all(key).into_iter().map(|value| value.as_utf8_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn extend_keeps_every_value() {
+        let mut parameters: Parameters = vec![("a", "1")].into_iter().collect();
+        parameters.extend(vec![("a", "2"), ("b", "3")]);
+
+        assert_eq!(parameters.get("a"), Some("2".into()));
+        assert_eq!(values(&parameters, "a"), vec!["1", "2"]);
+        assert_eq!(values(&parameters, "b"), vec!["3"]);
+    }
+
+    #[test]
+    fn mutate_repeated_values() {
+        let repeated = vec![("a", "1"), ("a", "2"), ("b", "3"), ("b", "4"), ("c", "5"), ("c", "6")];
+        let mut parameters: Parameters = repeated.into_iter().collect();
+
+        *parameters.get_mut("a").unwrap() = "7".into();
+        assert_eq!(values(&parameters, "a"), vec!["7"]);
+        assert_eq!(values(&parameters, "b"), vec!["3", "4"]);
+
+        parameters.entry("b").or_insert("8".into());
+        assert_eq!(values(&parameters, "b"), vec!["4"]);
+
+        parameters.retain(|key, _| key != "c");
+        assert!(values(&parameters, "c").is_empty());
+
+        parameters.clear();
+        assert!(values(&parameters, "a").is_empty());
+        assert!(values(&parameters, "b").is_empty());
     }
 }
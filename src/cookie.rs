@@ -0,0 +1,293 @@
+//!Cookie building tools.
+//!
+//!Cookies from the client are available through the `cookies` field in
+//!`Context`, where they are parsed when one of them is first accessed, while
+//!new cookies can be added to a response using a `CookieBuilder` and
+//!`Response::add_cookie`.
+//!
+//!```
+//!use rustful::{Context, Response};
+//!use rustful::cookie::{CookieBuilder, SameSite};
+//!
+//!fn log_in(context: Context, mut response: Response) {
+//!    response.add_cookie(
+//!        CookieBuilder::new("session", "abc123")
+//!            .path("/")
+//!            .max_age(3600)
+//!            .http_only()
+//!            .same_site(SameSite::Strict)
+//!    );
+//!    response.add_cookie(CookieBuilder::new("theme", "dark"));
+//!
+//!    response.send("welcome back");
+//!}
+//!```
+
+use std::fmt;
+use std::cell::RefCell;
+
+use header::HttpDate;
+use context::{Parameters, MaybeUtf8Owned};
+use utils;
+
+///The cookies from the `Cookie` headers of a request.
+///
+///The header values are stored as they are and parsed the first time a
+///cookie is accessed, so requests that never look at their cookies don't
+///have to pay for it. The values are percent decoded and unquoted, and the
+///first cookie is used if the same name appears more than once.
+pub struct Cookies {
+    headers: Vec<Vec<u8>>,
+    parsed: RefCell<Option<Parameters>>
+}
+
+impl Cookies {
+    ///Create a cookie collection from the raw values of the `Cookie` headers.
+    pub fn new(headers: Vec<Vec<u8>>) -> Cookies {
+        Cookies {
+            headers: headers,
+            parsed: RefCell::new(None)
+        }
+    }
+
+    ///Get a cookie value as a UTF-8 string. A lossy conversion will be
+    ///performed if it's not encoded as UTF-8. Use `get_raw` to get the
+    ///original data.
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.with_parsed(|cookies| cookies.get(name).map(|value| value.into_owned()))
+    }
+
+    ///Get a cookie value that may or may not be a UTF-8 string.
+    pub fn get_raw(&self, name: &str) -> Option<MaybeUtf8Owned> {
+        self.with_parsed(|cookies| cookies.get_raw(name).cloned())
+    }
+
+    ///Check if a cookie with the name `name` was sent.
+    pub fn contains(&self, name: &str) -> bool {
+        self.with_parsed(|cookies| cookies.contains_key(name))
+    }
+
+    ///Get every cookie as `Parameters`.
+    pub fn to_parameters(&self) -> Parameters {
+        self.with_parsed(|cookies| cookies.clone())
+    }
+
+    ///Get the raw values of the `Cookie` headers.
+    pub fn headers(&self) -> &[Vec<u8>] {
+        &self.headers
+    }
+
+    fn with_parsed<T, F: FnOnce(&Parameters) -> T>(&self, f: F) -> T {
+        let mut parsed = self.parsed.borrow_mut();
+        if parsed.is_none() {
+            *parsed = Some(utils::parse_cookies(&self.headers));
+        }
+
+        match *parsed {
+            Some(ref cookies) => f(cookies),
+            None => unreachable!("the cookies were just parsed")
+        }
+    }
+}
+
+impl Default for Cookies {
+    fn default() -> Cookies {
+        Cookies::new(vec![])
+    }
+}
+
+///The `SameSite` attribute of a cookie.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SameSite {
+    ///The cookie is only sent in first party requests.
+    Strict,
+
+    ///The cookie is also sent in top level navigation from other sites.
+    Lax,
+
+    ///The cookie is sent in all requests. It has to be secure.
+    None
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SameSite::Strict => f.write_str("Strict"),
+            SameSite::Lax => f.write_str("Lax"),
+            SameSite::None => f.write_str("None")
+        }
+    }
+}
+
+///A builder for `Set-Cookie` headers.
+///
+///The value will be percent encoded where necessary, so it will be decoded
+///back to its original form when it's sent back by the client.
+#[derive(Clone, Debug)]
+pub struct CookieBuilder {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<u64>,
+    expires: Option<HttpDate>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>
+}
+
+impl CookieBuilder {
+    ///Create a new cookie with a name and a value.
+    pub fn new<N: Into<String>, V: Into<String>>(name: N, value: V) -> CookieBuilder {
+        CookieBuilder {
+            name: name.into(),
+            value: value.into(),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None
+        }
+    }
+
+    ///Set the `Path` attribute.
+    pub fn path<P: Into<String>>(mut self, path: P) -> CookieBuilder {
+        self.path = Some(path.into());
+        self
+    }
+
+    ///Set the `Domain` attribute.
+    pub fn domain<D: Into<String>>(mut self, domain: D) -> CookieBuilder {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    ///Set the `Max-Age` attribute, in seconds.
+    pub fn max_age(mut self, seconds: u64) -> CookieBuilder {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    ///Set the `Expires` attribute.
+    pub fn expires(mut self, date: HttpDate) -> CookieBuilder {
+        self.expires = Some(date);
+        self
+    }
+
+    ///Set the `Secure` attribute.
+    pub fn secure(mut self) -> CookieBuilder {
+        self.secure = true;
+        self
+    }
+
+    ///Set the `HttpOnly` attribute.
+    pub fn http_only(mut self) -> CookieBuilder {
+        self.http_only = true;
+        self
+    }
+
+    ///Set the `SameSite` attribute. `SameSite::None` will also set the
+    ///`Secure` attribute, since it's required by browsers.
+    pub fn same_site(mut self, same_site: SameSite) -> CookieBuilder {
+        if same_site == SameSite::None {
+            self.secure = true;
+        }
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+impl fmt::Display for CookieBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}=", self.name));
+        try!(encode_value(&self.value, f));
+
+        if let Some(ref path) = self.path {
+            try!(write!(f, "; Path={}", path));
+        }
+
+        if let Some(ref domain) = self.domain {
+            try!(write!(f, "; Domain={}", domain));
+        }
+
+        if let Some(max_age) = self.max_age {
+            try!(write!(f, "; Max-Age={}", max_age));
+        }
+
+        if let Some(ref expires) = self.expires {
+            try!(write!(f, "; Expires={}", expires));
+        }
+
+        if self.secure {
+            try!(f.write_str("; Secure"));
+        }
+
+        if self.http_only {
+            try!(f.write_str("; HttpOnly"));
+        }
+
+        if let Some(same_site) = self.same_site {
+            try!(write!(f, "; SameSite={}", same_site));
+        }
+
+        Ok(())
+    }
+}
+
+fn encode_value(value: &str, f: &mut fmt::Formatter) -> fmt::Result {
+    for &byte in value.as_bytes() {
+        match byte {
+            b'"' | b',' | b';' | b'\\' | b'%' => try!(write!(f, "%{:02X}", byte)),
+            0x21...0x7E => try!(write!(f, "{}", byte as char)),
+            _ => try!(write!(f, "%{:02X}", byte))
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CookieBuilder, SameSite, Cookies};
+
+    #[test]
+    fn parse_cookies_on_first_access() {
+        let cookies = Cookies::new(vec![b"a=1; b=%20two".to_vec(), b"a=ignored; c=3".to_vec()]);
+        assert!(cookies.parsed.borrow().is_none());
+
+        assert_eq!(cookies.get("b"), Some(" two".to_owned()));
+        assert!(cookies.parsed.borrow().is_some());
+        assert_eq!(cookies.get("a"), Some("1".to_owned()));
+        assert_eq!(cookies.get_raw("c"), Some("3".into()));
+        assert!(!cookies.contains("d"));
+    }
+
+    #[test]
+    fn no_cookies() {
+        let cookies = Cookies::default();
+        assert_eq!(cookies.get("a"), None);
+        assert!(cookies.to_parameters().get("a").is_none());
+    }
+
+    #[test]
+    fn plain_cookie() {
+        assert_eq!(CookieBuilder::new("a", "1").to_string(), "a=1");
+    }
+
+    #[test]
+    fn cookie_attributes() {
+        let cookie = CookieBuilder::new("session", "a b;c")
+            .path("/")
+            .domain("example.com")
+            .max_age(60)
+            .http_only()
+            .same_site(SameSite::None);
+
+        assert_eq!(
+            cookie.to_string(),
+            "session=a%20b%3Bc; Path=/; Domain=example.com; Max-Age=60; Secure; HttpOnly; SameSite=None"
+        );
+    }
+}
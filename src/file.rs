@@ -1,9 +1,20 @@
 //!File related utilities.
 
-use std::path::{Path, Component};
+use std::path::{Path, PathBuf, Component};
+use std::fs::{self, Metadata};
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use time;
 
 use mime::{Mime, TopLevel, SubLevel};
 
+use StatusCode;
+use context::Context;
+use response::{Response, FileError};
+use handler::Handler;
+use header::{ETag, EntityTag, IfNoneMatch, LastModified, IfModifiedSince, HttpDate};
+
 include!(concat!(env!("OUT_DIR"), "/mime.rs"));
 
 ///Returns the MIME type from a given file extension, if known.
@@ -86,3 +97,274 @@ pub fn check_path<P: ?Sized + AsRef<Path>>(path: &P) -> Result<(), Component> {
 
     Ok(())
 }
+
+///A handler that serves files from a directory.
+///
+///The requested file is taken from a path variable, if one is specified, or
+///from the whole request path. Paths that try to escape the root directory
+///are rejected with `403 Forbidden` before the file system is touched, and
+///missing files result in `404 Not Found`. Directories are also forbidden,
+///unless an index file is configured.
+///
+///The `Content-Type` is guessed from the file extension, and the file is
+///streamed to the client with its size as `Content-Length`. A weak `ETag` is
+///derived from the file size and modification time, and `304 Not Modified`
+///is sent if it matches `If-None-Match`. The modification time is also sent
+///as `Last-Modified` and compared to `If-Modified-Since`, unless
+///`If-None-Match` is present. Modification times in the future are treated as
+///the current time.
+///
+///```no_run
+///# #[macro_use] extern crate rustful;
+///use rustful::{Server, TreeRouter};
+///use rustful::file::StaticFiles;
+///
+///# fn main() {
+///let router = insert_routes! {
+///    TreeRouter::new() => {
+///        "files/:file" => Get: StaticFiles::new("path/to/files").variable("file"),
+///        "*" => Get: StaticFiles::new("path/to/frontend").index("index.html")
+///    }
+///};
+///
+///Server {
+///    handlers: router,
+///    ..Server::default()
+///}.run().unwrap();
+///# }
+///```
+pub struct StaticFiles {
+    root: PathBuf,
+    variable: Option<String>,
+    index: Option<String>
+}
+
+impl StaticFiles {
+    ///Serve files from the `root` directory.
+    pub fn new<P: Into<PathBuf>>(root: P) -> StaticFiles {
+        StaticFiles {
+            root: root.into(),
+            variable: None,
+            index: None
+        }
+    }
+
+    ///Take the file path from a path variable, instead of the request path.
+    pub fn variable<S: Into<String>>(mut self, name: S) -> StaticFiles {
+        self.variable = Some(name.into());
+        self
+    }
+
+    ///Serve a file with this name when a directory is requested.
+    pub fn index<S: Into<String>>(mut self, name: S) -> StaticFiles {
+        self.index = Some(name.into());
+        self
+    }
+
+    fn find_file(&self, context: &Context) -> Result<(PathBuf, Metadata), StatusCode> {
+        let requested = match self.variable {
+            Some(ref variable) => context.variables.get(variable),
+            None => context.uri.as_utf8_path_lossy()
+        };
+
+        let requested = match requested {
+            Some(requested) => requested,
+            None => return Err(StatusCode::NotFound)
+        };
+
+        let relative = Path::new(requested.trim_left_matches('/'));
+        if check_path(relative).is_err() {
+            return Err(StatusCode::Forbidden);
+        }
+
+        let mut path = self.root.join(relative);
+        let mut metadata = try!(file_metadata(&path));
+
+        if metadata.is_dir() {
+            match self.index {
+                Some(ref index) => {
+                    path.push(index);
+                    metadata = try!(file_metadata(&path));
+                },
+                None => return Err(StatusCode::Forbidden)
+            }
+        }
+
+        if metadata.is_file() {
+            Ok((path, metadata))
+        } else {
+            Err(StatusCode::Forbidden)
+        }
+    }
+}
+
+fn file_metadata(path: &Path) -> Result<Metadata, StatusCode> {
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(metadata),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Err(StatusCode::NotFound),
+        Err(_) => Err(StatusCode::Forbidden)
+    }
+}
+
+//Get the modification time in seconds since the Unix epoch. Future
+//modification times are treated as `now`.
+fn modification_time(modified: SystemTime, now: SystemTime) -> u64 {
+    let modified = if modified > now { now } else { modified };
+    modified.duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0)
+}
+
+fn modified_since(modified: u64, since: &HttpDate) -> bool {
+    modified as i64 > since.0.to_timespec().sec
+}
+
+fn file_etag(metadata: &Metadata, modified: Option<u64>) -> EntityTag {
+    EntityTag::new(true, format!("{:x}-{:x}", metadata.len(), modified.unwrap_or(0)))
+}
+
+impl Handler for StaticFiles {
+    fn handle_request(&self, context: Context, mut response: Response) {
+        let (path, metadata) = match self.find_file(&context) {
+            Ok(file) => file,
+            Err(status) => return response.set_status(status)
+        };
+
+        let modified = metadata.modified().ok().map(|modified| modification_time(modified, SystemTime::now()));
+        let etag = file_etag(&metadata, modified);
+
+        let not_modified = match (context.headers.get::<IfNoneMatch>(), context.headers.get::<IfModifiedSince>(), modified) {
+            (Some(&IfNoneMatch::Any), _, _) => true,
+            (Some(&IfNoneMatch::Items(ref tags)), _, _) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+            (None, Some(&IfModifiedSince(ref since)), Some(modified)) => !modified_since(modified, since),
+            _ => false
+        };
+
+        response.headers_mut().set(ETag(etag));
+        if let Some(modified) = modified {
+            let modified = time::at_utc(time::Timespec::new(modified as i64, 0));
+            response.headers_mut().set(LastModified(HttpDate(modified)));
+        }
+
+        if not_modified {
+            return response.set_status(StatusCode::NotModified);
+        }
+
+        if let Err(FileError::Open(e, mut response)) = response.send_file(path) {
+            if e.kind() == io::ErrorKind::NotFound {
+                response.set_status(StatusCode::NotFound);
+            } else {
+                response.set_status(StatusCode::InternalServerError);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::process;
+    use std::time::{Duration, UNIX_EPOCH};
+    use time;
+    use {Server, StatusCode, Method, TreeRouter};
+    use router::Router;
+    use server::PathNormalization;
+    use header::{HttpDate, ETag, ContentLength};
+    use testing::{TestServer, MockRequest};
+    use super::{StaticFiles, modification_time, modified_since};
+
+    //A directory with `file.txt`, `empty/` and `indexed/index.html`.
+    fn test_directory(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("rustful-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("empty")).unwrap();
+        fs::create_dir_all(root.join("indexed")).unwrap();
+        File::create(root.join("file.txt")).unwrap().write_all(b"file").unwrap();
+        File::create(root.join("indexed/index.html")).unwrap().write_all(b"index").unwrap();
+        root
+    }
+
+    #[test]
+    fn reject_traversal() {
+        //The root doesn't exist, so anything but a 403 comes from the file
+        //system.
+        let server = TestServer::new(Server {
+            path_normalization: PathNormalization::Off,
+            ..Server::new(StaticFiles::new("/nonexistent/rustful/files"))
+        });
+
+        assert_eq!(server.get("/../secret").unwrap().status, StatusCode::Forbidden);
+        assert_eq!(server.get("/a/../../secret").unwrap().status, StatusCode::Forbidden);
+        assert_eq!(server.get("/secret").unwrap().status, StatusCode::NotFound);
+    }
+
+    #[test]
+    fn serve_files_and_directories() {
+        let root = test_directory("static-files");
+        let plain = TestServer::new(Server::new(StaticFiles::new(root.clone())));
+        let indexed = TestServer::new(Server::new(StaticFiles::new(root.clone()).index("index.html")));
+
+        let response = plain.get("/file.txt").unwrap();
+        assert_eq!(response.status, StatusCode::Ok);
+        assert_eq!(response.body_str(), Some("file"));
+
+        assert_eq!(plain.get("/missing.txt").unwrap().status, StatusCode::NotFound);
+        assert_eq!(plain.get("/indexed").unwrap().status, StatusCode::Forbidden);
+        assert_eq!(indexed.get("/empty").unwrap().status, StatusCode::NotFound);
+
+        let response = indexed.get("/indexed").unwrap();
+        assert_eq!(response.status, StatusCode::Ok);
+        assert_eq!(response.body_str(), Some("index"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn not_modified_without_length() {
+        let root = test_directory("static-files-not-modified");
+        let server = TestServer::new(Server::new(StaticFiles::new(root.clone())));
+
+        let response = server.get("/file.txt").unwrap();
+        let etag = response.headers.get::<ETag>().unwrap().0.to_string();
+        assert_eq!(response.headers.get::<ContentLength>(), Some(&ContentLength(4)));
+
+        let response = server.send(MockRequest::get("/file.txt").raw_header("If-None-Match", etag)).unwrap();
+        assert_eq!(response.status, StatusCode::NotModified);
+        assert_eq!(response.headers.get::<ContentLength>(), None);
+        assert!(response.body.is_empty());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn serve_files_from_variable() {
+        let root = test_directory("static-files-variable");
+        let mut router = TreeRouter::new();
+        router.insert(Method::Get, "/files/:file", StaticFiles::new(root.clone()).variable("file"));
+        let server = TestServer::new(Server::new(router));
+
+        let response = server.get("/files/file.txt").unwrap();
+        assert_eq!(response.status, StatusCode::Ok);
+        assert_eq!(response.body_str(), Some("file"));
+
+        assert_eq!(server.get("/files/missing.txt").unwrap().status, StatusCode::NotFound);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn future_modification_time() {
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        assert_eq!(modification_time(UNIX_EPOCH + Duration::from_secs(500), now), 500);
+        assert_eq!(modification_time(UNIX_EPOCH + Duration::from_secs(2000), now), 1000);
+    }
+
+    #[test]
+    fn if_modified_since() {
+        let since = HttpDate(time::at_utc(time::Timespec::new(1000, 0)));
+        assert!(!modified_since(999, &since));
+        assert!(!modified_since(1000, &since));
+        assert!(modified_since(1001, &since));
+    }
+}
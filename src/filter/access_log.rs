@@ -0,0 +1,170 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use time::{self, Tm};
+
+use StatusCode;
+use Method;
+use HttpVersion;
+use header::Headers;
+
+use context::Context;
+use response::Data;
+
+use super::{FilterContext, ContextFilter, ContextAction, ResponseFilter, ResponseAction, RequestId};
+
+///Information about a finished request and its response.
+///
+///It's formatted in the Common Log Format when it's displayed, but each
+///part is available for other formats.
+#[derive(Clone, Debug)]
+pub struct LogEntry {
+    ///The IP address of the client, as reported by `Context::real_ip`.
+    pub client_ip: IpAddr,
+
+    ///The request method.
+    pub method: Method,
+
+    ///The requested path, or `*`.
+    pub path: String,
+
+    ///The raw query string, if any.
+    pub query: Option<String>,
+
+    ///The ID of the request, if it was assigned by a `RequestIds` filter
+    ///before this filter.
+    pub request_id: Option<String>,
+
+    ///The HTTP version of the request.
+    pub http_version: HttpVersion,
+
+    ///The time when the request was received.
+    pub time: Tm,
+
+    ///The response status.
+    pub status: StatusCode,
+
+    ///The number of body bytes that passed through the filter.
+    pub body_size: u64,
+
+    ///The time it took from when the request was received until the
+    ///response was finished.
+    pub duration: Duration
+}
+
+impl fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let time = match self.time.strftime("%d/%b/%Y:%H:%M:%S %z") {
+            Ok(time) => time.to_string(),
+            Err(_) => return Err(fmt::Error)
+        };
+
+        try!(write!(f, "{} - - [{}] \"{} {}", self.client_ip, time, self.method, self.path));
+
+        if let Some(ref query) = self.query {
+            try!(write!(f, "?{}", query));
+        }
+
+        write!(f, " {}\" {} {}", self.http_version, self.status.to_u16(), self.body_size)
+    }
+}
+
+///A filter that logs each request when its response is finished.
+///
+///The request details are recorded by the context filter and passed to the
+///response filter through the filter storage, where the status, body size
+///and duration are added. The complete `LogEntry` is then passed to the
+///provided logger function, which decides how and where to write it.
+///
+///This filter has to be added as both a context filter and a response
+///filter. It's usually a good idea to add it as the first context filter and
+///the last response filter, to let it see the final status and body size.
+///The duration is measured from when the request was received, so it
+///includes the work of every filter. `Raw` responses bypass
+///the response filters and will not be logged.
+///
+///```
+///use rustful::{Server, Context, Response};
+///use rustful::filter::AccessLog;
+///
+///fn my_handler(context: Context, response: Response) {
+///    response.send("this will be logged");
+///}
+///
+///let log = AccessLog::new(|entry| println!("{}", entry));
+///
+///let server = Server {
+///    context_filters: vec![Box::new(log.clone())],
+///    response_filters: vec![Box::new(log)],
+///    ..Server::new(my_handler)
+///};
+///```
+#[derive(Clone)]
+pub struct AccessLog {
+    logger: Arc<Fn(&LogEntry) + Send + Sync>
+}
+
+impl AccessLog {
+    ///Create an access log filter that passes each `LogEntry` to `logger`.
+    pub fn new<F: Fn(&LogEntry) + Send + Sync + 'static>(logger: F) -> AccessLog {
+        AccessLog {
+            logger: Arc::new(logger)
+        }
+    }
+}
+
+struct PendingEntry(LogEntry);
+
+impl ContextFilter for AccessLog {
+    fn modify(&self, context: FilterContext, request_context: &mut Context) -> ContextAction {
+        let path = match request_context.uri.as_path() {
+            Some(path) => path.as_utf8_lossy().into_owned(),
+            None => "*".to_owned()
+        };
+        let request_id = context.storage.get::<RequestId>().map(|id| id.0.clone());
+
+        context.storage.insert(PendingEntry(LogEntry {
+            client_ip: request_context.real_ip(),
+            method: request_context.method.clone(),
+            path: path,
+            query: request_context.raw_query.as_ref().map(|q| q.as_utf8_lossy().into_owned()),
+            request_id: request_id,
+            http_version: request_context.http_version,
+            time: time::now_utc(),
+            status: StatusCode::Ok,
+            body_size: 0,
+            duration: Duration::from_secs(0)
+        }));
+
+        ContextAction::Next
+    }
+}
+
+impl ResponseFilter for AccessLog {
+    fn begin(&self, context: FilterContext, status: StatusCode, _headers: &mut Headers) -> (StatusCode, ResponseAction) {
+        if let Some(pending) = context.storage.get_mut::<PendingEntry>() {
+            pending.0.status = status;
+        }
+
+        (status, ResponseAction::Next(None))
+    }
+
+    fn write<'a>(&'a self, context: FilterContext, content: Option<Data<'a>>) -> ResponseAction {
+        if let (Some(pending), Some(content)) = (context.storage.get_mut::<PendingEntry>(), content.as_ref()) {
+            pending.0.body_size += content.as_bytes().len() as u64;
+        }
+
+        ResponseAction::Next(content)
+    }
+
+    fn end(&self, context: FilterContext) -> ResponseAction {
+        if let Some(PendingEntry(mut entry)) = context.storage.remove::<PendingEntry>() {
+            entry.duration = context.received_at.elapsed();
+            (self.logger)(&entry);
+        }
+
+        ResponseAction::Next(None)
+    }
+}
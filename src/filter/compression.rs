@@ -0,0 +1,224 @@
+use std::io::Write;
+use std::mem;
+
+use flate2::Compression as Level;
+use flate2::write::{GzEncoder, ZlibEncoder};
+
+use StatusCode;
+use header::{
+    Headers,
+    AcceptEncoding,
+    ContentEncoding,
+    ContentLength,
+    ContentType,
+    Encoding
+};
+use mime::{Mime, TopLevel, SubLevel};
+
+use context::Context;
+use response::Data;
+
+use super::{FilterContext, ContextFilter, ContextAction, ResponseFilter, ResponseAction, add_vary};
+
+///A filter that compresses response bodies with `gzip` or `deflate`.
+///
+///The encoding is chosen from the `Accept-Encoding` header in the request,
+///preferring `gzip`, and it's announced in `Content-Encoding`. `Vary:
+///Accept-Encoding` is added to every response that could have been
+///compressed. Responses that are already encoded, or have a content type
+///that is usually compressed (images, audio, video and archives), are left
+///as they are.
+///
+///The body is compressed incrementally, as it's written, so it works with
+///both `Response` and `Chunked`. The `Content-Length` header is removed and
+///recomputed by `Response::send`. `Raw` responses bypass all response
+///filters and will not be compressed.
+///
+///This filter has to be added as both a context filter and a response
+///filter, since the `Accept-Encoding` header is passed to the response filter
+///through the filter storage.
+///
+///```
+///use rustful::{Server, Context, Response};
+///use rustful::filter::Compression;
+///
+///fn my_handler(context: Context, response: Response) {
+///    response.send("this will be compressed, if possible");
+///}
+///
+///let server = Server {
+///    context_filters: vec![Box::new(Compression::new())],
+///    response_filters: vec![Box::new(Compression::new())],
+///    ..Server::new(my_handler)
+///};
+///```
+pub struct Compression {
+    level: Level
+}
+
+impl Compression {
+    ///Create a compression filter with the default compression level.
+    pub fn new() -> Compression {
+        Compression {
+            level: Level::Default
+        }
+    }
+
+    ///Create a compression filter that optimizes for speed.
+    pub fn fast() -> Compression {
+        Compression {
+            level: Level::Fast
+        }
+    }
+
+    ///Create a compression filter that optimizes for size.
+    pub fn best() -> Compression {
+        Compression {
+            level: Level::Best
+        }
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression::new()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Coding {
+    Gzip,
+    Deflate
+}
+
+struct AcceptedCoding(Option<Coding>);
+
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>)
+}
+
+impl Encoder {
+    fn write(&mut self, content: &[u8]) -> Vec<u8> {
+        //Writing to a `Vec` will only fail if the encoder is broken
+        match *self {
+            Encoder::Gzip(ref mut encoder) => {
+                encoder.write_all(content).expect("failed to compress");
+                mem::replace(encoder.get_mut(), vec![])
+            },
+            Encoder::Deflate(ref mut encoder) => {
+                encoder.write_all(content).expect("failed to compress");
+                mem::replace(encoder.get_mut(), vec![])
+            }
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish().expect("failed to compress"),
+            Encoder::Deflate(encoder) => encoder.finish().expect("failed to compress")
+        }
+    }
+}
+
+impl ContextFilter for Compression {
+    fn modify(&self, context: FilterContext, request_context: &mut Context) -> ContextAction {
+        let mut gzip = false;
+        let mut deflate = false;
+
+        if let Some(&AcceptEncoding(ref encodings)) = request_context.headers.get() {
+            for encoding in encodings {
+                if encoding.quality.0 > 0 {
+                    match encoding.item {
+                        Encoding::Gzip => gzip = true,
+                        Encoding::Deflate => deflate = true,
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        let coding = if gzip {
+            Some(Coding::Gzip)
+        } else if deflate {
+            Some(Coding::Deflate)
+        } else {
+            None
+        };
+
+        context.storage.insert(AcceptedCoding(coding));
+        ContextAction::Next
+    }
+}
+
+impl ResponseFilter for Compression {
+    fn begin(&self, context: FilterContext, status: StatusCode, headers: &mut Headers) -> (StatusCode, ResponseAction) {
+        let coding = match context.storage.remove::<AcceptedCoding>() {
+            Some(AcceptedCoding(coding)) => coding,
+            None => return (status, ResponseAction::Next(None))
+        };
+
+        if headers.has::<ContentEncoding>() || is_compressed(headers.get::<ContentType>()) {
+            return (status, ResponseAction::Next(None));
+        }
+
+        add_vary(headers, "Accept-Encoding");
+
+        if status == StatusCode::NoContent || status == StatusCode::NotModified {
+            return (status, ResponseAction::Next(None));
+        }
+
+        let encoder = match coding {
+            Some(Coding::Gzip) => {
+                headers.set(ContentEncoding(vec![Encoding::Gzip]));
+                Encoder::Gzip(GzEncoder::new(vec![], self.level))
+            },
+            Some(Coding::Deflate) => {
+                headers.set(ContentEncoding(vec![Encoding::Deflate]));
+                Encoder::Deflate(ZlibEncoder::new(vec![], self.level))
+            },
+            None => return (status, ResponseAction::Next(None))
+        };
+
+        headers.remove::<ContentLength>();
+        context.storage.insert(encoder);
+
+        (status, ResponseAction::Next(None))
+    }
+
+    fn write<'a>(&'a self, context: FilterContext, content: Option<Data<'a>>) -> ResponseAction {
+        match (context.storage.get_mut::<Encoder>(), content) {
+            (Some(encoder), Some(content)) => {
+                let compressed = encoder.write(content.as_bytes());
+                if compressed.is_empty() {
+                    ResponseAction::Next(None)
+                } else {
+                    ResponseAction::next(Some(compressed))
+                }
+            },
+            (_, content) => ResponseAction::Next(content)
+        }
+    }
+
+    fn end(&self, context: FilterContext) -> ResponseAction {
+        match context.storage.remove::<Encoder>() {
+            Some(encoder) => ResponseAction::next(Some(encoder.finish())),
+            None => ResponseAction::Next(None)
+        }
+    }
+}
+
+fn is_compressed(content_type: Option<&ContentType>) -> bool {
+    match content_type {
+        Some(&ContentType(Mime(TopLevel::Image, ref sub, _))) => sub != &SubLevel::Ext("svg+xml".into()),
+        Some(&ContentType(Mime(TopLevel::Audio, _, _))) |
+        Some(&ContentType(Mime(TopLevel::Video, _, _))) => true,
+        Some(&ContentType(Mime(TopLevel::Application, SubLevel::Ext(ref sub), _))) => {
+            match &**sub {
+                "zip" | "gzip" | "x-gzip" | "x-bzip2" | "x-xz" | "x-7z-compressed" | "x-rar-compressed" => true,
+                _ => false
+            }
+        },
+        _ => false
+    }
+}
@@ -0,0 +1,102 @@
+use std::hash::{Hash, Hasher};
+use std::collections::hash_map::DefaultHasher;
+
+use StatusCode;
+use Method;
+use header::{Headers, ETag, EntityTag, IfNoneMatch, ContentLength};
+
+use context::Context;
+use response::Data;
+
+use super::{FilterContext, ContextFilter, ContextAction, ResponseFilter, ResponseAction};
+
+///A filter for conditional `GET` and `HEAD` requests, using `ETag` and
+///`If-None-Match`.
+///
+///Responses with status `200 OK` and an `ETag` header are compared to the
+///`If-None-Match` header in the request. The response is turned into a `304
+///Not Modified` response if they match, and any content will be discarded.
+///The `Content-Length` header is removed, and the `304 Not Modified` response
+///is sent without one.
+///
+///This filter has to be added as both a context filter and a response
+///filter, since the request headers are passed to the response filter
+///through the filter storage. It will not affect `Raw` responses, since they
+///bypass the response filters.
+///
+///```
+///use rustful::{Server, Context, Response};
+///use rustful::filter::{ConditionalGet, etag_from_bytes};
+///use rustful::header::ETag;
+///
+///fn my_handler(context: Context, mut response: Response) {
+///    let content = "this rarely changes";
+///    response.headers_mut().set(ETag(etag_from_bytes(content.as_bytes())));
+///    response.send(content);
+///}
+///
+///let server = Server {
+///    context_filters: vec![Box::new(ConditionalGet)],
+///    response_filters: vec![Box::new(ConditionalGet)],
+///    ..Server::new(my_handler)
+///};
+///```
+pub struct ConditionalGet;
+
+struct RequestIfNoneMatch(IfNoneMatch);
+struct NotModified;
+
+impl ContextFilter for ConditionalGet {
+    fn modify(&self, context: FilterContext, request_context: &mut Context) -> ContextAction {
+        if request_context.method == Method::Get || request_context.method == Method::Head {
+            if let Some(if_none_match) = request_context.headers.get::<IfNoneMatch>() {
+                context.storage.insert(RequestIfNoneMatch(if_none_match.clone()));
+            }
+        }
+
+        ContextAction::Next
+    }
+}
+
+impl ResponseFilter for ConditionalGet {
+    fn begin(&self, context: FilterContext, status: StatusCode, headers: &mut Headers) -> (StatusCode, ResponseAction) {
+        if status != StatusCode::Ok {
+            return (status, ResponseAction::Next(None));
+        }
+
+        let not_modified = match (context.storage.get::<RequestIfNoneMatch>(), headers.get::<ETag>()) {
+            (Some(&RequestIfNoneMatch(IfNoneMatch::Any)), Some(_)) => true,
+            (Some(&RequestIfNoneMatch(IfNoneMatch::Items(ref tags))), Some(&ETag(ref etag))) => {
+                tags.iter().any(|tag| tag.weak_eq(etag))
+            },
+            _ => false
+        };
+
+        if not_modified {
+            headers.remove::<ContentLength>();
+            context.storage.insert(NotModified);
+            (StatusCode::NotModified, ResponseAction::Next(None))
+        } else {
+            (status, ResponseAction::Next(None))
+        }
+    }
+
+    fn write<'a>(&'a self, context: FilterContext, content: Option<Data<'a>>) -> ResponseAction {
+        if context.storage.contains::<NotModified>() {
+            ResponseAction::Next(None)
+        } else {
+            ResponseAction::Next(content)
+        }
+    }
+
+    fn end(&self, _context: FilterContext) -> ResponseAction {
+        ResponseAction::Next(None)
+    }
+}
+
+///Compute a strong entity tag from the content of a response.
+pub fn etag_from_bytes(content: &[u8]) -> EntityTag {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    EntityTag::new(false, format!("{:016x}", hasher.finish()))
+}
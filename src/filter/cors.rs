@@ -0,0 +1,222 @@
+use StatusCode;
+use Method;
+use header::Headers;
+
+use context::Context;
+use response::Data;
+
+use super::{FilterContext, ContextFilter, ContextAction, ResponseFilter, ResponseAction, add_vary};
+
+///The origins that are allowed to make cross-origin requests.
+#[derive(Clone, Debug)]
+pub enum AllowedOrigins {
+    ///Allow requests from any origin.
+    Any,
+
+    ///Only allow requests from these exact origins, such as
+    ///`"https://example.com"`.
+    List(Vec<String>)
+}
+
+///The rules for a `Cors` filter.
+#[derive(Clone, Debug)]
+pub struct CorsPolicy {
+    ///The origins that are allowed to make requests. The default is no
+    ///origins at all.
+    pub allowed_origins: AllowedOrigins,
+
+    ///The methods that are allowed in preflight requests. The default is
+    ///`GET`, `HEAD` and `POST`.
+    pub allowed_methods: Vec<Method>,
+
+    ///The request headers that are allowed in preflight requests. The
+    ///comparison is case insensitive. The default is an empty list.
+    pub allowed_headers: Vec<String>,
+
+    ///The response headers that the client is allowed to read, in addition
+    ///to the simple response headers. The default is an empty list.
+    pub exposed_headers: Vec<String>,
+
+    ///For how many seconds the result of a preflight request may be cached.
+    ///The default is `None`, which leaves it to the client.
+    pub max_age: Option<u32>,
+
+    ///Allow credentials, such as cookies, in cross-origin requests. The
+    ///origin will always be reflected, instead of using `*`, if this is
+    ///`true`. The default is `false`.
+    pub allow_credentials: bool
+}
+
+impl Default for CorsPolicy {
+    fn default() -> CorsPolicy {
+        CorsPolicy {
+            allowed_origins: AllowedOrigins::List(vec![]),
+            allowed_methods: vec![Method::Get, Method::Head, Method::Post],
+            allowed_headers: vec![],
+            exposed_headers: vec![],
+            max_age: None,
+            allow_credentials: false
+        }
+    }
+}
+
+///A filter for cross-origin resource sharing (CORS).
+///
+///Preflight requests, which are `OPTIONS` requests with an
+///`Access-Control-Request-Method` header, are answered directly by the
+///context filter, without calling the handler. They are rejected with `403
+///Forbidden` if the origin, the method or any of the headers are not allowed.
+///Other requests from allowed origins are handled as usual and the response
+///filter adds the `Access-Control-Allow-*` headers to their responses.
+///
+///This filter has to be added as both a context filter and a response
+///filter, since the origin is passed to the response filter through the
+///filter storage. It will not affect `Raw` responses, since they bypass the
+///response filters.
+///
+///```
+///use rustful::{Server, Context, Response};
+///use rustful::Method::{Get, Post, Delete};
+///use rustful::filter::{Cors, CorsPolicy, AllowedOrigins};
+///
+///fn my_handler(context: Context, response: Response) {
+///    response.send("shared with example.com");
+///}
+///
+///let policy = CorsPolicy {
+///    allowed_origins: AllowedOrigins::List(vec!["https://example.com".into()]),
+///    allowed_methods: vec![Get, Post, Delete],
+///    allowed_headers: vec!["Content-Type".into()],
+///    max_age: Some(3600),
+///    allow_credentials: true,
+///    ..CorsPolicy::default()
+///};
+///
+///let server = Server {
+///    context_filters: vec![Box::new(Cors::new(policy.clone()))],
+///    response_filters: vec![Box::new(Cors::new(policy))],
+///    ..Server::new(my_handler)
+///};
+///```
+pub struct Cors {
+    policy: CorsPolicy
+}
+
+impl Cors {
+    ///Create a CORS filter with a policy.
+    pub fn new(policy: CorsPolicy) -> Cors {
+        Cors {
+            policy: policy
+        }
+    }
+
+    //The value of `Access-Control-Allow-Origin`, if the origin is allowed.
+    fn allow_origin(&self, origin: &str) -> Option<String> {
+        match self.policy.allowed_origins {
+            AllowedOrigins::Any if self.policy.allow_credentials => Some(origin.to_owned()),
+            AllowedOrigins::Any => Some("*".to_owned()),
+            AllowedOrigins::List(ref origins) if origins.iter().any(|o| o == origin) => Some(origin.to_owned()),
+            AllowedOrigins::List(_) => None
+        }
+    }
+
+    fn preflight(&self, allow_origin: String, context: &Context) -> ContextAction {
+        let method = first_value(&context.headers, "Access-Control-Request-Method").and_then(|m| m.parse::<Method>().ok());
+        let method_allowed = match method {
+            Some(ref method) => self.policy.allowed_methods.contains(method),
+            None => false
+        };
+
+        let request_headers = first_value(&context.headers, "Access-Control-Request-Headers").unwrap_or_else(String::new);
+        let headers_allowed = request_headers.split(',').map(str::trim).filter(|h| !h.is_empty()).all(|header| {
+            let header = header.to_lowercase();
+            self.policy.allowed_headers.iter().any(|allowed| allowed.to_lowercase() == header)
+        });
+
+        if !method_allowed || !headers_allowed {
+            return ContextAction::Abort(StatusCode::Forbidden);
+        }
+
+        let mut headers = Headers::new();
+        self.set_common_headers(&mut headers, allow_origin);
+
+        let methods: Vec<_> = self.policy.allowed_methods.iter().map(|m| m.to_string()).collect();
+        headers.set_raw("Access-Control-Allow-Methods", vec![methods.join(", ").into_bytes()]);
+
+        if !self.policy.allowed_headers.is_empty() {
+            headers.set_raw("Access-Control-Allow-Headers", vec![self.policy.allowed_headers.join(", ").into_bytes()]);
+        }
+
+        if let Some(max_age) = self.policy.max_age {
+            headers.set_raw("Access-Control-Max-Age", vec![max_age.to_string().into_bytes()]);
+        }
+
+        ContextAction::Respond(StatusCode::NoContent, headers, vec![])
+    }
+
+    fn set_common_headers(&self, headers: &mut Headers, allow_origin: String) {
+        if allow_origin != "*" {
+            add_vary(headers, "Origin");
+        }
+
+        headers.set_raw("Access-Control-Allow-Origin", vec![allow_origin.into_bytes()]);
+
+        if self.policy.allow_credentials {
+            headers.set_raw("Access-Control-Allow-Credentials", vec![b"true".to_vec()]);
+        }
+    }
+}
+
+struct AllowOrigin(String);
+
+impl ContextFilter for Cors {
+    fn modify(&self, context: FilterContext, request_context: &mut Context) -> ContextAction {
+        let allow_origin = match first_value(&request_context.headers, "Origin") {
+            Some(origin) => self.allow_origin(&origin),
+            None => return ContextAction::Next
+        };
+
+        let is_preflight = request_context.method == Method::Options &&
+            request_context.headers.get_raw("Access-Control-Request-Method").is_some();
+
+        match allow_origin {
+            Some(allow_origin) => if is_preflight {
+                self.preflight(allow_origin, request_context)
+            } else {
+                context.storage.insert(AllowOrigin(allow_origin));
+                ContextAction::Next
+            },
+            None if is_preflight => ContextAction::Abort(StatusCode::Forbidden),
+            None => ContextAction::Next
+        }
+    }
+}
+
+impl ResponseFilter for Cors {
+    fn begin(&self, context: FilterContext, status: StatusCode, headers: &mut Headers) -> (StatusCode, ResponseAction) {
+        if let Some(AllowOrigin(allow_origin)) = context.storage.remove::<AllowOrigin>() {
+            self.set_common_headers(headers, allow_origin);
+
+            if !self.policy.exposed_headers.is_empty() {
+                headers.set_raw("Access-Control-Expose-Headers", vec![self.policy.exposed_headers.join(", ").into_bytes()]);
+            }
+        }
+
+        (status, ResponseAction::Next(None))
+    }
+
+    fn write<'a>(&'a self, _context: FilterContext, content: Option<Data<'a>>) -> ResponseAction {
+        ResponseAction::Next(content)
+    }
+
+    fn end(&self, _context: FilterContext) -> ResponseAction {
+        ResponseAction::Next(None)
+    }
+}
+
+fn first_value(headers: &Headers, name: &str) -> Option<String> {
+    headers.get_raw(name)
+        .and_then(|values| values.first())
+        .and_then(|value| String::from_utf8(value.clone()).ok())
+        .map(|value| value.trim().to_owned())
+}
@@ -0,0 +1,249 @@
+use rand::{OsRng, Rng};
+
+use {StatusCode, Method};
+use header::Headers;
+
+use context::Context;
+use cookie::{CookieBuilder, SameSite};
+use response::Data;
+use utils;
+
+use super::{FilterContext, ContextFilter, ContextAction, ResponseFilter, ResponseAction, SessionSigner, parsed_form};
+
+///The CSRF token of the current client, as issued by `Csrf`.
+///
+///It's stored in the request extensions and it should be included in the
+///forms that are sent to the client, or in the `X-CSRF-Token` header of
+///requests from scripts.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CsrfToken(pub String);
+
+//Marks a token that has to be sent to the client.
+struct NewToken(String);
+
+///A filter that protects against cross-site request forgery.
+///
+///Each client is given a random token, which is stored in a signed cookie
+///and made available to the handler as a `CsrfToken`. Requests with unsafe
+///methods, such as `POST`, `PUT` and `DELETE`, have to repeat the token in
+///the `X-CSRF-Token` header, or in the `_csrf` field of an
+///`application/x-www-form-urlencoded` form, and they are otherwise aborted
+///with `403 Forbidden`. `GET`, `HEAD`, `OPTIONS` and `TRACE` requests are
+///exempt.
+///
+///A form has to be read to find the token, so it's stored as a `ParsedForm`
+///in the request extensions, unless it has already been read by an other
+///filter. Other kinds of bodies, such as JSON and multipart forms, are left
+///unread and have to use the header.
+///
+///This filter has to be added as both a context filter and a response
+///filter, with the same configuration. It can be applied to all routes, or
+///only to those that change something, using route filters:
+///
+///```
+///use rustful::{Router, TreeRouter, Context, Response};
+///use rustful::Method::{Get, Post};
+///use rustful::filter::{RouteFilters, Csrf, CsrfToken, ParsedForm, HmacSha256};
+///
+///fn show_form(context: Context, response: Response) {
+///    if let Some(&CsrfToken(ref token)) = context.extensions.get() {
+///        response.send(format!(
+///            "<form method=\"post\"><input type=\"hidden\" name=\"_csrf\" value=\"{}\"><button>Post</button></form>",
+///            token
+///        ));
+///    }
+///}
+///
+///fn post_form(context: Context, response: Response) {
+///    let fields = context.extensions.get::<ParsedForm>().map_or(0, |form| form.0.len());
+///    response.send(format!("received {} fields", fields));
+///}
+///
+///let secret = b"a long and random secret key".to_vec();
+///
+///let mut router = TreeRouter::new();
+///router.insert(Get, "/form", show_form as fn(Context, Response));
+///router.insert(Post, "/form", post_form as fn(Context, Response));
+///router.insert_filters("/form", RouteFilters {
+///    context_filters: vec![Box::new(Csrf::new(HmacSha256::new(secret.clone())))],
+///    response_filters: vec![Box::new(Csrf::new(HmacSha256::new(secret)))]
+///});
+///```
+pub struct Csrf {
+    signer: Box<SessionSigner>,
+    cookie_name: String,
+    header: String,
+    field: String,
+    secure: bool
+}
+
+impl Csrf {
+    ///Create a CSRF filter that uses `signer` to sign the token cookie.
+    pub fn new<S: SessionSigner + 'static>(signer: S) -> Csrf {
+        Csrf {
+            signer: Box::new(signer),
+            cookie_name: "csrf_token".to_owned(),
+            header: "X-CSRF-Token".to_owned(),
+            field: "_csrf".to_owned(),
+            secure: false
+        }
+    }
+
+    ///Set the name of the token cookie. Default is `csrf_token`.
+    pub fn cookie_name<N: Into<String>>(mut self, name: N) -> Csrf {
+        self.cookie_name = name.into();
+        self
+    }
+
+    ///Set the name of the token header. Default is `X-CSRF-Token`.
+    pub fn header<H: Into<String>>(mut self, header: H) -> Csrf {
+        self.header = header.into();
+        self
+    }
+
+    ///Set the name of the token form field. Default is `_csrf`.
+    pub fn field<F: Into<String>>(mut self, field: F) -> Csrf {
+        self.field = field.into();
+        self
+    }
+
+    ///Only send the cookie over HTTPS.
+    pub fn secure(mut self) -> Csrf {
+        self.secure = true;
+        self
+    }
+
+    fn read_cookie(&self, cookie: &str) -> Option<String> {
+        let mut parts = cookie.split('.');
+        let (token, signature) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(token), Some(signature), None) => (token, signature),
+            _ => return None
+        };
+
+        match utils::base64_decode(signature.as_bytes()) {
+            Some(ref signature) if !token.is_empty() && self.signer.verify(token.as_bytes(), signature) => Some(token.to_owned()),
+            _ => None
+        }
+    }
+
+    fn write_cookie(&self, token: &str) -> CookieBuilder {
+        let signature = utils::base64_encode(&self.signer.sign(token.as_bytes()));
+        let cookie = CookieBuilder::new(&*self.cookie_name, format!("{}.{}", token, signature))
+            .path("/")
+            .http_only()
+            .same_site(SameSite::Lax);
+
+        if self.secure {
+            cookie.secure()
+        } else {
+            cookie
+        }
+    }
+
+    //Find the token in the header or the form.
+    fn submitted_token(&self, context: &mut Context) -> Result<Option<String>, StatusCode> {
+        let header = context.headers.get_raw(&self.header)
+            .and_then(|values| values.first())
+            .map(|value| String::from_utf8_lossy(value).trim().to_owned());
+
+        if header.is_some() {
+            return Ok(header);
+        }
+
+        let form = try!(parsed_form(context));
+        Ok(form.and_then(|form| form.get(&self.field)).map(|token| token.into_owned()))
+    }
+}
+
+impl ContextFilter for Csrf {
+    fn modify(&self, context: FilterContext, request_context: &mut Context) -> ContextAction {
+        let token = request_context.get_cookie(&self.cookie_name).and_then(|cookie| self.read_cookie(&cookie));
+
+        match request_context.method {
+            Method::Get | Method::Head | Method::Options | Method::Trace => {},
+            _ => {
+                let expected = match token {
+                    Some(ref token) => token,
+                    None => return ContextAction::Abort(StatusCode::Forbidden)
+                };
+
+                match self.submitted_token(request_context) {
+                    Ok(Some(ref submitted)) if utils::constant_time_eq(submitted.as_bytes(), expected.as_bytes()) => {},
+                    Ok(_) => return ContextAction::Abort(StatusCode::Forbidden),
+                    Err(status) => return ContextAction::Abort(status)
+                }
+            }
+        }
+
+        let token = token.unwrap_or_else(|| {
+            let token = generate_token();
+            context.storage.insert(NewToken(token.clone()));
+            token
+        });
+
+        request_context.extensions.insert(CsrfToken(token));
+        ContextAction::Next
+    }
+}
+
+impl ResponseFilter for Csrf {
+    fn begin(&self, context: FilterContext, status: StatusCode, headers: &mut Headers) -> (StatusCode, ResponseAction) {
+        if let Some(&NewToken(ref token)) = context.storage.get::<NewToken>() {
+            let mut cookies = headers.get_raw("Set-Cookie").map(|c| c.to_vec()).unwrap_or_else(Vec::new);
+            cookies.push(self.write_cookie(token).to_string().into_bytes());
+            headers.set_raw("Set-Cookie", cookies);
+        }
+
+        (status, ResponseAction::Next(None))
+    }
+
+    fn write<'a>(&'a self, _context: FilterContext, content: Option<Data<'a>>) -> ResponseAction {
+        ResponseAction::Next(content)
+    }
+
+    fn end(&self, _context: FilterContext) -> ResponseAction {
+        ResponseAction::Next(None)
+    }
+}
+
+//A random, URL safe token from the operating system's secure random number
+//generator, so it can't be guessed. The signature protects it from being
+//replaced.
+fn generate_token() -> String {
+    let mut rng = OsRng::new().expect("the operating system's random number generator is unavailable");
+    let mut bytes = [0u8; 16];
+    rng.fill_bytes(&mut bytes);
+
+    let mut token = String::with_capacity(32);
+    for byte in &bytes {
+        token.push_str(&format!("{:02x}", byte));
+    }
+
+    token
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Csrf, generate_token};
+    use filter::HmacSha256;
+
+    #[test]
+    fn verify_cookie() {
+        let csrf = Csrf::new(HmacSha256::new("secret"));
+        let token = generate_token();
+        let cookie = csrf.write_cookie(&token).to_string();
+        let value = &cookie["csrf_token=".len()..cookie.find(';').unwrap()];
+
+        assert_eq!(csrf.read_cookie(value), Some(token.clone()));
+        assert_eq!(csrf.read_cookie(&format!("{}.{}", generate_token(), &value[token.len() + 1..])), None);
+        assert_eq!(Csrf::new(HmacSha256::new("other")).read_cookie(value), None);
+        assert_eq!(csrf.read_cookie(&token), None);
+    }
+
+    #[test]
+    fn unique_tokens() {
+        let token = generate_token();
+        assert_eq!(token.len(), 32);
+        assert!(token != generate_token());
+    }
+}
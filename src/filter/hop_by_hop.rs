@@ -0,0 +1,167 @@
+use unicase::UniCase;
+
+use StatusCode;
+use header::{Headers, Connection, ConnectionOption};
+
+use context::Context;
+use response::Data;
+
+use super::{FilterContext, ContextFilter, ContextAction, ResponseFilter, ResponseAction};
+
+//The hop-by-hop headers from RFC 7230, section 6.1, and the ones that were
+//listed in RFC 2616. Any header that starts with `Proxy-` is also removed.
+const HOP_BY_HOP: &'static [&'static str] = &[
+    "Connection",
+    "Keep-Alive",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade"
+];
+
+///Remove the hop-by-hop headers from a set of headers.
+///
+///The removed headers are `Connection`, `Keep-Alive`, `TE`, `Trailer`,
+///`Transfer-Encoding`, `Upgrade`, any header that starts with `Proxy-`, and
+///any header that is listed in `Connection`, as described in [RFC 7230,
+///section 6.1](https://tools.ietf.org/html/rfc7230#section-6.1). These
+///headers only apply to a single connection, and should not be forwarded by
+///a proxy.
+///
+///This is useful when the headers of an upstream response are copied to a
+///`Response`, or when the `Context` headers are copied to an upstream
+///request.
+///
+///```
+///use rustful::header::Headers;
+///use rustful::filter::remove_hop_by_hop;
+///
+///let mut headers = Headers::new();
+///headers.set_raw("Connection", vec![b"X-Secret".to_vec()]);
+///headers.set_raw("X-Secret", vec![b"abc".to_vec()]);
+///headers.set_raw("Proxy-Authorization", vec![b"Basic YTpi".to_vec()]);
+///headers.set_raw("Content-Type", vec![b"text/plain".to_vec()]);
+///
+///remove_hop_by_hop(&mut headers);
+///
+///assert_eq!(headers.len(), 1);
+///assert!(headers.get_raw("Content-Type").is_some());
+///```
+pub fn remove_hop_by_hop(headers: &mut Headers) {
+    let mut names: Vec<UniCase<String>> = HOP_BY_HOP.iter().map(|&name| UniCase(name.to_owned())).collect();
+
+    if let Some(values) = headers.get_raw("Connection") {
+        for value in values {
+            for name in String::from_utf8_lossy(value).split(',') {
+                let name = name.trim();
+                if !name.is_empty() {
+                    names.push(UniCase(name.to_owned()));
+                }
+            }
+        }
+    }
+
+    let remove: Vec<String> = headers.iter().map(|header| header.name().to_owned()).filter(|name| {
+        is_proxy_header(name) || names.contains(&UniCase(name.clone()))
+    }).collect();
+
+    for name in remove {
+        headers.remove_raw(&name);
+    }
+}
+
+fn is_proxy_header(name: &str) -> bool {
+    name.len() > 6 && name.is_char_boundary(6) && UniCase(&name[..6]) == UniCase("Proxy-")
+}
+
+///A filter that removes hop-by-hop headers from requests and responses.
+///
+///It's meant for handlers that act as reverse proxies, and it removes the
+///same headers as `remove_hop_by_hop`. Nothing is removed by default, since
+///an ordinary server may depend on these headers, so this filter has to be
+///added to the server or to the proxy routes.
+///
+///As a context filter, it removes the headers from `Context::headers`, after
+///the server has used them to read the request. As a response filter, it
+///removes the headers that the handler has set, except for the `close` and
+///`keep-alive` options in `Connection`, which the server uses to decide if
+///the connection should be kept alive. The server adds its own
+///`Transfer-Encoding`, if it's needed, after the filters are done.
+///
+///```
+///use rustful::{Server, Context, Response};
+///use rustful::filter::HopByHop;
+///
+///fn proxy(context: Context, response: Response) {
+///    //Forward the request...
+///}
+///
+///let server = Server {
+///    context_filters: vec![Box::new(HopByHop)],
+///    response_filters: vec![Box::new(HopByHop)],
+///    ..Server::new(proxy)
+///};
+///```
+#[derive(Clone, Copy, Debug)]
+pub struct HopByHop;
+
+impl ContextFilter for HopByHop {
+    fn modify(&self, _context: FilterContext, request_context: &mut Context) -> ContextAction {
+        remove_hop_by_hop(&mut request_context.headers);
+        ContextAction::Next
+    }
+}
+
+impl ResponseFilter for HopByHop {
+    fn begin(&self, _context: FilterContext, status: StatusCode, headers: &mut Headers) -> (StatusCode, ResponseAction) {
+        let connection: Vec<_> = headers.get::<Connection>().map(|connection| {
+            connection.iter().filter(|option| match **option {
+                ConnectionOption::Close | ConnectionOption::KeepAlive => true,
+                ConnectionOption::ConnectionHeader(_) => false
+            }).cloned().collect()
+        }).unwrap_or_else(Vec::new);
+
+        remove_hop_by_hop(headers);
+
+        if !connection.is_empty() {
+            headers.set(Connection(connection));
+        }
+
+        (status, ResponseAction::Next(None))
+    }
+
+    fn write<'a>(&'a self, _context: FilterContext, content: Option<Data<'a>>) -> ResponseAction {
+        ResponseAction::Next(content)
+    }
+
+    fn end(&self, _context: FilterContext) -> ResponseAction {
+        ResponseAction::Next(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use header::Headers;
+    use super::remove_hop_by_hop;
+
+    #[test]
+    fn remove_listed_headers() {
+        let mut headers = Headers::new();
+        headers.set_raw("Connection", vec![b"keep-alive, x-a".to_vec(), b" X-B ,".to_vec()]);
+        headers.set_raw("Keep-Alive", vec![b"timeout=5".to_vec()]);
+        headers.set_raw("te", vec![b"trailers".to_vec()]);
+        headers.set_raw("Transfer-Encoding", vec![b"chunked".to_vec()]);
+        headers.set_raw("Upgrade", vec![b"websocket".to_vec()]);
+        headers.set_raw("proxy-connection", vec![b"close".to_vec()]);
+        headers.set_raw("X-A", vec![b"a".to_vec()]);
+        headers.set_raw("x-b", vec![b"b".to_vec()]);
+        headers.set_raw("X-C", vec![b"c".to_vec()]);
+        headers.set_raw("Proxy", vec![b"d".to_vec()]);
+
+        remove_hop_by_hop(&mut headers);
+
+        let mut names: Vec<_> = headers.iter().map(|header| header.name().to_owned()).collect();
+        names.sort();
+        assert_eq!(names, vec!["Proxy".to_owned(), "X-C".to_owned()]);
+    }
+}
@@ -0,0 +1,119 @@
+use std::str::FromStr;
+
+use {StatusCode, Method};
+
+use context::Context;
+
+use super::{FilterContext, ContextFilter, ContextAction, parsed_form};
+
+///A filter that lets `POST` requests pretend to use an other method.
+///
+///HTML forms can only send `GET` and `POST` requests, so this filter makes
+///it possible to reach `PUT`, `PATCH` and `DELETE` routes from a form. The
+///method is taken from the `X-HTTP-Method-Override` header, or from the
+///`_method` field of an `application/x-www-form-urlencoded` form, and it
+///replaces the method in the `Context`. Requests with other methods are not
+///affected.
+///
+///Only `PUT`, `PATCH` and `DELETE` are allowed by default, and requests that
+///ask for any other method are rejected with `400 Bad Request`. A form is
+///stored as a `ParsedForm` in the request extensions, after it has been read.
+///
+///The router uses the method from the `Context`, so this filter has to be
+///added as a global context filter, to run before the router. It should be
+///added before any filter, such as `Csrf`, that depends on the method.
+///
+///```
+///use rustful::{Server, Context, Response, Router, TreeRouter};
+///use rustful::Method::Delete;
+///use rustful::filter::MethodOverride;
+///
+///fn delete_post(context: Context, response: Response) {
+///    response.send("the post was deleted");
+///}
+///
+///let mut router = TreeRouter::new();
+///router.insert(Delete, "/posts/:id", delete_post as fn(Context, Response));
+///
+///let server = Server {
+///    context_filters: vec![Box::new(MethodOverride::new())],
+///    ..Server::new(router)
+///};
+///```
+pub struct MethodOverride {
+    allowed: Vec<Method>,
+    header: String,
+    field: String
+}
+
+impl MethodOverride {
+    ///Create a filter that allows `PUT`, `PATCH` and `DELETE`.
+    pub fn new() -> MethodOverride {
+        MethodOverride {
+            allowed: vec![Method::Put, Method::Patch, Method::Delete],
+            header: "X-HTTP-Method-Override".to_owned(),
+            field: "_method".to_owned()
+        }
+    }
+
+    ///Replace the set of allowed methods.
+    pub fn allow(mut self, methods: Vec<Method>) -> MethodOverride {
+        self.allowed = methods;
+        self
+    }
+
+    ///Set the name of the header. Default is `X-HTTP-Method-Override`.
+    pub fn header<H: Into<String>>(mut self, header: H) -> MethodOverride {
+        self.header = header.into();
+        self
+    }
+
+    ///Set the name of the form field. Default is `_method`.
+    pub fn field<F: Into<String>>(mut self, field: F) -> MethodOverride {
+        self.field = field.into();
+        self
+    }
+
+    //Find the method in the header or the form.
+    fn requested_method(&self, context: &mut Context) -> Result<Option<String>, StatusCode> {
+        let header = context.headers.get_raw(&self.header)
+            .and_then(|values| values.first())
+            .map(|value| String::from_utf8_lossy(value).trim().to_owned());
+
+        if header.is_some() {
+            return Ok(header);
+        }
+
+        let form = try!(parsed_form(context));
+        Ok(form.and_then(|form| form.get(&self.field)).map(|method| method.trim().to_owned()))
+    }
+}
+
+impl Default for MethodOverride {
+    fn default() -> MethodOverride {
+        MethodOverride::new()
+    }
+}
+
+impl ContextFilter for MethodOverride {
+    fn modify(&self, _context: FilterContext, request_context: &mut Context) -> ContextAction {
+        if request_context.method != Method::Post {
+            return ContextAction::Next;
+        }
+
+        let method = match self.requested_method(request_context) {
+            Ok(Some(method)) => method,
+            Ok(None) => return ContextAction::Next,
+            Err(status) => return ContextAction::Abort(status)
+        };
+
+        //Methods are case sensitive, but forms are usually written by hand.
+        match Method::from_str(&method.to_uppercase()) {
+            Ok(ref method) if self.allowed.contains(method) => {
+                request_context.method = method.clone();
+                ContextAction::Next
+            },
+            _ => ContextAction::Abort(StatusCode::BadRequest)
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//!Request and context filters.
+
+use std::time::Instant;
+
+use anymap::AnyMap;
+
+use unicase::UniCase;
+
+use StatusCode;
+use header::{Headers, Vary};
+use mime::{TopLevel, SubLevel};
+
+use context::{Context, Parameters};
+
+use response::Data;
+use server::Global;
+
+pub use self::access_log::{AccessLog, LogEntry};
+pub use self::conditional::{ConditionalGet, etag_from_bytes};
+pub use self::cors::{Cors, CorsPolicy, AllowedOrigins};
+pub use self::csrf::{Csrf, CsrfToken};
+pub use self::hop_by_hop::{HopByHop, remove_hop_by_hop};
+pub use self::method_override::MethodOverride;
+pub use self::request_id::{RequestIds, RequestId};
+pub use self::server_timing::{ServerTiming, Timings};
+pub use self::session::{Sessions, Session, SessionData, SessionSigner, HmacSha256};
+pub use self::vary::VaryNegotiated;
+#[cfg(feature = "compression")]
+pub use self::compression::Compression;
+
+mod access_log;
+mod conditional;
+mod cors;
+mod csrf;
+mod hop_by_hop;
+mod method_override;
+mod request_id;
+mod server_timing;
+mod session;
+mod vary;
+#[cfg(feature = "compression")]
+mod compression;
+
+///Contextual tools for filters.
+pub struct FilterContext<'a> {
+    ///Shared storage for filters. It is local to the current request and
+    ///accessible from the handler and all of the filters. It can be used to
+    ///send data between these units.
+    pub storage: &'a mut AnyMap,
+
+    ///Globally accessible data.
+    pub global: &'a Global,
+
+    ///The time when the request was received.
+    pub received_at: Instant,
+}
+
+///A trait for context filters.
+///
+///They are able to modify and react to a `Context` before it's sent to the handler.
+///
+///#Modifying the Request
+///
+///Every public field of the `Context` can be changed by a context filter,
+///including the request headers in `headers`, and the changes will be seen
+///by the following filters and the handler. Some parts of the request have,
+///however, already been processed when the filters run, so the headers fall
+///into three groups:
+///
+/// * `Host`, `Content-Length` and the `Content-Type` of forms are read again
+///   after the global filters. A changed `Host` header selects the router for
+///   that host, a changed `Content-Length` is checked against the server's
+///   `max_body_size`, and a changed `Content-Type` decides if the body can be
+///   parsed as a form. Changes to them in route filters don't affect the
+///   routing, since it has already happened.
+/// * `Cookie`, `Content-Encoding`, `Transfer-Encoding`, and the `Content-Type`
+///   of multipart bodies, have already been used to prepare `cookies` and
+///   `body`. Changing them will not change how the body is read, so they are
+///   best left as they are, unless the handler only looks at the headers.
+/// * The other headers are only used by filters and handlers, and they are
+///   safe to change, remove or add.
+///
+///The same goes for `method`, which is used for routing after the global
+///filters, as shown by `MethodOverride`.
+///
+///```
+///use rustful::Context;
+///use rustful::header::Host;
+///use rustful::filter::{FilterContext, ContextFilter, ContextAction};
+///
+///struct NormalizeHost;
+///
+///impl ContextFilter for NormalizeHost {
+///    fn modify(&self, _filter_context: FilterContext, context: &mut Context) -> ContextAction {
+///        let normalized = context.headers.get::<Host>().map(|host| Host {
+///            hostname: host.hostname.trim_right_matches('.').to_lowercase(),
+///            port: host.port
+///        });
+///
+///        if let Some(host) = normalized {
+///            context.headers.set(host);
+///        }
+///        context.headers.remove_raw("X-Debug");
+///
+///        ContextAction::next()
+///    }
+///}
+///```
+pub trait ContextFilter: Send + Sync {
+    ///Try to modify the handler `Context`.
+    fn modify(&self, context: FilterContext, request_context: &mut Context) -> ContextAction;
+}
+
+///A form that was read by a context filter.
+///
+///The request body can only be read once, so filters that have to look at
+///an `application/x-www-form-urlencoded` form, such as `Csrf` and
+///`MethodOverride`, store it in the request extensions instead, for the
+///handler and the other filters to use.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ParsedForm(pub Parameters);
+
+///A set of filters that are only applied to some of the routes.
+///
+///Route filters are attached to a part of a router, such as with
+///`TreeRouter::insert_filters`, and they are applied to the requests that
+///ends up there. The global filters in `Server` are always applied first,
+///followed by the route filters, from the outermost route to the innermost.
+///This is true for both context filters and response filters. A context
+///filter that aborts will stop any following filter, as well as the handler,
+///from running.
+#[derive(Default)]
+pub struct RouteFilters {
+    ///Filters that are applied to the context before it's sent to the
+    ///handler.
+    pub context_filters: Vec<Box<ContextFilter>>,
+
+    ///Filters that are applied to the response.
+    pub response_filters: Vec<Box<ResponseFilter>>
+}
+
+impl RouteFilters {
+    ///Create an empty set of route filters.
+    pub fn new() -> RouteFilters {
+        RouteFilters::default()
+    }
+}
+
+///The result from a context filter.
+#[derive(Clone)]
+pub enum ContextAction {
+    ///Continue to the next filter in the stack.
+    Next,
+
+    ///Abort and set HTTP status.
+    Abort(StatusCode),
+
+    ///Abort and send a complete response, with a status, headers and a
+    ///body, instead of calling the handler. The headers are added to the
+    ///default response headers and the response filters are still applied.
+    Respond(StatusCode, Headers, Vec<u8>)
+}
+
+impl<'a> ContextAction {
+    ///Continue to the next filter in the stack.
+    pub fn next() -> ContextAction {
+        ContextAction::Next
+    }
+
+    ///Abort and set HTTP status.
+    pub fn abort(status: StatusCode) -> ContextAction {
+        ContextAction::Abort(status)
+    }
+
+    ///Abort and send a complete response.
+    ///
+    ///```
+    ///use rustful::{Context, StatusCode};
+    ///use rustful::header::Headers;
+    ///use rustful::filter::{FilterContext, ContextFilter, ContextAction};
+    ///
+    ///struct RequireAuth;
+    ///
+    ///impl ContextFilter for RequireAuth {
+    ///    fn modify(&self, _filter_context: FilterContext, context: &mut Context) -> ContextAction {
+    ///        if context.basic_auth().map_or(false, |(user, password)| user == "admin" && password == "secret") {
+    ///            ContextAction::next()
+    ///        } else {
+    ///            let mut headers = Headers::new();
+    ///            headers.set_raw("WWW-Authenticate", vec![b"Basic realm=\"admin\"".to_vec()]);
+    ///            ContextAction::respond(StatusCode::Unauthorized, headers, "{\"error\":\"unauthorized\"}")
+    ///        }
+    ///    }
+    ///}
+    ///```
+    pub fn respond<B: Into<Vec<u8>>>(status: StatusCode, headers: Headers, body: B) -> ContextAction {
+        ContextAction::Respond(status, headers, body.into())
+    }
+}
+
+
+///A trait for response filters.
+///
+///They are able to modify headers and data before it gets written in the response.
+pub trait ResponseFilter: Send + Sync {
+    ///Set or modify headers before they are sent to the client and maybe initiate the body.
+    fn begin(&self, context: FilterContext, status: StatusCode, headers: &mut Headers) ->
+        (StatusCode, ResponseAction);
+
+    ///Handle content before writing it to the body.
+    fn write<'a>(&'a self, context: FilterContext, content: Option<Data<'a>>) -> ResponseAction;
+
+    ///End of body writing. Last chance to add content.
+    fn end(&self, context: FilterContext) -> ResponseAction;
+}
+
+///The result from a response filter.
+#[derive(Clone)]
+pub enum ResponseAction<'a> {
+    ///Continue to the next filter and maybe write data.
+    Next(Option<Data<'a>>),
+
+    ///Do not continue to the next filter.
+    SilentAbort,
+
+    ///Abort with an error.
+    Abort(String)
+}
+
+impl<'a> ResponseAction<'a> {
+    ///Continue to the next filter and maybe write data.
+    pub fn next<T: Into<Data<'a>>>(data: Option<T>) -> ResponseAction<'a> {
+        ResponseAction::Next(data.map(|d| d.into()))
+    }
+
+    ///Do not continue to the next filter.
+    pub fn silent_abort() -> ResponseAction<'a> {
+        ResponseAction::SilentAbort
+    }
+
+    ///Abort with an error.
+    pub fn abort(message: String) -> ResponseAction<'a> {
+        ResponseAction::Abort(message)
+    }
+}
+
+//Reads the request body into a `ParsedForm`, unless it has already been
+//read. Returns `None` if the body is not a form.
+fn parsed_form<'c>(context: &'c mut Context) -> Result<Option<&'c Parameters>, StatusCode> {
+    if !context.extensions.contains::<ParsedForm>() {
+        if !context.has_content_type(TopLevel::Application, SubLevel::WwwFormUrlEncoded) {
+            return Ok(None);
+        }
+
+        let form = try!(context.parse_form().map_err(StatusCode::from));
+        context.extensions.insert(ParsedForm(form));
+    }
+
+    Ok(context.extensions.get::<ParsedForm>().map(|form| &form.0))
+}
+
+//Adds `name` to the `Vary` header, unless it's already there.
+fn add_vary(headers: &mut Headers, name: &str) {
+    let name = UniCase(name.to_owned());
+
+    let vary = match headers.get::<Vary>() {
+        Some(&Vary::Any) => return,
+        Some(&Vary::Items(ref items)) if items.contains(&name) => return,
+        Some(&Vary::Items(ref items)) => {
+            let mut items = items.clone();
+            items.push(name);
+            Vary::Items(items)
+        },
+        None => Vary::Items(vec![name])
+    };
+
+    headers.set(vary);
+}
@@ -0,0 +1,175 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+use time;
+
+use StatusCode;
+use header::Headers;
+
+use context::Context;
+use response::Data;
+
+use super::{FilterContext, ContextFilter, ContextAction, ResponseFilter, ResponseAction};
+
+static REQUEST_COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
+
+///The ID of the current request, as assigned by `RequestIds`.
+///
+///It's stored in the filter storage, where it's available to the handler
+///and to any following filters.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RequestId(pub String);
+
+///A filter that assigns an ID to each request.
+///
+///The ID is taken from the `X-Request-Id` header in the request, or
+///generated if the header is missing or invalid, and it's echoed back in the
+///same header in the response. Generated IDs are random and formatted as
+///UUIDs. The header name can be changed and incoming IDs can be ignored, if
+///the clients are not trusted.
+///
+///The ID is stored as a `RequestId` in both the filter storage and the
+///request extensions, which makes it available to the handler through
+///`Context::extensions`, as well as to any filters that are added after this
+///one.
+///
+///This filter has to be added as both a context filter and a response
+///filter. It will not affect `Raw` responses, since they bypass the response
+///filters.
+///
+///```
+///use rustful::{Server, Context, Response};
+///use rustful::filter::{RequestIds, RequestId};
+///
+///fn my_handler(context: Context, response: Response) {
+///    if let Some(&RequestId(ref id)) = context.extensions.get::<RequestId>() {
+///        response.send(format!("request: {}", id));
+///    }
+///}
+///
+///let server = Server {
+///    context_filters: vec![Box::new(RequestIds::new())],
+///    response_filters: vec![Box::new(RequestIds::new())],
+///    ..Server::new(my_handler)
+///};
+///```
+pub struct RequestIds {
+    header: String,
+    trust_incoming: bool
+}
+
+impl RequestIds {
+    ///Create a filter that uses the `X-Request-Id` header.
+    pub fn new() -> RequestIds {
+        RequestIds {
+            header: "X-Request-Id".to_owned(),
+            trust_incoming: true
+        }
+    }
+
+    ///Use an other header than `X-Request-Id`.
+    pub fn header<H: Into<String>>(mut self, header: H) -> RequestIds {
+        self.header = header.into();
+        self
+    }
+
+    ///Use or ignore IDs from incoming requests. They are used by default.
+    pub fn trust_incoming(mut self, trust: bool) -> RequestIds {
+        self.trust_incoming = trust;
+        self
+    }
+}
+
+impl Default for RequestIds {
+    fn default() -> RequestIds {
+        RequestIds::new()
+    }
+}
+
+impl ContextFilter for RequestIds {
+    fn modify(&self, context: FilterContext, request_context: &mut Context) -> ContextAction {
+        let incoming = if self.trust_incoming {
+            request_context.headers.get_raw(&self.header)
+                .and_then(|values| values.first())
+                .and_then(|value| String::from_utf8(value.clone()).ok())
+                .map(|value| value.trim().to_owned())
+                .and_then(|value| if is_valid_id(&value) { Some(value) } else { None })
+        } else {
+            None
+        };
+
+        let id = RequestId(incoming.unwrap_or_else(generate_id));
+        request_context.extensions.insert(id.clone());
+        context.storage.insert(id);
+        ContextAction::Next
+    }
+}
+
+impl ResponseFilter for RequestIds {
+    fn begin(&self, context: FilterContext, status: StatusCode, headers: &mut Headers) -> (StatusCode, ResponseAction) {
+        if let Some(&RequestId(ref id)) = context.storage.get::<RequestId>() {
+            headers.set_raw(self.header.clone(), vec![id.clone().into_bytes()]);
+        }
+
+        (status, ResponseAction::Next(None))
+    }
+
+    fn write<'a>(&'a self, _context: FilterContext, content: Option<Data<'a>>) -> ResponseAction {
+        ResponseAction::Next(content)
+    }
+
+    fn end(&self, _context: FilterContext) -> ResponseAction {
+        ResponseAction::Next(None)
+    }
+}
+
+//Only accept short IDs with visible ASCII characters.
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 200 && id.bytes().all(|b| b > 0x20 && b < 0x7F)
+}
+
+//Generates a random, version 4 style, UUID. The randomly keyed hashers are
+//fed with a counter and the current time to make each ID unique.
+fn generate_id() -> String {
+    let count = REQUEST_COUNTER.fetch_add(1, Ordering::Relaxed) as u64;
+    let now = time::precise_time_ns();
+
+    let mut high = RandomState::new().build_hasher();
+    high.write_u64(count);
+    high.write_u64(now);
+    let high = high.finish();
+
+    let mut low = RandomState::new().build_hasher();
+    low.write_u64(now);
+    low.write_u64(count);
+    let low = low.finish();
+
+    let high = (high & 0xffff_ffff_ffff_0fff) | 0x0000_0000_0000_4000;
+    let low = (low & 0x3fff_ffff_ffff_ffff) | 0x8000_0000_0000_0000;
+
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        high >> 32,
+        (high >> 16) & 0xffff,
+        high & 0xffff,
+        low >> 48,
+        low & 0xffff_ffff_ffff
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{generate_id, is_valid_id};
+
+    #[test]
+    fn generated_ids() {
+        let a = generate_id();
+        let b = generate_id();
+
+        assert!(a != b);
+        assert!(is_valid_id(&a));
+        assert_eq!(a.len(), 36);
+        assert_eq!(&a[14..15], "4");
+    }
+}
@@ -0,0 +1,193 @@
+use std::time::{Duration, Instant};
+
+use anymap::AnyMap;
+
+use StatusCode;
+use header::Headers;
+
+use context::Context;
+use response::Data;
+
+use super::{FilterContext, ContextFilter, ContextAction, ResponseFilter, ResponseAction};
+
+///Named time measurements for the current request, for the `Server-Timing`
+///header.
+///
+///It's stored in the filter storage by `ServerTiming`, and the server adds
+///the time spent in the context filters (`filter`), the time it took to
+///find the handler (`route`) and the time spent in the handler until the
+///response was started (`handler`). Filters and handlers can add their own
+///measurements to it, either as finished durations or as spans that are
+///started and stopped. Spans that are still running when the response is
+///started are stopped at that point.
+///
+///```
+///use rustful::{Context, Response};
+///use rustful::filter::Timings;
+///
+///fn my_handler(context: Context, mut response: Response) {
+///    Timings::in_storage(response.filter_storage_mut()).start("db");
+///    //...
+///    Timings::in_storage(response.filter_storage_mut()).stop("db");
+///
+///    response.send("done");
+///}
+///```
+#[derive(Clone, Debug)]
+pub struct Timings {
+    metrics: Vec<(String, Duration)>,
+    running: Vec<(String, Instant)>
+}
+
+impl Timings {
+    ///Create an empty set of measurements.
+    pub fn new() -> Timings {
+        Timings {
+            metrics: vec![],
+            running: vec![]
+        }
+    }
+
+    ///Get the `Timings` from `storage`, or insert an empty one if it's
+    ///missing.
+    pub fn in_storage(storage: &mut AnyMap) -> &mut Timings {
+        if storage.get::<Timings>().is_none() {
+            storage.insert(Timings::new());
+        }
+
+        storage.get_mut::<Timings>().expect("the timings were just inserted")
+    }
+
+    ///Add a finished measurement. The name should be a simple token, such as
+    ///`db` or `cache_miss`.
+    pub fn add<N: Into<String>>(&mut self, name: N, duration: Duration) {
+        self.metrics.push((name.into(), duration));
+    }
+
+    ///Start measuring a span.
+    pub fn start<N: Into<String>>(&mut self, name: N) {
+        self.running.push((name.into(), Instant::now()));
+    }
+
+    ///Stop the most recently started span with the name `name`, if it's
+    ///running, and add it as a measurement.
+    pub fn stop(&mut self, name: &str) {
+        if let Some(index) = self.running.iter().rposition(|&(ref running, _)| running == name) {
+            let (name, start) = self.running.remove(index);
+            self.add(name, start.elapsed());
+        }
+    }
+
+    ///Stop every running span, in the order they were started.
+    pub fn stop_all(&mut self) {
+        for (name, start) in self.running.drain(..) {
+            self.metrics.push((name, start.elapsed()));
+        }
+    }
+
+    ///Get the finished measurements, in the order they were added.
+    pub fn metrics(&self) -> &[(String, Duration)] {
+        &self.metrics
+    }
+}
+
+impl Default for Timings {
+    fn default() -> Timings {
+        Timings::new()
+    }
+}
+
+///A filter that adds a `Server-Timing` header to the responses.
+///
+///The header contains the measurements in `Timings`, as well as the total
+///time since the request was received (`total`), and it can be displayed by
+///the developer tools in most browsers. It may reveal more than expected
+///about the server, so it may be a good idea to only add it during
+///development.
+///
+///This filter has to be added as both a context filter and a response
+///filter. It's best to add it as the first context filter, to let it measure
+///the time of the other filters, and the time of `Raw` responses will not
+///be reported, since they bypass the response filters.
+///
+///```
+///use rustful::{Server, Context, Response};
+///use rustful::filter::ServerTiming;
+///
+///fn my_handler(context: Context, response: Response) {
+///    response.send("hello");
+///}
+///
+///let server = Server {
+///    context_filters: vec![Box::new(ServerTiming)],
+///    response_filters: vec![Box::new(ServerTiming)],
+///    ..Server::new(my_handler)
+///};
+///```
+#[derive(Clone, Copy, Debug)]
+pub struct ServerTiming;
+
+impl ContextFilter for ServerTiming {
+    fn modify(&self, context: FilterContext, _request_context: &mut Context) -> ContextAction {
+        Timings::in_storage(context.storage);
+        ContextAction::Next
+    }
+}
+
+impl ResponseFilter for ServerTiming {
+    fn begin(&self, context: FilterContext, status: StatusCode, headers: &mut Headers) -> (StatusCode, ResponseAction) {
+        let timings = Timings::in_storage(context.storage);
+        timings.stop_all();
+
+        let mut header = String::new();
+        for &(ref name, duration) in timings.metrics() {
+            header.push_str(&format_metric(name, duration));
+            header.push_str(", ");
+        }
+        header.push_str(&format_metric("total", context.received_at.elapsed()));
+
+        headers.set_raw("Server-Timing", vec![header.into_bytes()]);
+
+        (status, ResponseAction::Next(None))
+    }
+
+    fn write<'a>(&'a self, _context: FilterContext, content: Option<Data<'a>>) -> ResponseAction {
+        ResponseAction::Next(content)
+    }
+
+    fn end(&self, _context: FilterContext) -> ResponseAction {
+        ResponseAction::Next(None)
+    }
+}
+
+//The duration is written in milliseconds.
+fn format_metric(name: &str, duration: Duration) -> String {
+    let milliseconds = duration.as_secs() as f64 * 1000.0 + duration.subsec_nanos() as f64 / 1_000_000.0;
+    format!("{};dur={:.3}", name, milliseconds)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use super::{Timings, format_metric};
+
+    #[test]
+    fn format_metrics() {
+        assert_eq!(format_metric("db", Duration::new(1, 500000)), "db;dur=1000.500");
+        assert_eq!(format_metric("total", Duration::from_millis(0)), "total;dur=0.000");
+    }
+
+    #[test]
+    fn stop_spans() {
+        let mut timings = Timings::new();
+        timings.add("a", Duration::from_millis(1));
+        timings.start("b");
+        timings.start("c");
+        timings.stop("b");
+        timings.stop("missing");
+        timings.stop_all();
+
+        let names: Vec<_> = timings.metrics().iter().map(|&(ref name, _)| &**name).collect();
+        assert_eq!(names, ["a", "b", "c"]);
+    }
+}
@@ -0,0 +1,394 @@
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use time;
+
+use StatusCode;
+use header::Headers;
+
+use context::{Context, Parameters};
+use cookie::{CookieBuilder, SameSite};
+use response::Data;
+use utils;
+
+use super::{FilterContext, ContextFilter, ContextAction, ResponseFilter, ResponseAction};
+
+///Signs and verifies session cookies.
+///
+///`HmacSha256` is used in most cases, but this makes it possible to use an
+///other algorithm, or an external library.
+pub trait SessionSigner: Send + Sync {
+    ///Create a signature for `data`.
+    fn sign(&self, data: &[u8]) -> Vec<u8>;
+
+    ///Check if `signature` is a valid signature for `data`. The default
+    ///implementation compares it to the result of `sign`, in constant time.
+    fn verify(&self, data: &[u8], signature: &[u8]) -> bool {
+        utils::constant_time_eq(&self.sign(data), signature)
+    }
+}
+
+///Signs session cookies using HMAC-SHA256 and a secret key.
+///
+///The key should be long, random and kept secret, since anyone who knows it
+///can create valid sessions.
+#[derive(Clone)]
+pub struct HmacSha256 {
+    key: Vec<u8>
+}
+
+impl HmacSha256 {
+    ///Create a signer with a secret key.
+    pub fn new<K: Into<Vec<u8>>>(key: K) -> HmacSha256 {
+        HmacSha256 {
+            key: key.into()
+        }
+    }
+}
+
+impl SessionSigner for HmacSha256 {
+    fn sign(&self, data: &[u8]) -> Vec<u8> {
+        utils::hmac_sha256(&self.key, data).to_vec()
+    }
+}
+
+///Data that can be stored in a session cookie.
+pub trait SessionData: Sized + Clone + 'static {
+    ///Encode the data as bytes.
+    fn encode_session(&self) -> Vec<u8>;
+
+    ///Decode data from `encode_session`. `None` is returned if it's
+    ///invalid, which is treated as if there was no session.
+    fn decode_session(data: &[u8]) -> Option<Self>;
+}
+
+impl SessionData for Vec<u8> {
+    fn encode_session(&self) -> Vec<u8> {
+        self.clone()
+    }
+
+    fn decode_session(data: &[u8]) -> Option<Vec<u8>> {
+        Some(data.to_vec())
+    }
+}
+
+impl SessionData for String {
+    fn encode_session(&self) -> Vec<u8> {
+        self.clone().into_bytes()
+    }
+
+    fn decode_session(data: &[u8]) -> Option<String> {
+        String::from_utf8(data.to_vec()).ok()
+    }
+}
+
+impl SessionData for Parameters {
+    fn encode_session(&self) -> Vec<u8> {
+        let pairs: Vec<_> = self.into_iter().map(|(key, value)| format!(
+            "{}={}",
+            utils::percent_encode_component(key.as_bytes()),
+            utils::percent_encode_component(value.as_bytes())
+        )).collect();
+        pairs.join("&").into_bytes()
+    }
+
+    fn decode_session(data: &[u8]) -> Option<Parameters> {
+        utils::parse_parameters(data, None)
+    }
+}
+
+///The session of the current request.
+///
+///It's stored in the filter storage by `Sessions`, where the handler can
+///change it. A changed session is written back to the session cookie when
+///the response is started, while an unchanged session leaves the cookie as
+///it is.
+#[derive(Clone, Debug)]
+pub struct Session<T> {
+    value: Option<T>,
+    changed: bool
+}
+
+impl<T> Session<T> {
+    ///Create an empty session.
+    pub fn new() -> Session<T> {
+        Session {
+            value: None,
+            changed: false
+        }
+    }
+
+    ///Get the session data, if there is any.
+    pub fn get(&self) -> Option<&T> {
+        self.value.as_ref()
+    }
+
+    ///Get the session data for modification. This will mark the session as
+    ///changed.
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        self.changed = true;
+        self.value.as_mut()
+    }
+
+    ///Replace the session data.
+    pub fn set(&mut self, value: T) {
+        self.changed = true;
+        self.value = Some(value);
+    }
+
+    ///Remove the session data, and the cookie.
+    pub fn clear(&mut self) {
+        self.changed = true;
+        self.value = None;
+    }
+
+    ///Check if the session has been changed.
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+}
+
+impl<T> Default for Session<T> {
+    fn default() -> Session<T> {
+        Session::new()
+    }
+}
+
+///A filter for signed cookie sessions.
+///
+///The session data is stored in a cookie, together with its expiration time
+///and a signature. The signature is verified when the cookie is read, and
+///cookies that have been tampered with, or have expired, are treated as if
+///there was no session. The data can be read by the client, so it should not
+///contain any secrets.
+///
+///The decoded data is added to the request extensions, where the handler
+///can read it, and a `Session<T>` is added to the filter storage. The
+///handler changes the session through the filter storage, and the cookie is
+///updated when the response is started.
+///
+///This filter has to be added as both a context filter and a response
+///filter, with the same configuration. The secret key can be kept in the
+///global data, to make it available for other uses.
+///
+///```
+///use rustful::{Server, Context, Response};
+///use rustful::context::Parameters;
+///use rustful::filter::{Sessions, Session, HmacSha256};
+///
+///fn count_visits(context: Context, mut response: Response) {
+///    let visits = context.extensions.get::<Parameters>()
+///        .map_or(0, |session| session.parse_or("visits", 0)) + 1;
+///
+///    let mut session = Parameters::new();
+///    session.insert("visits", visits.to_string());
+///    if let Some(stored) = response.filter_storage_mut().get_mut::<Session<Parameters>>() {
+///        stored.set(session);
+///    }
+///
+///    response.send(format!("visit number {}", visits));
+///}
+///
+///let secret = b"a long and random secret key".to_vec();
+///
+///let server = Server {
+///    context_filters: vec![Box::new(Sessions::<Parameters>::new(HmacSha256::new(secret.clone())))],
+///    response_filters: vec![Box::new(Sessions::<Parameters>::new(HmacSha256::new(secret)))],
+///    ..Server::new(count_visits)
+///};
+///```
+pub struct Sessions<T> {
+    signer: Box<SessionSigner>,
+    cookie_name: String,
+    path: String,
+    max_age: Option<Duration>,
+    secure: bool,
+    same_site: SameSite,
+    data: PhantomData<fn() -> T>
+}
+
+impl<T: SessionData> Sessions<T> {
+    ///Create a session filter that uses `signer` to sign the cookies. The
+    ///cookie is called `session` by default, and it's valid for the whole
+    ///site until the browser is closed.
+    pub fn new<S: SessionSigner + 'static>(signer: S) -> Sessions<T> {
+        Sessions {
+            signer: Box::new(signer),
+            cookie_name: "session".to_owned(),
+            path: "/".to_owned(),
+            max_age: None,
+            secure: false,
+            same_site: SameSite::Lax,
+            data: PhantomData
+        }
+    }
+
+    ///Set the name of the cookie.
+    pub fn cookie_name<N: Into<String>>(mut self, name: N) -> Sessions<T> {
+        self.cookie_name = name.into();
+        self
+    }
+
+    ///Set the path where the cookie is valid.
+    pub fn path<P: Into<String>>(mut self, path: P) -> Sessions<T> {
+        self.path = path.into();
+        self
+    }
+
+    ///Let each session expire after `max_age`, counted from when it was
+    ///last changed.
+    pub fn max_age(mut self, max_age: Duration) -> Sessions<T> {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    ///Only send the cookie over HTTPS.
+    pub fn secure(mut self) -> Sessions<T> {
+        self.secure = true;
+        self
+    }
+
+    ///Set the `SameSite` attribute of the cookie. Default is
+    ///`SameSite::Lax`.
+    pub fn same_site(mut self, same_site: SameSite) -> Sessions<T> {
+        self.same_site = same_site;
+        self
+    }
+
+    //The signature covers the cookie name, to prevent the value from being
+    //moved to an other cookie.
+    fn signed_part(&self, data: &str, expires: i64) -> String {
+        format!("{}={}.{}", self.cookie_name, data, expires)
+    }
+
+    //Format: base64(data).expires.base64(signature), where an expiration
+    //time of 0 means that it never expires.
+    fn encode(&self, value: &T, now: i64) -> String {
+        let data = utils::base64_encode(&value.encode_session());
+        let expires = self.max_age.map_or(0, |max_age| now + max_age.as_secs() as i64);
+        let signature = self.signer.sign(self.signed_part(&data, expires).as_bytes());
+        format!("{}.{}.{}", data, expires, utils::base64_encode(&signature))
+    }
+
+    fn decode(&self, cookie: &str, now: i64) -> Option<T> {
+        let mut parts = cookie.split('.');
+        let (data, expires, signature) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(data), Some(expires), Some(signature), None) => (data, expires, signature),
+            _ => return None
+        };
+
+        let expires: i64 = match expires.parse() {
+            Ok(expires) => expires,
+            Err(_) => return None
+        };
+
+        let signature = match utils::base64_decode(signature.as_bytes()) {
+            Some(signature) => signature,
+            None => return None
+        };
+
+        if !self.signer.verify(self.signed_part(data, expires).as_bytes(), &signature) {
+            return None;
+        }
+
+        if expires != 0 && expires <= now {
+            return None;
+        }
+
+        utils::base64_decode(data.as_bytes()).and_then(|data| T::decode_session(&data))
+    }
+
+    fn cookie(&self, value: String) -> CookieBuilder {
+        let cookie = CookieBuilder::new(&*self.cookie_name, value)
+            .path(&*self.path)
+            .http_only()
+            .same_site(self.same_site);
+
+        if self.secure {
+            cookie.secure()
+        } else {
+            cookie
+        }
+    }
+}
+
+impl<T: SessionData> ContextFilter for Sessions<T> {
+    fn modify(&self, context: FilterContext, request_context: &mut Context) -> ContextAction {
+        let value = request_context.get_cookie(&self.cookie_name)
+            .and_then(|cookie| self.decode(&cookie, time::get_time().sec));
+
+        if let Some(ref value) = value {
+            request_context.extensions.insert(value.clone());
+        }
+
+        context.storage.insert(Session {
+            value: value,
+            changed: false
+        });
+
+        ContextAction::Next
+    }
+}
+
+impl<T: SessionData> ResponseFilter for Sessions<T> {
+    fn begin(&self, context: FilterContext, status: StatusCode, headers: &mut Headers) -> (StatusCode, ResponseAction) {
+        let cookie = match context.storage.get::<Session<T>>() {
+            Some(session) if session.changed => match session.value {
+                Some(ref value) => {
+                    let cookie = self.cookie(self.encode(value, time::get_time().sec));
+                    match self.max_age {
+                        Some(max_age) => cookie.max_age(max_age.as_secs()),
+                        None => cookie
+                    }
+                },
+                None => self.cookie(String::new()).max_age(0)
+            },
+            _ => return (status, ResponseAction::Next(None))
+        };
+
+        let mut cookies = headers.get_raw("Set-Cookie").map(|c| c.to_vec()).unwrap_or_else(Vec::new);
+        cookies.push(cookie.to_string().into_bytes());
+        headers.set_raw("Set-Cookie", cookies);
+
+        (status, ResponseAction::Next(None))
+    }
+
+    fn write<'a>(&'a self, _context: FilterContext, content: Option<Data<'a>>) -> ResponseAction {
+        ResponseAction::Next(content)
+    }
+
+    fn end(&self, _context: FilterContext) -> ResponseAction {
+        ResponseAction::Next(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use super::{Sessions, HmacSha256};
+
+    #[test]
+    fn sign_and_verify() {
+        let sessions = Sessions::<String>::new(HmacSha256::new("secret"));
+        let cookie = sessions.encode(&"user=1".to_owned(), 1000);
+
+        assert_eq!(sessions.decode(&cookie, 1000), Some("user=1".to_owned()));
+        assert_eq!(sessions.decode(&cookie.replace("dXNlcj0x", "dXNlcj0y"), 1000), None);
+        assert_eq!(sessions.decode("garbage", 1000), None);
+
+        let other_key = Sessions::<String>::new(HmacSha256::new("other secret"));
+        assert_eq!(other_key.decode(&cookie, 1000), None);
+
+        let other_name = Sessions::<String>::new(HmacSha256::new("secret")).cookie_name("other");
+        assert_eq!(other_name.decode(&cookie, 1000), None);
+    }
+
+    #[test]
+    fn expire_sessions() {
+        let sessions = Sessions::<String>::new(HmacSha256::new("secret")).max_age(Duration::from_secs(60));
+        let cookie = sessions.encode(&"user=1".to_owned(), 1000);
+
+        assert_eq!(sessions.decode(&cookie, 1059), Some("user=1".to_owned()));
+        assert_eq!(sessions.decode(&cookie, 1060), None);
+    }
+}
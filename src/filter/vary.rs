@@ -0,0 +1,89 @@
+use StatusCode;
+use header::Headers;
+
+use context::NegotiatedHeaders;
+use response::Data;
+
+use super::{FilterContext, ResponseFilter, ResponseAction, add_vary};
+
+///A filter that lists the negotiated request headers in `Vary`.
+///
+///Handlers that choose between different representations, using methods
+///such as `Context::negotiate`, produce responses that depend on request
+///headers, such as `Accept`. Caches have to be told about this through the
+///`Vary` header, or they may serve the wrong representation to an other
+///client. This filter adds each header from `Context::negotiated` to `Vary`,
+///while leaving any existing names in place. A name is never listed more
+///than once, even if several negotiation methods have used it.
+///
+///```
+///use rustful::{Server, Context, Response, StatusCode};
+///use rustful::header::ContentType;
+///use rustful::filter::VaryNegotiated;
+///
+///fn my_handler(context: Context, mut response: Response) {
+///    let offers = ["application/json".parse().unwrap(), "text/plain".parse().unwrap()];
+///
+///    //The response will have `Vary: Accept`.
+///    match context.negotiate(&offers) {
+///        Some(mime) => {
+///            response.headers_mut().set(ContentType(mime));
+///            response.send("hello");
+///        },
+///        None => response.set_status(StatusCode::NotAcceptable)
+///    }
+///}
+///
+///let server = Server {
+///    response_filters: vec![Box::new(VaryNegotiated)],
+///    ..Server::new(my_handler)
+///};
+///```
+#[derive(Clone, Copy, Debug)]
+pub struct VaryNegotiated;
+
+impl ResponseFilter for VaryNegotiated {
+    fn begin(&self, context: FilterContext, status: StatusCode, headers: &mut Headers) -> (StatusCode, ResponseAction) {
+        if let Some(negotiated) = context.storage.get::<NegotiatedHeaders>() {
+            for name in negotiated.names() {
+                add_vary(headers, &name);
+            }
+        }
+
+        (status, ResponseAction::Next(None))
+    }
+
+    fn write<'a>(&'a self, _context: FilterContext, content: Option<Data<'a>>) -> ResponseAction {
+        ResponseAction::Next(content)
+    }
+
+    fn end(&self, _context: FilterContext) -> ResponseAction {
+        ResponseAction::Next(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use header::{Headers, Vary};
+    use super::super::add_vary;
+
+    #[test]
+    fn no_duplicate_tokens() {
+        let mut headers = Headers::new();
+        headers.set_raw("Vary", vec![b"Origin, accept".to_vec()]);
+
+        add_vary(&mut headers, "Accept");
+        add_vary(&mut headers, "Accept-Language");
+        add_vary(&mut headers, "accept-language");
+
+        let names: Vec<_> = match headers.get::<Vary>() {
+            Some(&Vary::Items(ref items)) => items.iter().map(|name| name.to_string()).collect(),
+            _ => panic!("expected a list of names")
+        };
+        assert_eq!(names, vec!["Origin".to_owned(), "accept".to_owned(), "Accept-Language".to_owned()]);
+
+        headers.set(Vary::Any);
+        add_vary(&mut headers, "Accept");
+        assert_eq!(headers.get::<Vary>(), Some(&Vary::Any));
+    }
+}
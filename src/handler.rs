@@ -2,7 +2,10 @@
 use std::borrow::Cow;
 
 use context::Context;
-use response::Response;
+use response::{Response, Redirect, IntoResponse};
+use header::Host;
+use StatusCode;
+use utils;
 use std::sync::Arc;
 
 ///A trait for request handlers.
@@ -34,3 +37,148 @@ impl Handler for Box<Handler> {
         (**self).handle_request(context, response);
     }
 }
+
+///A trait for handlers that return their response, instead of writing it.
+///
+///The result is turned into a response using `IntoResponse`, for both `Ok`
+///and `Err`, so errors can be propagated using `?` or `try!`. It's
+///implemented for functions and closures that take a `Context` and return a
+///`Result`. A `FallibleHandler` has to be wrapped in `Fallible` to be used
+///as a `Handler`:
+///
+///```
+///use rustful::{Server, Context, StatusCode};
+///use rustful::handler::Fallible;
+///
+///fn show_user(context: Context) -> Result<String, StatusCode> {
+///    let id: u64 = context.variables.parse("id").map_err(|_| StatusCode::BadRequest)?;
+///    let name = find_user(id).ok_or(StatusCode::NotFound)?;
+///    Ok(format!("user {} is called {}", id, name))
+///}
+///
+///# fn find_user(id: u64) -> Option<String> { None }
+///let server = Server::new(Fallible(show_user));
+///```
+pub trait FallibleHandler: Send + Sync + 'static {
+    ///The response type for successful requests.
+    type Output: IntoResponse;
+
+    ///The response type for failed requests.
+    type Error: IntoResponse;
+
+    ///Handle a request from the client and return the result.
+    fn try_handle(&self, context: Context) -> Result<Self::Output, Self::Error>;
+}
+
+impl<F, T, E> FallibleHandler for F where
+    F: Fn(Context) -> Result<T, E> + Send + Sync + 'static,
+    T: IntoResponse,
+    E: IntoResponse
+{
+    type Output = T;
+    type Error = E;
+
+    fn try_handle(&self, context: Context) -> Result<T, E> {
+        self(context)
+    }
+}
+
+///Turns a `FallibleHandler` into a `Handler`.
+pub struct Fallible<H>(pub H);
+
+impl<H: FallibleHandler> Handler for Fallible<H> {
+    fn handle_request(&self, context: Context, response: Response) {
+        self.0.try_handle(context).into_response(response);
+    }
+}
+
+///A handler that redirects every request to its `https://` equivalent.
+///
+///It's meant to be used in a plain HTTP server, next to the main HTTPS
+///server, to make sure that clients are using an encrypted connection. The
+///host name is taken from the `Host` header, and the path and query are
+///preserved.
+///
+///```no_run
+///# use rustful::{Server, Context, Response};
+///# use rustful::server::Scheme;
+///use rustful::handler::HttpsRedirect;
+///
+///# fn my_handler(context: Context, response: Response) {}
+///let _redirect = Server {
+///    host: 80.into(),
+///    ..Server::new(HttpsRedirect::new())
+///}.run().unwrap();
+///
+///let _listening = Server {
+///    host: 443.into(),
+///    scheme: Scheme::Https {
+///        cert: "cert.pem".into(),
+///        key: "key.pem".into()
+///    },
+///    ..Server::new(my_handler)
+///}.run().unwrap();
+///```
+pub struct HttpsRedirect {
+    port: Option<u16>,
+    status: Redirect
+}
+
+impl HttpsRedirect {
+    ///Redirect to the default HTTPS port, using `301 Moved Permanently`.
+    pub fn new() -> HttpsRedirect {
+        HttpsRedirect {
+            port: None,
+            status: Redirect::MovedPermanently
+        }
+    }
+
+    ///Redirect to a non-standard HTTPS port.
+    pub fn port(mut self, port: u16) -> HttpsRedirect {
+        self.port = Some(port);
+        self
+    }
+
+    ///Set the redirect status. `MovedPermanently` is the default, but
+    ///`PermanentRedirect` may be preferred, since it preserves the request
+    ///method.
+    pub fn status(mut self, status: Redirect) -> HttpsRedirect {
+        self.status = status;
+        self
+    }
+}
+
+impl Default for HttpsRedirect {
+    fn default() -> HttpsRedirect {
+        HttpsRedirect::new()
+    }
+}
+
+impl Handler for HttpsRedirect {
+    fn handle_request(&self, context: Context, mut response: Response) {
+        let hostname = match context.headers.get::<Host>() {
+            Some(host) => host.hostname.clone(),
+            None => return response.set_status(StatusCode::BadRequest)
+        };
+
+        let mut location = format!("https://{}", hostname);
+
+        if let Some(port) = self.port {
+            if port != 443 {
+                location.push_str(&format!(":{}", port));
+            }
+        }
+
+        match context.uri.as_path() {
+            Some(path) => location.push_str(&utils::percent_encode_path(&path[..])),
+            None => location.push('/')
+        }
+
+        if let Some(ref query) = context.raw_query {
+            location.push('?');
+            location.push_str(&query.as_utf8_lossy());
+        }
+
+        response.redirect(self.status, location);
+    }
+}
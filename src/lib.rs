@@ -75,12 +75,28 @@ extern crate rustc_serialize;
 #[cfg(feature = "multipart")]
 extern crate multipart;
 
+#[cfg(feature = "flate2")]
+extern crate flate2;
+
+#[cfg(feature = "encoding")]
+extern crate encoding;
+
+#[cfg(feature = "rustls")]
+extern crate rustls;
+
+#[cfg(feature = "ssl")]
+extern crate openssl;
+
 extern crate url;
 extern crate time;
 extern crate hyper;
 extern crate anymap;
 extern crate phf;
 extern crate num_cpus;
+extern crate unicase;
+extern crate regex;
+extern crate net2;
+extern crate rand;
 
 pub use hyper::mime;
 pub use hyper::method::Method;
@@ -89,8 +105,10 @@ pub use hyper::header;
 pub use hyper::Result as HttpResult;
 pub use hyper::Error as HttpError;
 pub use hyper::version::HttpVersion;
+pub use hyper::LanguageTag;
 
 pub use self::server::Server;
+pub use self::server::ServerError;
 pub use self::context::Context;
 pub use self::response::Response;
 pub use self::response::Error;
@@ -110,3 +128,7 @@ pub mod context;
 pub mod response;
 pub mod filter;
 pub mod file;
+pub mod cookie;
+pub mod websocket;
+pub mod sse;
+pub mod testing;
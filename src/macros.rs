@@ -161,6 +161,139 @@ macro_rules! __rustful_insert_internal {
     };
 }
 
+///The `route!` macro defines a route, with a struct for its variables.
+///
+///The route pattern is written as a sequence of segments, separated by `/`.
+///A segment can be an identifier or a string literal, which is matched as
+///it is, a variable, such as `:id`, or a wildcard, `*`. Variables are
+///`String`s by default, but they can also be given a type, as in `:id(u64)`,
+///that will be parsed using `FromStr`. A single `/` is the root route.
+///
+///Each variable becomes a public field in the struct, so a pattern with
+///duplicated variable names, or with unbalanced brackets, is rejected by the
+///compiler, rather than by the router at runtime. The contents of the string
+///literals can't be checked, so identifiers should be used where possible.
+///
+///The struct gets these methods:
+///
+/// * `path()` returns the pattern as a `&'static str`, such as
+///   `"/users/:id"`.
+/// * `insert(router, method, handler)` inserts a handler into a router,
+///   using the pattern.
+/// * `from_context(context)` reads and parses the variables from a
+///   `Context`, and returns `None` if any of them is missing or can't be
+///   parsed.
+///
+///```rust
+///#[macro_use]
+///extern crate rustful;
+///use rustful::{TreeRouter, Context, Response, StatusCode};
+///use rustful::Method::Get;
+///
+///route! {
+///    ///Shows a single post.
+///    #[derive(Debug)]
+///    pub struct ShowPost = users / :user / posts / :post(u64)
+///}
+///
+///fn show_post(context: Context, mut response: Response) {
+///    match ShowPost::from_context(&context) {
+///        Some(route) => response.send(format!("post {} by {}", route.post, route.user)),
+///        None => response.set_status(StatusCode::NotFound)
+///    }
+///}
+///
+///# fn main() {
+///let mut router = TreeRouter::new();
+///ShowPost::insert(&mut router, Get, show_post as fn(Context, Response));
+///
+///assert_eq!(ShowPost::path(), "/users/:user/posts/:post");
+///# }
+///```
+#[macro_export]
+macro_rules! route {
+    ($(#[$attr:meta])* pub struct $name:ident = $($pattern:tt)+) => {
+        __rustful_route_internal!([$(#[$attr])*] [pub] $name, [], [], $($pattern)+);
+    };
+    ($(#[$attr:meta])* struct $name:ident = $($pattern:tt)+) => {
+        __rustful_route_internal!([$(#[$attr])*] [] $name, [], [], $($pattern)+);
+    };
+}
+
+//Internal stuff. Only meant to be used through `route!`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __rustful_route_internal {
+    ([$($attr:tt)*] [$($vis:tt)*] $name:ident, [$($path:tt)*], [$($field:ident : $ty:ty,)*],) => {
+        $($attr)*
+        $($vis)* struct $name {
+            $(
+                #[allow(missing_docs)]
+                pub $field: $ty,
+            )*
+        }
+
+        #[allow(dead_code)]
+        impl $name {
+            ///The route pattern.
+            pub fn path() -> &'static str {
+                concat!($($path)*)
+            }
+
+            ///Insert `handler` into `router`, using the route pattern.
+            pub fn insert<R: $crate::Router>(router: &mut R, method: $crate::Method, handler: R::Handler) {
+                $crate::Router::insert(router, method, $name::path(), handler);
+            }
+
+            ///Read the route variables from `context`.
+            #[allow(unused_variables)]
+            pub fn from_context(context: &$crate::Context) -> Option<$name> {
+                Some($name {
+                    $(
+                        $field: match context.variables.parse(stringify!($field)) {
+                            Ok(value) => value,
+                            Err(_) => return None
+                        },
+                    )*
+                })
+            }
+        }
+    };
+    ($attr:tt $vis:tt $name:ident, [], [], /) => {
+        __rustful_route_internal!($attr $vis $name, ["/",], [],);
+    };
+    ($attr:tt $vis:tt $name:ident, [$($path:tt)*], [$($fields:tt)*], : $var:ident ($ty:ty) / $($rest:tt)+) => {
+        __rustful_route_internal!($attr $vis $name, [$($path)* "/:", stringify!($var),], [$($fields)* $var: $ty,], $($rest)+);
+    };
+    ($attr:tt $vis:tt $name:ident, [$($path:tt)*], [$($fields:tt)*], : $var:ident ($ty:ty)) => {
+        __rustful_route_internal!($attr $vis $name, [$($path)* "/:", stringify!($var),], [$($fields)* $var: $ty,],);
+    };
+    ($attr:tt $vis:tt $name:ident, [$($path:tt)*], [$($fields:tt)*], : $var:ident / $($rest:tt)+) => {
+        __rustful_route_internal!($attr $vis $name, [$($path)* "/:", stringify!($var),], [$($fields)* $var: String,], $($rest)+);
+    };
+    ($attr:tt $vis:tt $name:ident, [$($path:tt)*], [$($fields:tt)*], : $var:ident) => {
+        __rustful_route_internal!($attr $vis $name, [$($path)* "/:", stringify!($var),], [$($fields)* $var: String,],);
+    };
+    ($attr:tt $vis:tt $name:ident, [$($path:tt)*], [$($fields:tt)*], * / $($rest:tt)+) => {
+        __rustful_route_internal!($attr $vis $name, [$($path)* "/*",], [$($fields)*], $($rest)+);
+    };
+    ($attr:tt $vis:tt $name:ident, [$($path:tt)*], [$($fields:tt)*], *) => {
+        __rustful_route_internal!($attr $vis $name, [$($path)* "/*",], [$($fields)*],);
+    };
+    ($attr:tt $vis:tt $name:ident, [$($path:tt)*], [$($fields:tt)*], $segment:ident / $($rest:tt)+) => {
+        __rustful_route_internal!($attr $vis $name, [$($path)* "/", stringify!($segment),], [$($fields)*], $($rest)+);
+    };
+    ($attr:tt $vis:tt $name:ident, [$($path:tt)*], [$($fields:tt)*], $segment:ident) => {
+        __rustful_route_internal!($attr $vis $name, [$($path)* "/", stringify!($segment),], [$($fields)*],);
+    };
+    ($attr:tt $vis:tt $name:ident, [$($path:tt)*], [$($fields:tt)*], $segment:tt / $($rest:tt)+) => {
+        __rustful_route_internal!($attr $vis $name, [$($path)* "/", $segment,], [$($fields)*], $($rest)+);
+    };
+    ($attr:tt $vis:tt $name:ident, [$($path:tt)*], [$($fields:tt)*], $segment:tt) => {
+        __rustful_route_internal!($attr $vis $name, [$($path)* "/", $segment,], [$($fields)*],);
+    };
+}
+
 #[doc(hidden)]
 #[macro_export]
 macro_rules! __rustful_route_expr {
@@ -32,7 +32,7 @@
 //![raw]: struct.Raw.html
 
 use std;
-use std::io::{self, Write};
+use std::io::{self, Read, Write};
 use std::error;
 use std::borrow::Cow;
 use std::convert::From;
@@ -40,23 +40,38 @@ use std::str::{from_utf8, Utf8Error};
 use std::string::{FromUtf8Error};
 use std::fs::File;
 use std::path::Path;
+use std::cell::RefCell;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use hyper;
 
+#[cfg(feature = "rustc_json_body")]
+use rustc_serialize::json;
+#[cfg(feature = "rustc_json_body")]
+use rustc_serialize::Encodable;
+
 use anymap::AnyMap;
 
-use StatusCode;
+use {StatusCode, HttpVersion};
 
 use header::{
     Headers,
     ContentType,
+    ContentLength,
+    TransferEncoding,
     Connection,
-    ConnectionOption
+    ConnectionOption,
+    Location,
+    CacheControl,
+    CacheDirective
 };
 use filter::{FilterContext, ResponseFilter};
 use filter::ResponseAction as Action;
-use mime::{Mime, TopLevel, SubLevel};
+use mime::{Mime, TopLevel, SubLevel, Attr, Value};
 use server::Global;
+use cookie::CookieBuilder;
+use sse::EventStream;
 use utils::BytesExt;
 
 ///The result of a response action.
@@ -181,6 +196,37 @@ impl<'a, 'b> error::Error for FileError<'a, 'b> {
     }
 }
 
+///Status codes that can be used for redirects.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Redirect {
+    ///`301 Moved Permanently`.
+    MovedPermanently,
+
+    ///`302 Found`.
+    Found,
+
+    ///`303 See Other`.
+    SeeOther,
+
+    ///`307 Temporary Redirect`.
+    TemporaryRedirect,
+
+    ///`308 Permanent Redirect`.
+    PermanentRedirect
+}
+
+impl From<Redirect> for StatusCode {
+    fn from(redirect: Redirect) -> StatusCode {
+        match redirect {
+            Redirect::MovedPermanently => StatusCode::MovedPermanently,
+            Redirect::Found => StatusCode::Found,
+            Redirect::SeeOther => StatusCode::SeeOther,
+            Redirect::TemporaryRedirect => StatusCode::TemporaryRedirect,
+            Redirect::PermanentRedirect => StatusCode::PermanentRedirect
+        }
+    }
+}
+
 ///A unified representation of response data.
 #[derive(Clone)]
 pub enum Data<'a> {
@@ -249,6 +295,133 @@ impl<'a> Into<Data<'a>> for &'a str {
     }
 }
 
+///Types that can be turned into a complete response.
+///
+///It's used by [`Fallible`](../handler/struct.Fallible.html) handlers to
+///send their results, but it can also be used directly. The response is
+///finished by `into_response`.
+///
+///Text is sent as `text/plain; charset=UTF-8`, bytes as
+///`application/octet-stream` and `Json` as `application/json`. The body is
+///sent the same way as with `Response::send`, so `Content-Length` is set
+///when the status allows it.
+///
+///```
+///use rustful::{Context, Response, StatusCode};
+///use rustful::response::IntoResponse;
+///
+///fn my_handler(context: Context, response: Response) {
+///    if context.variables.get("id").is_some() {
+///        (StatusCode::Created, "created".to_owned()).into_response(response);
+///    } else {
+///        StatusCode::BadRequest.into_response(response);
+///    }
+///}
+///```
+pub trait IntoResponse {
+    ///Write `self` to `response` and finish it.
+    fn into_response(self, response: Response);
+}
+
+impl IntoResponse for () {
+    fn into_response(self, _response: Response) {}
+}
+
+impl<'a> IntoResponse for &'a str {
+    fn into_response(self, response: Response) {
+        send_with_type(response, text_plain(), self);
+    }
+}
+
+impl IntoResponse for String {
+    fn into_response(self, response: Response) {
+        send_with_type(response, text_plain(), self);
+    }
+}
+
+impl<'a> IntoResponse for &'a [u8] {
+    fn into_response(self, response: Response) {
+        send_with_type(response, octet_stream(), self);
+    }
+}
+
+impl IntoResponse for Vec<u8> {
+    fn into_response(self, response: Response) {
+        send_with_type(response, octet_stream(), self);
+    }
+}
+
+impl IntoResponse for StatusCode {
+    fn into_response(self, mut response: Response) {
+        response.set_status(self);
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for (StatusCode, T) {
+    fn into_response(self, mut response: Response) {
+        response.set_status(self.0);
+        self.1.into_response(response);
+    }
+}
+
+impl<T: IntoResponse, E: IntoResponse> IntoResponse for Result<T, E> {
+    fn into_response(self, response: Response) {
+        match self {
+            Ok(content) => content.into_response(response),
+            Err(error) => error.into_response(response)
+        }
+    }
+}
+
+///A value that is sent as JSON, with the `Content-Type` set to
+///`application/json`. A failure to encode it results in `500 Internal Server
+///Error`.
+///
+///```
+///# extern crate rustful;
+///# extern crate rustc_serialize;
+///use rustful::Context;
+///use rustful::response::Json;
+///
+///#[derive(RustcEncodable)]
+///struct Greeting {
+///    message: String
+///}
+///
+///fn greet(context: Context) -> Result<Json<Greeting>, rustful::StatusCode> {
+///    Ok(Json(Greeting { message: "hello".to_owned() }))
+///}
+///# fn main() {}
+///```
+#[cfg(feature = "rustc_json_body")]
+pub struct Json<T>(pub T);
+
+#[cfg(feature = "rustc_json_body")]
+impl<T: Encodable> IntoResponse for Json<T> {
+    fn into_response(self, mut response: Response) {
+        match json::encode(&self.0) {
+            Ok(encoded) => send_with_type(
+                response,
+                Mime(TopLevel::Application, SubLevel::Json, vec![]),
+                encoded
+            ),
+            Err(_) => response.set_status(StatusCode::InternalServerError)
+        }
+    }
+}
+
+fn send_with_type<'d, D: Into<Data<'d>>>(mut response: Response, content_type: Mime, content: D) {
+    response.headers_mut().set(ContentType(content_type));
+    response.send(content);
+}
+
+fn text_plain() -> Mime {
+    Mime(TopLevel::Text, SubLevel::Plain, vec![(Attr::Charset, Value::Utf8)])
+}
+
+fn octet_stream() -> Mime {
+    Mime(TopLevel::Application, SubLevel::Ext("octet-stream".into()), vec![])
+}
 
 ///An interface for sending data to the client.
 ///
@@ -257,10 +430,13 @@ impl<'a> Into<Data<'a>> for &'a str {
 ///its size is known.
 pub struct Response<'a, 'b> {
     writer: Option<hyper::server::response::Response<'a>>,
-    filters: &'b [Box<ResponseFilter>],
+    filters: Vec<&'b ResponseFilter>,
     global: &'b Global,
+    received_at: Instant,
     filter_storage: Option<AnyMap>,
-    force_close: bool
+    force_close: bool,
+    head: bool,
+    panic_slot: Option<&'b RefCell<Option<hyper::server::response::Response<'a>>>>
 }
 
 impl<'a, 'b> Response<'a, 'b> {
@@ -270,17 +446,101 @@ impl<'a, 'b> Response<'a, 'b> {
         response: hyper::server::response::Response<'a>,
         filters: &'b [Box<ResponseFilter>],
         global: &'b Global,
-        force_close: bool
+        received_at: Instant,
+        force_close: bool,
+        head: bool
     ) -> Response<'a, 'b> {
         Response {
             writer: Some(response),
-            filters: filters,
+            filters: filters.iter().map(|filter| &**filter).collect(),
             global: global,
+            received_at: received_at,
             filter_storage: Some(AnyMap::new()),
-            force_close: force_close
+            force_close: force_close,
+            head: head,
+            panic_slot: None
         }
     }
 
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn push_filter(&mut self, filter: &'b ResponseFilter) {
+        self.filters.push(filter);
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn rescue_on_panic(&mut self, slot: &'b RefCell<Option<hyper::server::response::Response<'a>>>) {
+        self.panic_slot = Some(slot);
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn switch_protocols(mut self) -> Result<(), Error> {
+        let mut writer = self.writer.take().expect("response used after drop");
+        self.filter_storage.take();
+
+        //A `101 Switching Protocols` response can't have a body, so the
+        //response filters are bypassed.
+        *writer.status_mut() = StatusCode::SwitchingProtocols;
+        writer.headers_mut().remove::<ContentLength>();
+        writer.headers_mut().remove::<TransferEncoding>();
+        try!(writer.start()).end().map_err(Error::Io)
+    }
+
+    ///Close the connection after this response, even if it would otherwise
+    ///be kept alive. `Connection: close` will be sent to the client.
+    ///
+    ///This is useful after errors, such as failed authentication attempts,
+    ///where the client shouldn't be allowed to reuse the connection.
+    ///
+    ///```
+    ///use rustful::{Context, Response, StatusCode};
+    ///
+    ///fn my_handler(context: Context, mut response: Response) {
+    ///    if context.headers.get_raw("Authorization").is_none() {
+    ///        response.set_status(StatusCode::Unauthorized);
+    ///        response.close_connection();
+    ///    }
+    ///}
+    ///```
+    pub fn close_connection(&mut self) {
+        self.force_close = true;
+    }
+
+    ///Check if the connection will be closed after this response. This is
+    ///decided by the server's keep-alive settings and the request, since
+    ///HTTP/1.0 clients have to ask for `keep-alive`, unless
+    ///`close_connection` has been called.
+    pub fn will_close_connection(&self) -> bool {
+        self.force_close || self.headers().get::<Connection>().map_or(false, |connection| {
+            connection.contains(&ConnectionOption::Close)
+        })
+    }
+
+    ///Get the HTTP version of the response, which is the same as the
+    ///version of the request.
+    pub fn http_version(&self) -> HttpVersion {
+        self.writer.as_ref().expect("version accessed after drop").version
+    }
+
+    ///Get the time when the request was received. This is the same as
+    ///`Context::received_at`.
+    pub fn received_at(&self) -> Instant {
+        self.received_at
+    }
+
+    ///Get the time that has passed since the request was received.
+    pub fn elapsed(&self) -> Duration {
+        self.received_at.elapsed()
+    }
+
+    ///Check if this is a response to a `HEAD` request. The headers will be
+    ///sent as usual, but any body content will be discarded.
+    pub fn is_head(&self) -> bool {
+        self.head
+    }
+
     ///Get the current status code.
     pub fn status(&self) -> StatusCode {
         self.writer.as_ref().expect("status accessed after drop").status()
@@ -314,9 +574,52 @@ impl<'a, 'b> Response<'a, 'b> {
         self.filter_storage.as_mut().expect("filter storage mutably accessed after drop")
     }
 
+    ///Add a cookie to the response. Each cookie will be sent in its own
+    ///`Set-Cookie` header.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///use rustful::cookie::CookieBuilder;
+    ///
+    ///fn my_handler(context: Context, mut response: Response) {
+    ///    response.add_cookie(CookieBuilder::new("visited", "yes").path("/"));
+    ///    response.send("hello");
+    ///}
+    ///```
+    pub fn add_cookie(&mut self, cookie: CookieBuilder) {
+        let headers = self.headers_mut();
+        let mut cookies = headers.get_raw("Set-Cookie").map(|c| c.to_vec()).unwrap_or_else(Vec::new);
+        cookies.push(cookie.to_string().into_bytes());
+        headers.set_raw("Set-Cookie", cookies);
+    }
+
+    ///Redirect the client to `location` and finish the response with an
+    ///empty body.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///use rustful::response::Redirect;
+    ///
+    ///fn my_handler(context: Context, response: Response) {
+    ///    response.redirect(Redirect::SeeOther, "/somewhere/else");
+    ///}
+    ///```
+    pub fn redirect<L: Into<String>>(mut self, status: Redirect, location: L) {
+        self.set_status(status.into());
+        self.headers_mut().set(Location(location.into()));
+        self.send("");
+    }
+
     ///Send data to the client and finish the response, ignoring eventual
     ///errors. Use `try_send` to get error information.
     ///
+    ///The response is never chunked, since the whole body is known. The
+    ///`Content-Length` header is set to the length of the body, after it has
+    ///passed through the response filters, unless the handler has already
+    ///set it. Any `Transfer-Encoding` header is removed. Informational,
+    ///`204 No Content` and `304 Not Modified` responses are sent without a
+    ///`Content-Length`, unless a `304` has one from the handler.
+    ///
     ///```
     ///use rustful::{Context, Response};
     ///
@@ -358,15 +661,16 @@ impl<'a, 'b> Response<'a, 'b> {
             if self.force_close {
                 writer.headers_mut().set(Connection(vec![ConnectionOption::Close]));
             }
-            writer.send(content.into().as_bytes()).map_err(|e| e.into())
+            send_body(writer, content.into().as_bytes(), self.head)
         } else {
             let mut buffer = vec![];
 
             let (status, write_queue) = try!(filter_headers(
-                self.filters,
+                &self.filters,
                 writer.status(),
                 writer.headers_mut(),
                 self.global,
+                self.received_at,
                 &mut filter_storage
             ));
             if self.force_close {
@@ -382,14 +686,14 @@ impl<'a, 'b> Response<'a, 'b> {
                 }
             }
 
-            let filter_result = filter_content(self.filters, content, self.global, &mut filter_storage);
+            let filter_result = filter_content(&self.filters, content, self.global, self.received_at, &mut filter_storage);
             match filter_result {
                 Action::Next(Some(content)) => buffer.push_bytes(content.as_bytes()),
                 Action::Abort(e) => return Err(Error::Filter(e)),
                 _ => {}
             }
 
-            let write_queue = try!(filter_end(self.filters, self.global, &mut filter_storage));
+            let write_queue = try!(filter_end(&self.filters, self.global, self.received_at, &mut filter_storage));
             for action in write_queue {
                 match action {
                     Action::Next(Some(content)) => buffer.push_bytes(content.as_bytes()),
@@ -398,8 +702,8 @@ impl<'a, 'b> Response<'a, 'b> {
                     Action::SilentAbort => break
                 }
             }
-            
-            writer.send(&buffer).map_err(|e| e.into())
+
+            send_body(writer, &buffer, self.head)
         }
     }
 
@@ -519,7 +823,7 @@ impl<'a, 'b> Response<'a, 'b> {
         let mime = path
             .extension()
             .and_then(|ext| to_mime(&ext.to_string_lossy()))
-            .unwrap_or_else(|| Mime(TopLevel::Application, SubLevel::Ext("octet-stream".into()), vec![]));
+            .unwrap_or_else(octet_stream);
 
         let mut file = match File::open(path) {
             Ok(file) => file,
@@ -537,6 +841,68 @@ impl<'a, 'b> Response<'a, 'b> {
         io::copy(&mut file, &mut writer).map_err(FileError::Send).map(|_| ())
     }
 
+    ///Stream the content of `reader` to the client and finish the response.
+    ///
+    ///The content is sent as a `Chunked` response, so the length doesn't
+    ///have to be known beforehand and the response filters are applied as
+    ///usual. It's read in blocks of up to 8 kB, where each block is written
+    ///before the next one is read, so a slow client will also slow down the
+    ///reading. An error is returned if either reading or writing fails.
+    ///
+    ///```
+    ///use std::io::Cursor;
+    ///use rustful::{Context, Response};
+    ///
+    ///fn my_handler(context: Context, response: Response) {
+    ///    let content = Cursor::new(b"streamed from a reader".to_vec());
+    ///    response.send_reader(content).unwrap_or(());
+    ///}
+    ///```
+    pub fn send_reader<R: Read>(self, mut reader: R) -> Result<(), Error> {
+        let mut chunked = self.into_chunked();
+        let mut buffer = [0; 8 * 1024];
+
+        loop {
+            let length = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(length) => length,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(Error::Io(e))
+            };
+
+            try!(chunked.try_send(&buffer[..length]));
+        }
+
+        chunked.end()
+    }
+
+    ///Send each item from `chunks` to the client and finish the response.
+    ///
+    ///The items are sent as a `Chunked` response, one chunk each, and the
+    ///next item is not requested until the previous one has been written.
+    ///Sending stops at the first error.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///fn my_handler(context: Context, response: Response) {
+    ///    let lines = (1..4).map(|i| format!("line {}\n", i));
+    ///    response.send_iter(lines).unwrap_or(());
+    ///}
+    ///```
+    pub fn send_iter<'d, I, Content>(self, chunks: I) -> Result<(), Error> where
+        I: IntoIterator<Item=Content>,
+        Content: Into<Data<'d>>
+    {
+        let mut chunked = self.into_chunked();
+
+        for chunk in chunks {
+            try!(chunked.try_send(chunk));
+        }
+
+        chunked.end()
+    }
+
     ///Write the status code and headers to the client and turn the `Response`
     ///into a `Chunked` response.
     pub fn into_chunked(mut self) -> Chunked<'a, 'b> {
@@ -547,17 +913,22 @@ impl<'a, 'b> Response<'a, 'b> {
         writer.headers_mut().remove_raw("content-length");
 
         let writer = filter_headers(
-            self.filters,
+            &self.filters,
             writer.status(),
             writer.headers_mut(),
             self.global,
-            self.filter_storage_mut()
+            self.received_at,
+            self.filter_storage.as_mut().expect("response used after drop")
         ).and_then(|(status, write_queue)|{
             if self.force_close {
                 writer.headers_mut().set(Connection(vec![ConnectionOption::Close]));
             }
             *writer.status_mut() = status;
-            let mut writer = try!(writer.start());
+            let mut writer = if self.head {
+                ChunkedWriter::Head(writer, 0)
+            } else {
+                ChunkedWriter::Streaming(try!(writer.start()))
+            };
 
             for action in write_queue {
                 match action {
@@ -573,12 +944,31 @@ impl<'a, 'b> Response<'a, 'b> {
 
         Chunked {
             writer: Some(writer),
-            filters: self.filters,
+            filters: std::mem::replace(&mut self.filters, vec![]),
             global: self.global,
+            received_at: self.received_at,
             filter_storage: self.filter_storage.take().expect("response used after drop")
         }
     }
 
+    ///Write the status code and headers to the client and turn the `Response`
+    ///into an `EventStream`, for sending Server-Sent Events.
+    ///
+    ///The `Content-Type` is set to `text/event-stream` and the response is
+    ///marked as uncacheable. Proxies are also asked to not buffer it, using
+    ///`X-Accel-Buffering: no`. See the [`sse`](../sse/index.html) module for
+    ///more details.
+    pub fn into_event_stream(mut self) -> EventStream<'a, 'b> {
+        {
+            let headers = self.headers_mut();
+            headers.set(ContentType(Mime(TopLevel::Text, SubLevel::Ext("event-stream".into()), vec![])));
+            headers.set(CacheControl(vec![CacheDirective::NoCache]));
+            headers.set_raw("X-Accel-Buffering", vec![b"no".to_vec()]);
+        }
+
+        EventStream::new(self.into_chunked())
+    }
+
     ///Write the status code and headers to the client and turn the `Response`
     ///into a `Raw` response. Any eventual response filters are bypassed to
     ///make sure that the data is not modified.
@@ -587,15 +977,17 @@ impl<'a, 'b> Response<'a, 'b> {
     ///possible to send responses that are too short.
     pub unsafe fn into_raw(mut self, content_length: u64) -> Raw<'a> {
         let mut writer = self.writer.take().expect("response used after drop");
+        check_body(writer.status(), false, None, content_length);
 
         if self.force_close {
             writer.headers_mut().set(Connection(vec![ConnectionOption::Close]));
         }
         writer.headers_mut().remove_raw("content-length");
-        writer.headers_mut().set(::header::ContentLength(content_length));
+        writer.headers_mut().set(ContentLength(content_length));
 
         Raw {
-            writer: Some(writer.start())
+            writer: Some(writer.start()),
+            head: self.head
         }
     }
 }
@@ -605,7 +997,17 @@ impl<'a, 'b> Drop for Response<'a, 'b> {
     ///Writes status code and headers and closes the connection.
     fn drop(&mut self) {
         if self.writer.is_some() {
-            self.send_sized(&[][..]);
+            if thread::panicking() {
+                //Hand the writer over to the server if it's able to send a
+                //proper error response. Otherwise, let hyper send an empty
+                //`500 Internal Server Error`.
+                let writer = self.writer.take();
+                if let Some(slot) = self.panic_slot {
+                    *slot.borrow_mut() = writer;
+                }
+            } else {
+                self.send_sized(&[][..]);
+            }
         }
     }
 }
@@ -617,9 +1019,10 @@ impl<'a, 'b> Drop for Response<'a, 'b> {
 ///This is useful for when the size of the data is unknown, but it comes with
 ///an overhead for each time `send` or `try_send` is called (simply put).
 pub struct Chunked<'a, 'b> {
-    writer: Option<Result<hyper::server::response::Response<'a, hyper::net::Streaming>, Error>>,
-    filters: &'b [Box<ResponseFilter>],
+    writer: Option<Result<ChunkedWriter<'a>, Error>>,
+    filters: Vec<&'b ResponseFilter>,
     global: &'b Global,
+    received_at: Instant,
     filter_storage: AnyMap
 }
 
@@ -689,7 +1092,7 @@ impl<'a, 'b> Chunked<'a, 'b> {
             } else { unreachable!(); }
         };
 
-        let filter_result = filter_content(self.filters, content, self.global, &mut self.filter_storage);
+        let filter_result = filter_content(&self.filters, content, self.global, self.received_at, &mut self.filter_storage);
 
         let write_result = match filter_result {
             Action::Next(Some(ref s)) => {
@@ -723,7 +1126,7 @@ impl<'a, 'b> Chunked<'a, 'b> {
 
     fn finish(&mut self) -> Result<(), Error> {
         let mut writer = try!(self.writer.take().expect("can only finish once"));
-        let write_queue = try!(filter_end(self.filters, self.global, &mut self.filter_storage));
+        let write_queue = try!(filter_end(&self.filters, self.global, self.received_at, &mut self.filter_storage));
 
         for action in write_queue {
             try!{
@@ -738,7 +1141,7 @@ impl<'a, 'b> Chunked<'a, 'b> {
         writer.end().map_err(Error::Io)
     }
 
-    fn borrow_writer(&mut self) -> Result<&mut hyper::server::response::Response<'a, hyper::net::Streaming>, Error> {
+    fn borrow_writer(&mut self) -> Result<&mut ChunkedWriter<'a>, Error> {
         match self.writer {
             Some(Ok(ref mut writer)) => Ok(writer),
             None => Err(Error::Io(io::Error::new(io::ErrorKind::BrokenPipe, "write after close"))),
@@ -782,7 +1185,8 @@ impl<'a, 'b> Drop for Chunked<'a, 'b> {
 ///__Unsafety__: The content length is set beforehand, which makes it possible
 ///to send responses that are too short.
 pub struct Raw<'a> {
-    writer: Option<Result<hyper::server::response::Response<'a, hyper::net::Streaming>, io::Error>>
+    writer: Option<Result<hyper::server::response::Response<'a, hyper::net::Streaming>, io::Error>>,
+    head: bool
 }
 
 impl<'a> Raw<'a> {
@@ -862,13 +1266,23 @@ impl<'a> Raw<'a> {
 
 impl<'a> Write for Raw<'a> {
     fn write(&mut self, content: &[u8]) -> io::Result<usize> {
+        let head = self.head;
         let mut writer = try!(self.borrow_writer());
-        writer.write(content)
+        if head {
+            Ok(content.len())
+        } else {
+            writer.write(content)
+        }
     }
 
     fn write_all(&mut self, content: &[u8]) -> io::Result<()> {
+        let head = self.head;
         let mut writer = try!(self.borrow_writer());
-        writer.write_all(content)
+        if head {
+            Ok(())
+        } else {
+            writer.write_all(content)
+        }
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -877,6 +1291,109 @@ impl<'a> Write for Raw<'a> {
     }
 }
 
+//The writer behind a `Chunked` response.
+enum ChunkedWriter<'a> {
+    Streaming(hyper::server::response::Response<'a, hyper::net::Streaming>),
+
+    //A response to a `HEAD` request is held back until the length of the
+    //discarded body is known.
+    Head(hyper::server::response::Response<'a>, u64)
+}
+
+impl<'a> ChunkedWriter<'a> {
+    fn end(self) -> io::Result<()> {
+        match self {
+            ChunkedWriter::Streaming(writer) => writer.end(),
+            ChunkedWriter::Head(mut writer, length) => {
+                writer.headers_mut().set(ContentLength(length));
+                try!(writer.start()).end()
+            }
+        }
+    }
+}
+
+impl<'a> Write for ChunkedWriter<'a> {
+    fn write(&mut self, content: &[u8]) -> io::Result<usize> {
+        match *self {
+            ChunkedWriter::Streaming(ref mut writer) => {
+                check_body(writer.status(), false, None, content.len() as u64);
+                writer.write(content)
+            },
+            ChunkedWriter::Head(ref writer, ref mut length) => {
+                *length += content.len() as u64;
+                check_body(writer.status(), true, None, *length);
+                Ok(content.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            ChunkedWriter::Streaming(ref mut writer) => writer.flush(),
+            ChunkedWriter::Head(..) => Ok(())
+        }
+    }
+}
+
+//Send a complete body, or only its length if it's a response to a `HEAD` request.
+fn send_body(mut writer: hyper::server::response::Response, content: &[u8], head: bool) -> Result<(), Error> {
+    let status = writer.status();
+    let declared = writer.headers().get::<ContentLength>().map(|length| length.0);
+    check_body(status, head, declared, content.len() as u64);
+
+    //The length is known, so any other framing has to go. A `Content-Length`
+    //from the handler is kept, which lets a `HEAD` handler describe the `GET`
+    //response without sending a body. Informational and `204 No Content`
+    //responses can't have one, and a `304 Not Modified` has no body of its
+    //own to measure.
+    writer.headers_mut().remove::<TransferEncoding>();
+    if status.is_informational() || status == StatusCode::NoContent {
+        writer.headers_mut().remove::<ContentLength>();
+    } else if status != StatusCode::NotModified && declared.is_none() {
+        writer.headers_mut().set(ContentLength(content.len() as u64));
+    }
+
+    if head {
+        try!(writer.start()).end().map_err(Error::Io)
+    } else {
+        writer.send(content).map_err(Error::Io)
+    }
+}
+
+//Panic if the response breaks the rules for its body, but only in debug
+//builds with the `response_checks` feature.
+fn check_body(status: StatusCode, head: bool, declared: Option<u64>, length: u64) {
+    if cfg!(all(feature = "response_checks", debug_assertions)) {
+        if let Some(message) = body_violation(status, head, declared, length) {
+            panic!("invalid response: {}", message);
+        }
+    }
+}
+
+//Look for bodies where there shouldn't be any, and for a `Content-Length`
+//from the handler that doesn't match the body. An empty `HEAD` body may
+//declare any length, since it's describing the `GET` response.
+fn body_violation(status: StatusCode, head: bool, declared: Option<u64>, length: u64) -> Option<String> {
+    let no_body = status.is_informational() || status == StatusCode::NoContent || status == StatusCode::NotModified;
+    if length > 0 && no_body {
+        return Some(format!("a `{}` response can't have a body, but {} bytes were written to it", status, length));
+    }
+
+    match declared {
+        Some(declared) if head && length > 0 && declared != length => Some(format!(
+            "the `HEAD` response declares `Content-Length: {}`, but its body is {} bytes, which makes it differ from `GET`. Send the same body as for `GET` and it will be left out",
+            declared,
+            length
+        )),
+        Some(declared) if !head && !no_body && declared != length => Some(format!(
+            "the response declares `Content-Length: {}`, but its body is {} bytes",
+            declared,
+            length
+        )),
+        _ => None
+    }
+}
+
 fn response_to_io_result<T>(res:  Result<T, Error>) -> io::Result<T> {
     match res {
         Ok(v) => Ok(v),
@@ -886,10 +1403,11 @@ fn response_to_io_result<T>(res:  Result<T, Error>) -> io::Result<T> {
 }
 
 fn filter_headers<'a>(
-    filters: &'a [Box<ResponseFilter>],
+    filters: &'a [&'a ResponseFilter],
     status: StatusCode,
     headers: &mut Headers,
     global: &Global,
+    received_at: Instant,
     filter_storage: &mut AnyMap
 ) -> Result<(StatusCode, Vec<Action<'a>>), Error> {
     let mut write_queue = Vec::new();
@@ -905,6 +1423,7 @@ fn filter_headers<'a>(
                     let filter_context = FilterContext {
                         storage: filter_storage,
                         global: global,
+                        received_at: received_at,
                     };
                     filter.begin(filter_context, status, headers)
                 };
@@ -919,6 +1438,7 @@ fn filter_headers<'a>(
                                 let filter_context = FilterContext {
                                     storage: filter_storage,
                                     global: global,
+                                    received_at: received_at,
                                 };
                                 Some(filter.write(filter_context, content))
                             },
@@ -948,7 +1468,7 @@ fn filter_headers<'a>(
     }
 }
 
-fn filter_content<'a, 'd: 'a, Content: Into<Data<'d>>>(filters: &'a [Box<ResponseFilter>], content: Content, global: &Global, filter_storage: &mut AnyMap) -> Action<'a> {
+fn filter_content<'a, 'd: 'a, Content: Into<Data<'d>>>(filters: &'a [&'a ResponseFilter], content: Content, global: &Global, received_at: Instant, filter_storage: &mut AnyMap) -> Action<'a> {
     let mut filter_result = Action::next(Some(content));
 
     for filter in filters {
@@ -957,6 +1477,7 @@ fn filter_content<'a, 'd: 'a, Content: Into<Data<'d>>>(filters: &'a [Box<Respons
                 let filter_context = FilterContext {
                     storage: filter_storage,
                     global: global,
+                    received_at: received_at,
                 };
                 filter.write(filter_context, content)
             },
@@ -967,13 +1488,14 @@ fn filter_content<'a, 'd: 'a, Content: Into<Data<'d>>>(filters: &'a [Box<Respons
     filter_result
 }
 
-fn filter_end<'a>(filters: &'a [Box<ResponseFilter>], global: &Global, filter_storage: &mut AnyMap) -> Result<Vec<Action<'a>>, Error> {
+fn filter_end<'a>(filters: &'a [&'a ResponseFilter], global: &Global, received_at: Instant, filter_storage: &mut AnyMap) -> Result<Vec<Action<'a>>, Error> {
     let otuputs: Vec<_> = filters.into_iter()
         .rev()
         .map(|filter| {
             let filter_context = FilterContext {
                 storage: filter_storage,
                 global: global,
+                received_at: received_at,
             };
 
             filter.end(filter_context)
@@ -992,6 +1514,7 @@ fn filter_end<'a>(filters: &'a [Box<ResponseFilter>], global: &Global, filter_st
                 let filter_context = FilterContext {
                     storage: filter_storage,
                     global: global,
+                    received_at: received_at,
                 };
                 Some(filter.write(filter_context, content))
             },
@@ -1013,3 +1536,74 @@ fn filter_end<'a>(filters: &'a [Box<ResponseFilter>], global: &Global, filter_st
 
     Ok(write_queue)
 }
+
+#[cfg(test)]
+mod test {
+    use {Server, Context, Response, StatusCode, Method};
+    use header::ContentLength;
+    use testing::TestServer;
+    use super::body_violation;
+
+    #[test]
+    fn find_body_violations() {
+        assert!(body_violation(StatusCode::Ok, false, None, 5).is_none());
+        assert!(body_violation(StatusCode::Ok, false, Some(5), 5).is_none());
+        assert!(body_violation(StatusCode::Ok, false, Some(3), 5).is_some());
+        assert!(body_violation(StatusCode::Ok, true, None, 5).is_none());
+        assert!(body_violation(StatusCode::Ok, true, Some(5), 5).is_none());
+        assert!(body_violation(StatusCode::Ok, true, Some(1234), 0).is_none());
+        assert!(body_violation(StatusCode::Ok, true, Some(3), 5).is_some());
+
+        assert!(body_violation(StatusCode::NoContent, false, None, 0).is_none());
+        assert!(body_violation(StatusCode::NoContent, false, None, 5).is_some());
+        assert!(body_violation(StatusCode::NotModified, true, None, 5).is_some());
+        assert!(body_violation(StatusCode::NotModified, false, Some(5), 0).is_none());
+    }
+
+    #[test]
+    fn set_content_length() {
+        fn handler(context: Context, mut response: Response) {
+            match context.uri.as_utf8_path() {
+                Some("/declared") => {
+                    response.headers_mut().set(ContentLength(5));
+                    response.send("hello");
+                },
+                Some("/empty") => response.set_status(StatusCode::NoContent),
+                Some("/not_modified") => response.set_status(StatusCode::NotModified),
+                _ => response.send("hello")
+            }
+        }
+
+        let server = TestServer::new(Server::new(handler));
+
+        let response = server.get("/").unwrap();
+        assert_eq!(response.headers.get_raw("Content-Length"), Some(&[b"5".to_vec()][..]));
+        assert_eq!(response.body_str(), Some("hello"));
+
+        let response = server.get("/declared").unwrap();
+        assert_eq!(response.headers.get_raw("Content-Length"), Some(&[b"5".to_vec()][..]));
+        assert_eq!(response.body_str(), Some("hello"));
+
+        assert_eq!(server.get("/empty").unwrap().headers.get::<ContentLength>(), None);
+        assert_eq!(server.get("/not_modified").unwrap().headers.get::<ContentLength>(), None);
+    }
+
+    #[test]
+    fn keep_head_content_length() {
+        fn handler(context: Context, mut response: Response) {
+            if context.method == Method::Head {
+                response.headers_mut().set(ContentLength(1234));
+            } else {
+                response.send("hello");
+            }
+        }
+
+        let server = TestServer::new(Server::new(handler));
+
+        let response = server.request(b"HEAD / HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(response.headers.get::<ContentLength>(), Some(&ContentLength(1234)));
+
+        let response = server.request(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(response.headers.get::<ContentLength>(), Some(&ContentLength(5)));
+    }
+}
@@ -25,6 +25,14 @@ impl<T: Router> Router for MethodRouter<T> {
         }
     }
 
+    fn find_methods(&self, _route: &mut RouteState) -> Vec<Method> {
+        self.items.keys().cloned().collect()
+    }
+
+    fn supported_methods(&self) -> Vec<Method> {
+        self.items.keys().cloned().collect()
+    }
+
     fn hyperlinks<'a>(&'a self, base: Link<'a>) -> Vec<Link<'a>> {
         self.items.iter().flat_map(|(method, item)| {
             let mut link = base.clone();
@@ -125,6 +125,30 @@
 //!"a/b" -> no match
 //!```
 //!
+//!A variable sequence at the end of a route works as a catch-all, which is
+//!useful for serving file trees or proxying. The requested path is percent
+//!decoded before it's routed, so the captured value will contain the decoded
+//!bytes, including any slashes, as they were sent. Static routes are still
+//!preferred over the catch-all, so `"static/*path"` and `"static/index"` can
+//!be used together.
+//!
+//!##Constrained Variable Segments (:label(pattern))
+//!
+//!A variable segment may be restricted to only match segments that fit a
+//!regular expression, by adding the expression in parentheses after the label.
+//!The expression has to match the whole segment and it can't contain any
+//!`/`. Segments that doesn't match will be tried against the other routes, so
+//!`"users/:id(\\d+)"` and `"users/:name"` can be used side by side. The
+//!expressions are compiled when the route is inserted and an invalid
+//!expression will cause a panic.
+//!
+//!```text
+//!pattern = "a/:v(\d+)/b"
+//!"a/12/b" -> v = "12"
+//!"a/c/b" -> no match
+//!"a/12c/b" -> no match
+//!```
+//!
 //!# Router Composition
 //!
 //!The default tree router is actually a composition of three routers:
@@ -176,11 +200,13 @@ use std::iter::{Iterator, FlatMap, Peekable};
 use std::slice::Split;
 use std::ops::Deref;
 use std::marker::PhantomData;
+use std::time::Duration;
 use hyper::method::Method;
 
 use handler::Handler;
-use context::MaybeUtf8Owned;
+use context::{MaybeUtf8Owned, MaybeUtf8Slice};
 use context::hypermedia::Link;
+use filter::RouteFilters;
 
 pub use self::tree_router::TreeRouter;
 pub use self::method_router::MethodRouter;
@@ -198,7 +224,16 @@ pub struct Endpoint<'a, T: 'a> {
     ///the router implementation.
     pub variables: HashMap<MaybeUtf8Owned, MaybeUtf8Owned>,
     ///Any associated hyperlinks.
-    pub hyperlinks: Vec<Link<'a>>
+    pub hyperlinks: Vec<Link<'a>>,
+    ///Filters that are specific to the matching endpoint, from the outermost
+    ///to the innermost. These are applied after the global filters.
+    pub filters: Vec<&'a RouteFilters>,
+    ///The pattern of the matching route, such as `/users/:id`. May be
+    ///`None`, depending on the router implementation.
+    pub route: Option<MaybeUtf8Slice<'a>>,
+    ///A timeout for the matching route, that replaces the server's read and
+    ///write timeouts while the handler is running.
+    pub timeout: Option<Duration>
 }
 
 impl<'a, T> From<Option<&'a T>> for Endpoint<'a, T> {
@@ -206,7 +241,10 @@ impl<'a, T> From<Option<&'a T>> for Endpoint<'a, T> {
         Endpoint {
             handler: handler,
             variables: HashMap::new(),
-            hyperlinks: vec![]
+            hyperlinks: vec![],
+            filters: vec![],
+            route: None,
+            timeout: None
         }
     }
 }
@@ -234,6 +272,50 @@ pub trait Router: Send + Sync + 'static {
     ///Change the router as if it was placed under the provided route.
     fn prefix<'a, R: Into<InsertState<'a, I>>, I: Clone + Iterator<Item = &'a [u8]>>(&mut self, route: R);
 
+    ///Insert the same item for each of the HTTP methods in `methods`.
+    ///
+    ///```
+    ///use rustful::Method::{Get, Post};
+    ///use rustful::{Router, TreeRouter, Context, Response};
+    ///
+    ///fn form(context: Context, response: Response) {
+    ///    response.send("show or submit the form");
+    ///}
+    ///
+    ///let mut router = TreeRouter::new();
+    ///router.insert_methods(&[Get, Post], "/form", form as fn(Context, Response));
+    ///```
+    fn insert_methods<'a, R: Into<InsertState<'a, I>>, I: Clone + Iterator<Item = &'a [u8]>>(&mut self, methods: &[Method], route: R, item: Self::Handler) where Self::Handler: Clone {
+        let route = route.into();
+        for method in methods {
+            self.insert(method.clone(), route.clone(), item.clone());
+        }
+    }
+
+    ///Mount an other router under `prefix`. This is the same as
+    ///`insert_router`, and the routes in the mounted router will behave as if
+    ///they were written with the prefix. Variables in the prefix are
+    ///collected together with the variables in the mounted router, and a
+    ///leading or trailing `/` in the prefix makes no difference.
+    ///
+    ///```
+    ///use rustful::Method::Get;
+    ///use rustful::{Router, TreeRouter, Context, Response};
+    ///
+    ///fn show_user(context: Context, response: Response) {
+    ///    response.send("a user");
+    ///}
+    ///
+    ///let mut api_router = TreeRouter::new();
+    ///api_router.insert(Get, "/users/:id", show_user as fn(Context, Response));
+    ///
+    ///let mut router = TreeRouter::new();
+    ///router.mount("/api/v1", api_router);
+    ///```
+    fn mount<'a, R: Into<InsertState<'a, I>>, I: Clone + Iterator<Item = &'a [u8]>>(&mut self, prefix: R, router: Self) where Self: Sized {
+        self.insert_router(prefix, router);
+    }
+
     ///Merge this router with an other one, overwriting conflicting parts.
     fn merge(&mut self, other: Self) where Self: Sized {
         self.insert_router("", other);
@@ -242,6 +324,20 @@ pub trait Router: Send + Sync + 'static {
     ///Find and return the matching handler and variable values.
     fn find<'a>(&'a self, method: &Method, route: &mut RouteState) -> Endpoint<'a, Self::Handler>;
 
+    ///Find the HTTP methods that are available for a route. An empty list
+    ///means that the route is unknown, or that the router doesn't separate
+    ///handlers by method. The default implementation returns an empty list.
+    #[allow(unused_variables)]
+    fn find_methods(&self, route: &mut RouteState) -> Vec<Method> {
+        vec![]
+    }
+
+    ///List all of the HTTP methods that are available somewhere in the
+    ///router. The default implementation returns an empty list.
+    fn supported_methods(&self) -> Vec<Method> {
+        vec![]
+    }
+
     ///List all of the hyperlinks into this router, based on the provided base
     ///link. It's up to the router implementation to decide how deep to go.
     fn hyperlinks<'a>(&'a self, base: Link<'a>) -> Vec<Link<'a>>;
@@ -312,6 +408,22 @@ impl<T: Router> Router for Option<T> {
         }
     }
 
+    fn find_methods(&self, route: &mut RouteState) -> Vec<Method> {
+        if let Some(ref router) = *self {
+            router.find_methods(route)
+        } else {
+            vec![]
+        }
+    }
+
+    fn supported_methods(&self) -> Vec<Method> {
+        if let Some(ref router) = *self {
+            router.supported_methods()
+        } else {
+            vec![]
+        }
+    }
+
     fn hyperlinks<'a>(&'a self, base: Link<'a>) -> Vec<Link<'a>> {
         if let Some(ref router) = *self {
             router.hyperlinks(base)
@@ -436,6 +548,7 @@ impl<I: Iterator> Iterator for RouteIter<I> {
 pub struct InsertState<'a, I: Iterator<Item=&'a [u8]>> {
     route: Peekable<I>,
     variables: Vec<MaybeUtf8Owned>,
+    pattern: Vec<u8>,
     _p: PhantomData<&'a [u8]>,
 }
 
@@ -445,6 +558,16 @@ impl<'a, I: Iterator<Item=&'a [u8]>> InsertState<'a, I> {
         self.variables
     }
 
+    ///Get the route pattern of the parsed path, such as `/users/:id`. The
+    ///segments are always separated by a single `/` and the root is `/`.
+    pub fn pattern(&self) -> MaybeUtf8Owned {
+        if self.pattern.is_empty() {
+            "/".into()
+        } else {
+            self.pattern.clone().into()
+        }
+    }
+
     ///Check if there are no more segments.
     pub fn is_empty(&mut self) -> bool {
         self.route.peek().is_none()
@@ -456,8 +579,15 @@ impl<'a, I: Iterator<Item=&'a [u8]>> Iterator for InsertState<'a, I> {
 
     fn next(&mut self) -> Option<&'a [u8]> {
         self.route.next().map(|segment| {
+            self.pattern.push(b'/');
+            self.pattern.extend_from_slice(segment);
+
             match segment.get(0) {
-                Some(&b'*') | Some(&b':') => self.variables.push(segment[1..].to_owned().into()),
+                Some(&b'*') => self.variables.push(segment[1..].to_owned().into()),
+                Some(&b':') => {
+                    let (name, _) = split_constraint(&segment[1..]);
+                    self.variables.push(name.to_owned().into())
+                },
                 _ => {}
             }
             segment
@@ -470,11 +600,23 @@ impl<'a, R: Route<'a> + ?Sized> From<&'a R> for InsertState<'a, R::Segments> {
         InsertState {
             route: route.segments().peekable(),
             variables: vec![],
+            pattern: vec![],
             _p: PhantomData,
         }
     }
 }
 
+//Splits a variable label into its name and its pattern, if any.
+fn split_constraint(label: &[u8]) -> (&[u8], Option<&[u8]>) {
+    if label.last() == Some(&b')') {
+        if let Some(start) = label.iter().position(|&c| c == b'(') {
+            return (&label[..start], Some(&label[start + 1..label.len() - 1]));
+        }
+    }
+
+    (label, None)
+}
+
 ///A state object for routing.
 pub struct RouteState<'a> {
     route: Vec<&'a [u8]>,
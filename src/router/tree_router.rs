@@ -2,11 +2,16 @@ use std::collections::HashMap;
 use std::collections::hash_map::Entry::{Occupied, Vacant};
 use std::iter::{Iterator, IntoIterator, FromIterator};
 use std::ops::Deref;
+use std::str;
+use std::sync::Arc;
+use std::time::Duration;
 use hyper::method::Method;
+use regex::bytes::Regex;
 
 use router::{Router, Route, Endpoint, MethodRouter, InsertState, RouteState, Variables};
 use context::{MaybeUtf8Owned, MaybeUtf8Slice};
 use context::hypermedia::{Link, LinkSegment, SegmentType};
+use filter::RouteFilters;
 use handler::Handler;
 
 use self::Branch::{Static, Variable, Wildcard};
@@ -31,18 +36,84 @@ enum Branch {
 ///hyperlinks may or may not point to a handler.
 ///
 ///Hyperlinks has to be activated by setting `find_hyperlinks` to  `true`.
+///
+///Route specific filters can be added to any part of the tree, using
+///`insert_filters`, and they will be applied to every route in that part.
+///The same goes for timeouts, using `insert_timeout`.
 
 #[derive(Clone)]
 pub struct TreeRouter<T: Router + Default> {
     item: T,
     static_routes: HashMap<MaybeUtf8Owned, TreeRouter<T>>,
     variable_route: Option<Box<TreeRouter<T>>>,
+    constrained_routes: Vec<Constrained<T>>,
     wildcard_route: Option<Box<TreeRouter<T>>>,
+    filters: Vec<Arc<RouteFilters>>,
+    timeout: Option<Duration>,
     ///Should the router search for hyperlinks? Setting this to `true` may
     ///slow down endpoint search, but enables hyperlinks.
     pub find_hyperlinks: bool
 }
 
+//A node in a linked list of route filters and timeouts, stored in a `Vec`
+//during search.
+struct FilterLink<'a> {
+    filters: &'a [Arc<RouteFilters>],
+    timeout: Option<Duration>,
+    parent: Option<usize>
+}
+
+//Collects the filters in a linked list, from the outermost to the innermost.
+fn collect_filters<'a>(links: &[FilterLink<'a>], mut link: Option<usize>) -> Vec<&'a RouteFilters> {
+    let mut filters = vec![];
+
+    while let Some(index) = link {
+        let current = &links[index];
+        filters.extend(current.filters.iter().rev().map(|filters| &**filters));
+        link = current.parent;
+    }
+
+    filters.reverse();
+    filters
+}
+
+//Finds the innermost timeout in a linked list.
+fn find_timeout(links: &[FilterLink], mut link: Option<usize>) -> Option<Duration> {
+    while let Some(index) = link {
+        let current = &links[index];
+        if current.timeout.is_some() {
+            return current.timeout;
+        }
+        link = current.parent;
+    }
+
+    None
+}
+
+//A variable segment with a pattern. The pattern is anchored to match the
+//whole segment, while `source` is kept for comparing routes.
+#[derive(Clone)]
+struct Constrained<T: Router + Default> {
+    source: String,
+    pattern: Regex,
+    router: TreeRouter<T>
+}
+
+impl<T: Router + Default> Constrained<T> {
+    fn new(source: String) -> Constrained<T> {
+        let pattern = match Regex::new(&format!("^(?:{})$", source)) {
+            Ok(pattern) => pattern,
+            Err(e) => panic!("invalid route variable pattern '{}': {}", source, e)
+        };
+
+        Constrained {
+            source: source,
+            pattern: pattern,
+            router: TreeRouter::default()
+        }
+    }
+}
+
 impl<H: Handler> TreeRouter<MethodRouter<Variables<H>>> {
     ///Creates an empty `TreeRouter<MethodRouter<Variables<H>>>`, which is
     ///probably the most common composition. It will select handlers based on
@@ -56,13 +127,97 @@ impl<H: Handler> TreeRouter<MethodRouter<Variables<H>>> {
 
 
 impl<T: Router + Default> TreeRouter<T> {
+    ///Add filters to every route that starts with `route`, including `route`
+    ///itself. They will be applied after the global filters and after any
+    ///filters that were added to a shorter route.
+    ///
+    ///```
+    ///use rustful::Method::Get;
+    ///use rustful::{Router, TreeRouter, Context, Response};
+    ///use rustful::filter::{RouteFilters, ConditionalGet};
+    ///
+    ///fn show_settings(context: Context, response: Response) {
+    ///    response.send("all the settings");
+    ///}
+    ///
+    ///let mut router = TreeRouter::new();
+    ///router.insert(Get, "/admin/settings", show_settings as fn(Context, Response));
+    ///router.insert_filters("/admin", RouteFilters {
+    ///    context_filters: vec![Box::new(ConditionalGet)],
+    ///    response_filters: vec![Box::new(ConditionalGet)]
+    ///});
+    ///```
+    pub fn insert_filters<'a, R: Into<InsertState<'a, I>>, I: Iterator<Item = &'a [u8]>>(&mut self, route: R, filters: RouteFilters) {
+        let mut route = route.into();
+        let endpoint = (&mut route).fold(self, |endpoint, segment| {
+            endpoint.find_or_insert_router(segment)
+        });
+
+        endpoint.filters.push(Arc::new(filters));
+    }
+
+    ///Set a timeout for every route that starts with `route`, including
+    ///`route` itself. It replaces the server's read and write timeouts while
+    ///the handler is running, as well as any timeout that was set for a
+    ///shorter route. This makes it possible to give a few slow routes more
+    ///time, or a few sensitive routes less time.
+    ///
+    ///```
+    ///use std::time::Duration;
+    ///use rustful::Method::Get;
+    ///use rustful::{Router, TreeRouter, Context, Response};
+    ///
+    ///fn generate_report(context: Context, response: Response) {
+    ///    response.send("a long report");
+    ///}
+    ///
+    ///let mut router = TreeRouter::new();
+    ///router.insert(Get, "/reports/:name", generate_report as fn(Context, Response));
+    ///router.insert_timeout("/reports", Duration::from_secs(300));
+    ///```
+    pub fn insert_timeout<'a, R: Into<InsertState<'a, I>>, I: Iterator<Item = &'a [u8]>>(&mut self, route: R, timeout: Duration) {
+        let mut route = route.into();
+        let endpoint = (&mut route).fold(self, |endpoint, segment| {
+            endpoint.find_or_insert_router(segment)
+        });
+
+        endpoint.timeout = Some(timeout);
+    }
+
+    //Links the filters and the timeout in this node to `parent`, if there
+    //are any.
+    fn link_filters<'a>(&'a self, parent: Option<usize>, links: &mut Vec<FilterLink<'a>>) -> Option<usize> {
+        if self.filters.is_empty() && self.timeout.is_none() {
+            parent
+        } else {
+            links.push(FilterLink {
+                filters: &self.filters,
+                timeout: self.timeout,
+                parent: parent
+            });
+            Some(links.len() - 1)
+        }
+    }
+
     //Tries to find a router matching the key or inserts a new one if none exists.
     fn find_or_insert_router<'a>(&'a mut self, key: &[u8]) -> &'a mut TreeRouter<T> {
+        let constraint = match key.get(0) {
+            Some(&b':') => super::split_constraint(&key[1..]).1,
+            _ => None
+        };
+
         if let Some(&b'*') = key.get(0) {
             if self.wildcard_route.is_none() {
                 self.wildcard_route = Some(Box::new(TreeRouter::default()));
             }
             &mut **self.wildcard_route.as_mut::<'a>().unwrap()
+        } else if let Some(source) = constraint {
+            let source = match str::from_utf8(source) {
+                Ok(source) => source,
+                Err(_) => panic!("route variable patterns must be valid UTF-8")
+            };
+
+            self.find_or_insert_constrained(source)
         } else if let Some(&b':') = key.get(0) {
             if self.variable_route.is_none() {
                 self.variable_route = Some(Box::new(TreeRouter::default()));
@@ -76,9 +231,26 @@ impl<T: Router + Default> TreeRouter<T> {
         }
     }
 
+    //Tries to find a constrained router with the same pattern or inserts a new one.
+    fn find_or_insert_constrained(&mut self, source: &str) -> &mut TreeRouter<T> {
+        let index = match self.constrained_routes.iter().position(|route| route.source == source) {
+            Some(index) => index,
+            None => {
+                self.constrained_routes.push(Constrained::new(source.to_owned()));
+                self.constrained_routes.len() - 1
+            }
+        };
+
+        &mut self.constrained_routes[index].router
+    }
+
     //Mergers this TreeRouter with an other TreeRouter.
     fn merge_router<'a, I: Iterator<Item = &'a [u8]> + Clone>(&mut self, state: InsertState<'a, I>, router: TreeRouter<T>) {
         self.item.insert_router(state.clone(), router.item);
+        self.filters.extend(router.filters);
+        if router.timeout.is_some() {
+            self.timeout = router.timeout;
+        }
 
         for (key, router) in router.static_routes {
             let next = match self.static_routes.entry(key.clone()) {
@@ -98,6 +270,10 @@ impl<T: Router + Default> TreeRouter<T> {
             }
         }
 
+        for route in router.constrained_routes {
+            self.find_or_insert_constrained(&route.source).merge_router(state.clone(), route.router);
+        }
+
         if let Some(router) = router.wildcard_route {
             if self.wildcard_route.is_none() {
                 self.wildcard_route = Some(Box::new(TreeRouter::default()));
@@ -108,6 +284,70 @@ impl<T: Router + Default> TreeRouter<T> {
             }
         }
     }
+
+    //Pushes the possible continuations of `branch` onto the search stack.
+    fn branch_out<'a>(&'a self, branch: Branch, snapshot: (usize, usize), link: Option<usize>, segment: &[u8], route: &mut RouteState, search: &mut Search<'a, T>) {
+        match branch {
+            Static => {
+                self.static_routes.get(segment).map(|next| {
+                    route.skip();
+                    search.push_children(next, route.snapshot(), link);
+                });
+            },
+            Variable => {
+                //The unconstrained route is pushed first, to be tried last.
+                let unconstrained = self.variable_route.as_ref().map(|next| &**next);
+                let constrained = self.constrained_routes.iter().rev()
+                    .filter(|next| next.pattern.is_match(segment))
+                    .map(|next| &next.router);
+                let mut nexts = unconstrained.into_iter().chain(constrained).peekable();
+
+                if nexts.peek().is_some() {
+                    route.keep();
+                    let snapshot = route.snapshot();
+                    for next in nexts {
+                        search.push_children(next, snapshot, link);
+                    }
+                }
+            },
+            Wildcard => {
+                self.wildcard_route.as_ref().map(|next| {
+                    route.fuse();
+                    let s = route.snapshot();
+                    search.stack.push((self, Wildcard, s, link));
+                    route.go_to(snapshot);
+
+                    route.keep();
+                    search.push_children(next, route.snapshot(), link);
+                });
+            }
+        }
+    }
+}
+
+//The state of a search through the tree.
+struct Search<'a, T: Router + Default + 'a> {
+    stack: Vec<(&'a TreeRouter<T>, Branch, (usize, usize), Option<usize>)>,
+    filters: Vec<FilterLink<'a>>
+}
+
+impl<'a, T: Router + Default> Search<'a, T> {
+    fn new(root: &'a TreeRouter<T>, snapshot: (usize, usize)) -> Search<'a, T> {
+        let mut search = Search {
+            stack: vec![],
+            filters: vec![]
+        };
+        search.push_children(root, snapshot, None);
+        search
+    }
+
+    //Pushes the branches of `next` onto the stack, in reverse search order.
+    fn push_children(&mut self, next: &'a TreeRouter<T>, snapshot: (usize, usize), parent: Option<usize>) {
+        let link = next.link_filters(parent, &mut self.filters);
+        self.stack.push((next, Wildcard, snapshot, link));
+        self.stack.push((next, Variable, snapshot, link));
+        self.stack.push((next, Static, snapshot, link));
+    }
 }
 
 impl<T: Router + Default> Router for TreeRouter<T> {
@@ -115,17 +355,21 @@ impl<T: Router + Default> Router for TreeRouter<T> {
 
     fn find<'a>(&'a self, method: &Method, route: &mut RouteState) -> Endpoint<'a, Self::Handler> {
         let now = route.snapshot();
-        let mut stack = vec![(self, Wildcard, now), (self, Variable, now), (self, Static, now)];
+        let mut search = Search::new(self, now);
 
         let mut result: Endpoint<Self::Handler> = None.into();
 
-        while let Some((current, branch, snapshot)) = stack.pop() {
+        while let Some((current, branch, snapshot, link)) = search.stack.pop() {
             route.go_to(snapshot);
             if route.is_empty() && result.handler.is_none() {
                 let endpoint = current.item.find(&method, route);
                 if endpoint.handler.is_some() {
                     result.handler = endpoint.handler;
                     result.variables = endpoint.variables;
+                    result.route = endpoint.route;
+                    result.filters = collect_filters(&search.filters, link);
+                    result.filters.extend(endpoint.filters);
+                    result.timeout = endpoint.timeout.or_else(|| find_timeout(&search.filters, link));
                     if !self.find_hyperlinks {
                         return result;
                     }
@@ -158,7 +402,7 @@ impl<T: Router + Default> Router for TreeRouter<T> {
                         });
                     }
 
-                    if let Some(ref _next) = current.variable_route {
+                    if current.variable_route.is_some() || !current.constrained_routes.is_empty() {
                         result.hyperlinks.push(Link {
                             method: None,
                             path: vec![LinkSegment {
@@ -181,40 +425,7 @@ impl<T: Router + Default> Router for TreeRouter<T> {
                     }
                 }
             } else if let Some(segment) = route.get() {
-                match branch {
-                    Static => {
-                        current.static_routes.get(segment).map(|next| {
-                            route.skip();
-                            let snapshot = route.snapshot();
-                            stack.push((next, Wildcard, snapshot));
-                            stack.push((next, Variable, snapshot));
-                            stack.push((next, Static, snapshot));
-                        });
-                    },
-                    Variable => {
-                        current.variable_route.as_ref().map(|next| {
-                            route.keep();
-                            let snapshot = route.snapshot();
-                            stack.push((next, Wildcard, snapshot));
-                            stack.push((next, Variable, snapshot));
-                            stack.push((next, Static, snapshot));
-                        });
-                    },
-                    Wildcard => {
-                        current.wildcard_route.as_ref().map(|next| {
-                            route.fuse();
-                            let s = route.snapshot();
-                            stack.push((current, Wildcard, s));
-                            route.go_to(snapshot);
-
-                            route.keep();
-                            let snapshot = route.snapshot();
-                            stack.push((next, Wildcard, snapshot));
-                            stack.push((next, Variable, snapshot));
-                            stack.push((next, Static, snapshot));
-                        });
-                    }
-                }
+                current.branch_out(branch, snapshot, link, segment, route, &mut search);
             }
         }
 
@@ -233,7 +444,7 @@ impl<T: Router + Default> Router for TreeRouter<T> {
             links.push(link);
         }
 
-        if let Some(ref _next) = self.variable_route {
+        if self.variable_route.is_some() || !self.constrained_routes.is_empty() {
             let mut link = base.clone();
             link.path.push(LinkSegment {
                 label: MaybeUtf8Slice::new(),
@@ -301,12 +512,60 @@ impl<T: Router + Default> Router for TreeRouter<T> {
             router.prefix(route.clone());
         }
 
+        for constrained in &mut self.constrained_routes {
+            constrained.router.prefix(route.clone());
+        }
+
         if let Some(router) = self.wildcard_route.as_mut() {
             router.prefix(route.clone());
         }
 
         self.item.prefix(route);
     }
+
+    fn find_methods(&self, route: &mut RouteState) -> Vec<Method> {
+        let now = route.snapshot();
+        let mut search = Search::new(self, now);
+
+        let mut methods = vec![];
+
+        while let Some((current, branch, snapshot, link)) = search.stack.pop() {
+            route.go_to(snapshot);
+            if route.is_empty() {
+                //Each node is visited once per branch, so only check it once.
+                if branch == Static {
+                    for method in current.item.find_methods(route) {
+                        if !methods.contains(&method) {
+                            methods.push(method);
+                        }
+                    }
+                }
+            } else if let Some(segment) = route.get() {
+                current.branch_out(branch, snapshot, link, segment, route, &mut search);
+            }
+        }
+
+        methods
+    }
+
+    fn supported_methods(&self) -> Vec<Method> {
+        let mut methods = self.item.supported_methods();
+
+        let children = self.static_routes.values()
+            .chain(self.variable_route.as_ref().map(|r| &**r))
+            .chain(self.constrained_routes.iter().map(|r| &r.router))
+            .chain(self.wildcard_route.as_ref().map(|r| &**r));
+
+        for child in children {
+            for method in child.supported_methods() {
+                if !methods.contains(&method) {
+                    methods.push(method);
+                }
+            }
+        }
+
+        methods
+    }
 }
 
 impl<T: Handler, D: Deref<Target=R>, R: ?Sized + for<'a> Route<'a>> FromIterator<(Method, D, T)> for TreeRouter<MethodRouter<Variables<T>>> {
@@ -360,7 +619,10 @@ impl<T: Default + Router> Default for TreeRouter<T> {
             item: T::default(),
             static_routes: HashMap::new(),
             variable_route: None,
+            constrained_routes: vec![],
             wildcard_route: None,
+            filters: vec![],
+            timeout: None,
             find_hyperlinks: false
         }
     }
@@ -581,6 +843,23 @@ mod test {
         check!(router(&Get, b"path/to/test1/no") => None, [[:""]]);
     }
 
+    #[test]
+    fn constrained_variable_routes() {
+        let routes = vec![
+            (Get, r"users/:id(\d+)", "by_id".into()),
+            (Get, r"users/:name([a-z]+)/posts", "posts".into()),
+            (Get, "users/:name", "by_name".into())
+        ];
+
+        let router = routes.into_iter().collect::<TreeRouter<_>>();
+
+        check!(router(&Get, b"users/42") => Some("by_id"), {"id" => "42"});
+        check!(router(&Get, b"users/alice") => Some("by_name"), {"name" => "alice"});
+        check!(router(&Get, b"users/42a") => Some("by_name"), {"name" => "42a"});
+        check!(router(&Get, b"users/alice/posts") => Some("posts"), {"name" => "alice"});
+        check!(router(&Get, b"users/Alice/posts") => None);
+    }
+
     #[test]
     fn one_wildcard_end_route() {
         let routes = vec![(Get, "path/to/*tail", "test 1".into())];
@@ -595,6 +874,22 @@ mod test {
         check!(router(&Get, b"path") => None, [["to"]]);
     }
 
+    #[test]
+    fn catch_all_route() {
+        let routes = vec![
+            (Get, "static/*path", "files".into()),
+            (Get, "static/index", "index".into())
+        ];
+
+        let router = routes.into_iter().collect::<TreeRouter<_>>();
+
+        check!(router(&Get, b"static/index") => Some("index"));
+        check!(router(&Get, b"static/index/more") => Some("files"), {"path" => "index/more"});
+        check!(router(&Get, b"static/my files/\xC3\xA5.txt") => Some("files"), {"path" => "my files/\u{e5}.txt"});
+        check!(router(&Get, b"static/a//b") => Some("files"), {"path" => "a//b"});
+        check!(router(&Get, b"static") => None);
+    }
+
 
     #[test]
     fn one_wildcard_middle_route() {
@@ -697,6 +992,48 @@ mod test {
         check!(router(&Head, b"/") => None, [[./Get], [./Post], [./Delete], [./Put]]);
     }
 
+    #[test]
+    fn find_methods() {
+        let routes = vec![
+            (Get, "path/to/test", "get".into()),
+            (Post, "path/:a/test", "post".into()),
+            (Delete, "path/*", "delete".into())
+        ];
+
+        let router = routes.into_iter().collect::<TreeRouter<_>>();
+
+        let methods = router.find_methods(&mut (&b"path/to/test"[..]).into());
+        assert_eq!(methods.len(), 3);
+        assert!(methods.contains(&Get));
+        assert!(methods.contains(&Post));
+        assert!(methods.contains(&Delete));
+
+        let methods = router.find_methods(&mut (&b"path/from/test"[..]).into());
+        assert_eq!(methods.len(), 2);
+        assert!(methods.contains(&Post));
+        assert!(methods.contains(&Delete));
+
+        assert!(router.find_methods(&mut (&b"other/path"[..]).into()).is_empty());
+    }
+
+    #[test]
+    fn insert_several_methods() {
+        let mut router = TreeRouter::new();
+        router.insert_methods(&[Get, Post], "path/:a", TestHandler::from("both"));
+        router.insert(Delete, "path/:a", TestHandler::from("delete"));
+
+        check!(router(&Get, b"path/to") => Some("both"), {"a" => "to"});
+        check!(router(&Post, b"path/to") => Some("both"), {"a" => "to"});
+        check!(router(&Delete, b"path/to") => Some("delete"), {"a" => "to"});
+        check!(router(&Put, b"path/to") => None);
+
+        let methods = router.find_methods(&mut (&b"path/to"[..]).into());
+        assert_eq!(methods.len(), 3);
+        assert!(methods.contains(&Get));
+        assert!(methods.contains(&Post));
+        assert!(methods.contains(&Delete));
+    }
+
     #[test]
     fn merge_routers() {
         let routes1 = vec![
@@ -760,6 +1097,106 @@ mod test {
         check!(router1(&Get, b"path") => None, [["to"], [*""]]);
     }
 
+    #[test]
+    fn mount_router() {
+        let routes1 = vec![(Get, "/", "root".into())];
+        let routes2 = vec![
+            (Get, "/", "api".into()),
+            (Get, "users/:id", "user".into()),
+            (Get, "files/*path", "files".into())
+        ];
+
+        let mut router1 = routes1.into_iter().collect::<TreeRouter<_>>();
+        router1.find_hyperlinks = true;
+        let router2 = routes2.into_iter().collect::<TreeRouter<_>>();
+
+        router1.mount("/api/:version/", router2);
+
+        check!(router1(&Get, b"/") => Some("root"), [["api"]]);
+        check!(router1(&Get, b"api/v1") => Some("api"), {"version" => "v1"}, [["users"], ["files"]]);
+        check!(router1(&Get, b"api/v1/users/12") => Some("user"), {"version" => "v1", "id" => "12"});
+        check!(router1(&Get, b"api/v2/files/a/b") => Some("files"), {"version" => "v2", "path" => "a/b"});
+        check!(router1(&Get, b"users/12") => None);
+    }
+
+    #[test]
+    fn route_patterns() {
+        let routes1 = vec![
+            (Get, "/", "root".into()),
+            (Get, "users/:id/", "user".into()),
+            (Get, "files/*path", "files".into())
+        ];
+        let routes2 = vec![
+            (Get, "/", "api".into()),
+            (Get, "status", "status".into())
+        ];
+
+        let mut router = routes1.into_iter().collect::<TreeRouter<_>>();
+        router.mount("/api/:version", routes2.into_iter().collect());
+
+        let route = |path: &'static str| router.find(&Get, &mut path.into()).route.map(|r| r.as_utf8_lossy().into_owned());
+
+        assert_eq!(route("/"), Some("/".to_owned()));
+        assert_eq!(route("/users/12"), Some("/users/:id".to_owned()));
+        assert_eq!(route("/files/a/b"), Some("/files/*path".to_owned()));
+        assert_eq!(route("/api/v1"), Some("/api/:version".to_owned()));
+        assert_eq!(route("/api/v1/status"), Some("/api/:version/status".to_owned()));
+        assert_eq!(route("/nothing"), None);
+    }
+
+    #[test]
+    fn route_filters() {
+        use filter::{RouteFilters, ConditionalGet};
+
+        fn filters(count: usize) -> RouteFilters {
+            RouteFilters {
+                context_filters: (0..count).map(|_| Box::new(ConditionalGet) as Box<_>).collect(),
+                response_filters: vec![]
+            }
+        }
+
+        let routes = vec![
+            (Get, "public", "public".into()),
+            (Get, "admin", "admin".into()),
+            (Get, "admin/users/:id", "user".into())
+        ];
+
+        let mut router = routes.into_iter().collect::<TreeRouter<_>>();
+        router.insert_filters("admin", filters(1));
+        router.insert_filters("admin/users", filters(2));
+
+        let count = |path: &[u8]| {
+            let endpoint = router.find(&Get, &mut path.into());
+            endpoint.filters.iter().map(|f| f.context_filters.len()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(count(b"public"), Vec::<usize>::new());
+        assert_eq!(count(b"admin"), vec![1]);
+        assert_eq!(count(b"admin/users/12"), vec![1, 2]);
+        assert_eq!(count(b"admin/other"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn route_timeouts() {
+        use std::time::Duration;
+
+        let routes = vec![
+            (Get, "public", "public".into()),
+            (Get, "reports/:name", "report".into()),
+            (Get, "reports/fast", "fast".into())
+        ];
+
+        let mut router = routes.into_iter().collect::<TreeRouter<_>>();
+        router.insert_timeout("reports", Duration::from_secs(300));
+        router.insert_timeout("reports/fast", Duration::from_secs(1));
+
+        let timeout = |path: &[u8]| router.find(&Get, &mut path.into()).timeout;
+
+        assert_eq!(timeout(b"public"), None);
+        assert_eq!(timeout(b"reports/monthly"), Some(Duration::from_secs(300)));
+        assert_eq!(timeout(b"reports/fast"), Some(Duration::from_secs(1)));
+    }
+
     #[test]
     fn prefix_router_variables() {
         let routes1 = vec![
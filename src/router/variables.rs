@@ -14,6 +14,7 @@ use {Method, Handler};
 pub struct Variables<H: Handler> {
     handler: H,
     variables: Vec<MaybeUtf8Owned>,
+    pattern: MaybeUtf8Owned,
 }
 
 impl<H: Handler> Router for Variables<H> {
@@ -24,6 +25,9 @@ impl<H: Handler> Router for Variables<H> {
             handler: Some(&self.handler),
             variables: route.variables(&self.variables),
             hyperlinks: vec![],
+            filters: vec![],
+            route: Some(self.pattern.as_slice()),
+            timeout: None,
         }
     }
 
@@ -33,15 +37,19 @@ impl<H: Handler> Router for Variables<H> {
     }
 
     fn build<'a, R: Into<InsertState<'a, I>>, I: Iterator<Item = &'a [u8]>>(_method: Method, route: R, item: Self::Handler) -> Variables<H> {
+        let route = route.into();
         Variables {
             handler: item,
-            variables: route.into().variables(),
+            pattern: route.pattern(),
+            variables: route.variables(),
         }
     }
 
     fn insert<'a, R: Into<InsertState<'a, I>>, I: Iterator<Item = &'a [u8]>>(&mut self, _method: Method, route: R, item: Self::Handler) {
+        let route = route.into();
         self.handler = item;
-        self.variables = route.into().variables();
+        self.pattern = route.pattern();
+        self.variables = route.variables();
     }
 
     fn insert_router<'a, R: Into<InsertState<'a, I>>, I: Clone + Iterator<Item = &'a [u8]>>(&mut self, route: R, mut router: Variables<H>) {
@@ -50,7 +58,18 @@ impl<H: Handler> Router for Variables<H> {
     }
 
     fn prefix<'a, R: Into<InsertState<'a, I>>, I: Clone + Iterator<Item = &'a [u8]>>(&mut self, route: R) {
-        let mut new_vars = route.into().variables();
+        let route = route.into();
+
+        let mut pattern = route.pattern();
+        if self.pattern.as_bytes() != b"/" {
+            if pattern.as_bytes() == b"/" {
+                pattern = MaybeUtf8Owned::new();
+            }
+            pattern.push_bytes(self.pattern.as_bytes());
+        }
+        self.pattern = pattern;
+
+        let mut new_vars = route.variables();
         new_vars.extend(self.variables.drain(..));
         self.variables = new_vars;
     }
@@ -61,6 +80,7 @@ impl<H: Handler + Default> Default for Variables<H> {
         Variables {
             handler: H::default(),
             variables: vec![],
+            pattern: "/".into(),
         }
     }
 }
@@ -0,0 +1,238 @@
+use std::time::Duration;
+
+use filter::{ContextFilter, ResponseFilter};
+use router::Router;
+use server::{Server, Host, Scheme, KeepAlive, Global, Listening, ServerError, WorkerInit, TlsConfig};
+use mime::Mime;
+use anymap::any::Any;
+
+
+///A builder for `Server`, with chained setters.
+///
+///It's an alternative to filling out a `Server` directly, which can be more
+///convenient when some settings depend on the environment. The result is an
+///ordinary `Server`, so any setting that doesn't have a method here can
+///still be changed afterwards.
+///
+///```no_run
+///use std::time::Duration;
+///use rustful::{Server, Context, Response};
+///use rustful::filter::RequestIds;
+///
+///# fn my_handler(context: Context, response: Response) {}
+///# let production = false;
+///let mut builder = Server::builder(my_handler)
+///    .host(8080.into())
+///    .threads(8)
+///    .timeout(Duration::from_secs(30))
+///    .push_context_filter(RequestIds::new())
+///    .push_response_filter(RequestIds::new());
+///
+///if production {
+///    builder = builder.server("");
+///}
+///
+///let server_result = builder.run();
+///```
+pub struct ServerBuilder<R: Router> {
+    server: Server<R>
+}
+
+impl<R: Router> ServerBuilder<R> {
+    ///Start building a server with the default settings, as in
+    ///`Server::new`.
+    pub fn new(handlers: R) -> ServerBuilder<R> {
+        ServerBuilder {
+            server: Server::new(handlers)
+        }
+    }
+
+    ///Set the host address and port where the server will listen.
+    pub fn host(mut self, host: Host) -> ServerBuilder<R> {
+        self.server.host = host;
+        self
+    }
+
+    ///Use HTTP or HTTPS.
+    pub fn scheme(mut self, scheme: Scheme) -> ServerBuilder<R> {
+        self.server.scheme = scheme;
+        self
+    }
+
+    ///Listen to an additional address, with its own scheme.
+    pub fn listener(mut self, host: Host, scheme: Scheme) -> ServerBuilder<R> {
+        self.server.listeners.push((host, scheme));
+        self
+    }
+
+    ///Set the TLS settings for the HTTPS listeners.
+    pub fn tls(mut self, tls: TlsConfig) -> ServerBuilder<R> {
+        self.server.tls = tls;
+        self
+    }
+
+    ///Set the number of threads in the server thread pool.
+    pub fn threads(mut self, threads: usize) -> ServerBuilder<R> {
+        self.server.threads = Some(threads);
+        self
+    }
+
+    ///Allow `keep-alive` connections.
+    pub fn keep_alive(mut self, keep_alive: KeepAlive) -> ServerBuilder<R> {
+        self.server.keep_alive = Some(keep_alive);
+        self
+    }
+
+    ///Set both the read and the write timeout.
+    pub fn timeout(mut self, timeout: Duration) -> ServerBuilder<R> {
+        self.server.read_timeout = Some(timeout);
+        self.server.write_timeout = Some(timeout);
+        self
+    }
+
+    ///Set the read timeout.
+    pub fn read_timeout(mut self, timeout: Duration) -> ServerBuilder<R> {
+        self.server.read_timeout = Some(timeout);
+        self
+    }
+
+    ///Set the write timeout.
+    pub fn write_timeout(mut self, timeout: Duration) -> ServerBuilder<R> {
+        self.server.write_timeout = Some(timeout);
+        self
+    }
+
+    ///Limit the number of open connections from the same client IP address.
+    pub fn max_connections_per_ip(mut self, limit: usize) -> ServerBuilder<R> {
+        self.server.max_connections_per_ip = Some(limit);
+        self
+    }
+
+    ///Set the maximum number of connections that may wait to be accepted.
+    pub fn backlog(mut self, backlog: u32) -> ServerBuilder<R> {
+        self.server.backlog = Some(backlog);
+        self
+    }
+
+    ///Enable or disable `TCP_NODELAY` for each connection.
+    pub fn tcp_nodelay(mut self, nodelay: bool) -> ServerBuilder<R> {
+        self.server.tcp_nodelay = nodelay;
+        self
+    }
+
+    ///Enable TCP keep-alive probes after the connection has been idle for
+    ///`idle`.
+    pub fn tcp_keep_alive(mut self, idle: Duration) -> ServerBuilder<R> {
+        self.server.tcp_keep_alive = Some(idle);
+        self
+    }
+
+    ///Limit the size of buffered request bodies.
+    pub fn max_body_size(mut self, size: u64) -> ServerBuilder<R> {
+        self.server.max_body_size = Some(size);
+        self
+    }
+
+    ///Limit the size of decoded request bodies.
+    pub fn max_decoded_body_size(mut self, size: u64) -> ServerBuilder<R> {
+        self.server.max_decoded_body_size = Some(size);
+        self
+    }
+
+    ///Limit the length of the request target.
+    pub fn max_uri_length(mut self, length: usize) -> ServerBuilder<R> {
+        self.server.max_uri_length = Some(length);
+        self
+    }
+
+    ///Start the server in the state where it answers every request with
+    ///`503 Service Unavailable`, until it's marked as ready with
+    ///`Shutdown::set_ready`.
+    pub fn start_unready(mut self) -> ServerBuilder<R> {
+        self.server.start_ready = false;
+        self
+    }
+
+    ///Set the `Retry-After` value of the `503 Service Unavailable` responses
+    ///that are sent while the server isn't ready, or shutting down.
+    pub fn retry_after(mut self, retry_after: Duration) -> ServerBuilder<R> {
+        self.server.retry_after = Some(retry_after);
+        self
+    }
+
+    ///Set the content of the `Server` header. An empty string will leave the
+    ///header out.
+    pub fn server<S: Into<String>>(mut self, server: S) -> ServerBuilder<R> {
+        self.server.server = server.into();
+        self
+    }
+
+    ///Set the default media type.
+    pub fn content_type(mut self, content_type: Mime) -> ServerBuilder<R> {
+        self.server.content_type = content_type;
+        self
+    }
+
+    ///Set the fallback handler.
+    pub fn fallback_handler(mut self, handler: R::Handler) -> ServerBuilder<R> {
+        self.server.fallback_handler = Some(handler);
+        self
+    }
+
+    ///Add handlers for a specific host name. The name is converted to lower
+    ///case.
+    pub fn host_handlers<S: AsRef<str>>(mut self, host_name: S, handlers: R) -> ServerBuilder<R> {
+        self.server.host_handlers.insert(host_name.as_ref().to_lowercase(), handlers);
+        self
+    }
+
+    ///Set the globally accessible data.
+    pub fn global<G: Into<Global>>(mut self, global: G) -> ServerBuilder<R> {
+        self.server.global = global.into();
+        self
+    }
+
+    ///Add a value to the globally accessible data. It will replace any
+    ///previous value of the same type. Handlers can get it back using
+    ///`Context::get_global`.
+    pub fn insert_global<T: Any + Send + Sync>(mut self, value: T) -> ServerBuilder<R> {
+        self.server.global.insert(value);
+        self
+    }
+
+    ///Set the function that creates the state for each worker thread.
+    pub fn worker_init<F: WorkerInit>(mut self, init: F) -> ServerBuilder<R> {
+        self.server.worker_init = Some(Box::new(init));
+        self
+    }
+
+    ///Add a filter to the end of the context filter stack.
+    pub fn push_context_filter<F: ContextFilter + 'static>(mut self, filter: F) -> ServerBuilder<R> {
+        self.server.context_filters.push(Box::new(filter));
+        self
+    }
+
+    ///Add a filter to the end of the response filter stack.
+    pub fn push_response_filter<F: ResponseFilter + 'static>(mut self, filter: F) -> ServerBuilder<R> {
+        self.server.response_filters.push(Box::new(filter));
+        self
+    }
+
+    ///Get the finished `Server`.
+    pub fn build(self) -> Server<R> {
+        self.server
+    }
+
+    ///Build and start the server.
+    pub fn run(self) -> Result<Listening, ServerError> {
+        self.server.run()
+    }
+}
+
+impl<R: Router> From<Server<R>> for ServerBuilder<R> {
+    fn from(server: Server<R>) -> ServerBuilder<R> {
+        ServerBuilder {
+            server: server
+        }
+    }
+}
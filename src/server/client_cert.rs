@@ -0,0 +1,271 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use hyper::net::NetworkStream;
+
+///The verified certificate of a TLS client.
+///
+///It's added to the request extensions when `TlsConfig::client_auth` asks
+///for client certificates and the client has sent one. The subject names are
+///read from the certificate, while anything else can be found by parsing
+///`der` with an X.509 library.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientCertificate {
+    ///The DER encoded certificate.
+    pub der: Vec<u8>,
+
+    ///The common name (`CN`) of the subject, if there is one.
+    pub common_name: Option<String>,
+
+    ///The DNS names, email addresses, URIs and IP addresses from the subject
+    ///alternative name extension.
+    pub alt_names: Vec<String>,
+}
+
+impl ClientCertificate {
+    ///Read the subject names from a DER encoded certificate. Names that
+    ///can't be found, or are malformed, are left out.
+    pub fn from_der(der: Vec<u8>) -> ClientCertificate {
+        let (common_name, alt_names) = subject_names(&der).unwrap_or((None, vec![]));
+        ClientCertificate {
+            der: der,
+            common_name: common_name,
+            alt_names: alt_names
+        }
+    }
+}
+
+//Find the client certificate of a TLS connection.
+#[cfg(feature = "ssl")]
+pub fn client_certificate(stream: &NetworkStream) -> Option<ClientCertificate> {
+    use hyper::net::HttpStream;
+    use openssl::ssl::SslStream;
+
+    stream.downcast_ref::<SslStream<HttpStream>>()
+        .and_then(|stream| stream.ssl().peer_certificate())
+        .and_then(|cert| cert.save_der().ok())
+        .map(ClientCertificate::from_der)
+}
+
+//Find the client certificate of a TLS connection.
+#[cfg(all(feature = "rustls", not(feature = "ssl")))]
+pub fn client_certificate(stream: &NetworkStream) -> Option<ClientCertificate> {
+    use server::TlsStream;
+
+    stream.downcast_ref::<TlsStream>()
+        .and_then(|stream| stream.peer_certificates())
+        .and_then(|certs| certs.into_iter().next())
+        .map(ClientCertificate::from_der)
+}
+
+#[cfg(not(any(feature = "ssl", feature = "rustls")))]
+pub fn client_certificate(_stream: &NetworkStream) -> Option<ClientCertificate> {
+    None
+}
+
+const COMMON_NAME: &'static [u8] = &[0x55, 0x04, 0x03];
+const SUBJECT_ALT_NAME: &'static [u8] = &[0x55, 0x1d, 0x11];
+
+//Read a DER element and return its tag, its content and the rest of the
+//data. Only definite lengths are allowed, as in DER.
+fn der_element(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    if data.len() < 2 {
+        return None;
+    }
+
+    let (length, start) = match data[1] {
+        length @ 0...0x7f => (length as usize, 2),
+        0x81...0x84 => {
+            let count = (data[1] & 0x7f) as usize;
+            if data.len() < 2 + count {
+                return None;
+            }
+            (data[2..2 + count].iter().fold(0, |length, &byte| (length << 8) | byte as usize), 2 + count)
+        },
+        _ => return None
+    };
+
+    if data.len() - start < length {
+        return None;
+    }
+
+    Some((data[0], &data[start..start + length], &data[start + length..]))
+}
+
+//Find the subject common name and alternative names, as described in
+//RFC 5280, section 4.1.
+fn subject_names(der: &[u8]) -> Option<(Option<String>, Vec<String>)> {
+    let certificate = match der_element(der) {
+        Some((0x30, certificate, _)) => certificate,
+        _ => return None
+    };
+
+    let mut fields = match der_element(certificate) {
+        Some((0x30, tbs_certificate, _)) => tbs_certificate,
+        _ => return None
+    };
+
+    //The version is optional
+    if let Some((0xa0, _, rest)) = der_element(fields) {
+        fields = rest;
+    }
+
+    //Skip the serial number, signature algorithm, issuer and validity
+    for _ in 0..4 {
+        fields = match der_element(fields) {
+            Some((_, _, rest)) => rest,
+            None => return None
+        };
+    }
+
+    let (subject, mut fields) = match der_element(fields) {
+        Some((0x30, subject, rest)) => (subject, rest),
+        _ => return None
+    };
+
+    let common_name = find_common_name(subject);
+    let mut alt_names = vec![];
+
+    //The extensions come last, after the public key and the optional
+    //unique identifiers.
+    while let Some((tag, field, rest)) = der_element(fields) {
+        if tag == 0xa3 {
+            if let Some((0x30, extensions, _)) = der_element(field) {
+                alt_names = find_alt_names(extensions);
+            }
+        }
+        fields = rest;
+    }
+
+    Some((common_name, alt_names))
+}
+
+fn find_common_name(mut names: &[u8]) -> Option<String> {
+    let mut common_name = None;
+
+    while let Some((_, set, rest)) = der_element(names) {
+        let mut attributes = set;
+        while let Some((_, attribute, next)) = der_element(attributes) {
+            if let Some((0x06, COMMON_NAME, value)) = der_element(attribute) {
+                if let Some((_, value, _)) = der_element(value) {
+                    common_name = Some(String::from_utf8_lossy(value).into_owned());
+                }
+            }
+            attributes = next;
+        }
+        names = rest;
+    }
+
+    common_name
+}
+
+fn find_alt_names(mut extensions: &[u8]) -> Vec<String> {
+    let mut alt_names = vec![];
+
+    while let Some((_, extension, rest)) = der_element(extensions) {
+        if let Some((0x06, SUBJECT_ALT_NAME, mut value)) = der_element(extension) {
+            //Skip the critical flag
+            if let Some((0x01, _, after)) = der_element(value) {
+                value = after;
+            }
+
+            if let Some((0x04, octets, _)) = der_element(value) {
+                if let Some((0x30, mut names, _)) = der_element(octets) {
+                    while let Some((tag, name, next)) = der_element(names) {
+                        match tag {
+                            //rfc822Name, dNSName and uniformResourceIdentifier
+                            0x81 | 0x82 | 0x86 => alt_names.push(String::from_utf8_lossy(name).into_owned()),
+                            //iPAddress
+                            0x87 => if let Some(ip) = ip_address(name) {
+                                alt_names.push(ip.to_string());
+                            },
+                            _ => {}
+                        }
+                        names = next;
+                    }
+                }
+            }
+        }
+        extensions = rest;
+    }
+
+    alt_names
+}
+
+fn ip_address(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => Some(IpAddr::V4(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]))),
+        16 => {
+            let mut segments = [0u16; 8];
+            for (segment, pair) in segments.iter_mut().zip(bytes.chunks(2)) {
+                *segment = ((pair[0] as u16) << 8) | pair[1] as u16;
+            }
+            Some(IpAddr::V6(Ipv6Addr::new(
+                segments[0], segments[1], segments[2], segments[3],
+                segments[4], segments[5], segments[6], segments[7]
+            )))
+        },
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ClientCertificate;
+
+    fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut element = vec![tag];
+        if content.len() < 0x80 {
+            element.push(content.len() as u8);
+        } else {
+            element.push(0x82);
+            element.push((content.len() >> 8) as u8);
+            element.push(content.len() as u8);
+        }
+        element.extend_from_slice(content);
+        element
+    }
+
+    fn name(common_name: &str) -> Vec<u8> {
+        let mut attribute = der(0x06, &[0x55, 0x04, 0x03]);
+        attribute.extend(der(0x0c, common_name.as_bytes()));
+        der(0x30, &der(0x31, &der(0x30, &attribute)))
+    }
+
+    #[test]
+    fn read_subject_names() {
+        let mut alt_names = der(0x82, b"client.example.com");
+        alt_names.extend(der(0x87, &[127, 0, 0, 1]));
+        alt_names.extend(der(0x81, b"client@example.com"));
+
+        let mut extension = der(0x06, &[0x55, 0x1d, 0x11]);
+        extension.extend(der(0x01, &[0xff]));
+        extension.extend(der(0x04, &der(0x30, &alt_names)));
+
+        let mut tbs = der(0xa0, &der(0x02, &[2]));
+        tbs.extend(der(0x02, &[1]));
+        tbs.extend(der(0x30, &der(0x06, &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02])));
+        tbs.extend(name("Example CA"));
+        tbs.extend(der(0x30, &[]));
+        tbs.extend(name("client"));
+        tbs.extend(der(0x30, &[0; 200]));
+        tbs.extend(der(0xa3, &der(0x30, &der(0x30, &extension))));
+
+        let mut certificate = der(0x30, &tbs);
+        certificate.extend(der(0x30, &[]));
+        certificate.extend(der(0x03, &[0]));
+        let certificate = der(0x30, &certificate);
+
+        let parsed = ClientCertificate::from_der(certificate.clone());
+        assert_eq!(parsed.der, certificate);
+        assert_eq!(parsed.common_name, Some("client".to_owned()));
+        assert_eq!(parsed.alt_names, vec![
+            "client.example.com".to_owned(),
+            "127.0.0.1".to_owned(),
+            "client@example.com".to_owned()
+        ]);
+
+        let broken = ClientCertificate::from_der(certificate[..certificate.len() - 1].to_vec());
+        assert_eq!(broken.common_name, None);
+        assert!(broken.alt_names.is_empty());
+    }
+}
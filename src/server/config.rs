@@ -1,25 +1,44 @@
-use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6, Ipv4Addr};
+use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6, Ipv4Addr, IpAddr};
 use std::str::FromStr;
 use std::any::TypeId;
 use std::mem::swap;
 use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::fmt;
 
 use anymap::Map;
 use anymap::any::{Any, UncheckedAnyExt};
 
+use StatusCode;
+use header::Headers;
+use mime::Mime;
+
 ///HTTP or HTTPS.
 pub enum Scheme {
     ///Standard HTTP.
     Http,
 
-    ///HTTP with SSL encryption.
-    #[cfg(feature = "ssl")]
+    ///HTTP with SSL encryption. OpenSSL is used if the `ssl` feature is
+    ///enabled, and rustls is used if only the `rustls` feature is enabled.
+    #[cfg(any(feature = "ssl", feature = "rustls"))]
     Https {
         ///Path to SSL certificate.
         cert: ::std::path::PathBuf,
 
         ///Path to key file.
         key: ::std::path::PathBuf
+    },
+
+    ///HTTP with SSL encryption, using a PEM encoded certificate chain and
+    ///private key from memory. This is useful when the certificate and key
+    ///comes from an environment variable or some kind of secret storage.
+    #[cfg(any(feature = "ssl", feature = "rustls"))]
+    HttpsPem {
+        ///PEM encoded SSL certificate chain.
+        cert: Vec<u8>,
+
+        ///PEM encoded private key.
+        key: Vec<u8>
     }
 }
 
@@ -100,6 +119,90 @@ impl FromStr for Host {
     }
 }
 
+///A range of IP addresses, written as `address/prefix` (CIDR notation).
+///
+///A single address can also be written without a prefix.
+///
+///```
+///use rustful::server::IpNetwork;
+///
+///let network: IpNetwork = "10.0.0.0/8".parse().unwrap();
+///assert!(network.contains(&"10.1.2.3".parse().unwrap()));
+///assert!(!network.contains(&"11.1.2.3".parse().unwrap()));
+///
+///let single: IpNetwork = "::1".parse().unwrap();
+///assert!(single.contains(&"::1".parse().unwrap()));
+///assert!(!single.contains(&"::2".parse().unwrap()));
+///```
+#[derive(Eq, PartialEq, Debug, Hash, Clone, Copy)]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix: u8
+}
+
+impl IpNetwork {
+    ///Create a network from an address and a prefix length. The prefix will
+    ///be limited to the size of the address.
+    pub fn new(address: IpAddr, prefix: u8) -> IpNetwork {
+        let max = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128
+        };
+
+        IpNetwork {
+            address: address,
+            prefix: ::std::cmp::min(prefix, max)
+        }
+    }
+
+    ///Check if an address belongs to this network.
+    pub fn contains(&self, address: &IpAddr) -> bool {
+        match (self.address, *address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => prefix_eq(&network.octets(), &address.octets(), self.prefix),
+            (IpAddr::V6(network), IpAddr::V6(address)) => prefix_eq(&network.octets(), &address.octets(), self.prefix),
+            _ => false
+        }
+    }
+}
+
+fn prefix_eq(a: &[u8], b: &[u8], prefix: u8) -> bool {
+    let whole = prefix as usize / 8;
+    let rest = prefix % 8;
+
+    if a[..whole] != b[..whole] {
+        return false;
+    }
+
+    if rest == 0 {
+        true
+    } else {
+        let mask = 0xffu8 << (8 - rest);
+        a[whole] & mask == b[whole] & mask
+    }
+}
+
+impl From<IpAddr> for IpNetwork {
+    fn from(address: IpAddr) -> IpNetwork {
+        IpNetwork::new(address, 128)
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = <IpAddr as FromStr>::Err;
+
+    fn from_str(s: &str) -> Result<IpNetwork, Self::Err> {
+        let mut parts = s.splitn(2, '/');
+        let address = try!(parts.next().unwrap_or("").parse());
+
+        match parts.next().map(|prefix| prefix.parse()) {
+            Some(Ok(prefix)) => Ok(IpNetwork::new(address, prefix)),
+            //Make the error come from the address parser
+            Some(Err(_)) => "".parse().map(|address| IpNetwork::new(address, 0)),
+            None => Ok(address.into())
+        }
+    }
+}
+
 ///A somewhat lazy container for globally accessible data.
 ///
 ///It will try to be as simple as possible and allocate as little as possible,
@@ -243,6 +346,158 @@ enum GlobalState {
     Many(Map<Any + Send + Sync>),
 }
 
+///TLS settings for the HTTPS listeners.
+///
+///They apply to every listener with `Scheme::Https` or `Scheme::HttpsPem`,
+///and they are ignored by plain HTTP listeners.
+#[derive(Clone, Debug, Default)]
+pub struct TlsConfig {
+    ///How TLS clients are asked for certificates. Default is
+    ///`ClientAuth::None`.
+    pub client_auth: ClientAuth,
+
+    ///The oldest protocol version that clients may use. Default is `None`,
+    ///which leaves it to the TLS library.
+    pub min_version: Option<TlsVersion>,
+
+    ///The newest protocol version that clients may use. Default is `None`,
+    ///which leaves it to the TLS library.
+    pub max_version: Option<TlsVersion>,
+
+    ///The allowed cipher suites, as a colon separated list. OpenSSL takes a
+    ///cipher list string, such as `"HIGH:!aNULL:!MD5"`, while rustls takes
+    ///the names of its `CipherSuite` variants, such as
+    ///`"TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256"` or
+    ///`"TLS13_AES_128_GCM_SHA256"`. Default is `None`, which
+    ///leaves it to the TLS library.
+    ///
+    ///```no_run
+    ///# #[cfg(feature = "ssl")]
+    ///# fn main() {
+    ///use rustful::{Server, Context, Response};
+    ///use rustful::server::{Scheme, TlsConfig, TlsVersion};
+    ///
+    ///# fn my_handler(context: Context, response: Response) {}
+    ///let server = Server {
+    ///    host: 443.into(),
+    ///    scheme: Scheme::Https {
+    ///        cert: "cert.pem".into(),
+    ///        key: "key.pem".into()
+    ///    },
+    ///    tls: TlsConfig {
+    ///        min_version: Some(TlsVersion::Tls12),
+    ///        ciphers: Some("ECDHE+AESGCM:!aNULL".into()),
+    ///        ..TlsConfig::default()
+    ///    },
+    ///    ..Server::new(my_handler)
+    ///};
+    ///# }
+    ///# #[cfg(not(feature = "ssl"))]
+    ///# fn main() {}
+    ///```
+    pub ciphers: Option<String>,
+}
+
+///A TLS protocol version.
+///
+///The versions are ordered from oldest to newest. Which of them are
+///supported depends on the TLS library, and the server will refuse to start
+///if `TlsConfig` asks for one that isn't.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TlsVersion {
+    ///TLS 1.0.
+    Tls10,
+
+    ///TLS 1.1.
+    Tls11,
+
+    ///TLS 1.2.
+    Tls12,
+
+    ///TLS 1.3.
+    Tls13,
+}
+
+impl fmt::Display for TlsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TlsVersion::Tls10 => f.write_str("TLS 1.0"),
+            TlsVersion::Tls11 => f.write_str("TLS 1.1"),
+            TlsVersion::Tls12 => f.write_str("TLS 1.2"),
+            TlsVersion::Tls13 => f.write_str("TLS 1.3"),
+        }
+    }
+}
+
+///Client certificate authentication, also known as mutual TLS.
+///
+///The client certificates are verified during the TLS handshake, using the
+///CA certificates in a PEM file. The certificate of a verified client is
+///added to the request extensions as a `ClientCertificate`, where filters
+///and handlers can find its subject.
+///
+///```no_run
+///# #[cfg(any(feature = "ssl", feature = "rustls"))]
+///# fn main() {
+///use rustful::{Server, Context, Response, StatusCode};
+///use rustful::server::{Scheme, TlsConfig, ClientAuth, ClientCertificate};
+///
+///fn my_handler(context: Context, mut response: Response) {
+///    match context.extensions.get::<ClientCertificate>().and_then(|cert| cert.common_name.as_ref()) {
+///        Some(name) => response.send(format!("hello, {}", name)),
+///        None => response.set_status(StatusCode::Forbidden)
+///    }
+///}
+///
+///let server = Server {
+///    host: 8443.into(),
+///    scheme: Scheme::Https {
+///        cert: "cert.pem".into(),
+///        key: "key.pem".into()
+///    },
+///    tls: TlsConfig {
+///        client_auth: ClientAuth::Required("clients-ca.pem".into()),
+///        ..TlsConfig::default()
+///    },
+///    ..Server::new(my_handler)
+///};
+///# }
+///# #[cfg(not(any(feature = "ssl", feature = "rustls")))]
+///# fn main() {}
+///```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ClientAuth {
+    ///Don't ask for client certificates.
+    None,
+
+    ///Ask for a client certificate and verify it against the CA
+    ///certificates in this file, but let clients without certificates
+    ///connect.
+    Optional(PathBuf),
+
+    ///Require a client certificate, signed by one of the CA certificates in
+    ///this file. Clients without a valid certificate are rejected during
+    ///the TLS handshake.
+    Required(PathBuf),
+}
+
+impl ClientAuth {
+    ///Get the path to the CA certificates, if clients are asked for
+    ///certificates.
+    pub fn ca_file(&self) -> Option<&Path> {
+        match *self {
+            ClientAuth::None => None,
+            ClientAuth::Optional(ref ca) | ClientAuth::Required(ref ca) => Some(ca)
+        }
+    }
+}
+
+impl Default for ClientAuth {
+    fn default() -> ClientAuth {
+        ClientAuth::None
+    }
+}
+
 ///Settings for `keep-alive` connections to the server.
 pub struct KeepAlive {
     ///How long a `keep-alive` connection may idle before it's forced close.
@@ -253,3 +508,58 @@ pub struct KeepAlive {
     ///threads goes below this.
     pub free_threads: usize,
 }
+
+///What to do with `.` and `..` segments, and repeated slashes, in request
+///paths.
+///
+///Paths are checked after they have been percent decoded, so `%2e%2e` is
+///treated the same way as `..`.
+#[derive(Eq, PartialEq, Debug, Hash, Clone, Copy)]
+pub enum PathNormalization {
+    ///Leave the path as it is.
+    Off,
+
+    ///Resolve `.` and `..` segments and collapse repeated slashes before
+    ///routing. A `..` segment can't go above the root.
+    Normalize,
+
+    ///Respond with `400 Bad Request` if the path contains anything that
+    ///would be changed by `Normalize`.
+    Reject
+}
+
+///Renders the bodies of error responses that are produced by the server,
+///such as `404 Not Found` when no handler matches, or when a filter aborts a
+///request.
+///
+///It's implemented for functions and closures with the same signature as
+///`render`:
+///
+///```
+///use rustful::{Server, Context, Response, StatusCode};
+///use rustful::header::Headers;
+///use rustful::mime::Mime;
+///
+///fn render_error(status: StatusCode, _request_headers: &Headers) -> Option<(Mime, Vec<u8>)> {
+///    let body = format!("{{\"error\": \"{}\"}}", status);
+///    Some(("application/json".parse().unwrap(), body.into_bytes()))
+///}
+///
+///# fn my_handler(context: Context, response: Response) {}
+///let server = Server {
+///    error_renderer: Some(Box::new(render_error)),
+///    ..Server::new(my_handler)
+///};
+///```
+pub trait ErrorRenderer: Send + Sync + 'static {
+    ///Render the body of an error response with `status`, and its media
+    ///type. The request headers, such as `Accept`, can be used to choose an
+    ///appropriate format. Returning `None` will leave the body empty.
+    fn render(&self, status: StatusCode, request_headers: &Headers) -> Option<(Mime, Vec<u8>)>;
+}
+
+impl<F: Fn(StatusCode, &Headers) -> Option<(Mime, Vec<u8>)> + Send + Sync + 'static> ErrorRenderer for F {
+    fn render(&self, status: StatusCode, request_headers: &Headers) -> Option<(Mime, Vec<u8>)> {
+        self(status, request_headers)
+    }
+}
@@ -0,0 +1,70 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+
+use HttpError;
+
+///Error that may occur while starting a server.
+///
+///```no_run
+///use std::error::Error;
+///use rustful::{Server, Context, Response};
+///use rustful::server::ServerError;
+///
+///# fn my_handler(context: Context, response: Response) {}
+///match Server::new(my_handler).run() {
+///    Ok(_listening) => {},
+///    Err(ServerError::Bind(address, ref e)) if e.kind() == std::io::ErrorKind::AddrInUse => {
+///        println!("{} is already used by an other program", address);
+///    },
+///    Err(e) => println!("could not start the server: {}", e)
+///}
+///```
+#[derive(Debug)]
+pub enum ServerError {
+    ///The server could not listen to its address. The address may already
+    ///be in use, or it may require special privileges.
+    Bind(SocketAddr, io::Error),
+
+    ///The TLS certificate or private key could not be loaded. The files may
+    ///be missing, or they may be invalid.
+    Tls(HttpError),
+
+    ///The server configuration is invalid.
+    Config(String),
+
+    ///The HTTP server could not be started.
+    Http(HttpError)
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ServerError::Bind(ref address, ref e) => write!(f, "could not listen to {}: {}", address, e),
+            ServerError::Tls(ref e) => write!(f, "could not set up TLS: {}", e),
+            ServerError::Config(ref desc) => write!(f, "invalid configuration: {}", desc),
+            ServerError::Http(ref e) => write!(f, "could not start the server: {}", e)
+        }
+    }
+}
+
+impl Error for ServerError {
+    fn description(&self) -> &str {
+        match *self {
+            ServerError::Bind(_, ref e) => e.description(),
+            ServerError::Tls(ref e) => e.description(),
+            ServerError::Config(ref desc) => desc,
+            ServerError::Http(ref e) => e.description()
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            ServerError::Bind(_, ref e) => Some(e),
+            ServerError::Tls(ref e) => Some(e),
+            ServerError::Config(_) => None,
+            ServerError::Http(ref e) => Some(e)
+        }
+    }
+}
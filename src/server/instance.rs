@@ -1,8 +1,14 @@
 use std::collections::HashMap;
-use std::net::SocketAddr;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::time::Duration;
-#[cfg(feature = "ssl")]
+use std::io;
+use std::mem;
+use std::cell::{Cell, RefCell};
+use std::panic::{self, AssertUnwindSafe};
+use std::net::{SocketAddr, IpAddr, Ipv4Addr, Ipv6Addr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Condvar, RwLock, TryLockError};
+use std::thread;
+use std::time::{Duration, Instant};
+#[cfg(any(feature = "ssl", feature = "rustls"))]
 use std::path::PathBuf;
 
 use time;
@@ -12,28 +18,41 @@ use num_cpus;
 use url::percent_encoding::{percent_decode, percent_decode_to};
 use url::{Url, SchemeData};
 
+use net2::{TcpBuilder, TcpStreamExt};
+
 use hyper;
 use hyper::server::Handler as HyperHandler;
-use hyper::header::{Date, ContentType};
+use hyper::header::{Date, ContentType, ContentLength, Headers, Allow, Connection, ConnectionOption};
 use hyper::mime::Mime;
 use hyper::uri::RequestUri;
-use hyper::net::HttpListener;
+use hyper::net::{HttpStream, NetworkListener, NetworkStream};
+#[cfg(any(feature = "ssl", feature = "rustls"))]
+use hyper::net::Ssl;
 #[cfg(feature = "ssl")]
-use hyper::net::{Openssl, HttpsListener};
+use hyper::net::Openssl;
+#[cfg(all(feature = "rustls", not(feature = "ssl")))]
+use server::Rustls;
 
-pub use hyper::server::Listening;
+use std::fmt;
 
 use anymap::AnyMap;
 
 use StatusCode;
+use Method;
 
-use context::{self, Context, Uri, MaybeUtf8Owned, Parameters};
-use filter::{FilterContext, ContextFilter, ContextAction, ResponseFilter};
+use context::{self, Context, Uri, AbsoluteTarget, MaybeUtf8Owned, Parameters, NegotiatedHeaders};
+use filter::{FilterContext, ContextFilter, ContextAction, ResponseFilter, Timings};
 use router::{Router, Endpoint};
 use handler::Handler;
 use response::Response;
 use header::HttpDate;
-use server::{Scheme, Global, KeepAlive};
+use cookie::Cookies;
+use server::{ServerError, Scheme, Global, KeepAlive, IpNetwork, ErrorRenderer, PathNormalization, WorkerState, WorkerInit, TlsConfig};
+#[cfg(feature = "ssl")]
+use server::{ClientAuth, TlsVersion};
+#[cfg(all(feature = "rustls", not(feature = "ssl")))]
+use server::tls;
+use server::client_cert::client_certificate;
 
 use HttpResult;
 use Server;
@@ -60,22 +79,49 @@ use utils;
 ///}.build();
 ///```
 pub struct ServerInstance<R: Router> {
-    handlers: R,
+    handlers: RouterHandle<R>,
+    host_handlers: HashMap<String, R>,
     fallback_handler: Option<R::Handler>,
 
     host: SocketAddr,
 
+    clock: Box<Fn() -> time::Tm + Send + Sync>,
     server: String,
     content_type: Mime,
+    auto_options: bool,
+    max_body_size: Option<u64>,
+    max_decoded_body_size: Option<u64>,
+    max_header_size: Option<usize>,
+    max_uri_length: Option<usize>,
+    forwarded_headers: bool,
+    query_semicolons: bool,
+    max_parameters: Option<usize>,
+    strict_utf8: bool,
+    path_normalization: PathNormalization,
+    trusted_proxies: Vec<IpNetwork>,
+    health_check: Option<String>,
+    error_renderer: Option<Box<ErrorRenderer>>,
+    catch_panics: bool,
+    retry_after: Option<Duration>,
+    listeners: Vec<(SocketAddr, Scheme)>,
+    listener: ListenerState,
+    tls: TlsConfig,
 
     threads: usize,
     keep_alive: Option<KeepAlive>,
-    threads_in_use: AtomicUsize,
+    max_connections_per_ip: Option<usize>,
+    backlog: Option<u32>,
+    socket_options: SocketOptions,
+    clients: Arc<ClientCounter>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    shutdown: Shutdown,
 
     context_filters: Vec<Box<ContextFilter>>,
     response_filters: Vec<Box<ResponseFilter>>,
 
     global: Global,
+    worker_init: Option<Box<WorkerInit>>,
 }
 
 impl<R: Router> ServerInstance<R> {
@@ -83,53 +129,129 @@ impl<R: Router> ServerInstance<R> {
     ///the same as `Server{...}.build()`.
     pub fn new(config: Server<R>) -> (ServerInstance<R>, Scheme) {
         (ServerInstance {
-            handlers: config.handlers,
+            handlers: RouterHandle::new(config.handlers),
+            host_handlers: config.host_handlers.into_iter().map(|(host, handlers)| (host.to_lowercase(), handlers)).collect(),
             fallback_handler: config.fallback_handler,
             host: config.host.into(),
+            clock: config.clock,
             server: config.server,
             content_type: config.content_type,
+            auto_options: config.auto_options,
+            max_body_size: config.max_body_size,
+            max_decoded_body_size: config.max_decoded_body_size,
+            max_header_size: config.max_header_size,
+            max_uri_length: config.max_uri_length,
+            forwarded_headers: config.forwarded_headers,
+            query_semicolons: config.query_semicolons,
+            max_parameters: config.max_parameters,
+            strict_utf8: config.strict_utf8,
+            path_normalization: config.path_normalization,
+            trusted_proxies: config.trusted_proxies,
+            health_check: config.health_check,
+            error_renderer: config.error_renderer,
+            catch_panics: config.catch_panics,
+            retry_after: config.retry_after,
+            listeners: config.listeners.into_iter().map(|(host, scheme)| (host.into(), scheme)).collect(),
+            listener: ListenerState::new(false),
+            tls: config.tls,
             threads: config.threads.unwrap_or_else(|| (num_cpus::get() * 5) / 4),
             keep_alive: config.keep_alive,
-            threads_in_use: AtomicUsize::new(0),
+            max_connections_per_ip: config.max_connections_per_ip,
+            backlog: config.backlog,
+            socket_options: SocketOptions {
+                nodelay: config.tcp_nodelay,
+                keep_alive: config.tcp_keep_alive
+            },
+            clients: Arc::new(ClientCounter::new()),
+            read_timeout: config.read_timeout,
+            write_timeout: config.write_timeout,
+            shutdown: Shutdown::new(config.start_ready),
             context_filters: config.context_filters,
             response_filters: config.response_filters,
             global: config.global,
+            worker_init: config.worker_init,
         },
         config.scheme)
     }
 
-    ///Start the server.
-    #[cfg(feature = "ssl")]
-    pub fn run(self, scheme: Scheme) -> HttpResult<Listening> {
-        let host = self.host;
-        let threads = self.threads;
-        let mut server = match scheme {
-            Scheme::Http => try!(HyperServer::http(host)),
-            Scheme::Https {cert, key} => try!(HyperServer::https(host, cert, key)),
-        };
-        server.keep_alive(self.keep_alive.as_ref().map(|k| k.timeout));
-        server.run(self, threads)
+    ///The number of threads in the server thread pool, after the default has
+    ///been resolved.
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    ///Get a handle for shutting down the server gracefully.
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    ///Get a handle for replacing the main router while the server is
+    ///running.
+    pub fn router_handle(&self) -> RouterHandle<R> {
+        self.handlers.clone()
     }
 
-    ///Start the server.
-    #[cfg(not(feature = "ssl"))]
-    pub fn run(self, _scheme: Scheme) -> HttpResult<Listening> {
-        let host = self.host;
+    ///Start the server. It listens to its `host` with `scheme`, and to each
+    ///of the additional `listeners` from the configuration. All of the
+    ///sockets are bound before any of them starts to accept connections, so
+    ///nothing is started if one of them fails.
+    pub fn run(mut self, scheme: Scheme) -> Result<Listening, ServerError> {
+        try!(self.check_threads());
+
+        let mut listeners = vec![(self.host, scheme)];
+        listeners.extend(mem::replace(&mut self.listeners, vec![]));
+
+        let mut servers = Vec::with_capacity(listeners.len());
+        for (host, scheme) in listeners {
+            let socket = try!(bind(host, self.backlog).and_then(ListenerSocket::new).map_err(|e| ServerError::Bind(host, e)));
+            let socket = Arc::new(socket);
+            self.shutdown.add_listener(socket.clone());
+
+            let listener = SocketListener {
+                socket: socket,
+                options: self.socket_options,
+                connections: self.max_connections_per_ip.map(|limit| ConnectionLimit::new(self.clients.clone(), limit))
+            };
+            let mut server = try!(HyperServer::new(listener, scheme, &self.tls));
+            server.keep_alive(self.keep_alive.as_ref().map(|k| k.timeout));
+            server.timeouts(self.read_timeout, self.write_timeout);
+            servers.push(server);
+        }
+
         let threads = self.threads;
-        let mut server = try!(HyperServer::http(host));
-        server.keep_alive(self.keep_alive.as_ref().map(|k| k.timeout));
-        server.run(self, threads)
+        let instance = Arc::new(self);
+        let mut listening = Vec::with_capacity(servers.len());
+        for server in servers {
+            let handler = ListenerHandler {
+                instance: instance.clone(),
+                state: ListenerState::new(server.is_https())
+            };
+            listening.push(try!(server.run(handler, threads)));
+        }
+
+        Ok(Listening {
+            inner: listening
+        })
+    }
+
+    fn check_threads(&self) -> Result<(), ServerError> {
+        if self.threads == 0 {
+            Err(ServerError::Config("the server needs at least one thread".to_owned()))
+        } else {
+            Ok(())
+        }
     }
 
-    fn modify_context(&self, filter_storage: &mut AnyMap, context: &mut Context) -> ContextAction {
+    fn modify_context(&self, filters: &[Box<ContextFilter>], filter_storage: &mut AnyMap, context: &mut Context) -> ContextAction {
         let mut result = ContextAction::Next;
 
-        for filter in &self.context_filters {
+        for filter in filters {
             result = match result {
                 ContextAction::Next => {
                     let filter_context = FilterContext {
                         storage: filter_storage,
                         global: &self.global,
+                        received_at: context.received_at,
                     };
                     filter.modify(filter_context, context)
                 },
@@ -140,17 +262,176 @@ impl<R: Router> ServerInstance<R> {
         result
     }
 
+    fn allowed_methods(&self, router: &R, uri: &Uri) -> Vec<Method> {
+        let mut methods = match uri.as_path() {
+            Some(path) => router.find_methods(&mut (&path[..]).into()),
+            None => router.supported_methods()
+        };
+
+        if !methods.is_empty() {
+            if methods.contains(&Method::Get) && !methods.contains(&Method::Head) {
+                methods.push(Method::Head);
+            }
+
+            if self.auto_options && !methods.contains(&Method::Options) {
+                methods.push(Method::Options);
+            }
+        }
+
+        methods
+    }
+
+    fn scheme_and_port(&self, listener_scheme: &str, headers: &Headers, host_port: Option<u16>) -> (String, u16) {
+        let mut scheme = listener_scheme.to_owned();
+        let mut port = host_port;
+
+        if self.forwarded_headers {
+            if let Some(proto) = first_forwarded_value(headers, "X-Forwarded-Proto") {
+                scheme = proto.to_lowercase();
+                port = None;
+            }
+
+            if let Some(forwarded_port) = first_forwarded_value(headers, "X-Forwarded-Port").and_then(|p| p.parse().ok()) {
+                port = Some(forwarded_port);
+            }
+        }
+
+        let port = port.unwrap_or_else(|| if scheme == "https" { 443 } else { 80 });
+        (scheme, port)
+    }
+
+    fn send_error(&self, mut response: Response, status: StatusCode, request_headers: &Headers) {
+        response.set_status(status);
+
+        if let Some(ref renderer) = self.error_renderer {
+            if let Some((content_type, body)) = renderer.render(status, request_headers) {
+                response.headers_mut().set(ContentType(content_type));
+                response.send(body);
+            }
+        }
+    }
+
+    fn call_handler<'a, 'b>(
+        &self,
+        handler: &R::Handler,
+        context: Context,
+        response: Response<'a, 'b>,
+        panic_slot: &RefCell<Option<hyper::server::response::Response<'a>>>
+    ) {
+        if !self.catch_panics {
+            handler.handle_request(context, response);
+            return;
+        }
+
+        //The request headers are only kept if they may be needed later.
+        let request_headers = if self.error_renderer.is_some() {
+            Some(context.headers.clone())
+        } else {
+            None
+        };
+
+        let head = response.is_head();
+
+        //The handler doesn't have to be unwind safe. Only the response
+        //writer is touched after a panic, and it's handed over as it was
+        //before the response was sent.
+        let result = panic::catch_unwind(AssertUnwindSafe(|| handler.handle_request(context, response)));
+
+        if result.is_ok() {
+            return;
+        }
+
+        if let Some(mut writer) = panic_slot.borrow_mut().take() {
+            let status = StatusCode::InternalServerError;
+            *writer.status_mut() = status;
+            writer.headers_mut().set(Connection(vec![ConnectionOption::Close]));
+
+            let rendered = match (self.error_renderer.as_ref(), request_headers.as_ref()) {
+                (Some(renderer), Some(headers)) => renderer.render(status, headers),
+                _ => None
+            };
+
+            let _ = match rendered {
+                Some((content_type, body)) => {
+                    writer.headers_mut().set(ContentType(content_type));
+                    if head {
+                        writer.headers_mut().set(ContentLength(body.len() as u64));
+                        writer.start().and_then(|writer| writer.end())
+                    } else {
+                        writer.send(&body)
+                    }
+                },
+                None => {
+                    writer.headers_mut().remove::<ContentType>();
+                    writer.send(&[])
+                }
+            };
+        }
+    }
+
+    fn body_too_large(&self, headers: &Headers) -> bool {
+        match (self.max_body_size, headers.get::<ContentLength>()) {
+            (Some(max_size), Some(&ContentLength(length))) => length > max_size,
+            _ => false
+        }
+    }
+
+    fn is_health_check(&self, uri: &Uri) -> bool {
+        match (self.health_check.as_ref(), uri.as_utf8_path()) {
+            (Some(health_check), Some(path)) => health_check == path,
+            _ => false
+        }
+    }
+
+    //Select a host specific router, or the current main router. The host of
+    //an absolute target takes precedence over the `Host` header.
+    fn select_router<'r>(&'r self, handlers: &'r R, target: Option<&AbsoluteTarget>, headers: &Headers) -> &'r R {
+        if self.host_handlers.is_empty() {
+            return handlers;
+        }
+
+        let host = match target {
+            Some(target) => Some(target.host.to_lowercase()),
+            None => headers.get::<::header::Host>().map(|host| host.hostname.to_lowercase())
+        };
+
+        host.and_then(|host| self.host_handlers.get(&host)).unwrap_or(handlers)
+    }
+}
+
+fn send_filter_response(mut response: Response, status: StatusCode, headers: Headers, body: Vec<u8>) {
+    response.set_status(status);
+    response.headers_mut().extend(headers.iter());
+    response.send(body);
 }
 
 struct ParsedUri {
-    host: Option<(String, Option<u16>)>,
+    target: Option<AbsoluteTarget>,
     uri: Uri,
     query: Parameters,
+    raw_query: Option<MaybeUtf8Owned>,
     fragment: Option<MaybeUtf8Owned>
 }
 
-impl<R: Router> HyperHandler for ServerInstance<R> {
-    fn handle(&self, request: hyper::server::request::Request, writer: hyper::server::response::Response) {
+impl ParsedUri {
+    //Check if the decoded path, query and fragment are valid UTF-8.
+    fn is_utf8(&self) -> bool {
+        let path_is_utf8 = match self.uri {
+            Uri::Path(ref path) => path.is_utf8(),
+            Uri::Asterisk => true
+        };
+
+        let query_is_utf8 = self.query.keys().all(|key| {
+            key.is_utf8() && self.query.get_all(key).iter().all(|value| value.is_utf8())
+        });
+
+        path_is_utf8 && query_is_utf8 && self.fragment.as_ref().map_or(true, |fragment| fragment.is_utf8())
+    }
+}
+
+impl<R: Router> ServerInstance<R> {
+    fn handle_request(&self, listener: &ListenerState, request: hyper::server::request::Request, writer: hyper::server::response::Response) {
+        let received_at = Instant::now();
         let (
             request_addr,
             request_method,
@@ -160,266 +441,1590 @@ impl<R: Router> HyperHandler for ServerInstance<R> {
             request_reader
         ) = request.deconstruct();
 
+        reset_route_timeout(&**request_reader.get_ref().get_ref(), self.write_timeout);
+
+        let _request_guard = self.shutdown.begin_request();
+        let unavailable = self.shutdown.is_shutting_down() || !self.shutdown.is_ready();
+
         let force_close = if let Some(ref keep_alive) = self.keep_alive {
-            self.threads_in_use.load(Ordering::SeqCst) + keep_alive.free_threads > self.threads
+            listener.threads_in_use.load(Ordering::SeqCst) + keep_alive.free_threads > self.threads
         } else {
             false
         };
 
-        let mut response = Response::new(writer, &self.response_filters, &self.global, force_close);
-        response.headers_mut().set(Date(HttpDate(time::now_utc())));
+        if unavailable {
+            let mut writer = writer;
+            *writer.status_mut() = StatusCode::ServiceUnavailable;
+            writer.headers_mut().set(Connection(vec![ConnectionOption::Close]));
+            if let Some(retry_after) = self.retry_after {
+                writer.headers_mut().set_raw("Retry-After", vec![retry_after.as_secs().to_string().into_bytes()]);
+            }
+            let _ = writer.send(&[]);
+            return;
+        }
+
+        //The request keeps using this router, even if it's replaced.
+        let handlers = self.handlers.get();
+
+        let panic_slot = RefCell::new(None);
+        let head = request_method == Method::Head;
+        let mut response = Response::new(writer, &self.response_filters, &self.global, received_at, force_close, head);
+        if self.catch_panics {
+            response.rescue_on_panic(&panic_slot);
+        }
+        response.headers_mut().set(Date(HttpDate((self.clock)())));
         response.headers_mut().set(ContentType(self.content_type.clone()));
-        response.headers_mut().set(hyper::header::Server(self.server.clone()));
+        if !self.server.is_empty() {
+            response.headers_mut().set(hyper::header::Server(self.server.clone()));
+        }
+
+        if uri_too_long(&request_uri, self.max_uri_length) {
+            response.headers_mut().set(Connection(vec![ConnectionOption::Close]));
+            self.send_error(response, StatusCode::UriTooLong, &request_headers);
+            return;
+        }
+
+        if let Some(max_size) = self.max_header_size {
+            if request_head_size(&request_method, &request_uri, &request_headers) > max_size {
+                response.headers_mut().set(Connection(vec![ConnectionOption::Close]));
+                self.send_error(response, StatusCode::RequestHeaderFieldsTooLarge, &request_headers);
+                return;
+            }
+        }
 
-        let path_components = match request_uri {
-            RequestUri::AbsoluteUri(url) => Some(parse_url(url)),
-            RequestUri::AbsolutePath(path) => Some(parse_path(&path)),
+        let mut path_components = match request_uri {
+            RequestUri::AbsoluteUri(url) => parse_url(url, self.query_semicolons, self.max_parameters),
+            RequestUri::AbsolutePath(path) => parse_path(&path, self.query_semicolons, self.max_parameters),
             RequestUri::Star => {
                 Some(ParsedUri {
-                    host: None,
+                    target: None,
                     uri: Uri::Asterisk,
                     query: Parameters::new(),
+                    raw_query: None,
                     fragment: None
                 })
             },
             _ => None
         };
 
+        if self.strict_utf8 && path_components.as_ref().map_or(false, |parsed| !parsed.is_utf8()) {
+            self.send_error(response, StatusCode::BadRequest, &request_headers);
+            return;
+        }
+
+        if let Some(ref mut parsed) = path_components {
+            if !self.apply_path_normalization(parsed) {
+                self.send_error(response, StatusCode::BadRequest, &request_headers);
+                return;
+            }
+        }
+
         match path_components {
-            Some(ParsedUri{ host, uri, query, fragment }) => {
-                if let Some((name, port)) = host {
+            Some(ParsedUri{ target, uri, query, raw_query, fragment }) => {
+                if self.is_health_check(&uri) {
+                    match request_method {
+                        Method::Get | Method::Head => response.send("ok"),
+                        _ => {
+                            response.set_status(StatusCode::MethodNotAllowed);
+                            response.headers_mut().set(Allow(vec![Method::Get, Method::Head]));
+                        }
+                    }
+                    return;
+                }
+
+                //The target authority replaces the `Host` header, as
+                //required by RFC 7230.
+                if let Some(ref target) = target {
                     request_headers.set(::header::Host {
-                        hostname: name,
-                        port: port
+                        hostname: target.host.clone(),
+                        port: target.port
                     });
                 }
 
-                let body = context::body::BodyReader::from_reader(request_reader, &request_headers);
+                let (host, host_port) = match request_headers.get_raw("Host") {
+                    Some(values) if values.len() == 1 => match parse_host(&values[0]) {
+                        Some((host, port)) => (if host.is_empty() { None } else { Some(host) }, port),
+                        None => {
+                            self.send_error(response, StatusCode::BadRequest, &request_headers);
+                            return;
+                        }
+                    },
+                    Some(_) => {
+                        self.send_error(response, StatusCode::BadRequest, &request_headers);
+                        return;
+                    },
+                    None => (None, None)
+                };
+
+                let cookies = request_headers.get_raw("Cookie")
+                    .map_or_else(Cookies::default, |headers| Cookies::new(headers.to_vec()));
+
+                let (scheme, port) = self.scheme_and_port(listener.scheme(), &request_headers, host_port);
+                let forwarded_for = forwarded_client_ip(request_addr.ip(), &request_headers, &self.trusted_proxies);
+
+                //The certificate has already been verified during the
+                //handshake, if the client sent one.
+                let mut extensions = AnyMap::new();
+                if listener.https && self.tls.client_auth.ca_file().is_some() {
+                    if let Some(certificate) = client_certificate(&**request_reader.get_ref().get_ref()) {
+                        extensions.insert(certificate);
+                    }
+                }
+
+                let deadline = self.read_timeout.map(|timeout| received_at + timeout);
+                let body = context::body::BodyReader::from_reader(request_reader, &request_headers, self.max_body_size, self.max_decoded_body_size, self.max_parameters, deadline);
+                let negotiated = NegotiatedHeaders::new();
 
                 let mut context = Context {
                     headers: request_headers,
                     http_version: request_version,
                     method: request_method,
                     address: request_addr,
+                    forwarded_for: forwarded_for,
+                    scheme: scheme,
+                    listener_scheme: listener.scheme(),
+                    host: host,
+                    port: port,
                     uri: uri,
+                    absolute_target: target,
+                    route: None,
                     hyperlinks: vec![],
                     variables: Parameters::new(),
                     query: query.into(),
+                    raw_query: raw_query,
                     fragment: fragment,
+                    cookies: cookies,
+                    extensions: extensions,
                     global: &self.global,
+                    negotiated: negotiated.clone(),
+                    received_at: received_at,
                     body: body
                 };
 
                 let mut filter_storage = AnyMap::new();
+                filter_storage.insert(negotiated);
 
-                match self.modify_context(&mut filter_storage, &mut context) {
+                let filters_start = Instant::now();
+                match self.modify_context(&self.context_filters, &mut filter_storage, &mut context) {
                     ContextAction::Next => {
+                        let mut filter_time = filters_start.elapsed();
                         *response.filter_storage_mut() = filter_storage;
 
-                        let endpoint = context.uri.as_path().map_or_else(|| {
+                        let route_start = Instant::now();
+                        let router = self.select_router(&handlers, context.absolute_target.as_ref(), &context.headers);
+
+                        let mut endpoint = context.uri.as_path().map_or_else(|| {
                             Endpoint {
                                 handler: None,
                                 variables: HashMap::new(),
-                                hyperlinks: vec![]
+                                hyperlinks: vec![],
+                                filters: vec![],
+                                route: None,
+                                timeout: None
+                            }
+                        }, |path| router.find(&context.method, &mut (&path[..]).into()));
+
+                        //Let `GET` handlers answer `HEAD` requests, unless
+                        //there is a dedicated `HEAD` handler.
+                        if endpoint.handler.is_none() && context.method == Method::Head {
+                            if let Some(path) = context.uri.as_path() {
+                                endpoint = router.find(&Method::Get, &mut (&path[..]).into());
                             }
-                        }, |path| self.handlers.find(&context.method, &mut (&path[..]).into()));
+                        }
 
                         let Endpoint {
                             handler,
                             variables,
-                            hyperlinks
+                            hyperlinks,
+                            filters,
+                            route,
+                            timeout
                         } = endpoint;
+                        let route_time = route_start.elapsed();
+
+                        if handler.is_none() && self.auto_options && context.method == Method::Options {
+                            let methods = self.allowed_methods(router, &context.uri);
+                            if !methods.is_empty() {
+                                response.headers_mut().set(Allow(methods));
+                                return;
+                            }
+                        }
 
                         if let Some(handler) = handler.or(self.fallback_handler.as_ref()) {
+                            //The body is left unread, so the connection
+                            //can't be reused.
+                            if self.body_too_large(&context.headers) {
+                                response.headers_mut().set(Connection(vec![ConnectionOption::Close]));
+                                self.send_error(response, StatusCode::PayloadTooLarge, &context.headers);
+                                return;
+                            }
+
                             context.hyperlinks = hyperlinks;
                             context.variables = variables.into();
-                            handler.handle_request(context, response);
+                            context.route = route;
+
+                            let deadline = timeout.map(|timeout| received_at + timeout);
+                            if let Some(timeout) = timeout {
+                                context.body.set_deadline(deadline);
+                                set_route_timeout(&**context.body.raw_stream().get_ref(), timeout);
+                            }
+
+                            //Route filters are applied after the global filters.
+                            let route_filters_start = Instant::now();
+                            let mut action = ContextAction::Next;
+                            for route_filters in filters {
+                                for filter in &route_filters.response_filters {
+                                    response.push_filter(&**filter);
+                                }
+
+                                if let ContextAction::Next = action {
+                                    action = self.modify_context(&route_filters.context_filters, response.filter_storage_mut(), &mut context);
+                                }
+                            }
+                            filter_time += route_filters_start.elapsed();
+
+                            //The timings are only recorded if a filter, such
+                            //as `ServerTiming`, has asked for them.
+                            if let Some(timings) = response.filter_storage_mut().get_mut::<Timings>() {
+                                timings.add("filter", filter_time);
+                                timings.add("route", route_time);
+                                timings.start("handler");
+                            }
+
+                            //The route ran out of time before the handler
+                            //could be called.
+                            if let ContextAction::Next = action {
+                                if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+                                    action = ContextAction::Abort(StatusCode::ServiceUnavailable);
+                                }
+                            }
+
+                            match action {
+                                ContextAction::Next => self.call_handler(handler, context, response, &panic_slot),
+                                ContextAction::Abort(status) => self.send_error(response, status, &context.headers),
+                                ContextAction::Respond(status, headers, body) => send_filter_response(response, status, headers, body)
+                            }
                         } else {
-                            response.set_status(StatusCode::NotFound);
+                            let methods = if context.uri.is_path() {
+                                self.allowed_methods(router, &context.uri)
+                            } else {
+                                vec![]
+                            };
+
+                            if methods.is_empty() {
+                                self.send_error(response, StatusCode::NotFound, &context.headers);
+                            } else {
+                                response.headers_mut().set(Allow(methods));
+                                self.send_error(response, StatusCode::MethodNotAllowed, &context.headers);
+                            }
                         }
                     },
                     ContextAction::Abort(status) => {
                         *response.filter_storage_mut() = filter_storage;
-                        response.set_status(status);
+                        self.send_error(response, status, &context.headers);
+                    },
+                    ContextAction::Respond(status, headers, body) => {
+                        *response.filter_storage_mut() = filter_storage;
+                        send_filter_response(response, status, headers, body);
                     }
                 }
             },
             None => {
-                response.set_status(StatusCode::BadRequest);
+                self.send_error(response, StatusCode::BadRequest, &request_headers);
             }
         }
     }
 
-    fn on_connection_start(&self) {
-        self.threads_in_use.fetch_add(1, Ordering::SeqCst);
-    }
-
-    fn on_connection_end(&self) {
-        self.threads_in_use.fetch_sub(1, Ordering::SeqCst);
-    }
-}
+    //Decide if a client with `Expect: 100-continue` should send the body.
+    //Only the checks that don't need the body or the context filters can be
+    //done this early. Hyper will close the connection if the status is not
+    //`100 Continue`.
+    fn continue_status(&self, method: &Method, uri: &RequestUri, headers: &Headers) -> StatusCode {
+        if self.shutdown.is_shutting_down() || !self.shutdown.is_ready() {
+            return StatusCode::ServiceUnavailable;
+        }
 
-fn parse_path(path: &str) -> ParsedUri {
-    match path.find('?') {
-        Some(index) => {
-            let (query, fragment) = parse_fragment(&path[index+1..]);
+        if uri_too_long(uri, self.max_uri_length) {
+            return StatusCode::UriTooLong;
+        }
 
-            let mut path = percent_decode(path[..index].as_bytes());
-            if path.is_empty() {
-                path.push(b'/');
+        if let Some(max_size) = self.max_header_size {
+            if request_head_size(method, uri, headers) > max_size {
+                return StatusCode::RequestHeaderFieldsTooLarge;
             }
+        }
 
-            ParsedUri {
-                host: None,
-                uri: Uri::Path(path.into()),
-                query: utils::parse_parameters(query.as_bytes()),
-                fragment: fragment.map(|f| percent_decode(f.as_bytes()).into())
+        if self.body_too_large(headers) {
+            return StatusCode::PayloadTooLarge;
+        }
+
+        //There's no point in sending a body to an unknown path.
+        if self.fallback_handler.is_none() {
+            let mut parsed = match *uri {
+                RequestUri::AbsolutePath(ref path) => parse_path(path, self.query_semicolons, self.max_parameters),
+                RequestUri::AbsoluteUri(ref url) => parse_url(url.clone(), self.query_semicolons, self.max_parameters),
+                _ => None
+            };
+
+            if let Some(ref mut parsed) = parsed {
+                if !self.apply_path_normalization(parsed) {
+                    return StatusCode::BadRequest;
+                }
             }
-        },
-        None => {
-            let (path, fragment) = parse_fragment(&path);
 
-            let mut path = percent_decode(path.as_bytes());
-            if path.is_empty() {
-                path.push(b'/');
+            if let Some(ParsedUri { uri, target, .. }) = parsed {
+                let handlers = self.handlers.get();
+                let router = self.select_router(&handlers, target.as_ref(), headers);
+                let found = uri.as_path().map_or(true, |path| {
+                    router.find(method, &mut (&path[..]).into()).handler.is_some()
+                });
+
+                if !found && self.allowed_methods(router, &uri).is_empty() && !self.is_health_check(&uri) {
+                    return StatusCode::NotFound;
+                }
             }
+        }
 
-            ParsedUri {
-                host: None,
-                uri: Uri::Path(path.into()),
-                query: Parameters::new(),
-                fragment: fragment.map(|f| percent_decode(f.as_bytes()).into())
+        StatusCode::Continue
+    }
+
+    //Apply the path normalization policy. Returns `false` if the request
+    //should be rejected.
+    fn apply_path_normalization(&self, parsed: &mut ParsedUri) -> bool {
+        if self.path_normalization == PathNormalization::Off {
+            return true;
+        }
+
+        if let Uri::Path(ref mut path) = parsed.uri {
+            if let Some(normalized) = normalize_path(path) {
+                if self.path_normalization == PathNormalization::Reject {
+                    return false;
+                }
+
+                *path = normalized.into();
             }
         }
+
+        true
     }
-}
 
-fn parse_fragment(path: &str) -> (&str, Option<&str>) {
-    match path.find('#') {
-        Some(index) => (&path[..index], Some(&path[index+1..])),
-        None => (path, None)
+    fn connection_started(&self, listener: &ListenerState) {
+        //Connections are handled by one worker thread each, so this is
+        //before the thread's first request.
+        if let Some(ref init) = self.worker_init {
+            WorkerState::init(&**init);
+        }
+
+        listener.threads_in_use.fetch_add(1, Ordering::SeqCst);
     }
-}
 
-fn parse_url(url: Url) -> ParsedUri {
-    let mut path = Vec::new();
-    for component in url.path().unwrap_or(&[]) {
-        path.push(b'/');
-        percent_decode_to(component.as_bytes(), &mut path);
+    fn connection_ended(&self, listener: &ListenerState) {
+        listener.threads_in_use.fetch_sub(1, Ordering::SeqCst);
+        release_connection_slot();
     }
-    if path.is_empty() {
-        path.push(b'/');
+}
+
+impl<R: Router> HyperHandler for ServerInstance<R> {
+    fn handle(&self, request: hyper::server::request::Request, writer: hyper::server::response::Response) {
+        self.handle_request(&self.listener, request, writer);
     }
 
-    let query = url.query_pairs()
-            .unwrap_or_default()
-            .into_iter()
-            .collect();
+    fn check_continue(&self, (method, uri, headers): (&Method, &RequestUri, &Headers)) -> StatusCode {
+        self.continue_status(method, uri, headers)
+    }
 
-    let host = if let SchemeData::Relative(data) = url.scheme_data {
-        Some((data.host.serialize(), data.port))
-    } else {
-        None
-    };
+    fn on_connection_start(&self) {
+        self.connection_started(&self.listener);
+    }
 
-    ParsedUri {
-        host: host,
-        uri: Uri::Path(path.into()),
-        query: query,
-        fragment: url.fragment.map(|f| percent_decode(f.as_bytes()).into())
+    fn on_connection_end(&self) {
+        self.connection_ended(&self.listener);
     }
 }
 
-//Helper to handle multiple protocols.
-enum HyperServer {
-    Http(hyper::server::Server<HttpListener>),
-    #[cfg(feature = "ssl")]
-    Https(hyper::server::Server<HttpsListener<Openssl>>),
+//The state of a single listener. Each listener has its own thread pool.
+struct ListenerState {
+    https: bool,
+    threads_in_use: AtomicUsize
 }
 
-impl HyperServer {
-    fn http(host: SocketAddr) -> HttpResult<HyperServer> {
-        hyper::server::Server::http(host).map(HyperServer::Http)
+impl ListenerState {
+    fn new(https: bool) -> ListenerState {
+        ListenerState {
+            https: https,
+            threads_in_use: AtomicUsize::new(0)
+        }
     }
 
-    #[cfg(feature = "ssl")]
-    fn https(host: SocketAddr, cert: PathBuf, key: PathBuf) -> HttpResult<HyperServer> {
-        let ssl = try!(Openssl::with_cert_and_key(cert, key));
-        hyper::server::Server::https(host, ssl).map(HyperServer::Https)
+    fn scheme(&self) -> &'static str {
+        if self.https { "https" } else { "http" }
     }
+}
 
-    #[cfg(feature = "ssl")]
-    fn keep_alive(&mut self, timeout: Option<Duration>) {
-        match *self {
-            HyperServer::Http(ref mut s) => s.keep_alive(timeout),
-            HyperServer::Https(ref mut s) => s.keep_alive(timeout),
-        }
+//Lets the listeners share the same server instance.
+struct ListenerHandler<R: Router> {
+    instance: Arc<ServerInstance<R>>,
+    state: ListenerState
+}
+
+impl<R: Router> HyperHandler for ListenerHandler<R> {
+    fn handle(&self, request: hyper::server::request::Request, writer: hyper::server::response::Response) {
+        self.instance.handle_request(&self.state, request, writer);
     }
 
-    #[cfg(not(feature = "ssl"))]
-    fn keep_alive(&mut self, timeout: Option<Duration>) {
-        match *self {
-            HyperServer::Http(ref mut s) => s.keep_alive(timeout),
-        }
+    fn check_continue(&self, (method, uri, headers): (&Method, &RequestUri, &Headers)) -> StatusCode {
+        self.instance.continue_status(method, uri, headers)
     }
 
-    #[cfg(feature = "ssl")]
-    fn run<R: Router>(self, server: ServerInstance<R>, threads: usize) -> HttpResult<Listening> {
-        match self {
-            HyperServer::Http(s) => s.handle_threads(server, threads),
-            HyperServer::Https(s) => s.handle_threads(server, threads),
-        }
+    fn on_connection_start(&self) {
+        self.instance.connection_started(&self.state);
     }
 
-    #[cfg(not(feature = "ssl"))]
-    fn run<R: Router>(self, server: ServerInstance<R>, threads: usize) -> HttpResult<Listening> {
-        match self {
-            HyperServer::Http(s) => s.handle_threads(server, threads),
-        }
+    fn on_connection_end(&self) {
+        self.instance.connection_ended(&self.state);
     }
 }
 
+thread_local!(static ROUTE_TIMEOUT: Cell<bool> = Cell::new(false));
 
-#[test]
-fn parse_path_parts() {
-    let with = "this".to_owned().into();
-    let and = "that".to_owned().into();
-    let ParsedUri { uri, query, fragment, .. } = parse_path("/path/to/something?with=this&and=that#lol");
-    assert_eq!(uri.as_path(), Some("/path/to/something".into()));
-    assert_eq!(query.get_raw("with"), Some(&with));
-    assert_eq!(query.get_raw("and"), Some(&and));
-    assert_eq!(fragment, Some("lol".to_owned().into()));
+//Replaces the socket timeouts with the timeout of a route. The read timeout
+//is reset by hyper before each request, but the write timeout is only set
+//when the connection is accepted, so it's reset by `reset_route_timeout`.
+fn set_route_timeout(stream: &NetworkStream, timeout: Duration) {
+    let _ = stream.set_read_timeout(Some(timeout));
+    let _ = stream.set_write_timeout(Some(timeout));
+    ROUTE_TIMEOUT.with(|changed| changed.set(true));
 }
 
-#[test]
-fn parse_strange_path() {
-    let with = "this".to_owned().into();
-    let and = "what?".to_owned().into();
-    let ParsedUri { uri, query, fragment, .. } = parse_path("/path/to/something?with=this&and=what?#");
-    assert_eq!(uri.as_path(), Some("/path/to/something".into()));
-    assert_eq!(query.get_raw("with"), Some(&with));
-    assert_eq!(query.get_raw("and"), Some(&and));
-    assert_eq!(fragment, Some(String::new().into()));
+//Restores the server's write timeout if a route has replaced it. Each
+//worker thread handles one connection at the time, so it's enough to keep
+//track of it per thread.
+fn reset_route_timeout(stream: &NetworkStream, write_timeout: Option<Duration>) {
+    ROUTE_TIMEOUT.with(|changed| if changed.get() {
+        let _ = stream.set_write_timeout(write_timeout);
+        changed.set(false);
+    });
 }
 
-#[test]
-fn parse_missing_path_parts() {
-    let with = "this".to_owned().into();
-    let and = "that".to_owned().into();
-    let ParsedUri { uri, query, fragment, .. } = parse_path("/path/to/something?with=this&and=that");
-    assert_eq!(uri.as_path(), Some("/path/to/something".into()));
-    assert_eq!(query.get_raw("with"), Some(&with));
-    assert_eq!(query.get_raw("and"), Some(&and));
+//The length of the request target, as it was received.
+fn request_uri_length(uri: &RequestUri) -> usize {
+    match *uri {
+        RequestUri::AbsolutePath(ref path) => path.len(),
+        RequestUri::AbsoluteUri(ref url) => url.to_string().len(),
+        RequestUri::Authority(ref authority) => authority.len(),
+        RequestUri::Star => 1
+    }
+}
+
+fn uri_too_long(uri: &RequestUri, max_length: Option<usize>) -> bool {
+    max_length.map_or(false, |max_length| request_uri_length(uri) > max_length)
+}
+
+//The approximate size of the request head, with `\r\n` line endings.
+fn request_head_size(method: &Method, uri: &RequestUri, headers: &Headers) -> usize {
+    //The method, the URI and `HTTP/1.1`, separated by spaces.
+    let request_line = method.to_string().len() + 1 + request_uri_length(uri) + 9 + 2;
+
+    headers.iter().fold(request_line, |size, header| {
+        let values = headers.get_raw(header.name()).unwrap_or(&[]);
+        values.iter().fold(size, |size, value| size + header.name().len() + 2 + value.len() + 2)
+    })
+}
+
+//Walk the forwarded addresses from right to left and find the first one
+//that is not trusted.
+fn forwarded_client_ip(peer: IpAddr, headers: &Headers, trusted: &[IpNetwork]) -> Option<IpAddr> {
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|network| network.contains(ip));
+
+    if !is_trusted(&peer) {
+        return None;
+    }
+
+    let hops = if let Some(values) = headers.get_raw("Forwarded") {
+        forwarded_values(values).into_iter()
+            .flat_map(|element| element.split(';').map(str::to_owned).collect::<Vec<_>>())
+            .filter_map(|pair| {
+                let mut parts = pair.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some(name), Some(value)) if name.trim().to_lowercase() == "for" => Some(value.trim().to_owned()),
+                    _ => None
+                }
+            })
+            .collect()
+    } else if let Some(values) = headers.get_raw("X-Forwarded-For") {
+        forwarded_values(values)
+    } else {
+        vec![]
+    };
+
+    let mut client = None;
+    for hop in hops.iter().rev() {
+        match parse_forwarded_ip(hop) {
+            Some(ip) => {
+                client = Some(ip);
+                if !is_trusted(&ip) {
+                    break;
+                }
+            },
+            //Unknown or obfuscated addresses can't be trusted any further
+            None => break
+        }
+    }
+
+    client
+}
+
+//Split comma separated header values into trimmed, non-empty elements.
+fn forwarded_values(values: &[Vec<u8>]) -> Vec<String> {
+    values.iter()
+        .filter_map(|value| ::std::str::from_utf8(value).ok())
+        .flat_map(|value| value.split(',').map(str::trim).map(str::to_owned).collect::<Vec<_>>())
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+//Parse `192.0.2.60`, `192.0.2.60:4711`, `2001:db8::1` or `"[2001:db8::1]:4711"`.
+fn parse_forwarded_ip(value: &str) -> Option<IpAddr> {
+    let value = value.trim_matches('"');
+
+    if value.starts_with('[') {
+        value[1..].split(']').next().and_then(|ip| ip.parse().ok()).map(IpAddr::V6)
+    } else if let Ok(ip) = value.parse() {
+        Some(ip)
+    } else {
+        value.parse::<SocketAddr>().ok().map(|addr| addr.ip())
+    }
+}
+
+//Parse a `Host` header value into a lower case host and an optional port.
+//The host is empty if the value is empty, and `None` is returned if it's
+//malformed.
+fn parse_host(value: &[u8]) -> Option<(String, Option<u16>)> {
+    let value = match ::std::str::from_utf8(value) {
+        Ok(value) => value.trim(),
+        Err(_) => return None
+    };
+
+    let (host, port) = if value.starts_with('[') {
+        let end = match value.find(']') {
+            Some(end) => end,
+            None => return None
+        };
+
+        if value[1..end].parse::<::std::net::Ipv6Addr>().is_err() {
+            return None;
+        }
+
+        match &value[end + 1..] {
+            "" => (&value[..end + 1], None),
+            rest if rest.starts_with(':') => (&value[..end + 1], Some(&rest[1..])),
+            _ => return None
+        }
+    } else {
+        let mut parts = value.splitn(2, ':');
+        let host = parts.next().unwrap_or("");
+        let valid = host.bytes().all(|b| match b {
+            b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' => true,
+            b'-' | b'.' | b'_' | b'~' | b'%' => true,
+            b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b',' | b';' | b'=' => true,
+            _ => false
+        });
+
+        if !valid {
+            return None;
+        }
+
+        (host, parts.next())
+    };
+
+    //An empty port is allowed and means the default port.
+    let port = match port {
+        Some("") | None => None,
+        Some(port) => match port.parse() {
+            Ok(port) => Some(port),
+            Err(_) => return None
+        }
+    };
+
+    if host.is_empty() && port.is_some() {
+        return None;
+    }
+
+    Some((host.to_lowercase(), port))
+}
+
+//Get the first value from a comma separated header, like `X-Forwarded-For`.
+fn first_forwarded_value(headers: &Headers, name: &str) -> Option<String> {
+    headers.get_raw(name)
+        .and_then(|values| values.first())
+        .and_then(|value| ::std::str::from_utf8(value).ok())
+        .and_then(|value| value.split(',').next())
+        .map(|value| value.trim().to_owned())
+        .and_then(|value| if value.is_empty() { None } else { Some(value) })
+}
+
+//Returns `None` if there are more than `max_parameters` query parameters.
+fn parse_path(path: &str, semicolons: bool, max_parameters: Option<usize>) -> Option<ParsedUri> {
+    match path.find('?') {
+        Some(index) => {
+            let (query, fragment) = parse_fragment(&path[index+1..]);
+
+            let mut path = percent_decode(path[..index].as_bytes());
+            if path.is_empty() {
+                path.push(b'/');
+            }
+
+            let parameters = match utils::parse_parameters_with(query.as_bytes(), semicolons, max_parameters) {
+                Some(parameters) => parameters,
+                None => return None
+            };
+
+            Some(ParsedUri {
+                target: None,
+                uri: Uri::Path(path.into()),
+                query: parameters,
+                raw_query: Some(query.to_owned().into()),
+                fragment: fragment.map(|f| percent_decode(f.as_bytes()).into())
+            })
+        },
+        None => {
+            let (path, fragment) = parse_fragment(&path);
+
+            let mut path = percent_decode(path.as_bytes());
+            if path.is_empty() {
+                path.push(b'/');
+            }
+
+            Some(ParsedUri {
+                target: None,
+                uri: Uri::Path(path.into()),
+                query: Parameters::new(),
+                raw_query: None,
+                fragment: fragment.map(|f| percent_decode(f.as_bytes()).into())
+            })
+        }
+    }
+}
+
+//Resolve `.` and `..` segments and collapse repeated slashes. Returns
+//`None` if the path is already normalized.
+fn normalize_path(path: &[u8]) -> Option<Vec<u8>> {
+    let mut segments = Vec::new();
+    let mut trailing_slash = false;
+
+    for segment in path.split(|&byte| byte == b'/') {
+        if segment.is_empty() || segment == b"." {
+            trailing_slash = true;
+        } else if segment == b".." {
+            segments.pop();
+            trailing_slash = true;
+        } else {
+            segments.push(segment);
+            trailing_slash = false;
+        }
+    }
+
+    let mut normalized = Vec::with_capacity(path.len());
+    for segment in &segments {
+        normalized.push(b'/');
+        normalized.extend_from_slice(segment);
+    }
+    if trailing_slash || segments.is_empty() {
+        normalized.push(b'/');
+    }
+
+    if normalized[..] == path[..] {
+        None
+    } else {
+        Some(normalized)
+    }
+}
+
+fn parse_fragment(path: &str) -> (&str, Option<&str>) {
+    match path.find('#') {
+        Some(index) => (&path[..index], Some(&path[index+1..])),
+        None => (path, None)
+    }
+}
+
+//Returns `None` if there are more than `max_parameters` query parameters.
+fn parse_url(url: Url, semicolons: bool, max_parameters: Option<usize>) -> Option<ParsedUri> {
+    let mut path = Vec::new();
+    for component in url.path().unwrap_or(&[]) {
+        path.push(b'/');
+        percent_decode_to(component.as_bytes(), &mut path);
+    }
+    if path.is_empty() {
+        path.push(b'/');
+    }
+
+    let query = match url.query {
+        Some(ref query) => match utils::parse_parameters_with(query.as_bytes(), semicolons, max_parameters) {
+            Some(parameters) => parameters,
+            None => return None
+        },
+        None => Parameters::new()
+    };
+
+    let target = if let SchemeData::Relative(data) = url.scheme_data {
+        Some(AbsoluteTarget {
+            scheme: url.scheme,
+            host: data.host.serialize(),
+            port: data.port
+        })
+    } else {
+        None
+    };
+
+    Some(ParsedUri {
+        target: target,
+        uri: Uri::Path(path.into()),
+        query: query,
+        raw_query: url.query.map(|q| q.into()),
+        fragment: url.fragment.map(|f| percent_decode(f.as_bytes()).into())
+    })
+}
+
+///A handle to a running server.
+///
+///The server keeps running in the background, and dropping the handle will
+///block the current thread until the server stops.
+///
+///```no_run
+///# use rustful::{Server, Context, Response};
+///# fn my_handler(context: Context, response: Response) {}
+///let listening = Server {
+///    //Let the OS choose a free port.
+///    host: 0.into(),
+///    ..Server::new(my_handler)
+///}.run().unwrap();
+///
+///println!("listening on port {}", listening.local_addr().port());
+///```
+///
+///A server with additional `listeners` has one handle for all of them, and
+///they are stopped together.
+pub struct Listening {
+    inner: Vec<hyper::server::Listening>
+}
+
+impl Listening {
+    ///The address the server is bound to. It has the actual port number,
+    ///even if the server was started with port `0`. This is the address of
+    ///the main `host` if there are more than one listener.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.inner[0].socket
+    }
+
+    ///The addresses of all of the listeners, starting with the main `host`,
+    ///followed by the additional `listeners` in the order they were added.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.inner.iter().map(|listening| listening.socket).collect()
+    }
+
+    ///Stop the server from listening to its socket addresses. This
+    ///currently depends on hyper's `Listening::close`, which doesn't stop
+    ///the server in practice, so `Shutdown` should be used instead.
+    pub fn close(&mut self) -> HttpResult<()> {
+        for listening in &mut self.inner {
+            try!(listening.close());
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Listening {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Listening {{ local_addrs: {:?} }}", self.local_addrs())
+    }
+}
+
+///A handle for shutting down a server gracefully.
+///
+///The server stops accepting connections when `shutdown` is called, and its
+///sockets are closed, so new clients are refused. Requests that are
+///already being handled are allowed to finish, while any following requests
+///on their connections are rejected with `503 Service Unavailable` and the
+///connections are closed. The worker threads are left idle, so the process
+///is expected to exit when `wait` returns.
+///
+///The same handle controls if the server is ready. Requests are rejected in
+///the same way while it's not ready, which makes it possible to start the
+///server before it's fully initialized, using `Server::start_ready`, or to
+///let a load balancer notice that the server is about to be shut down,
+///before `shutdown` is called. The `503` responses include a `Retry-After`
+///header, as configured in `Server::retry_after`.
+///
+///```no_run
+///# use rustful::{Server, Context, Response};
+///# fn my_handler(context: Context, response: Response) {}
+///let (listening, shutdown) = Server {
+///    host: 8080.into(),
+///    ..Server::new(my_handler)
+///}.run_with_shutdown().unwrap();
+///
+///# fn wait_for_sigterm() {}
+///wait_for_sigterm();
+///
+///shutdown.shutdown();
+///shutdown.wait();
+///std::process::exit(0);
+///```
+#[derive(Clone)]
+pub struct Shutdown {
+    inner: Arc<ShutdownState>
+}
+
+struct ShutdownState {
+    shutting_down: AtomicBool,
+    ready: AtomicBool,
+    active_requests: Mutex<usize>,
+    done: Condvar,
+    listeners: Mutex<Vec<Arc<ListenerSocket>>>
+}
+
+impl Shutdown {
+    fn new(ready: bool) -> Shutdown {
+        Shutdown {
+            inner: Arc::new(ShutdownState {
+                shutting_down: AtomicBool::new(false),
+                ready: AtomicBool::new(ready),
+                active_requests: Mutex::new(0),
+                done: Condvar::new(),
+                listeners: Mutex::new(vec![])
+            })
+        }
+    }
+
+    ///Stop accepting connections and handling new requests. This will only
+    ///block until the sockets are closed, and not until the ongoing requests
+    ///are done. Use `wait` for that.
+    pub fn shutdown(&self) {
+        self.inner.shutting_down.store(true, Ordering::SeqCst);
+
+        let listeners = match self.inner.listeners.lock() {
+            Ok(listeners) => listeners,
+            Err(poisoned) => poisoned.into_inner()
+        };
+        for listener in listeners.iter() {
+            listener.close();
+        }
+        drop(listeners);
+
+        let _active = self.inner.active_requests.lock().unwrap();
+        self.inner.done.notify_all();
+    }
+
+    ///Check if `shutdown` has been called.
+    pub fn is_shutting_down(&self) -> bool {
+        self.inner.shutting_down.load(Ordering::SeqCst)
+    }
+
+    ///Mark the server as ready, or not ready, to handle requests. This can
+    ///be changed back and forth while the server is running, but it has no
+    ///effect after `shutdown` has been called.
+    pub fn set_ready(&self, ready: bool) {
+        self.inner.ready.store(ready, Ordering::SeqCst);
+    }
+
+    ///Check if the server is marked as ready to handle requests.
+    pub fn is_ready(&self) -> bool {
+        self.inner.ready.load(Ordering::SeqCst)
+    }
+
+    ///Block until `shutdown` has been called and every ongoing request has
+    ///been handled.
+    pub fn wait(&self) {
+        let mut active = self.inner.active_requests.lock().unwrap();
+        while !self.is_shutting_down() || *active > 0 {
+            active = self.inner.done.wait(active).unwrap();
+        }
+    }
+
+    ///Block until `shutdown` has been called and every ongoing request has
+    ///been handled, or until `timeout` has passed. Returns `true` if the
+    ///server was shut down in time.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut active = self.inner.active_requests.lock().unwrap();
+
+        while !self.is_shutting_down() || *active > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+
+            active = self.inner.done.wait_timeout(active, deadline - now).unwrap().0;
+        }
+
+        true
+    }
+
+    //Close the socket along with the others when the server shuts down, or
+    //right away if it already has.
+    fn add_listener(&self, listener: Arc<ListenerSocket>) {
+        let mut listeners = match self.inner.listeners.lock() {
+            Ok(listeners) => listeners,
+            Err(poisoned) => poisoned.into_inner()
+        };
+
+        if self.is_shutting_down() {
+            listener.close();
+        }
+        listeners.push(listener);
+    }
+
+    fn begin_request(&self) -> RequestGuard {
+        *self.inner.active_requests.lock().unwrap() += 1;
+        RequestGuard {
+            state: &*self.inner
+        }
+    }
+}
+
+///A handle for replacing the main router of a running server.
+///
+///The router can be replaced at any time, without restarting the server,
+///which is useful when the routes depend on configuration, feature flags or
+///plugins that change at runtime. Each request uses the router that was
+///current when it arrived, so ongoing requests are not affected by a
+///replacement, and the old router is dropped when the last of them is done.
+///Only the main router, from `Server::handlers`, can be replaced. The host
+///specific routers are fixed.
+///
+///The router is kept behind a `RwLock` to make this possible, so each
+///request has to take a read lock and increment a reference count, before
+///it's routed. This is cheap compared to the rest of the request, but the
+///lock and the counter are shared by every thread, so they may become a
+///point of contention for servers with many threads and very small
+///responses. A replacement blocks new requests for as long as it takes to
+///swap two pointers.
+///
+///```no_run
+///# use rustful::{Server, Context, Response, TreeRouter};
+///# use rustful::router::{MethodRouter, Variables};
+///# fn load_routes() -> TreeRouter<MethodRouter<Variables<fn(Context, Response)>>> { TreeRouter::new() }
+///let (server, scheme) = Server {
+///    host: 8080.into(),
+///    ..Server::new(load_routes())
+///}.build();
+///
+///let routes = server.router_handle();
+///let listening = server.run(scheme).unwrap();
+///
+///# fn wait_for_reload() {}
+///wait_for_reload();
+///
+///routes.swap(load_routes());
+///```
+pub struct RouterHandle<R> {
+    current: Arc<RwLock<Arc<R>>>
+}
+
+impl<R: Router> RouterHandle<R> {
+    fn new(router: R) -> RouterHandle<R> {
+        RouterHandle {
+            current: Arc::new(RwLock::new(Arc::new(router)))
+        }
+    }
+
+    ///Get the current router.
+    pub fn get(&self) -> Arc<R> {
+        match self.current.read() {
+            Ok(current) => current.clone(),
+            Err(poisoned) => poisoned.into_inner().clone()
+        }
+    }
+
+    ///Replace the current router with `router`, and return the previous
+    ///one. New requests will be routed by `router`, while ongoing requests
+    ///are finished using the previous router.
+    pub fn swap(&self, router: R) -> Arc<R> {
+        let router = Arc::new(router);
+        let mut current = match self.current.write() {
+            Ok(current) => current,
+            Err(poisoned) => poisoned.into_inner()
+        };
+        mem::replace(&mut *current, router)
+    }
+}
+
+impl<R> Clone for RouterHandle<R> {
+    fn clone(&self) -> RouterHandle<R> {
+        RouterHandle {
+            current: self.current.clone()
+        }
+    }
+}
+
+//Keeps track of an ongoing request, even if the handler panics.
+struct RequestGuard<'a> {
+    state: &'a ShutdownState
+}
+
+impl<'a> Drop for RequestGuard<'a> {
+    fn drop(&mut self) {
+        let mut active = match self.state.active_requests.lock() {
+            Ok(active) => active,
+            Err(poisoned) => poisoned.into_inner()
+        };
+        *active -= 1;
+        self.state.done.notify_all();
+    }
+}
+
+//Counts the open connections from each client address.
+struct ClientCounter {
+    active_connections: Mutex<HashMap<IpAddr, usize>>
+}
+
+impl ClientCounter {
+    fn new() -> ClientCounter {
+        ClientCounter {
+            active_connections: Mutex::new(HashMap::new())
+        }
+    }
+
+    //Register a connection from `ip`, unless there are already `limit` open
+    //connections from it.
+    fn begin_connection(counter: &Arc<ClientCounter>, ip: IpAddr, limit: usize) -> Option<ClientGuard> {
+        let mut active = match counter.active_connections.lock() {
+            Ok(active) => active,
+            Err(poisoned) => poisoned.into_inner()
+        };
+
+        let count = active.entry(ip).or_insert(0);
+        if *count >= limit {
+            None
+        } else {
+            *count += 1;
+            Some(ClientGuard {
+                counter: counter.clone(),
+                ip: ip
+            })
+        }
+    }
+}
+
+//Keeps track of an open connection from a client, even if the handler panics.
+struct ClientGuard {
+    counter: Arc<ClientCounter>,
+    ip: IpAddr
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        let mut active = match self.counter.active_connections.lock() {
+            Ok(active) => active,
+            Err(poisoned) => poisoned.into_inner()
+        };
+
+        let done = match active.get_mut(&self.ip) {
+            Some(count) => {
+                *count -= 1;
+                *count == 0
+            },
+            None => false
+        };
+
+        if done {
+            active.remove(&self.ip);
+        }
+    }
+}
+
+//Split a PEM encoded certificate chain into separate certificates.
+#[cfg(feature = "ssl")]
+fn split_pem_certificates(pem: &[u8]) -> Vec<Vec<u8>> {
+    const END: &'static [u8] = b"-----END CERTIFICATE-----";
+
+    let mut certs = vec![];
+    let mut rest = pem;
+
+    while let Some(index) = rest.windows(END.len()).position(|w| w == END) {
+        let (cert, tail) = rest.split_at(index + END.len());
+        certs.push(cert.to_vec());
+        rest = tail;
+    }
+
+    certs
+}
+
+//Helper to handle multiple protocols.
+enum HyperServer {
+    Http(hyper::server::Server<SocketListener>),
+    #[cfg(feature = "ssl")]
+    Https(hyper::server::Server<TlsSocketListener<Openssl>>),
+    #[cfg(all(feature = "rustls", not(feature = "ssl")))]
+    Rustls(hyper::server::Server<TlsSocketListener<Rustls>>),
+}
+
+#[derive(Clone, Copy)]
+struct SocketOptions {
+    nodelay: bool,
+    keep_alive: Option<Duration>
+}
+
+impl SocketOptions {
+    fn apply(&self, stream: &HttpStream) -> io::Result<()> {
+        if self.nodelay {
+            try!(stream.0.set_nodelay(true));
+        }
+
+        if let Some(idle) = self.keep_alive {
+            try!(stream.0.set_keepalive(Some(idle)));
+        }
+
+        Ok(())
+    }
+}
+
+//The client slot of the connection that is handled by this thread. Hyper
+//handles each connection on the thread that accepted it, so the slot is
+//taken in `accept` and released in `on_connection_end`, or if the TLS
+//handshake fails. It's also released if the thread stops.
+thread_local!(static CONNECTION_SLOT: RefCell<Option<ClientGuard>> = RefCell::new(None));
+
+fn release_connection_slot() {
+    let slot = CONNECTION_SLOT.with(|slot| slot.borrow_mut().take());
+    drop(slot);
+}
+
+//Limits the open connections from each client address.
+#[derive(Clone)]
+struct ConnectionLimit {
+    counter: Arc<ClientCounter>,
+    limit: usize
+}
+
+impl ConnectionLimit {
+    fn new(counter: Arc<ClientCounter>, limit: usize) -> ConnectionLimit {
+        ConnectionLimit {
+            counter: counter,
+            limit: limit
+        }
+    }
+
+    fn accept(&self, stream: &mut HttpStream) -> io::Result<()> {
+        let ip = try!(stream.peer_addr()).ip();
+        match ClientCounter::begin_connection(&self.counter, ip, self.limit) {
+            Some(guard) => {
+                let previous = CONNECTION_SLOT.with(|slot| slot.borrow_mut().take());
+                drop(previous);
+                CONNECTION_SLOT.with(|slot| *slot.borrow_mut() = Some(guard));
+                Ok(())
+            },
+            None => Err(io::Error::new(io::ErrorKind::Other, format!("too many connections from {}", ip)))
+        }
+    }
+}
+
+//A listening socket that is shared by the worker threads of a listener,
+//and that can be closed while they are waiting for connections.
+struct ListenerSocket {
+    listener: RwLock<Option<TcpListener>>,
+    address: SocketAddr,
+    closed: AtomicBool
+}
+
+impl ListenerSocket {
+    fn new(listener: TcpListener) -> io::Result<ListenerSocket> {
+        Ok(ListenerSocket {
+            address: try!(listener.local_addr()),
+            listener: RwLock::new(Some(listener)),
+            closed: AtomicBool::new(false)
+        })
+    }
+
+    //Wait for a connection. It's `None` if the socket has been closed.
+    fn accept(&self) -> io::Result<Option<TcpStream>> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+
+        let listener = match self.listener.read() {
+            Ok(listener) => listener,
+            Err(poisoned) => poisoned.into_inner()
+        };
+
+        let stream = match *listener {
+            Some(ref listener) => try!(listener.accept()).0,
+            None => return Ok(None)
+        };
+
+        //The connection may be one of the wake-up calls from `close`.
+        if self.closed.load(Ordering::SeqCst) {
+            Ok(None)
+        } else {
+            Ok(Some(stream))
+        }
+    }
+
+    //Stop accepting connections and close the socket. There's no portable
+    //way to interrupt a blocking `accept`, so the waiting threads are woken
+    //by connecting to the socket, until none of them is using it.
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+
+        let wake_address = match self.address {
+            SocketAddr::V4(ref address) if address.ip().is_unspecified() => SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), address.port()),
+            SocketAddr::V6(ref address) if address.ip().is_unspecified() => SocketAddr::new(IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1)), address.port()),
+            address => address
+        };
+
+        loop {
+            match self.listener.try_write() {
+                Ok(mut listener) => {
+                    *listener = None;
+                    return;
+                },
+                Err(TryLockError::Poisoned(poisoned)) => {
+                    *poisoned.into_inner() = None;
+                    return;
+                },
+                Err(TryLockError::WouldBlock) => {
+                    let _ = TcpStream::connect(wake_address);
+                    thread::sleep(Duration::from_millis(1));
+                }
+            }
+        }
+    }
+}
+
+//Sets the socket options on each accepted connection, and closes it right
+//away if its client has too many open connections.
+#[derive(Clone)]
+struct SocketListener {
+    socket: Arc<ListenerSocket>,
+    options: SocketOptions,
+    connections: Option<ConnectionLimit>
+}
+
+impl NetworkListener for SocketListener {
+    type Stream = HttpStream;
+
+    fn accept(&mut self) -> HttpResult<HttpStream> {
+        let mut stream = match try!(self.socket.accept()) {
+            Some(stream) => HttpStream(stream),
+            //The server is shutting down and this worker has nothing more to
+            //do. Hyper would call `accept` again right away if it got an
+            //error.
+            None => loop {
+                thread::park();
+            }
+        };
+        try!(self.options.apply(&stream));
+        if let Some(ref connections) = self.connections {
+            //The stream is closed when it's dropped with the error.
+            try!(connections.accept(&mut stream));
+        }
+        Ok(stream)
+    }
+
+    fn local_addr(&mut self) -> io::Result<SocketAddr> {
+        Ok(self.socket.address)
+    }
+}
+
+//Sets the socket options on each accepted connection, before the TLS
+//handshake.
+#[cfg(any(feature = "ssl", feature = "rustls"))]
+#[derive(Clone)]
+struct TlsSocketListener<S> {
+    listener: SocketListener,
+    ssl: S
+}
+
+#[cfg(any(feature = "ssl", feature = "rustls"))]
+impl<S: Ssl + Clone> NetworkListener for TlsSocketListener<S> {
+    type Stream = S::Stream;
+
+    fn accept(&mut self) -> HttpResult<S::Stream> {
+        let stream = try!(self.listener.accept());
+        self.ssl.wrap_server(stream).map_err(|e| {
+            //Hyper won't handle the connection, so it won't end it either.
+            release_connection_slot();
+            e
+        })
+    }
+
+    fn local_addr(&mut self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+}
+
+#[cfg(feature = "ssl")]
+fn openssl_error<E: ::std::error::Error + Send + Sync + 'static>(e: E) -> ServerError {
+    ServerError::Tls(hyper::Error::Ssl(Box::new(e)))
+}
+
+//Apply the cipher list, the protocol versions and the client
+//authentication settings.
+#[cfg(feature = "ssl")]
+fn configure_openssl(context: &mut ::openssl::ssl::SslContext, tls: &TlsConfig) -> Result<(), ServerError> {
+    use openssl::ssl::{SslContextOptions, SSL_VERIFY_NONE, SSL_VERIFY_PEER, SSL_VERIFY_FAIL_IF_NO_PEER_CERT};
+    use openssl::ssl::{SSL_OP_NO_SSLV2, SSL_OP_NO_SSLV3, SSL_OP_NO_TLSV1, SSL_OP_NO_TLSV1_1, SSL_OP_NO_TLSV1_2};
+
+    let ciphers = tls.ciphers.as_ref().map_or("DEFAULT", |ciphers| &ciphers[..]);
+    try!(context.set_cipher_list(ciphers).map_err(openssl_error));
+
+    //Older versions are disabled one by one, since there is no direct way
+    //to set the range.
+    let mut disabled = SslContextOptions::empty();
+    match tls.min_version {
+        None => {},
+        Some(TlsVersion::Tls10) => disabled = SSL_OP_NO_SSLV2 | SSL_OP_NO_SSLV3,
+        Some(TlsVersion::Tls11) => disabled = SSL_OP_NO_SSLV2 | SSL_OP_NO_SSLV3 | SSL_OP_NO_TLSV1,
+        Some(TlsVersion::Tls12) => disabled = SSL_OP_NO_SSLV2 | SSL_OP_NO_SSLV3 | SSL_OP_NO_TLSV1 | SSL_OP_NO_TLSV1_1,
+        Some(TlsVersion::Tls13) => return Err(ServerError::Config("TLS 1.3 is not supported by OpenSSL".to_owned())),
+    }
+    match tls.max_version {
+        None | Some(TlsVersion::Tls12) | Some(TlsVersion::Tls13) => {},
+        Some(TlsVersion::Tls11) => disabled = disabled | SSL_OP_NO_TLSV1_2,
+        Some(TlsVersion::Tls10) => disabled = disabled | SSL_OP_NO_TLSV1_1 | SSL_OP_NO_TLSV1_2,
+    }
+    if !disabled.is_empty() {
+        context.set_options(disabled);
+    }
+
+    match tls.client_auth {
+        ClientAuth::None => context.set_verify(SSL_VERIFY_NONE, None),
+        ClientAuth::Optional(ref ca) => {
+            try!(context.set_CA_file(ca).map_err(openssl_error));
+            context.set_verify(SSL_VERIFY_PEER, None);
+        },
+        ClientAuth::Required(ref ca) => {
+            try!(context.set_CA_file(ca).map_err(openssl_error));
+            context.set_verify(SSL_VERIFY_PEER | SSL_VERIFY_FAIL_IF_NO_PEER_CERT, None);
+        },
+    }
+
+    Ok(())
+}
+
+//Bind a listener to `host`, with a custom backlog if one is set.
+fn bind(host: SocketAddr, backlog: Option<u32>) -> io::Result<TcpListener> {
+    let listener = match backlog {
+        Some(backlog) => {
+            let builder = try!(match host {
+                SocketAddr::V4(_) => TcpBuilder::new_v4(),
+                SocketAddr::V6(_) => TcpBuilder::new_v6()
+            });
+
+            //The standard library does the same in `TcpListener::bind`.
+            if cfg!(unix) {
+                try!(builder.reuse_address(true));
+            }
+
+            try!(builder.bind(host));
+            try!(builder.listen(backlog as i32))
+        },
+        None => try!(TcpListener::bind(host))
+    };
+
+    Ok(listener)
+}
+
+impl HyperServer {
+    #[cfg(any(feature = "ssl", feature = "rustls"))]
+    fn new(listener: SocketListener, scheme: Scheme, tls: &TlsConfig) -> Result<HyperServer, ServerError> {
+        match scheme {
+            Scheme::Http => Ok(HyperServer::http(listener)),
+            Scheme::Https {cert, key} => HyperServer::https(listener, cert, key, tls),
+            Scheme::HttpsPem {cert, key} => HyperServer::https_pem(listener, &cert, &key, tls),
+        }
+    }
+
+    #[cfg(not(any(feature = "ssl", feature = "rustls")))]
+    fn new(listener: SocketListener, _scheme: Scheme, _tls: &TlsConfig) -> Result<HyperServer, ServerError> {
+        Ok(HyperServer::http(listener))
+    }
+
+    fn http(listener: SocketListener) -> HyperServer {
+        HyperServer::Http(hyper::server::Server::new(listener))
+    }
+
+    #[cfg(feature = "ssl")]
+    fn https(listener: SocketListener, cert: PathBuf, key: PathBuf, tls: &TlsConfig) -> Result<HyperServer, ServerError> {
+        use openssl::ssl::{SslContext, SslMethod};
+        use openssl::x509::X509FileType;
+
+        //The same as `Openssl::with_cert_and_key`, but with the settings
+        //from `tls`.
+        let mut context = try!(SslContext::new(SslMethod::Sslv23).map_err(openssl_error));
+        try!(context.set_certificate_file(&cert, X509FileType::PEM).map_err(openssl_error));
+        try!(context.set_private_key_file(&key, X509FileType::PEM).map_err(openssl_error));
+        try!(context.check_private_key().map_err(openssl_error));
+        try!(configure_openssl(&mut context, tls));
+
+        let ssl = Openssl {
+            context: ::std::sync::Arc::new(context)
+        };
+        Ok(HyperServer::Https(hyper::server::Server::new(TlsSocketListener {
+            listener: listener,
+            ssl: ssl
+        })))
+    }
+
+    #[cfg(all(feature = "rustls", not(feature = "ssl")))]
+    fn https(listener: SocketListener, cert: PathBuf, key: PathBuf, tls: &TlsConfig) -> Result<HyperServer, ServerError> {
+        let ssl = try!(tls::acceptor_from_files(&cert, &key, tls));
+        Ok(HyperServer::Rustls(hyper::server::Server::new(TlsSocketListener {
+            listener: listener,
+            ssl: ssl
+        })))
+    }
+
+    #[cfg(feature = "ssl")]
+    fn https_pem(listener: SocketListener, cert: &[u8], key: &[u8], tls: &TlsConfig) -> Result<HyperServer, ServerError> {
+        use openssl::ssl::{SslContext, SslMethod};
+        use openssl::x509::X509;
+        use openssl::crypto::pkey::PKey;
+
+        let mut context = try!(SslContext::new(SslMethod::Sslv23).map_err(openssl_error));
+
+        //The first certificate is the server's own, and the rest is the chain
+        let mut certs = split_pem_certificates(cert).into_iter();
+        match certs.next() {
+            Some(cert) => {
+                let cert = try!(X509::from_pem(&mut &cert[..]).map_err(openssl_error));
+                try!(context.set_certificate(&cert).map_err(openssl_error));
+            },
+            None => return Err(openssl_error(::std::io::Error::new(::std::io::ErrorKind::InvalidData, "no certificates found")))
+        }
+        for cert in certs {
+            let cert = try!(X509::from_pem(&mut &cert[..]).map_err(openssl_error));
+            try!(context.add_extra_chain_cert(&cert).map_err(openssl_error));
+        }
+
+        let key = try!(PKey::private_key_from_pem(&mut &key[..]).map_err(openssl_error));
+        try!(context.set_private_key(&key).map_err(openssl_error));
+        try!(context.check_private_key().map_err(openssl_error));
+        try!(configure_openssl(&mut context, tls));
+
+        let ssl = Openssl {
+            context: ::std::sync::Arc::new(context)
+        };
+        Ok(HyperServer::Https(hyper::server::Server::new(TlsSocketListener {
+            listener: listener,
+            ssl: ssl
+        })))
+    }
+
+    #[cfg(all(feature = "rustls", not(feature = "ssl")))]
+    fn https_pem(listener: SocketListener, cert: &[u8], key: &[u8], tls: &TlsConfig) -> Result<HyperServer, ServerError> {
+        let ssl = try!(tls::acceptor(&mut &cert[..], &mut &key[..], tls));
+        Ok(HyperServer::Rustls(hyper::server::Server::new(TlsSocketListener {
+            listener: listener,
+            ssl: ssl
+        })))
+    }
+
+    fn keep_alive(&mut self, timeout: Option<Duration>) {
+        match *self {
+            HyperServer::Http(ref mut s) => s.keep_alive(timeout),
+            #[cfg(feature = "ssl")]
+            HyperServer::Https(ref mut s) => s.keep_alive(timeout),
+            #[cfg(all(feature = "rustls", not(feature = "ssl")))]
+            HyperServer::Rustls(ref mut s) => s.keep_alive(timeout),
+        }
+    }
+
+    fn timeouts(&mut self, read: Option<Duration>, write: Option<Duration>) {
+        match *self {
+            HyperServer::Http(ref mut s) => {
+                s.set_read_timeout(read);
+                s.set_write_timeout(write);
+            },
+            #[cfg(feature = "ssl")]
+            HyperServer::Https(ref mut s) => {
+                s.set_read_timeout(read);
+                s.set_write_timeout(write);
+            },
+            #[cfg(all(feature = "rustls", not(feature = "ssl")))]
+            HyperServer::Rustls(ref mut s) => {
+                s.set_read_timeout(read);
+                s.set_write_timeout(write);
+            },
+        }
+    }
+
+    fn is_https(&self) -> bool {
+        match *self {
+            HyperServer::Http(_) => false,
+            #[cfg(feature = "ssl")]
+            HyperServer::Https(_) => true,
+            #[cfg(all(feature = "rustls", not(feature = "ssl")))]
+            HyperServer::Rustls(_) => true,
+        }
+    }
+
+    fn run<H: HyperHandler + 'static>(self, handler: H, threads: usize) -> Result<hyper::server::Listening, ServerError> {
+        match self {
+            HyperServer::Http(s) => s.handle_threads(handler, threads),
+            #[cfg(feature = "ssl")]
+            HyperServer::Https(s) => s.handle_threads(handler, threads),
+            #[cfg(all(feature = "rustls", not(feature = "ssl")))]
+            HyperServer::Rustls(s) => s.handle_threads(handler, threads),
+        }.map_err(ServerError::Http)
+    }
+}
+
+
+#[test]
+fn parse_path_parts() {
+    let with = "this".to_owned().into();
+    let and = "that".to_owned().into();
+    let ParsedUri { uri, query, fragment, .. } = parse_path("/path/to/something?with=this&and=that#lol", false, None).unwrap();
+    assert_eq!(uri.as_path(), Some("/path/to/something".into()));
+    assert_eq!(query.get_raw("with"), Some(&with));
+    assert_eq!(query.get_raw("and"), Some(&and));
+    assert_eq!(fragment, Some("lol".to_owned().into()));
+}
+
+#[test]
+fn parse_path_parts_with_repeated_keys() {
+    let one = "1".to_owned().into();
+    let two = "2".to_owned().into();
+    let three = "3".to_owned().into();
+    let b = "b".to_owned().into();
+    let ParsedUri { query, .. } = parse_path("/path?a=1&b=b&a=2&a=3", false, None).unwrap();
+    assert_eq!(query.get_raw("a"), Some(&three));
+    assert_eq!(query.get_all("a"), vec![&one, &two, &three]);
+    assert_eq!(query.get_all("b"), vec![&b]);
+    assert!(query.get_all("c").is_empty());
+}
+
+#[test]
+fn parse_strange_path() {
+    let with = "this".to_owned().into();
+    let and = "what?".to_owned().into();
+    let ParsedUri { uri, query, fragment, .. } = parse_path("/path/to/something?with=this&and=what?#", false, None).unwrap();
+    assert_eq!(uri.as_path(), Some("/path/to/something".into()));
+    assert_eq!(query.get_raw("with"), Some(&with));
+    assert_eq!(query.get_raw("and"), Some(&and));
+    assert_eq!(fragment, Some(String::new().into()));
+}
+
+#[test]
+fn parse_missing_path_parts() {
+    let with = "this".to_owned().into();
+    let and = "that".to_owned().into();
+    let ParsedUri { uri, query, fragment, .. } = parse_path("/path/to/something?with=this&and=that", false, None).unwrap();
+    assert_eq!(uri.as_path(), Some("/path/to/something".into()));
+    assert_eq!(query.get_raw("with"), Some(&with));
+    assert_eq!(query.get_raw("and"), Some(&and));
     assert_eq!(fragment, None);
 
 
-    let ParsedUri { uri, query, fragment, .. } = parse_path("/path/to/something#lol");
+    let ParsedUri { uri, query, fragment, .. } = parse_path("/path/to/something#lol", false, None).unwrap();
     assert_eq!(uri.as_path(), Some("/path/to/something".into()));
     assert_eq!(query.len(), 0);
     assert_eq!(fragment, Some("lol".to_owned().into()));
 
 
-    let ParsedUri { uri, query, fragment, .. } = parse_path("?with=this&and=that#lol");
+    let ParsedUri { uri, query, fragment, .. } = parse_path("?with=this&and=that#lol", false, None).unwrap();
     assert_eq!(uri.as_path(), Some("/".into()));
     assert_eq!(query.get_raw("with"), Some(&with));
     assert_eq!(query.get_raw("and"), Some(&and));
@@ -432,19 +2037,33 @@ fn parse_url_parts() {
     let with = "this".to_owned().into();
     let and = "that".to_owned().into();
     let url = Url::parse("http://example.com/path/to/something?with=this&and=that#lol").unwrap();
-    let ParsedUri { uri, query, fragment, .. } = parse_url(url);
+    let ParsedUri { uri, query, fragment, .. } = parse_url(url, false, None).unwrap();
     assert_eq!(uri.as_path(), Some("/path/to/something".into()));
     assert_eq!(query.get_raw("with"), Some(&with));
     assert_eq!(query.get_raw("and"), Some(&and));
     assert_eq!(fragment, Some("lol".to_owned().into()));
 }
 
+#[test]
+fn parse_url_target() {
+    let url = Url::parse("https://Example.com:8443/path").unwrap();
+    let ParsedUri { target, .. } = parse_url(url, false, None).unwrap();
+    assert_eq!(target, Some(AbsoluteTarget {
+        scheme: "https".to_owned(),
+        host: "example.com".to_owned(),
+        port: Some(8443)
+    }));
+
+    let ParsedUri { target, .. } = parse_path("/path", false, None).unwrap();
+    assert_eq!(target, None);
+}
+
 #[test]
 fn parse_strange_url() {
     let with = "this".to_owned().into();
     let and = "what?".to_owned().into();
     let url = Url::parse("http://example.com/path/to/something?with=this&and=what?#").unwrap();
-    let ParsedUri { uri, query, fragment, .. } = parse_url(url);
+    let ParsedUri { uri, query, fragment, .. } = parse_url(url, false, None).unwrap();
     assert_eq!(uri.as_path(), Some("/path/to/something".into()));
     assert_eq!(query.get_raw("with"), Some(&with));
     assert_eq!(query.get_raw("and"), Some(&and));
@@ -456,7 +2075,7 @@ fn parse_missing_url_parts() {
     let with = "this".to_owned().into();
     let and = "that".to_owned().into();
     let url = Url::parse("http://example.com/path/to/something?with=this&and=that").unwrap();
-    let ParsedUri { uri, query, fragment, .. } = parse_url(url);
+    let ParsedUri { uri, query, fragment, .. } = parse_url(url, false, None).unwrap();
     assert_eq!(uri.as_path(), Some("/path/to/something".into()));
     assert_eq!(query.get_raw("with"), Some(&with));
     assert_eq!(query.get_raw("and"), Some(&and));
@@ -464,16 +2083,483 @@ fn parse_missing_url_parts() {
 
 
     let url = Url::parse("http://example.com/path/to/something#lol").unwrap();
-    let ParsedUri { uri, query, fragment, .. } = parse_url(url);
+    let ParsedUri { uri, query, fragment, .. } = parse_url(url, false, None).unwrap();
     assert_eq!(uri.as_path(), Some("/path/to/something".into()));
     assert_eq!(query.len(), 0);
     assert_eq!(fragment, Some("lol".to_owned().into()));
 
 
     let url = Url::parse("http://example.com?with=this&and=that#lol").unwrap();
-    let ParsedUri { uri, query, fragment, .. } = parse_url(url);
+    let ParsedUri { uri, query, fragment, .. } = parse_url(url, false, None).unwrap();
     assert_eq!(uri.as_path(), Some("/".into()));
     assert_eq!(query.get_raw("with"), Some(&with));
     assert_eq!(query.get_raw("and"), Some(&and));
     assert_eq!(fragment, Some("lol".to_owned().into()));
 }
+
+#[test]
+fn forwarded_for_trusted_proxies() {
+    let trusted = vec!["10.0.0.0/8".parse().unwrap()];
+    let proxy = "10.0.0.1".parse().unwrap();
+
+    let mut headers = Headers::new();
+    headers.set_raw("X-Forwarded-For", vec![b"1.1.1.1, 2.2.2.2, 10.0.0.2".to_vec()]);
+    assert_eq!(forwarded_client_ip(proxy, &headers, &trusted), Some("2.2.2.2".parse().unwrap()));
+    assert_eq!(forwarded_client_ip("3.3.3.3".parse().unwrap(), &headers, &trusted), None);
+
+    let mut headers = Headers::new();
+    headers.set_raw("Forwarded", vec![b"for=1.1.1.1, for=\"[2001:db8::1]:4711\";proto=https".to_vec()]);
+    assert_eq!(forwarded_client_ip(proxy, &headers, &trusted), Some("2001:db8::1".parse().unwrap()));
+
+    let mut headers = Headers::new();
+    headers.set_raw("X-Forwarded-For", vec![b"10.0.0.3".to_vec()]);
+    assert_eq!(forwarded_client_ip(proxy, &headers, &trusted), Some("10.0.0.3".parse().unwrap()));
+}
+
+#[test]
+fn parse_raw_query() {
+    let ParsedUri { raw_query, .. } = parse_path("/path?b=2&a=%201#lol", false, None).unwrap();
+    assert_eq!(raw_query, Some("b=2&a=%201".to_owned().into()));
+
+    let ParsedUri { raw_query, .. } = parse_path("/path#lol", false, None).unwrap();
+    assert_eq!(raw_query, None);
+}
+
+#[test]
+fn limit_query_parameters() {
+    assert!(parse_path("/path?a=1&b=2", false, Some(2)).is_some());
+    assert!(parse_path("/path?a=1&b=2&a=3", false, Some(2)).is_none());
+    assert!(parse_path("/path?a=1;b=2;c=3", true, Some(2)).is_none());
+
+    let url = Url::parse("http://example.com/path?a=1&b=2&c=3").unwrap();
+    assert!(parse_url(url, false, Some(2)).is_none());
+}
+
+#[test]
+fn normalize_paths() {
+    fn normalize(path: &str) -> Option<String> {
+        normalize_path(path.as_bytes()).map(|path| String::from_utf8(path).unwrap())
+    }
+
+    assert_eq!(normalize("/"), None);
+    assert_eq!(normalize("/a/b"), None);
+    assert_eq!(normalize("/a/b/"), None);
+    assert_eq!(normalize("//a//b"), Some("/a/b".into()));
+    assert_eq!(normalize("/a/./b/."), Some("/a/b/".into()));
+    assert_eq!(normalize("/a/../b"), Some("/b".into()));
+    assert_eq!(normalize("/a/b/.."), Some("/a/".into()));
+    assert_eq!(normalize("/../../etc/passwd"), Some("/etc/passwd".into()));
+
+    let ParsedUri { uri, .. } = parse_path("/static/%2e%2e/%2E%2E/secret", false, None).unwrap();
+    let path = uri.as_path().unwrap();
+    assert_eq!(normalize_path(&path), Some(b"/secret".to_vec()));
+}
+
+#[test]
+fn detect_invalid_utf8() {
+    assert!(parse_path("/p%C3%A5th?a=%C3%A5#%C3%A5", false, None).unwrap().is_utf8());
+    assert!(!parse_path("/p%FFth", false, None).unwrap().is_utf8());
+    assert!(!parse_path("/path?a=1&a=%FF", false, None).unwrap().is_utf8());
+    assert!(!parse_path("/path?%FF=1", false, None).unwrap().is_utf8());
+    assert!(!parse_path("/path#%FF", false, None).unwrap().is_utf8());
+}
+
+#[test]
+fn parse_path_parts_with_semicolons() {
+    let a = "1".to_owned().into();
+    let b = "2".to_owned().into();
+    let ParsedUri { uri, query, fragment, .. } = parse_path("/path/to/something?a=1;b=2#lol", true, None).unwrap();
+    assert_eq!(uri.as_path(), Some("/path/to/something".into()));
+    assert_eq!(query.get_raw("a"), Some(&a));
+    assert_eq!(query.get_raw("b"), Some(&b));
+    assert_eq!(fragment, Some("lol".to_owned().into()));
+
+    let ab = "1;b".to_owned().into();
+    let ParsedUri { query, .. } = parse_path("/path/to/something?a=1;b=2#lol", false, None).unwrap();
+    assert_eq!(query.get_raw("a"), Some(&ab));
+    assert_eq!(query.get_raw("b"), None);
+}
+
+#[test]
+fn parse_url_parts_with_semicolons() {
+    let a = "1".to_owned().into();
+    let b = "2".to_owned().into();
+    let url = Url::parse("http://example.com/path/to/something?a=1;b=2#lol").unwrap();
+    let ParsedUri { uri, query, fragment, .. } = parse_url(url, true, None).unwrap();
+    assert_eq!(uri.as_path(), Some("/path/to/something".into()));
+    assert_eq!(query.get_raw("a"), Some(&a));
+    assert_eq!(query.get_raw("b"), Some(&b));
+    assert_eq!(fragment, Some("lol".to_owned().into()));
+}
+
+#[test]
+fn measure_request_head() {
+    let mut headers = Headers::new();
+    headers.set_raw("Host", vec![b"example.com".to_vec()]);
+    headers.set_raw("Cookie", vec![b"a=1".to_vec(), b"b=2".to_vec()]);
+
+    //"GET /path HTTP/1.1\r\n"
+    let request_line = 3 + 1 + 5 + 9 + 2;
+    //"Host: example.com\r\n" and two "Cookie: a=1\r\n"
+    let header_lines = (4 + 2 + 11 + 2) + 2 * (6 + 2 + 3 + 2);
+
+    let uri = RequestUri::AbsolutePath("/path".to_owned());
+    assert_eq!(request_head_size(&Method::Get, &uri, &headers), request_line + header_lines);
+}
+
+#[test]
+fn limit_connections_per_client() {
+    let counter = Arc::new(ClientCounter::new());
+    let mut first_worker = SocketListener {
+        socket: Arc::new(ListenerSocket::new(bind("127.0.0.1:0".parse().unwrap(), None).unwrap()).unwrap()),
+        options: SocketOptions {
+            nodelay: false,
+            keep_alive: None
+        },
+        connections: Some(ConnectionLimit::new(counter.clone(), 1))
+    };
+    let mut second_worker = first_worker.clone();
+    let address = first_worker.local_addr().unwrap();
+
+    let _first = TcpStream::connect(address).unwrap();
+    let _second = TcpStream::connect(address).unwrap();
+    let _third = TcpStream::connect(address).unwrap();
+
+    let first = first_worker.accept().unwrap();
+    assert!(second_worker.accept().is_err());
+    assert_eq!(counter.active_connections.lock().unwrap().len(), 1);
+
+    //The slot is free for any worker when the connection has ended, as in
+    //`on_connection_end`.
+    drop(first);
+    release_connection_slot();
+    assert!(counter.active_connections.lock().unwrap().is_empty());
+    let third = second_worker.accept().unwrap();
+    assert_eq!(counter.active_connections.lock().unwrap().len(), 1);
+
+    drop(third);
+    release_connection_slot();
+    assert!(counter.active_connections.lock().unwrap().is_empty());
+}
+
+#[test]
+fn select_host_router() {
+    use std::ptr;
+
+    fn handler(_context: Context, _response: Response) {}
+
+    let mut api = ::TreeRouter::<Option<fn(Context, Response)>>::default();
+    api.insert(Method::Get, "/", handler as fn(Context, Response));
+    let mut server = Server::new(::TreeRouter::default());
+    server.host_handlers.insert("api.example.com".to_owned(), api.clone());
+    server.host_handlers.insert("Www.Example.com".to_owned(), api);
+    let (instance, _) = server.build();
+
+    let handlers = instance.handlers.get();
+    let api = &instance.host_handlers["api.example.com"];
+    let www = &instance.host_handlers["www.example.com"];
+    let select = |target: Option<&AbsoluteTarget>, host: &str| {
+        let mut headers = Headers::new();
+        headers.set_raw("Host", vec![host.as_bytes().to_vec()]);
+        instance.select_router(&handlers, target, &headers) as *const _
+    };
+
+    assert!(ptr::eq(select(None, "API.Example.com"), api));
+    assert!(ptr::eq(select(None, "api.example.com:8080"), api));
+    assert!(ptr::eq(select(None, "www.example.com"), www));
+    assert!(ptr::eq(select(None, "WWW.example.com"), www));
+    assert!(ptr::eq(select(None, "example.com"), &*handlers));
+
+    let target = AbsoluteTarget {
+        scheme: "http".to_owned(),
+        host: "Api.Example.com".to_owned(),
+        port: None
+    };
+    assert!(ptr::eq(select(Some(&target), "www.example.com"), api));
+}
+
+#[test]
+fn route_by_host() {
+    use testing::TestServer;
+
+    fn main(_context: Context, response: Response) {
+        response.send("main");
+    }
+
+    fn api(_context: Context, response: Response) {
+        response.send("api");
+    }
+
+    let mut main_router = ::TreeRouter::<Option<fn(Context, Response)>>::default();
+    main_router.insert(Method::Get, "/", main as fn(Context, Response));
+    let mut api_router = ::TreeRouter::<Option<fn(Context, Response)>>::default();
+    api_router.insert(Method::Get, "/", api as fn(Context, Response));
+    api_router.insert(Method::Get, "/users", api as fn(Context, Response));
+
+    let mut server = Server::new(main_router);
+    server.host_handlers.insert("api.example.com".to_owned(), api_router);
+    let server = TestServer::new(server);
+
+    let response = server.request(b"GET / HTTP/1.1\r\nHost: API.example.com:8080\r\n\r\n").unwrap();
+    assert_eq!(response.body_str(), Some("api"));
+
+    let response = server.request(b"GET http://api.example.com/ HTTP/1.1\r\nHost: www.example.com\r\n\r\n").unwrap();
+    assert_eq!(response.body_str(), Some("api"));
+
+    let response = server.request(b"GET / HTTP/1.1\r\nHost: www.example.com\r\n\r\n").unwrap();
+    assert_eq!(response.body_str(), Some("main"));
+
+    let response = server.request(b"GET /users HTTP/1.1\r\nHost: www.example.com\r\n\r\n").unwrap();
+    assert_eq!(response.status, StatusCode::NotFound);
+
+    let response = server.request(b"GET /missing HTTP/1.1\r\nHost: api.example.com\r\n\r\n").unwrap();
+    assert_eq!(response.status, StatusCode::NotFound);
+}
+
+#[test]
+fn parse_host_header() {
+    assert_eq!(parse_host(b"Example.com"), Some(("example.com".to_owned(), None)));
+    assert_eq!(parse_host(b"example.com:8080"), Some(("example.com".to_owned(), Some(8080))));
+    assert_eq!(parse_host(b"example.com:"), Some(("example.com".to_owned(), None)));
+    assert_eq!(parse_host(b"192.0.2.1:80"), Some(("192.0.2.1".to_owned(), Some(80))));
+    assert_eq!(parse_host(b"[2001:DB8::1]"), Some(("[2001:db8::1]".to_owned(), None)));
+    assert_eq!(parse_host(b"[2001:db8::1]:443"), Some(("[2001:db8::1]".to_owned(), Some(443))));
+    assert_eq!(parse_host(b""), Some(("".to_owned(), None)));
+
+    assert_eq!(parse_host(b"example.com:http"), None);
+    assert_eq!(parse_host(b"example.com:99999"), None);
+    assert_eq!(parse_host(b"a:b:c"), None);
+    assert_eq!(parse_host(b"[2001:db8::1"), None);
+    assert_eq!(parse_host(b"[not an ip]"), None);
+    assert_eq!(parse_host(b"[::1]x"), None);
+    assert_eq!(parse_host(b"exa mple.com"), None);
+    assert_eq!(parse_host(b"user@example.com"), None);
+    assert_eq!(parse_host(b":80"), None);
+}
+
+#[test]
+fn reject_zero_threads() {
+    fn handler(_context: Context, _response: Response) {}
+
+    let (server, scheme) = Server {
+        threads: Some(0),
+        host: 0.into(),
+        ..Server::new(handler)
+    }.build();
+
+    assert_eq!(server.threads(), 0);
+    match server.run(scheme) {
+        Err(ServerError::Config(_)) => {},
+        other => panic!("expected a configuration error, got {:?}", other)
+    }
+}
+
+#[test]
+fn validate_configuration() {
+    fn handler(_context: Context, _response: Response) {}
+
+    assert!(Server::new(handler as fn(Context, Response)).validate().is_ok());
+    assert!(Server { threads: Some(0), ..Server::new(handler) }.validate().is_err());
+    assert!(Server { max_connections_per_ip: Some(0), ..Server::new(handler) }.validate().is_err());
+
+    let empty = ::TreeRouter::<Option<fn(Context, Response)>>::default();
+    assert!(Server::new(empty.clone()).validate().is_err());
+    assert!(Server { fallback_handler: Some(handler as fn(Context, Response)), ..Server::new(empty) }.validate().is_ok());
+
+    let mut router = ::TreeRouter::<Option<fn(Context, Response)>>::default();
+    router.insert(Method::Get, "/", handler as fn(Context, Response));
+    assert!(Server::new(router).validate().is_ok());
+
+    let tls = TlsConfig {
+        client_auth: ::server::ClientAuth::Required("does/not/exist.pem".into()),
+        ..TlsConfig::default()
+    };
+    assert!(Server { tls: tls, ..Server::new(handler) }.validate().is_err());
+
+    let tls = TlsConfig {
+        min_version: Some(::server::TlsVersion::Tls12),
+        max_version: Some(::server::TlsVersion::Tls11),
+        ..TlsConfig::default()
+    };
+    assert!(Server { tls: tls, ..Server::new(handler) }.validate().is_err());
+}
+
+#[test]
+fn report_bind_errors() {
+    fn handler(_context: Context, _response: Response) {}
+
+    let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = taken.local_addr().unwrap();
+
+    let (server, scheme) = Server {
+        host: address.into(),
+        ..Server::new(handler)
+    }.build();
+
+    match server.run(scheme) {
+        Err(ServerError::Bind(bound, _)) => assert_eq!(bound, address),
+        other => panic!("expected a bind error, got {:?}", other)
+    }
+}
+
+#[test]
+fn serve_multiple_listeners() {
+    use std::io::{Read, Write};
+
+    fn handler(_context: Context, response: Response) {
+        response.send("hello");
+    }
+
+    let localhost: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let listening = Server {
+        host: localhost.into(),
+        listeners: vec![(localhost.into(), Scheme::Http)],
+        threads: Some(1),
+        ..Server::new(handler)
+    }.run().unwrap();
+
+    let addresses = listening.local_addrs();
+    assert_eq!(addresses.len(), 2);
+    assert_eq!(addresses[0], listening.local_addr());
+
+    for address in addresses {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nhello"));
+    }
+
+    //Dropping the handle would wait for the server to stop.
+    mem::forget(listening);
+}
+
+#[test]
+fn downcast_transport() {
+    use std::io::{Read, Write};
+
+    fn handler(mut context: Context, response: Response) {
+        let local_addr = context.transport::<HttpStream>().and_then(|stream| stream.0.local_addr().ok());
+        match local_addr {
+            Some(address) => response.send(address.to_string()),
+            None => response.send("not an HttpStream")
+        }
+    }
+
+    let listening = Server {
+        host: "127.0.0.1:0".parse::<SocketAddr>().unwrap().into(),
+        threads: Some(1),
+        ..Server::new(handler)
+    }.run().unwrap();
+    let address = listening.local_addr();
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.ends_with(&format!("\r\n\r\n{}", address)));
+
+    //Dropping the handle would wait for the server to stop.
+    mem::forget(listening);
+}
+
+#[test]
+fn stop_accepting_on_shutdown() {
+    use std::io::{Read, Write};
+
+    fn handler(_context: Context, response: Response) {
+        response.send("hello");
+    }
+
+    let (listening, shutdown) = Server {
+        host: "127.0.0.1:0".parse::<SocketAddr>().unwrap().into(),
+        threads: Some(2),
+        ..Server::new(handler)
+    }.run_with_shutdown().unwrap();
+    let address = listening.local_addr();
+
+    //A connection that is kept alive while the other thread is waiting for
+    //a new one.
+    let mut open = TcpStream::connect(address).unwrap();
+    open.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut response = vec![];
+    let mut buffer = [0; 256];
+    while !response.ends_with(b"hello") {
+        let read = open.read(&mut buffer).unwrap();
+        assert!(read > 0);
+        response.extend_from_slice(&buffer[..read]);
+    }
+
+    shutdown.shutdown();
+    assert!(TcpStream::connect(address).is_err());
+
+    open.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut response = String::new();
+    open.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+    assert!(shutdown.wait_timeout(Duration::from_secs(5)));
+
+    //Dropping the handle would wait for the server to stop.
+    mem::forget(listening);
+}
+
+#[test]
+fn bind_with_backlog() {
+    assert!(bind("127.0.0.1:0".parse().unwrap(), Some(16)).is_ok());
+    assert!(bind("127.0.0.1:0".parse().unwrap(), None).is_ok());
+}
+
+#[test]
+fn limit_uri_length() {
+    let path = RequestUri::AbsolutePath("/path?a=1".to_owned());
+    assert!(!uri_too_long(&path, None));
+    assert!(!uri_too_long(&path, Some(9)));
+    assert!(uri_too_long(&path, Some(8)));
+}
+
+#[test]
+fn toggle_readiness() {
+    fn handler(_context: Context, _response: Response) {}
+
+    let (server, _) = Server {
+        start_ready: false,
+        ..Server::new(handler)
+    }.build();
+
+    let shutdown = server.shutdown_handle();
+    let uri = RequestUri::AbsolutePath("/".to_owned());
+    let headers = Headers::new();
+    assert!(!shutdown.is_ready());
+    assert_eq!(server.check_continue((&Method::Post, &uri, &headers)), StatusCode::ServiceUnavailable);
+
+    shutdown.set_ready(true);
+    assert!(server.shutdown_handle().is_ready());
+    assert_eq!(server.check_continue((&Method::Post, &uri, &headers)), StatusCode::Continue);
+}
+
+#[test]
+fn replace_router() {
+    use router::TreeRouter;
+
+    fn handler(_context: Context, _response: Response) {}
+    fn has_route<R: Router>(router: &R, path: &str) -> bool {
+        router.find(&Method::Get, &mut path.into()).handler.is_some()
+    }
+
+    let mut router = TreeRouter::new();
+    router.insert(Method::Get, "/a", handler as fn(Context, Response));
+    let (server, _) = Server::new(router).build();
+
+    let routes = server.router_handle();
+    let ongoing = routes.get();
+
+    let mut router = TreeRouter::new();
+    router.insert(Method::Get, "/b", handler as fn(Context, Response));
+    let previous = routes.swap(router);
+
+    assert!(has_route(&*ongoing, "/a"));
+    assert!(has_route(&*previous, "/a"));
+    assert!(!has_route(&*server.router_handle().get(), "/a"));
+    assert!(has_route(&*server.router_handle().get(), "/b"));
+}
@@ -1,22 +1,39 @@
 //!Server configuration and instance.
 
 use std::borrow::ToOwned;
+use std::collections::HashMap;
+use std::time::Duration;
 
 use hyper;
 use hyper::mime::Mime;
 
-pub use hyper::server::Listening;
+use time::{self, Tm};
+
+use num_cpus;
 
 use filter::{ContextFilter, ResponseFilter};
 use router::Router;
+use context::hypermedia::Link;
 
-use HttpResult;
 
-pub use self::instance::ServerInstance;
-pub use self::config::{Host, Global, Scheme, KeepAlive};
+pub use self::instance::{ServerInstance, Shutdown, RouterHandle, Listening};
+pub use self::error::ServerError;
+pub use self::builder::ServerBuilder;
+pub use self::worker::{WorkerState, WorkerInit};
+pub use self::config::{Host, Global, Scheme, KeepAlive, IpNetwork, ErrorRenderer, PathNormalization, TlsConfig, TlsVersion, ClientAuth};
+pub use self::client_cert::ClientCertificate;
+#[cfg(feature = "rustls")]
+pub use self::tls::{Rustls, TlsStream};
+pub use hyper::net::{NetworkStream, HttpStream};
 
 mod instance;
+mod error;
+mod builder;
+mod worker;
 mod config;
+mod client_cert;
+#[cfg(feature = "rustls")]
+mod tls;
 
 ///Used to set up and run a server.
 ///
@@ -44,9 +61,16 @@ pub struct Server<R: Router> {
     ///One or several response handlers.
     pub handlers: R,
 
+    ///Additional response handlers for specific host names. The host name is
+    ///taken from the request URI, if it's in absolute form, or from the
+    ///`Host` header, and requests for any other host will be routed to
+    ///`handlers`. The host names are not case sensitive.
+    pub host_handlers: HashMap<String, R>,
+
     ///A fallback handler for when none is found in `handlers`. Leaving this
-    ///unspecified will cause an empty `404` response to be automatically sent
-    ///instead.
+    ///unspecified will cause a `404` response to be automatically sent
+    ///instead, or a `405` response if the path is known, but not the method.
+    ///Their bodies are rendered by `error_renderer`.
     pub fallback_handler: Option<R::Handler>,
 
     ///The host address and port where the server will listen for requests.
@@ -56,9 +80,41 @@ pub struct Server<R: Router> {
     ///Use good old HTTP or the more secure HTTPS. Default is HTTP.
     pub scheme: Scheme,
 
+    ///Additional addresses to listen to, each with its own scheme. This
+    ///makes it possible to serve both HTTP and HTTPS from the same server,
+    ///with the same handlers, filters and global data. Each listener has its
+    ///own thread pool, with `threads` threads. Default is no additional
+    ///listeners.
+    ///
+    ///```no_run
+    ///# #[cfg(any(feature = "ssl", feature = "rustls"))]
+    ///# fn main() {
+    ///use rustful::{Server, Context, Response};
+    ///use rustful::server::Scheme;
+    ///
+    ///# fn my_handler(context: Context, response: Response) {}
+    ///let listening = Server {
+    ///    host: 80.into(),
+    ///    listeners: vec![(443.into(), Scheme::Https {
+    ///        cert: "cert.pem".into(),
+    ///        key: "key.pem".into()
+    ///    })],
+    ///    ..Server::new(my_handler)
+    ///}.run().unwrap();
+    ///# }
+    ///# #[cfg(not(any(feature = "ssl", feature = "rustls")))]
+    ///# fn main() {}
+    ///```
+    pub listeners: Vec<(Host, Scheme)>,
+
+    ///TLS settings for the HTTPS listeners, such as client certificate
+    ///authentication. Default is no client certificates.
+    pub tls: TlsConfig,
+
     ///The number of threads to be used in the server thread pool. The default
     ///(`None`) will cause the server to optimistically use the formula
-    ///`(num_cores * 5) / 4`.
+    ///`(num_cores * 5) / 4`. It has to be at least `1`, or the server will
+    ///refuse to start. See also `threads_per_cpu`.
     pub threads: Option<usize>,
 
     ///The server's `keep-alive` policy. Setting this to `Some(...)` will
@@ -66,19 +122,207 @@ pub struct Server<R: Router> {
     ///will force connections to close after each request. Default is `None`.
     pub keep_alive: Option<KeepAlive>,
 
-    ///The content of the server header. Default is `"rustful"`.
+    ///The maximum number of open connections from the same client IP
+    ///address. Idle keep-alive connections are counted as well, since they
+    ///keep a worker thread busy. Connections over the limit are closed as
+    ///soon as they are accepted, without a response. The address of the
+    ///directly connected peer is used, so clients behind the same proxy
+    ///share the limit. Default is `None`, which means no limit.
+    pub max_connections_per_ip: Option<usize>,
+
+    ///The maximum number of connections that may wait to be accepted. The
+    ///operating system may drop connection attempts when this queue is
+    ///full, so it may have to be raised to handle sudden spikes. The system
+    ///may also silently cap it to its own limit, such as `somaxconn` on
+    ///Linux. Default is `None`, which uses the standard library default of
+    ///128.
+    pub backlog: Option<u32>,
+
+    ///Disable Nagle's algorithm (`TCP_NODELAY`) for each connection, so
+    ///small writes are sent immediately instead of being held back while
+    ///waiting for more data. This reduces latency for interactive use, at the
+    ///cost of sending more packets. Default is `true`.
+    pub tcp_nodelay: bool,
+
+    ///Enable TCP keep-alive probes (`SO_KEEPALIVE`) for each connection,
+    ///after it has been idle for the given duration. This detects clients
+    ///that have disappeared without closing their connections, and is
+    ///unrelated to HTTP `keep_alive`. The duration is rounded to whole
+    ///seconds on most platforms, and it's only a hint on some platforms, such
+    ///as older versions of Windows, where the system wide setting may be used
+    ///instead. Default is `None`, which leaves it off.
+    pub tcp_keep_alive: Option<Duration>,
+
+    ///The maximum time it may take to read a request. It's used as the socket
+    ///read timeout, for clients that stop sending, and as a deadline for the
+    ///whole request body, for clients that send it too slowly. Reading the
+    ///body after the deadline results in a `BodyError::TimedOut` error, which
+    ///translates to `408 Request Timeout`. It can be replaced for specific
+    ///routes, using `TreeRouter::insert_timeout`. Default is `None`, which
+    ///means no limit.
+    pub read_timeout: Option<Duration>,
+
+    ///The maximum time a single write to the client may block. The response
+    ///will fail and the connection will be closed if the client stops
+    ///receiving. Handlers are not interrupted, so it doesn't limit the time
+    ///it takes to produce the response. It can be replaced for specific
+    ///routes, using `TreeRouter::insert_timeout`. Default is `None`, which
+    ///means no limit.
+    pub write_timeout: Option<Duration>,
+
+    ///The clock that is used for the `Date` header. It can be replaced with
+    ///a fixed time to make responses predictable in tests:
+    ///
+    ///```
+    ///extern crate rustful;
+    ///extern crate time;
+    ///
+    ///use rustful::{Server, Context, Response};
+    ///
+    ///# fn my_handler(context: Context, response: Response) {}
+    ///# fn main() {
+    ///let server = Server {
+    ///    clock: Box::new(|| time::at_utc(time::Timespec::new(1234567890, 0))),
+    ///    ..Server::new(my_handler)
+    ///};
+    ///# }
+    ///```
+    ///
+    ///Default is `time::now_utc`.
+    pub clock: Box<Fn() -> Tm + Send + Sync>,
+
+    ///The content of the `Server` header. An empty string will leave the
+    ///header out. It's set before the handler is called, so it can be
+    ///changed or removed for individual responses, using
+    ///`Response::headers_mut` or a response filter. Default is `"rustful"`.
     pub server: String,
 
     ///The default media type. Default is `text/plain, charset: UTF-8`.
     pub content_type: Mime,
 
+    ///Automatically answer `OPTIONS` requests with an `Allow` header, listing
+    ///the available methods, if there is no `OPTIONS` handler for the
+    ///requested path. An asterisk (`*`) path will list every method in the
+    ///router. Default is `true`.
+    pub auto_options: bool,
+
+    ///The maximum number of bytes that will be read when a request body is
+    ///buffered, using `BodyReader::read_body` and similar methods. Larger
+    ///bodies will be rejected. Requests with a larger `Content-Length` are
+    ///rejected with `413 Payload Too Large` before the body is read, or even
+    ///sent, if the client uses `Expect: 100-continue`. Bodies without a
+    ///known length are only checked while they are read. Default is `None`,
+    ///which means no limit.
+    pub max_body_size: Option<u64>,
+
+    ///The maximum size of a request body after its `Content-Encoding` has
+    ///been decoded, in bytes. Compressed bodies may expand to many times
+    ///their size, so this limit applies even if `max_body_size` is `None`.
+    ///Larger bodies are rejected with `BodyError::TooLarge`, which translates
+    ///to `413 Payload Too Large`. The smallest of this and `max_body_size` is
+    ///used. Default is `Some(16777216)` (16 MiB), and `None` means no limit.
+    pub max_decoded_body_size: Option<u64>,
+
+    ///The maximum size of the request head, in bytes, including the request
+    ///line and all of the headers. Larger requests are rejected with `431
+    ///Request Header Fields Too Large`, before the context filters and the
+    ///router are invoked. The size is measured after the head has been
+    ///parsed, so hyper's own limits apply as well. Default is `Some(16384)`,
+    ///and `None` means no limit.
+    pub max_header_size: Option<usize>,
+
+    ///The maximum length of the request target, in bytes, as it was
+    ///received. Requests with longer targets are rejected with `414 URI Too
+    ///Long`, before the target is parsed. Default is `Some(8192)`, and `None`
+    ///means no limit.
+    pub max_uri_length: Option<usize>,
+
+    ///Let the `X-Forwarded-Proto` and `X-Forwarded-Port` headers override
+    ///the request scheme and port in `Context`. This should only be enabled
+    ///when the server is behind a trusted proxy that sets these headers.
+    ///Default is `false`.
+    pub forwarded_headers: bool,
+
+    ///Proxies that are trusted to report the client address through the
+    ///`Forwarded` or `X-Forwarded-For` headers. See `Context::real_ip` for
+    ///more details. Default is no proxies.
+    pub trusted_proxies: Vec<IpNetwork>,
+
+    ///Treat `;` as a query parameter separator, in addition to `&`. Default
+    ///is `false`.
+    pub query_semicolons: bool,
+
+    ///The maximum number of query parameters, or form parameters when the
+    ///body is parsed as a form. Requests with more query parameters are
+    ///rejected with `400 Bad Request`. Default is `Some(1000)`, and `None`
+    ///means no limit.
+    pub max_parameters: Option<usize>,
+
+    ///Reject requests with `400 Bad Request` if their path, query or
+    ///fragment is not valid UTF-8 after percent decoding. They are otherwise
+    ///passed on as raw bytes, which may be inconvenient for handlers that
+    ///only work with strings. Default is `false`.
+    pub strict_utf8: bool,
+
+    ///How `.` and `..` segments, and repeated slashes, in the request path
+    ///are handled. This affects routing as well as the path in `Context`,
+    ///and protects handlers that map paths to files from directory
+    ///traversal. Default is `PathNormalization::Normalize`.
+    pub path_normalization: PathNormalization,
+
+    ///A path, such as `"/healthz"`, where `GET` and `HEAD` requests are
+    ///answered with `200 OK` and the body `ok`. These requests bypass the
+    ///context filters and the router, so the endpoint works regardless of
+    ///how the routes are set up. Other methods are answered with `405 Method
+    ///Not Allowed`. Default is `None`, which disables the endpoint.
+    pub health_check: Option<String>,
+
+    ///Renders the bodies of the error responses that are sent by the server
+    ///itself, such as `404 Not Found`, `405 Method Not Allowed` and `400 Bad
+    ///Request`, and the responses to requests that are aborted by context
+    ///filters. Default is `None`, which leaves these bodies empty.
+    pub error_renderer: Option<Box<ErrorRenderer>>,
+
+    ///Catch panics in the handlers and respond with `500 Internal Server
+    ///Error`, with a body from `error_renderer`, and close the connection.
+    ///The panic is still reported by the panic hook, which prints it to
+    ///`stderr` by default. Handlers are not required to be unwind safe, so
+    ///any state they share has to tolerate being left in the middle of an
+    ///update. A response can't be changed if it has already been turned into
+    ///a `Chunked` or `Raw` response.
+    ///
+    ///Setting this to `false` will let the panics unwind through the server
+    ///thread, which ends the connection after an empty `500` response, if
+    ///possible. This can be useful during development. Default is `true`.
+    pub catch_panics: bool,
+
+    ///Start the server in the ready state. A server that isn't ready
+    ///answers every request with `503 Service Unavailable`, including health
+    ///checks, until it's marked as ready with
+    ///[`Shutdown::set_ready`](struct.Shutdown.html#method.set_ready). This
+    ///can be used to keep the traffic away until caches have been warmed up,
+    ///or connections have been established. Default is `true`.
+    pub start_ready: bool,
+
+    ///The value of the `Retry-After` header in the `503 Service Unavailable`
+    ///responses that are sent while the server isn't ready, or while it's
+    ///shutting down. It's sent as a number of seconds. Default is 5 seconds,
+    ///and `None` leaves the header out.
+    pub retry_after: Option<Duration>,
+
     ///Globally accessible data.
     pub global: Global,
 
-    ///The context filter stack.
+    ///Creates the `WorkerState` for each worker thread, before it handles
+    ///its first request. Default is `None`, which leaves the state empty.
+    pub worker_init: Option<Box<WorkerInit>>,
+
+    ///The context filter stack. These filters are applied to every request,
+    ///before any route specific filters.
     pub context_filters: Vec<Box<ContextFilter>>,
 
-    ///The response filter stack.
+    ///The response filter stack. These filters are applied to every
+    ///response, before any route specific filters.
     pub response_filters: Vec<Box<ResponseFilter>>
 }
 
@@ -101,35 +345,201 @@ impl<R: Router> Server<R> {
     pub fn new(handlers: R) -> Server<R> {
         Server {
             handlers: handlers,
+            host_handlers: HashMap::new(),
             fallback_handler: None,
             host: 80.into(),
             scheme: Scheme::Http,
+            listeners: Vec::new(),
+            tls: TlsConfig::default(),
             threads: None,
             keep_alive: None,
+            max_connections_per_ip: None,
+            backlog: None,
+            tcp_nodelay: true,
+            tcp_keep_alive: None,
+            read_timeout: None,
+            write_timeout: None,
+            clock: Box::new(time::now_utc),
             server: "rustful".to_owned(),
             content_type: Mime(
                 hyper::mime::TopLevel::Text,
                 hyper::mime::SubLevel::Plain,
                 vec![(hyper::mime::Attr::Charset, hyper::mime::Value::Utf8)]
             ),
+            auto_options: true,
+            max_body_size: None,
+            max_decoded_body_size: Some(16 * 1024 * 1024),
+            max_header_size: Some(16 * 1024),
+            max_uri_length: Some(8 * 1024),
+            forwarded_headers: false,
+            trusted_proxies: Vec::new(),
+            query_semicolons: false,
+            max_parameters: Some(1000),
+            strict_utf8: false,
+            path_normalization: PathNormalization::Normalize,
+            health_check: None,
+            error_renderer: None,
+            catch_panics: true,
+            start_ready: true,
+            retry_after: Some(Duration::from_secs(5)),
             global: Global::default(),
+            worker_init: None,
             context_filters: Vec::new(),
             response_filters: Vec::new(),
         }
     }
 
-    ///Start the server.
-    pub fn run(self) -> HttpResult<Listening> {
+    ///Start building a server with chained setters. See
+    ///[`ServerBuilder`](struct.ServerBuilder.html) for more details.
+    pub fn builder(handlers: R) -> ServerBuilder<R> {
+        ServerBuilder::new(handlers)
+    }
+
+    ///Check the configuration for problems that would make the server fail
+    ///to start, or behave oddly, and report the first one as a
+    ///`ServerError::Config`. This is done by `run` and `run_with_shutdown`,
+    ///before anything else, and the following is checked:
+    ///
+    /// * `threads` must not be `Some(0)`.
+    /// * `max_connections_per_ip` must not be `Some(0)`, since it would
+    ///   reject every connection.
+    /// * There must be at least one route, in `handlers` or `host_handlers`,
+    ///   or a `fallback_handler`. The routes are found through
+    ///   `Router::hyperlinks`.
+    /// * The certificate and key files of `Scheme::Https`, in `scheme` or
+    ///   `listeners`, must exist. Their content is only checked when the
+    ///   server is started.
+    /// * The CA file of `tls.client_auth` must exist, if there is one.
+    /// * `tls.min_version` must not be newer than `tls.max_version`, and
+    ///   `tls.ciphers` must not be empty. The TLS library may still reject
+    ///   them when the server is started, if they aren't supported.
+    ///
+    ///```
+    ///use rustful::{Server, Context, Response};
+    ///use rustful::server::ServerError;
+    ///
+    ///# fn my_handler(context: Context, response: Response) {}
+    ///let server = Server {
+    ///    threads: Some(0),
+    ///    ..Server::new(my_handler)
+    ///};
+    ///assert!(server.validate().is_err());
+    ///
+    ///let server = Server::new(None::<fn(Context, Response)>);
+    ///match server.validate() {
+    ///    Err(ServerError::Config(message)) => println!("{}", message),
+    ///    _ => panic!("an empty router should be rejected")
+    ///}
+    ///```
+    pub fn validate(&self) -> Result<(), ServerError> {
+        if self.threads == Some(0) {
+            return Err(ServerError::Config("the server needs at least one thread".to_owned()));
+        }
+
+        if self.max_connections_per_ip == Some(0) {
+            return Err(ServerError::Config("max_connections_per_ip must be at least 1".to_owned()));
+        }
+
+        let has_routes = |router: &R| !router.hyperlinks(Link {
+            method: None,
+            path: vec![],
+            handler: None
+        }).is_empty();
+
+        if self.fallback_handler.is_none() && !has_routes(&self.handlers) && !self.host_handlers.values().any(has_routes) {
+            return Err(ServerError::Config("there are no handlers and no fallback handler".to_owned()));
+        }
+
+        try!(check_tls_files(&self.scheme));
+        for &(_, ref scheme) in &self.listeners {
+            try!(check_tls_files(scheme));
+        }
+
+        if let Some(ca_file) = self.tls.client_auth.ca_file() {
+            if !ca_file.is_file() {
+                return Err(ServerError::Config(format!("the client CA file {} doesn't exist", ca_file.display())));
+            }
+        }
+
+        if let (Some(min), Some(max)) = (self.tls.min_version, self.tls.max_version) {
+            if min > max {
+                return Err(ServerError::Config(format!("the minimum TLS version ({}) is newer than the maximum ({})", min, max)));
+            }
+        }
+
+        if self.tls.ciphers.as_ref().map_or(false, |ciphers| ciphers.trim().is_empty()) {
+            return Err(ServerError::Config("the TLS cipher list is empty".to_owned()));
+        }
+
+        Ok(())
+    }
+
+    ///Validate the configuration and start the server.
+    pub fn run(self) -> Result<Listening, ServerError> {
+        try!(self.validate());
         let (server, scheme) = self.build();
         server.run(scheme)
     }
 
+    ///Validate the configuration, start the server and get a handle for
+    ///shutting it down gracefully. See [`Shutdown`](struct.Shutdown.html)
+    ///for more details.
+    pub fn run_with_shutdown(self) -> Result<(Listening, Shutdown), ServerError> {
+        try!(self.validate());
+        let (server, scheme) = self.build();
+        let shutdown = server.shutdown_handle();
+        server.run(scheme).map(|listening| (listening, shutdown))
+    }
+
     ///Build a runnable instance of the server.
     pub fn build(self) -> (ServerInstance<R>, Scheme) {
         ServerInstance::new(self)
     }
 }
 
+//Check that the certificate and key files exist.
+#[cfg(any(feature = "ssl", feature = "rustls"))]
+fn check_tls_files(scheme: &Scheme) -> Result<(), ServerError> {
+    if let Scheme::Https { ref cert, ref key } = *scheme {
+        if !cert.is_file() {
+            return Err(ServerError::Config(format!("the certificate file {} doesn't exist", cert.display())));
+        }
+
+        if !key.is_file() {
+            return Err(ServerError::Config(format!("the private key file {} doesn't exist", key.display())));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(any(feature = "ssl", feature = "rustls")))]
+fn check_tls_files(_scheme: &Scheme) -> Result<(), ServerError> {
+    Ok(())
+}
+
+///Calculate a number of server threads, relative to the number of CPU cores.
+///The result is always at least `1`.
+///
+///```
+///use rustful::{Server, Context, Response};
+///use rustful::server::threads_per_cpu;
+///
+///# fn my_handler(context: Context, response: Response) {}
+///let server = Server {
+///    threads: Some(threads_per_cpu(2.0)),
+///    ..Server::new(my_handler)
+///};
+///```
+pub fn threads_per_cpu(multiplier: f64) -> usize {
+    let threads = (num_cpus::get() as f64 * multiplier) as usize;
+    if threads > 0 {
+        threads
+    } else {
+        1
+    }
+}
+
 impl<R: Router + Default> Default for Server<R> {
     fn default() -> Server<R> {
         Server::new(R::default())
@@ -0,0 +1,320 @@
+
+use std::fs::File;
+use std::io::{self, Read, Write, BufReader};
+use std::net::{SocketAddr, Shutdown};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hyper;
+use hyper::net::{HttpStream, NetworkStream, Ssl};
+
+use rustls::{self, ServerConfig, ServerSession, Session, ProtocolVersion, RootCertStore};
+use rustls::{NoClientAuth, AllowAnyAuthenticatedClient, AllowAnyAnonymousOrAuthenticatedClient};
+use rustls::internal::pemfile;
+
+use HttpResult;
+use server::{ServerError, TlsConfig, TlsVersion, ClientAuth};
+
+///An SSL implementation for hyper, using rustls.
+#[derive(Clone)]
+pub struct Rustls {
+    config: Arc<ServerConfig>
+}
+
+impl Rustls {
+    ///Load a PEM encoded certificate chain and private key from files.
+    pub fn with_cert_and_key<C: AsRef<Path>, K: AsRef<Path>>(cert: C, key: K) -> HttpResult<Rustls> {
+        let cert = try!(File::open(cert).map_err(ssl_error));
+        let key = try!(File::open(key).map_err(ssl_error));
+        Rustls::from_pem(&mut BufReader::new(cert), &mut BufReader::new(key))
+    }
+
+    ///Load a PEM encoded certificate chain and private key from files, and
+    ///verify client certificates with the PEM encoded CA certificates in
+    ///`ca`. Clients without certificates are rejected if `mandatory` is
+    ///`true`.
+    pub fn with_client_auth<C: AsRef<Path>, K: AsRef<Path>, A: AsRef<Path>>(cert: C, key: K, ca: A, mandatory: bool) -> HttpResult<Rustls> {
+        let cert = try!(File::open(cert).map_err(ssl_error));
+        let key = try!(File::open(key).map_err(ssl_error));
+        let ca = try!(File::open(ca).map_err(ssl_error));
+        Rustls::from_pem_with_client_auth(&mut BufReader::new(cert), &mut BufReader::new(key), &mut BufReader::new(ca), mandatory)
+    }
+
+    ///Read a PEM encoded certificate chain and private key.
+    pub fn from_pem(cert: &mut io::BufRead, key: &mut io::BufRead) -> HttpResult<Rustls> {
+        let config = try!(server_config(cert, key, None));
+
+        Ok(Rustls {
+            config: Arc::new(config)
+        })
+    }
+
+    ///Read a PEM encoded certificate chain and private key, and verify
+    ///client certificates with the PEM encoded CA certificates from `ca`.
+    ///Clients without certificates are rejected if `mandatory` is `true`.
+    pub fn from_pem_with_client_auth(cert: &mut io::BufRead, key: &mut io::BufRead, ca: &mut io::BufRead, mandatory: bool) -> HttpResult<Rustls> {
+        let config = try!(server_config(cert, key, Some((try!(client_auth_roots(ca)), mandatory))));
+
+        Ok(Rustls {
+            config: Arc::new(config)
+        })
+    }
+}
+
+impl Ssl for Rustls {
+    type Stream = TlsStream;
+
+    fn wrap_client(&self, _stream: HttpStream, _host: &str) -> HttpResult<TlsStream> {
+        Err(ssl_error(io::Error::new(io::ErrorKind::Other, "client connections are not supported")))
+    }
+
+    fn wrap_server(&self, stream: HttpStream) -> HttpResult<TlsStream> {
+        Ok(TlsStream {
+            inner: Arc::new(Mutex::new(Connection {
+                session: ServerSession::new(&self.config),
+                stream: stream,
+                eof: false,
+                tls_error: None,
+                io_error: None
+            }))
+        })
+    }
+}
+
+///A TLS encrypted stream.
+#[derive(Clone)]
+pub struct TlsStream {
+    inner: Arc<Mutex<Connection>>
+}
+
+impl TlsStream {
+    ///The DER encoded certificate chain of the client, starting with the
+    ///client's own certificate. It's only available after the handshake,
+    ///and if the client has sent a certificate.
+    pub fn peer_certificates(&self) -> Option<Vec<Vec<u8>>> {
+        self.inner.lock().unwrap().session.get_peer_certificates()
+            .map(|certs| certs.into_iter().map(|cert| cert.0).collect())
+    }
+}
+
+struct Connection {
+    session: ServerSession,
+    stream: HttpStream,
+    eof: bool,
+    tls_error: Option<rustls::TLSError>,
+    io_error: Option<io::Error>
+}
+
+impl Connection {
+    fn underlying_io(&mut self) {
+        if self.io_error.is_some() || self.tls_error.is_some() {
+            return;
+        }
+
+        while self.io_error.is_none() && self.session.wants_write() {
+            if let Err(e) = self.session.write_tls(&mut self.stream) {
+                self.io_error = Some(e);
+            }
+        }
+
+        if !self.eof && self.session.wants_read() {
+            match self.session.read_tls(&mut self.stream) {
+                Ok(0) => self.eof = true,
+                Ok(_) => {},
+                Err(e) => self.io_error = Some(e)
+            }
+        }
+
+        if let Err(e) = self.session.process_new_packets() {
+            self.tls_error = Some(e);
+        }
+    }
+
+    fn check_errors(&mut self) -> io::Result<()> {
+        if let Some(e) = self.io_error.take() {
+            return Err(e);
+        }
+
+        if let Some(e) = self.tls_error.take() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)));
+        }
+
+        Ok(())
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            try!(self.check_errors());
+
+            match try!(self.session.read(buf)) {
+                0 if self.eof => return Ok(0),
+                0 => self.underlying_io(),
+                read => return Ok(read)
+            }
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = try!(self.session.write(buf));
+        try!(self.check_errors());
+        self.underlying_io();
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.session.flush());
+        try!(self.check_errors());
+        self.underlying_io();
+        self.check_errors()
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.lock().unwrap().read(buf)
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.lock().unwrap().flush()
+    }
+}
+
+impl NetworkStream for TlsStream {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        self.inner.lock().unwrap().stream.peer_addr()
+    }
+
+    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.inner.lock().unwrap().stream.set_read_timeout(dur)
+    }
+
+    fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.inner.lock().unwrap().stream.set_write_timeout(dur)
+    }
+
+    fn close(&mut self, how: Shutdown) -> io::Result<()> {
+        let mut connection = self.inner.lock().unwrap();
+        connection.session.send_close_notify();
+        connection.underlying_io();
+        connection.stream.close(how)
+    }
+}
+
+//Load the certificate chain and private key from files, and apply the
+//settings from `tls`.
+pub fn acceptor_from_files(cert: &Path, key: &Path, tls: &TlsConfig) -> Result<Rustls, ServerError> {
+    let cert = try!(File::open(cert).map_err(|e| ServerError::Tls(ssl_error(e))));
+    let key = try!(File::open(key).map_err(|e| ServerError::Tls(ssl_error(e))));
+    acceptor(&mut BufReader::new(cert), &mut BufReader::new(key), tls)
+}
+
+//Load the certificate chain and private key, and apply the settings from
+//`tls`.
+pub fn acceptor(cert: &mut io::BufRead, key: &mut io::BufRead, tls: &TlsConfig) -> Result<Rustls, ServerError> {
+    let client_auth = match tls.client_auth {
+        ClientAuth::None => None,
+        ClientAuth::Optional(ref ca) => Some((ca, false)),
+        ClientAuth::Required(ref ca) => Some((ca, true)),
+    };
+
+    let client_roots = match client_auth {
+        Some((ca, mandatory)) => {
+            let ca = try!(File::open(ca).map_err(|e| ServerError::Tls(ssl_error(e))));
+            let roots = try!(client_auth_roots(&mut BufReader::new(ca)).map_err(ServerError::Tls));
+            Some((roots, mandatory))
+        },
+        None => None
+    };
+
+    let mut config = try!(server_config(cert, key, client_roots).map_err(ServerError::Tls));
+
+    //Only TLS 1.2 and 1.3 are supported, so the range has to include one of them
+    let in_range = |version| tls.min_version.map_or(true, |min| min <= version) && tls.max_version.map_or(true, |max| max >= version);
+    config.versions.retain(|version| match *version {
+        ProtocolVersion::TLSv1_3 => in_range(TlsVersion::Tls13),
+        ProtocolVersion::TLSv1_2 => in_range(TlsVersion::Tls12),
+        _ => false
+    });
+    if config.versions.is_empty() {
+        return Err(ServerError::Config("rustls only supports TLS 1.2 and TLS 1.3".to_owned()));
+    }
+
+    if let Some(ref ciphers) = tls.ciphers {
+        let mut suites = vec![];
+        for name in ciphers.split(':').map(str::trim).filter(|name| !name.is_empty()) {
+            match rustls::ALL_CIPHERSUITES.iter().find(|suite| format!("{:?}", suite.suite) == name) {
+                Some(suite) => suites.push(*suite),
+                None => return Err(ServerError::Config(format!("the cipher suite {} is not supported by rustls", name)))
+            }
+        }
+        config.ciphersuites = suites;
+    }
+
+    Ok(Rustls {
+        config: Arc::new(config)
+    })
+}
+
+//Load the server's certificate chain and private key. Client certificates
+//are verified with `client_roots`, if there are any, and they are required
+//if its flag is `true`.
+fn server_config(cert: &mut io::BufRead, key: &mut io::BufRead, client_roots: Option<(RootCertStore, bool)>) -> HttpResult<ServerConfig> {
+    let certs = try!(pemfile::certs(cert).map_err(|_| invalid_pem("invalid certificate")));
+    if certs.is_empty() {
+        return Err(invalid_pem("no certificates found"));
+    }
+
+    let mut key_pem = vec![];
+    try!(key.read_to_end(&mut key_pem).map_err(ssl_error));
+
+    //Try PKCS#8 first and fall back to RSA keys
+    let mut keys = try!(pemfile::pkcs8_private_keys(&mut &key_pem[..]).map_err(|_| invalid_pem("invalid private key")));
+    if keys.is_empty() {
+        keys = try!(pemfile::rsa_private_keys(&mut &key_pem[..]).map_err(|_| invalid_pem("invalid private key")));
+    }
+
+    let key = match keys.into_iter().next() {
+        Some(key) => key,
+        None => return Err(invalid_pem("no private key found"))
+    };
+
+    let verifier = match client_roots {
+        Some((roots, true)) => AllowAnyAuthenticatedClient::new(roots),
+        Some((roots, false)) => AllowAnyAnonymousOrAuthenticatedClient::new(roots),
+        None => NoClientAuth::new()
+    };
+
+    let mut config = ServerConfig::new(verifier);
+    try!(config.set_single_cert(certs, key).map_err(|e| hyper::Error::Ssl(Box::new(e))));
+
+    Ok(config)
+}
+
+//Load the CA certificates for verifying client certificates.
+fn client_auth_roots(ca: &mut io::BufRead) -> HttpResult<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    match roots.add_pem_file(ca) {
+        Ok((0, _)) => Err(invalid_pem("no CA certificates found")),
+        Ok(_) => Ok(roots),
+        Err(()) => Err(invalid_pem("invalid CA certificate"))
+    }
+}
+
+fn invalid_pem(message: &'static str) -> hyper::Error {
+    ssl_error(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+fn ssl_error(error: io::Error) -> hyper::Error {
+    hyper::Error::Ssl(Box::new(error))
+}
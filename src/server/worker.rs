@@ -0,0 +1,96 @@
+use std::cell::RefCell;
+
+use anymap::AnyMap;
+
+thread_local!(static WORKER_STATE: RefCell<Option<WorkerState>> = RefCell::new(None));
+
+///State that is local to a single worker thread.
+///
+///Each worker thread gets its own `WorkerState`, which is filled by the
+///server's `worker_init` before the thread handles its first request. The
+///values are only ever accessed from that thread, so they don't have to be
+///`Send` or `Sync`, and they are dropped when the thread exits. This makes it
+///a good place for things like random number generators, buffers and
+///connections that would otherwise be shared behind a lock.
+///
+///The values are accessed from handlers through `Context::with_worker_state`.
+pub struct WorkerState {
+    values: AnyMap
+}
+
+impl WorkerState {
+    ///Add a value, replacing any previous value of the same type.
+    pub fn insert<T: 'static>(&mut self, value: T) {
+        self.values.insert(RefCell::new(value));
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn init(init: &WorkerInit) {
+        WORKER_STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            if state.is_none() {
+                let mut new_state = WorkerState {
+                    values: AnyMap::new()
+                };
+                init.init(&mut new_state);
+                *state = Some(new_state);
+            }
+        });
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn with<T: 'static, R, F: FnOnce(&mut T) -> R>(f: F) -> Option<R> {
+        WORKER_STATE.with(|state| {
+            let state = state.borrow();
+            let value = match *state {
+                Some(ref state) => state.values.get::<RefCell<T>>(),
+                None => None
+            };
+
+            value.map(|value| f(&mut value.borrow_mut()))
+        })
+    }
+}
+
+///Creates the state for each worker thread.
+///
+///It's called once in each worker thread, before the thread handles its
+///first request. It's implemented for functions and closures with the same
+///signature as `init`:
+///
+///```
+///use std::collections::HashMap;
+///use rustful::{Server, Context, Response};
+///use rustful::server::WorkerState;
+///
+///struct Cache(HashMap<String, String>);
+///
+///fn init_worker(state: &mut WorkerState) {
+///    state.insert(Cache(HashMap::new()));
+///}
+///
+///fn my_handler(context: Context, response: Response) {
+///    let cached = context.with_worker_state(|cache: &mut Cache| {
+///        cache.0.get("greeting").cloned()
+///    });
+///
+///    response.send(cached.and_then(|greeting| greeting).unwrap_or_else(|| "hello".to_owned()));
+///}
+///
+///let server = Server {
+///    worker_init: Some(Box::new(init_worker)),
+///    ..Server::new(my_handler)
+///};
+///```
+pub trait WorkerInit: Send + Sync + 'static {
+    ///Add the initial values to the state of a new worker thread.
+    fn init(&self, state: &mut WorkerState);
+}
+
+impl<F: Fn(&mut WorkerState) + Send + Sync + 'static> WorkerInit for F {
+    fn init(&self, state: &mut WorkerState) {
+        self(state)
+    }
+}
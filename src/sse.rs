@@ -0,0 +1,223 @@
+//!Server-Sent Events.
+//!
+//!An event stream is a long lived `text/event-stream` response, where events
+//!are pushed to the client as they happen. It's created from a `Response`
+//!using `into_event_stream`, and it's written through the same chunked path
+//!as `Chunked` responses, so the response filters are applied as usual. Each
+//!event is flushed as soon as it has been written, and writing blocks until
+//!it's done, so a slow client will also slow down the handler.
+//!
+//!```
+//!use std::sync::mpsc::channel;
+//!use rustful::{Context, Response};
+//!use rustful::sse::Event;
+//!
+//!fn updates(context: Context, response: Response) {
+//!    let (sender, receiver) = channel();
+//!
+//!    //Events would usually come from somewhere else.
+//!    sender.send(Event::new("hello").event("greeting")).unwrap();
+//!    drop(sender);
+//!
+//!    let mut events = response.into_event_stream();
+//!    events.send_from(&receiver).unwrap_or(());
+//!}
+//!```
+//!
+//!A client that reconnects will include the ID of the last event it
+//!received, which is available through `Context::last_event_id`.
+
+use std::fmt;
+use std::io::Write;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use response::{Chunked, Data, Error};
+
+///A single event.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Event {
+    event: Option<String>,
+    id: Option<String>,
+    retry: Option<u64>,
+    data: String
+}
+
+impl Event {
+    ///Create an event with `data` as its content. Multiple lines will be
+    ///sent as multiple `data` fields.
+    pub fn new<D: Into<String>>(data: D) -> Event {
+        Event {
+            event: None,
+            id: None,
+            retry: None,
+            data: data.into()
+        }
+    }
+
+    ///Set the event type. The client will see it as a `message` if it's not
+    ///set.
+    pub fn event<N: Into<String>>(mut self, name: N) -> Event {
+        self.event = Some(name.into());
+        self
+    }
+
+    ///Set the event ID, which the client will send back in `Last-Event-ID`
+    ///if it has to reconnect.
+    pub fn id<I: Into<String>>(mut self, id: I) -> Event {
+        self.id = Some(id.into());
+        self
+    }
+
+    ///Tell the client how many milliseconds it should wait before
+    ///reconnecting.
+    pub fn retry(mut self, milliseconds: u64) -> Event {
+        self.retry = Some(milliseconds);
+        self
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref event) = self.event {
+            try!(write!(f, "event: {}\n", first_line(event)));
+        }
+
+        if let Some(ref id) = self.id {
+            try!(write!(f, "id: {}\n", first_line(id)));
+        }
+
+        if let Some(retry) = self.retry {
+            try!(write!(f, "retry: {}\n", retry));
+        }
+
+        for line in self.data.split('\n') {
+            try!(write!(f, "data: {}\n", line.trim_right_matches('\r')));
+        }
+
+        write!(f, "\n")
+    }
+}
+
+impl<'a> From<&'a str> for Event {
+    fn from(data: &'a str) -> Event {
+        Event::new(data)
+    }
+}
+
+impl From<String> for Event {
+    fn from(data: String) -> Event {
+        Event::new(data)
+    }
+}
+
+///A stream of Server-Sent Events.
+///
+///Heartbeats, in the form of empty comments, are sent by `keep_alive` and
+///`send_from` if nothing else has been sent for a while. This prevents
+///proxies and clients from giving up on an idle connection. The interval is
+///15 seconds by default.
+pub struct EventStream<'a, 'b> {
+    writer: Chunked<'a, 'b>,
+    heartbeat: Duration,
+    last_write: Instant
+}
+
+impl<'a, 'b> EventStream<'a, 'b> {
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn new(writer: Chunked<'a, 'b>) -> EventStream<'a, 'b> {
+        EventStream {
+            writer: writer,
+            heartbeat: Duration::from_secs(15),
+            last_write: Instant::now()
+        }
+    }
+
+    ///Change how long the stream can be idle before a heartbeat is sent.
+    pub fn set_heartbeat(&mut self, interval: Duration) {
+        self.heartbeat = interval;
+    }
+
+    ///Send an event to the client.
+    pub fn send<E: Into<Event>>(&mut self, event: E) -> Result<(), Error> {
+        self.write(event.into().to_string())
+    }
+
+    ///Send a comment to the client. Comments are ignored by the client, but
+    ///they can be useful for debugging.
+    pub fn comment(&mut self, text: &str) -> Result<(), Error> {
+        let mut comment = String::new();
+        for line in text.split('\n') {
+            comment.push(':');
+            comment.push_str(line.trim_right_matches('\r'));
+            comment.push('\n');
+        }
+        comment.push('\n');
+
+        self.write(comment)
+    }
+
+    ///Send a heartbeat if nothing has been sent during the heartbeat
+    ///interval.
+    pub fn keep_alive(&mut self) -> Result<(), Error> {
+        if self.last_write.elapsed() >= self.heartbeat {
+            self.write(":\n\n")
+        } else {
+            Ok(())
+        }
+    }
+
+    ///Send every event from `events`, with heartbeats in between, until the
+    ///sending side is disconnected or the stream fails.
+    pub fn send_from<E: Into<Event>>(&mut self, events: &Receiver<E>) -> Result<(), Error> {
+        loop {
+            let elapsed = self.last_write.elapsed();
+            let timeout = if elapsed < self.heartbeat {
+                self.heartbeat - elapsed
+            } else {
+                Duration::from_millis(0)
+            };
+
+            match events.recv_timeout(timeout) {
+                Ok(event) => try!(self.send(event)),
+                Err(RecvTimeoutError::Timeout) => try!(self.keep_alive()),
+                Err(RecvTimeoutError::Disconnected) => return Ok(())
+            }
+        }
+    }
+
+    ///Finish the stream and collect eventual errors.
+    ///
+    ///This is optional and will happen silently when the stream drops out of
+    ///scope.
+    pub fn end(self) -> Result<(), Error> {
+        self.writer.end()
+    }
+
+    fn write<'d, D: Into<Data<'d>>>(&mut self, content: D) -> Result<(), Error> {
+        try!(self.writer.try_send(content));
+        try!(self.writer.flush());
+        self.last_write = Instant::now();
+        Ok(())
+    }
+}
+
+//Field values can't span multiple lines.
+fn first_line(value: &str) -> &str {
+    value.split(|c| c == '\n' || c == '\r').next().unwrap_or("")
+}
+
+#[cfg(test)]
+mod test {
+    use super::Event;
+
+    #[test]
+    fn format_events() {
+        assert_eq!(Event::new("hello").to_string(), "data: hello\n\n");
+        assert_eq!(
+            Event::new("a\r\nb\n").event("update").id("1\n2").retry(100).to_string(),
+            "event: update\nid: 1\nretry: 100\ndata: a\ndata: b\ndata: \n\n"
+        );
+    }
+}
@@ -0,0 +1,427 @@
+//!Tools for testing handlers and servers without a network connection.
+//!
+//!A `TestServer` runs requests through the same steps as a running server,
+//!including the filters, the router and the handler, but the requests are
+//!read from memory and the responses are written to memory. This makes the
+//!tests fast and deterministic, and they don't need any free ports.
+//!
+//!```
+//!use rustful::{Server, Context, Response, StatusCode};
+//!use rustful::testing::TestServer;
+//!
+//!fn say_hello(context: Context, response: Response) {
+//!    let name = context.query.get("name").map_or("world".into(), |name| name.into_owned());
+//!    response.send(format!("hello, {}", name));
+//!}
+//!
+//!let server = TestServer::new(Server::new(say_hello));
+//!
+//!let response = server.get("/?name=tests").unwrap();
+//!assert_eq!(response.status, StatusCode::Ok);
+//!assert_eq!(response.body_str(), Some("hello, tests"));
+//!```
+//!
+//!More detailed requests can be put together using a `MockRequest`.
+
+use std::io::{self, Read, Write, Cursor};
+use std::net::SocketAddr;
+use std::str;
+use std::time::Duration;
+
+use hyper;
+use hyper::buffer::BufReader;
+use hyper::header::{ContentLength, TransferEncoding, Encoding, Host};
+use hyper::http::h1::{self, HttpReader};
+use hyper::net::NetworkStream;
+use hyper::server::Handler as HyperHandler;
+
+use {StatusCode, HttpResult, HttpVersion, Server, Method};
+use header::{Headers, Header, HeaderFormat};
+use router::Router;
+use server::ServerInstance;
+use utils;
+
+///Runs requests through a server, without a network connection.
+///
+///Each request is handled as if it was the first request of a new
+///connection, from `127.0.0.1`, and the response is collected into a
+///`TestResponse`. The connection is not kept alive, and any `Expect:
+///100-continue` header is ignored.
+pub struct TestServer<R: Router> {
+    instance: ServerInstance<R>,
+    client_addr: SocketAddr
+}
+
+impl<R: Router> TestServer<R> {
+    ///Create a test server from a server configuration. The host and the
+    ///scheme are not used, so any HTTPS settings are ignored.
+    pub fn new(server: Server<R>) -> TestServer<R> {
+        TestServer {
+            instance: server.build().0,
+            client_addr: "127.0.0.1:50000".parse().expect("invalid test address")
+        }
+    }
+
+    ///Get the underlying server instance. This gives access to its
+    ///shutdown and router handles.
+    pub fn instance(&self) -> &ServerInstance<R> {
+        &self.instance
+    }
+
+    ///Send a `GET` request for `path`, which may include a query.
+    pub fn get(&self, path: &str) -> HttpResult<TestResponse> {
+        self.send(MockRequest::get(path))
+    }
+
+    ///Send a request from a `MockRequest`.
+    ///
+    ///```
+    ///use rustful::{Server, Context, Response, StatusCode};
+    ///use rustful::Method::Post;
+    ///use rustful::testing::{TestServer, MockRequest};
+    ///
+    ///fn login(mut context: Context, mut response: Response) {
+    ///    let form = context.body.read_query_body().ok();
+    ///    match (form.as_ref().and_then(|form| form.get("user")), context.headers.get_raw("X-Token")) {
+    ///        (Some(user), Some(_)) => response.send(format!("welcome, {}", user)),
+    ///        _ => response.set_status(StatusCode::Forbidden)
+    ///    }
+    ///}
+    ///
+    ///let server = TestServer::new(Server::new(login));
+    ///
+    ///let request = MockRequest::new(Post, "/login")
+    ///    .raw_header("X-Token", "abc")
+    ///    .raw_header("Content-Type", "application/x-www-form-urlencoded")
+    ///    .body("user=alice");
+    ///
+    ///let response = server.send(request).unwrap();
+    ///assert_eq!(response.body_str(), Some("welcome, alice"));
+    ///```
+    pub fn send(&self, request: MockRequest) -> HttpResult<TestResponse> {
+        let client_addr = request.client_addr.unwrap_or(self.client_addr);
+        self.handle(&request.into_bytes(), client_addr)
+    }
+
+    ///Send a raw HTTP request. It has to be a complete request, with a
+    ///request line, headers and an optional body.
+    ///
+    ///```
+    ///use rustful::{Server, Context, Response};
+    ///use rustful::testing::TestServer;
+    ///
+    ///fn echo(mut context: Context, response: Response) {
+    ///    let body = context.body.read_body().unwrap_or_else(|_| vec![]);
+    ///    response.send(body);
+    ///}
+    ///
+    ///let server = TestServer::new(Server::new(echo));
+    ///
+    ///let response = server.request(
+    ///    b"POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello"
+    ///).unwrap();
+    ///assert_eq!(response.body, b"hello");
+    ///```
+    pub fn request(&self, request: &[u8]) -> HttpResult<TestResponse> {
+        self.handle(request, self.client_addr)
+    }
+
+    fn handle(&self, request: &[u8], client_addr: SocketAddr) -> HttpResult<TestResponse> {
+        let mut stream = MemoryStream {
+            input: Cursor::new(request.to_vec()),
+            peer_addr: client_addr
+        };
+        let mut output = Vec::new();
+        let head;
+
+        {
+            let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+            let request = try!(hyper::server::request::Request::new(&mut reader, client_addr));
+            head = request.method == Method::Head;
+
+            let mut headers = Headers::new();
+            let response = hyper::server::response::Response::new(&mut output, &mut headers);
+
+            self.instance.on_connection_start();
+            self.instance.handle(request, response);
+            self.instance.on_connection_end();
+        }
+
+        TestResponse::parse(&output, head)
+    }
+}
+
+///A request for a `TestServer`.
+///
+///The request is built from its parts and it's written as an HTTP/1.1
+///request when it's sent, so it's parsed in the same way as a request from a
+///real client. A `Host: localhost` header is added if there is no `Host`
+///header, and a `Content-Length` header is added if there is no
+///`Content-Length` or `Transfer-Encoding` header.
+#[derive(Clone, Debug)]
+pub struct MockRequest {
+    method: Method,
+    path: String,
+    query: Vec<(String, String)>,
+    headers: Headers,
+    body: Vec<u8>,
+    client_addr: Option<SocketAddr>
+}
+
+impl MockRequest {
+    ///Create a request with `method` and `path`. The path may include a
+    ///query.
+    pub fn new<P: Into<String>>(method: Method, path: P) -> MockRequest {
+        MockRequest {
+            method: method,
+            path: path.into(),
+            query: vec![],
+            headers: Headers::new(),
+            body: vec![],
+            client_addr: None
+        }
+    }
+
+    ///Create a `GET` request for `path`.
+    pub fn get<P: Into<String>>(path: P) -> MockRequest {
+        MockRequest::new(Method::Get, path)
+    }
+
+    ///Create a `POST` request for `path`.
+    pub fn post<P: Into<String>>(path: P) -> MockRequest {
+        MockRequest::new(Method::Post, path)
+    }
+
+    ///Add a query parameter. It will be percent encoded and appended to any
+    ///query that is already in the path.
+    pub fn query<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> MockRequest {
+        self.query.push((key.into(), value.into()));
+        self
+    }
+
+    ///Set a typed header.
+    pub fn header<H: Header + HeaderFormat>(mut self, header: H) -> MockRequest {
+        self.headers.set(header);
+        self
+    }
+
+    ///Add a raw header value. It's added to any previous values with the
+    ///same name.
+    pub fn raw_header<N: Into<String>, V: Into<Vec<u8>>>(mut self, name: N, value: V) -> MockRequest {
+        let name = name.into();
+        let mut values = self.headers.get_raw(&name).map(|values| values.to_vec()).unwrap_or_else(Vec::new);
+        values.push(value.into());
+        self.headers.set_raw(name, values);
+        self
+    }
+
+    ///Set the request body.
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> MockRequest {
+        self.body = body.into();
+        self
+    }
+
+    ///Set the address of the client. Default is the address of the
+    ///`TestServer`.
+    pub fn client_addr(mut self, address: SocketAddr) -> MockRequest {
+        self.client_addr = Some(address);
+        self
+    }
+
+    fn into_bytes(mut self) -> Vec<u8> {
+        let mut target = self.path;
+        for (i, &(ref key, ref value)) in self.query.iter().enumerate() {
+            let separator = if i == 0 && !target.contains('?') { '?' } else { '&' };
+            target.push(separator);
+            target.push_str(&utils::percent_encode_component(key.as_bytes()));
+            target.push('=');
+            target.push_str(&utils::percent_encode_component(value.as_bytes()));
+        }
+
+        if !self.headers.has::<Host>() {
+            self.headers.set(Host {
+                hostname: "localhost".to_owned(),
+                port: None
+            });
+        }
+
+        if !self.headers.has::<ContentLength>() && !self.headers.has::<TransferEncoding>() {
+            self.headers.set(ContentLength(self.body.len() as u64));
+        }
+
+        let mut request = format!("{} {} HTTP/1.1\r\n{}\r\n", self.method, target, self.headers).into_bytes();
+        request.extend_from_slice(&self.body);
+        request
+    }
+}
+
+///A response from a `TestServer`.
+#[derive(Clone, Debug)]
+pub struct TestResponse {
+    ///The HTTP version of the response.
+    pub version: HttpVersion,
+
+    ///The response status.
+    pub status: StatusCode,
+
+    ///The response headers.
+    pub headers: Headers,
+
+    ///The response body, without any transfer encoding. It's always empty
+    ///for `HEAD` requests.
+    pub body: Vec<u8>
+}
+
+impl TestResponse {
+    ///Get the body as a string, if it's valid UTF-8.
+    pub fn body_str(&self) -> Option<&str> {
+        str::from_utf8(&self.body).ok()
+    }
+
+    fn parse(response: &[u8], head: bool) -> HttpResult<TestResponse> {
+        let mut reader = BufReader::new(response);
+        let incoming = try!(h1::parse_response(&mut reader));
+        let status = StatusCode::from_u16(incoming.subject.0);
+
+        let no_body = head || match status {
+            StatusCode::NoContent | StatusCode::NotModified => true,
+            status => status.is_informational()
+        };
+
+        let chunked = incoming.headers.get::<TransferEncoding>().map_or(false, |encodings| encodings.contains(&Encoding::Chunked));
+
+        let mut body_reader = if no_body {
+            HttpReader::EmptyReader(reader)
+        } else if chunked {
+            HttpReader::ChunkedReader(reader, None)
+        } else if let Some(&ContentLength(length)) = incoming.headers.get() {
+            HttpReader::SizedReader(reader, length)
+        } else {
+            HttpReader::EofReader(reader)
+        };
+
+        let mut body = Vec::new();
+        try!(body_reader.read_to_end(&mut body));
+
+        Ok(TestResponse {
+            version: incoming.version,
+            status: status,
+            headers: incoming.headers,
+            body: body
+        })
+    }
+}
+
+//A stream that reads a request from memory and discards anything that is
+//written directly to it.
+struct MemoryStream {
+    input: Cursor<Vec<u8>>,
+    peer_addr: SocketAddr
+}
+
+impl Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl NetworkStream for MemoryStream {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        Ok(self.peer_addr)
+    }
+
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Server, Context, Response, StatusCode, Method, TreeRouter};
+    use router::Router;
+    use super::{TestServer, MockRequest};
+
+    fn hello(_context: Context, response: Response) {
+        response.send("hello");
+    }
+
+    fn test_server() -> TestServer<TreeRouter<::router::MethodRouter<::router::Variables<fn(Context, Response)>>>> {
+        let mut router = TreeRouter::new();
+        router.insert(Method::Get, "/hello", hello as fn(Context, Response));
+        TestServer::new(Server::new(router))
+    }
+
+    #[test]
+    fn route_requests() {
+        let server = test_server();
+
+        let response = server.get("/hello").unwrap();
+        assert_eq!(response.status, StatusCode::Ok);
+        assert_eq!(response.body_str(), Some("hello"));
+
+        let response = server.get("/missing").unwrap();
+        assert_eq!(response.status, StatusCode::NotFound);
+
+        let response = server.request(b"POST /hello HTTP/1.1\r\nContent-Length: 0\r\n\r\n").unwrap();
+        assert_eq!(response.status, StatusCode::MethodNotAllowed);
+    }
+
+    #[test]
+    fn head_requests() {
+        let response = test_server().request(b"HEAD /hello HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(response.status, StatusCode::Ok);
+        assert!(response.body.is_empty());
+    }
+
+    #[test]
+    fn send_mock_requests() {
+        let request = MockRequest::post("/path?a=1")
+            .query("b", "x y")
+            .raw_header("X-Test", "1")
+            .raw_header("X-Test", "2")
+            .body("hello");
+
+        let bytes = String::from_utf8(request.into_bytes()).unwrap();
+        assert!(bytes.starts_with("POST /path?a=1&b=x%20y HTTP/1.1\r\n"));
+        assert!(bytes.contains("Host: localhost\r\n"));
+        assert!(bytes.contains("Content-Length: 5\r\n"));
+        assert!(bytes.ends_with("\r\n\r\nhello"));
+
+        let response = test_server().send(MockRequest::get("/hello")).unwrap();
+        assert_eq!(response.body_str(), Some("hello"));
+    }
+
+    #[test]
+    fn keep_listener_scheme() {
+        fn schemes(context: Context, response: Response) {
+            response.send(format!("{} {}", context.scheme, context.listener_scheme));
+        }
+
+        let server = TestServer::new(Server {
+            forwarded_headers: true,
+            ..Server::new(schemes)
+        });
+
+        let response = server.send(MockRequest::get("/").raw_header("X-Forwarded-Proto", "https")).unwrap();
+        assert_eq!(response.body_str(), Some("https http"));
+    }
+
+    #[test]
+    fn reject_broken_requests() {
+        assert!(test_server().request(b"GET /hello HTTP/1.1\r\n").is_err());
+    }
+}
@@ -1,31 +1,581 @@
 use std::io::Write;
 use url::percent_encoding::percent_decode;
+#[cfg(feature = "charsets")]
+use encoding::{EncodingRef, DecoderTrap};
+#[cfg(feature = "charsets")]
+use encoding::label::encoding_from_whatwg_label;
+use mime::{Mime, TopLevel, SubLevel, Attr};
+use header::QualityItem;
+use LanguageTag;
 use context::Parameters;
+use context::body::TextDecoding;
 
-pub fn parse_parameters(source: &[u8]) -> Parameters {
+//Returns `None` if there are more than `limit` parameters.
+pub fn parse_parameters(source: &[u8], limit: Option<usize>) -> Option<Parameters> {
+    parse_parameters_with(source, false, limit)
+}
+
+pub fn parse_parameters_with(source: &[u8], semicolons: bool, limit: Option<usize>) -> Option<Parameters> {
     let mut parameters = Parameters::new();
+    let mut count = 0;
     let source: Vec<u8> = source.iter()
                                 .map(|&e| if e == b'+' { b' ' } else { e })
                                 .collect();
 
-    for parameter in source.split(|&e| e == b'&') {
+    for parameter in source.split(|&e| e == b'&' || (semicolons && e == b';')) {
+        if parameter.is_empty() {
+            continue;
+        }
+
+        count += 1;
+        if limit.map_or(false, |limit| count > limit) {
+            return None;
+        }
+
         let mut parts = parameter.split(|&e| e == b'=');
 
         match (parts.next(), parts.next()) {
             (Some(name), Some(value)) => {
                 let name = percent_decode(name);
                 let value = percent_decode(value);
-                parameters.insert(name, value);
+                parameters.append(name, value);
             },
             (Some(name), None) => {
                 let name = percent_decode(name);
-                parameters.insert(name, String::new());
+                parameters.append(name, String::new());
             },
             _ => {}
         }
     }
 
-    parameters
+    Some(parameters)
+}
+
+pub fn parse_cookies(headers: &[Vec<u8>]) -> Parameters {
+    let mut cookies = Parameters::new();
+
+    for header in headers {
+        for pair in header.split(|&e| e == b';') {
+            let pair = trim_bytes(pair);
+            if pair.is_empty() {
+                continue;
+            }
+
+            let (name, value) = match pair.iter().position(|&e| e == b'=') {
+                Some(index) => (trim_bytes(&pair[..index]), trim_bytes(&pair[index+1..])),
+                None => continue
+            };
+
+            if name.is_empty() {
+                continue;
+            }
+
+            let value = if value.len() >= 2 && value[0] == b'"' && value[value.len() - 1] == b'"' {
+                &value[1..value.len() - 1]
+            } else {
+                value
+            };
+
+            //The first occurrence is usually the most specific one
+            cookies.entry(percent_decode(name)).or_insert_with(|| percent_decode(value).into());
+        }
+    }
+
+    cookies
+}
+
+fn trim_bytes(mut bytes: &[u8]) -> &[u8] {
+    while let Some((&first, rest)) = bytes.split_first() {
+        if first == b' ' || first == b'\t' {
+            bytes = rest;
+        } else {
+            break;
+        }
+    }
+
+    while let Some((&last, rest)) = bytes.split_last() {
+        if last == b' ' || last == b'\t' {
+            bytes = rest;
+        } else {
+            break;
+        }
+    }
+
+    bytes
+}
+
+pub fn percent_encode_path(path: &[u8]) -> String {
+    percent_encode(path, |byte| byte == b'/')
+}
+
+//Encode everything except unreserved characters and those that are allowed
+//by `allow`.
+fn percent_encode<F: Fn(u8) -> bool>(source: &[u8], allow: F) -> String {
+    let mut encoded = String::with_capacity(source.len());
+
+    for &byte in source {
+        match byte {
+            b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            byte if allow(byte) => encoded.push(byte as char),
+            byte => encoded.push_str(&format!("%{:02X}", byte))
+        }
+    }
+
+    encoded
+}
+
+//Encode everything except unreserved characters.
+pub fn percent_encode_component(source: &[u8]) -> String {
+    percent_encode(source, |_| false)
+}
+
+//Find the index of the offered media type that is preferred the most by the
+//ranges from an `Accept` header. The quality of each offer is taken from its
+//most specific matching range and ties are won by the first offer.
+pub fn negotiate_media_type(accept: &[QualityItem<Mime>], offers: &[Mime]) -> Option<usize> {
+    let mut best: Option<(usize, u16)> = None;
+
+    for (index, offer) in offers.iter().enumerate() {
+        let mut quality = None;
+        for range in accept {
+            if let Some(specificity) = media_range_specificity(&range.item, offer) {
+                match quality {
+                    Some((best_specificity, _)) if best_specificity >= specificity => {},
+                    _ => quality = Some((specificity, range.quality.0))
+                }
+            }
+        }
+
+        if let Some((_, quality)) = quality {
+            if quality > 0 && best.map_or(true, |(_, best_quality)| quality > best_quality) {
+                best = Some((index, quality));
+            }
+        }
+    }
+
+    best.map(|(index, _)| index)
+}
+
+//Parse the language ranges from `Accept-Language` headers, in lower case and
+//with their quality values in thousandths. Malformed items are skipped.
+//Hyper's `AcceptLanguage` is not used, since it rejects the `*` range.
+pub fn parse_accept_language(values: &[Vec<u8>]) -> Vec<(String, u16)> {
+    let mut ranges = vec![];
+
+    for value in values {
+        for item in String::from_utf8_lossy(value).split(',') {
+            let mut parts = item.split(';');
+            let range = parts.next().unwrap_or("").trim().to_lowercase();
+            if range.is_empty() || !range.chars().all(|c| c == '*' || c == '-' || c.is_alphanumeric()) {
+                continue;
+            }
+
+            let mut quality = Some(1000);
+            for param in parts {
+                let param = param.trim();
+                if param.starts_with("q=") || param.starts_with("Q=") {
+                    quality = match param[2..].parse::<f32>() {
+                        Ok(q) if q >= 0.0 && q <= 1.0 => Some((q * 1000.0).round() as u16),
+                        _ => None
+                    };
+                }
+            }
+
+            if let Some(quality) = quality {
+                ranges.push((range, quality));
+            }
+        }
+    }
+
+    ranges
+}
+
+//Find the index of the offered language that is preferred the most by the
+//ranges from an `Accept-Language` header. The quality of each offer is taken
+//from its most specific matching range, and an exact match beats a partial
+//match with the same quality. Ties are won by the first offer.
+pub fn negotiate_language(accept: &[(String, u16)], offers: &[LanguageTag]) -> Option<usize> {
+    let mut best: Option<(usize, u16, u8)> = None;
+
+    for (index, offer) in offers.iter().enumerate() {
+        let offer = offer.to_string().to_lowercase();
+        let mut quality = None;
+        for &(ref range, range_quality) in accept {
+            if let Some(specificity) = language_range_specificity(range, &offer) {
+                match quality {
+                    Some((best_specificity, _)) if best_specificity >= specificity => {},
+                    _ => quality = Some((specificity, range_quality))
+                }
+            }
+        }
+
+        if let Some((specificity, quality)) = quality {
+            if quality > 0 && best.map_or(true, |(_, best_quality, best_specificity)| (quality, specificity) > (best_quality, best_specificity)) {
+                best = Some((index, quality, specificity));
+            }
+        }
+    }
+
+    best.map(|(index, _, _)| index)
+}
+
+//`en` matches `en-us` as a prefix, and `en-us` falls back to `en`.
+fn language_range_specificity(range: &str, offer: &str) -> Option<u8> {
+    if range == "*" {
+        Some(0)
+    } else if range == offer {
+        Some(3)
+    } else if offer.starts_with(range) && offer[range.len()..].starts_with('-') {
+        Some(2)
+    } else if range.starts_with(offer) && range[offer.len()..].starts_with('-') {
+        Some(1)
+    } else {
+        None
+    }
+}
+
+//The `charset` parameter of a media type, in lower case.
+pub fn mime_charset(mime: &Mime) -> Option<String> {
+    let Mime(_, _, ref params) = *mime;
+    params.iter()
+        .find(|&&(ref attr, _)| *attr == Attr::Charset)
+        .map(|&(_, ref value)| value.to_string().to_lowercase())
+}
+
+//Check if text in `charset` can be read as UTF-8.
+pub fn is_utf8_charset(charset: &str) -> bool {
+    match charset {
+        "utf-8" | "utf8" | "us-ascii" => true,
+        _ => false
+    }
+}
+
+//A supported charset. UTF-8 is always supported, while the other charsets
+//require the `charsets` feature.
+pub enum Charset {
+    Utf8,
+    #[cfg(feature = "charsets")]
+    Other(EncodingRef)
+}
+
+impl Charset {
+    //Find a charset from a label, such as `utf-8` or `iso-8859-1`, using
+    //the same labels as web browsers.
+    pub fn from_label(label: &str) -> Option<Charset> {
+        let label = label.trim().to_lowercase();
+        if is_utf8_charset(&label) {
+            Some(Charset::Utf8)
+        } else {
+            other_charset(&label)
+        }
+    }
+
+    //Returns `None` if the text is invalid and `decoding` is strict.
+    pub fn decode(&self, bytes: Vec<u8>, decoding: TextDecoding) -> Option<String> {
+        match *self {
+            Charset::Utf8 => match decoding {
+                TextDecoding::Strict => String::from_utf8(bytes).ok(),
+                TextDecoding::Lossy => match String::from_utf8(bytes) {
+                    Ok(text) => Some(text),
+                    Err(e) => Some(String::from_utf8_lossy(&e.into_bytes()).into_owned())
+                }
+            },
+            #[cfg(feature = "charsets")]
+            Charset::Other(encoding) => {
+                let trap = match decoding {
+                    TextDecoding::Strict => DecoderTrap::Strict,
+                    TextDecoding::Lossy => DecoderTrap::Replace
+                };
+                encoding.decode(&bytes, trap).ok()
+            }
+        }
+    }
+
+    //Decode every name and value in `parameters` as UTF-8. They are left as
+    //they are if the charset is UTF-8, since `Parameters` can hold invalid
+    //UTF-8.
+    pub fn decode_parameters(&self, parameters: Parameters) -> Parameters {
+        match *self {
+            Charset::Utf8 => parameters,
+            #[cfg(feature = "charsets")]
+            Charset::Other(_) => {
+                let mut decoded = Parameters::new();
+                for name in parameters.keys() {
+                    let decoded_name = self.decode(name.as_bytes().to_vec(), TextDecoding::Lossy).unwrap_or_else(String::new);
+                    for value in parameters.get_all(name.as_bytes()) {
+                        let decoded_value = self.decode(value.as_bytes().to_vec(), TextDecoding::Lossy).unwrap_or_else(String::new);
+                        decoded.append(decoded_name.clone(), decoded_value);
+                    }
+                }
+                decoded
+            }
+        }
+    }
+}
+
+#[cfg(feature = "charsets")]
+fn other_charset(label: &str) -> Option<Charset> {
+    encoding_from_whatwg_label(label).map(Charset::Other)
+}
+
+#[cfg(not(feature = "charsets"))]
+fn other_charset(_label: &str) -> Option<Charset> {
+    None
+}
+
+//How specific `range` is if it matches `offer`. Ranges with parameters only
+//match offers with the same parameters.
+fn media_range_specificity(range: &Mime, offer: &Mime) -> Option<u8> {
+    let Mime(ref top, ref sub, ref params) = *range;
+    let Mime(ref offer_top, ref offer_sub, ref offer_params) = *offer;
+
+    if let TopLevel::Star = *top {
+        Some(0)
+    } else if top != offer_top {
+        None
+    } else if let SubLevel::Star = *sub {
+        Some(1)
+    } else if sub != offer_sub {
+        None
+    } else if params.is_empty() {
+        Some(2)
+    } else if params.iter().all(|param| offer_params.contains(param)) {
+        Some(3)
+    } else {
+        None
+    }
+}
+
+//A straightforward SHA-1 implementation, for the few places where it's
+//required by a protocol. It should not be used for anything security related.
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    let bits = (data.len() as u64).wrapping_mul(8);
+    for i in 0..8 {
+        message.push((bits >> (56 - i * 8)) as u8);
+    }
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            w[i] = (block[i * 4] as u32) << 24
+                | (block[i * 4 + 1] as u32) << 16
+                | (block[i * 4 + 2] as u32) << 8
+                | block[i * 4 + 3] as u32;
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let (mut a, mut b, mut c, mut d, mut e) = (state[0], state[1], state[2], state[3], state[4]);
+
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0...19 => ((b & c) | (!b & d), 0x5A827999),
+                20...39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40...59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6u32)
+            };
+
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        state[0] = state[0].wrapping_add(a);
+        state[1] = state[1].wrapping_add(b);
+        state[2] = state[2].wrapping_add(c);
+        state[3] = state[3].wrapping_add(d);
+        state[4] = state[4].wrapping_add(e);
+    }
+
+    let mut digest = [0u8; 20];
+    for (i, word) in state.iter().enumerate() {
+        digest[i * 4] = (word >> 24) as u8;
+        digest[i * 4 + 1] = (word >> 16) as u8;
+        digest[i * 4 + 2] = (word >> 8) as u8;
+        digest[i * 4 + 3] = *word as u8;
+    }
+
+    digest
+}
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2
+];
+
+//A straightforward SHA-256 implementation, for signing data with
+//`hmac_sha256`.
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19
+    ];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    let bits = (data.len() as u64).wrapping_mul(8);
+    for i in 0..8 {
+        message.push((bits >> (56 - i * 8)) as u8);
+    }
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 64];
+        for i in 0..16 {
+            w[i] = (block[i * 4] as u32) << 24
+                | (block[i * 4 + 1] as u32) << 16
+                | (block[i * 4 + 2] as u32) << 8
+                | block[i * 4 + 3] as u32;
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let mut v = state;
+
+        for i in 0..64 {
+            let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^ v[4].rotate_right(25);
+            let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+            let temp1 = v[7].wrapping_add(s1).wrapping_add(ch).wrapping_add(SHA256_K[i]).wrapping_add(w[i]);
+            let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^ v[0].rotate_right(22);
+            let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            let temp2 = s0.wrapping_add(maj);
+
+            v[7] = v[6];
+            v[6] = v[5];
+            v[5] = v[4];
+            v[4] = v[3].wrapping_add(temp1);
+            v[3] = v[2];
+            v[2] = v[1];
+            v[1] = v[0];
+            v[0] = temp1.wrapping_add(temp2);
+        }
+
+        for (word, value) in state.iter_mut().zip(v.iter()) {
+            *word = word.wrapping_add(*value);
+        }
+    }
+
+    let mut digest = [0u8; 32];
+    for (i, word) in state.iter().enumerate() {
+        digest[i * 4] = (word >> 24) as u8;
+        digest[i * 4 + 1] = (word >> 16) as u8;
+        digest[i * 4 + 2] = (word >> 8) as u8;
+        digest[i * 4 + 3] = *word as u8;
+    }
+
+    digest
+}
+
+//HMAC with SHA-256, as described in RFC 2104.
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > 64 {
+        block[..32].copy_from_slice(&sha256(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner: Vec<u8> = block.iter().map(|&b| b ^ 0x36).collect();
+    inner.extend_from_slice(data);
+
+    let mut outer: Vec<u8> = block.iter().map(|&b| b ^ 0x5c).collect();
+    outer.extend_from_slice(&sha256(&inner));
+
+    sha256(&outer)
+}
+
+//Compare two byte strings in a time that only depends on their lengths.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0u8, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+const BASE64_CHARS: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+//Encode bytes as padded, standard base64.
+pub fn base64_encode(source: &[u8]) -> String {
+    let mut encoded = String::with_capacity((source.len() + 2) / 3 * 4);
+
+    for chunk in source.chunks(3) {
+        let b0 = chunk[0] as usize;
+        let b1 = chunk.get(1).map_or(0, |&b| b as usize);
+        let b2 = chunk.get(2).map_or(0, |&b| b as usize);
+
+        encoded.push(BASE64_CHARS[b0 >> 2] as char);
+        encoded.push(BASE64_CHARS[((b0 & 0x03) << 4) | (b1 >> 4)] as char);
+        if chunk.len() > 1 {
+            encoded.push(BASE64_CHARS[((b1 & 0x0F) << 2) | (b2 >> 6)] as char);
+        } else {
+            encoded.push('=');
+        }
+        if chunk.len() > 2 {
+            encoded.push(BASE64_CHARS[b2 & 0x3F] as char);
+        } else {
+            encoded.push('=');
+        }
+    }
+
+    encoded
+}
+
+//Decode padded, standard base64. Returns `None` if it's malformed.
+pub fn base64_decode(source: &[u8]) -> Option<Vec<u8>> {
+    if source.len() % 4 != 0 {
+        return None;
+    }
+
+    let chunks = source.len() / 4;
+    let mut decoded = Vec::with_capacity(chunks * 3);
+
+    for (i, chunk) in source.chunks(4).enumerate() {
+        let padding = chunk.iter().rev().take_while(|&&b| b == b'=').count();
+        if padding > 2 || (padding > 0 && i + 1 < chunks) {
+            return None;
+        }
+
+        let mut value = 0u32;
+        for &b in &chunk[..4 - padding] {
+            match BASE64_CHARS.iter().position(|&c| c == b) {
+                Some(digit) => value = value << 6 | digit as u32,
+                None => return None
+            }
+        }
+        value <<= 6 * padding as u32;
+
+        decoded.push((value >> 16) as u8);
+        if padding < 2 {
+            decoded.push((value >> 8) as u8);
+        }
+        if padding < 1 {
+            decoded.push(value as u8);
+        }
+    }
+
+    Some(decoded)
 }
 
 ///Extension trait for byte vectors.
@@ -43,11 +593,14 @@ impl BytesExt for Vec<u8> {
 #[cfg(test)]
 mod test {
     use std::borrow::ToOwned;
-    use super::parse_parameters;
+    use mime::Mime;
+    use header::{QualityItem, Quality, qitem};
+    use context::body::TextDecoding;
+    use super::{parse_parameters, parse_cookies, negotiate_media_type, parse_accept_language, negotiate_language, mime_charset, Charset, sha1, sha256, hmac_sha256, base64_encode, base64_decode};
 
     #[test]
     fn parsing_parameters() {
-        let parameters = parse_parameters(b"a=1&aa=2&ab=202");
+        let parameters = parse_parameters(b"a=1&aa=2&ab=202", None).unwrap();
         let a = "1".to_owned().into();
         let aa = "2".to_owned().into();
         let ab = "202".to_owned().into();
@@ -58,7 +611,7 @@ mod test {
 
     #[test]
     fn parsing_parameters_with_plus() {
-        let parameters = parse_parameters(b"a=1&aa=2+%2B+extra+meat&ab=202+fifth+avenue");
+        let parameters = parse_parameters(b"a=1&aa=2+%2B+extra+meat&ab=202+fifth+avenue", None).unwrap();
         let a = "1".to_owned().into();
         let aa = "2 + extra meat".to_owned().into();
         let ab = "202 fifth avenue".to_owned().into();
@@ -69,7 +622,7 @@ mod test {
 
     #[test]
     fn parsing_strange_parameters() {
-        let parameters = parse_parameters(b"a=1=2&=2&ab=");
+        let parameters = parse_parameters(b"a=1=2&=2&ab=", None).unwrap();
         let a = "1".to_owned().into();
         let aa = "2".to_owned().into();
         let ab = "".to_owned().into();
@@ -77,4 +630,173 @@ mod test {
         assert_eq!(parameters.get_raw(""), Some(&aa));
         assert_eq!(parameters.get_raw("ab"), Some(&ab));
     }
+
+    #[test]
+    fn parsing_empty_parameters() {
+        assert_eq!(parse_parameters(b"", None).unwrap().len(), 0);
+
+        let parameters = parse_parameters(b"a=1&&b=2&", None).unwrap();
+        let a = "1".to_owned().into();
+        let b = "2".to_owned().into();
+        assert_eq!(parameters.len(), 2);
+        assert_eq!(parameters.get_raw("a"), Some(&a));
+        assert_eq!(parameters.get_raw("b"), Some(&b));
+    }
+
+    #[test]
+    fn limit_parameters() {
+        assert_eq!(parse_parameters(b"a=1&&b=2&", Some(2)).map(|p| p.len()), Some(2));
+        assert!(parse_parameters(b"a=1&b=2&c=3", Some(2)).is_none());
+        assert!(parse_parameters(b"a=1&a=2&a=3", Some(2)).is_none());
+    }
+
+    #[test]
+    fn parsing_cookies() {
+        let cookies = parse_cookies(&[
+            b"a=1; b=\"two words\";c=%3Bsemi%3B".to_vec(),
+            b"a=ignored; d=; =nameless; broken".to_vec()
+        ]);
+        let a = "1".to_owned().into();
+        let b = "two words".to_owned().into();
+        let c = ";semi;".to_owned().into();
+        let d = "".to_owned().into();
+        assert_eq!(cookies.len(), 4);
+        assert_eq!(cookies.get_raw("a"), Some(&a));
+        assert_eq!(cookies.get_raw("b"), Some(&b));
+        assert_eq!(cookies.get_raw("c"), Some(&c));
+        assert_eq!(cookies.get_raw("d"), Some(&d));
+    }
+
+    #[test]
+    fn negotiating_media_types() {
+        let html: Mime = "text/html".parse().unwrap();
+        let json: Mime = "application/json".parse().unwrap();
+        let offers = [html.clone(), json.clone()];
+
+        let accept = [qitem(json.clone())];
+        assert_eq!(negotiate_media_type(&accept, &offers), Some(1));
+
+        let accept = [QualityItem::new("text/*".parse().unwrap(), Quality(500)), qitem(json.clone())];
+        assert_eq!(negotiate_media_type(&accept, &offers), Some(1));
+
+        let accept = [qitem("*/*".parse().unwrap()), QualityItem::new(html.clone(), Quality(0))];
+        assert_eq!(negotiate_media_type(&accept, &offers), Some(1));
+
+        let accept = [qitem("*/*".parse().unwrap())];
+        assert_eq!(negotiate_media_type(&accept, &offers), Some(0));
+
+        let accept = [qitem("image/*".parse().unwrap())];
+        assert_eq!(negotiate_media_type(&accept, &offers), None);
+    }
+
+    #[test]
+    fn negotiating_languages() {
+        let offers = ["en".parse().unwrap(), "en-US".parse().unwrap(), "sv".parse().unwrap()];
+
+        let accept = parse_accept_language(&[b"sv;q=0.8, en-US".to_vec(), b"broken;q=2, ;q=1".to_vec()]);
+        assert_eq!(accept, vec![("sv".to_owned(), 800), ("en-us".to_owned(), 1000)]);
+        assert_eq!(negotiate_language(&accept, &offers), Some(1));
+
+        let accept = parse_accept_language(&[b"en-GB, sv;q=0.5".to_vec()]);
+        assert_eq!(negotiate_language(&accept, &offers), Some(0));
+
+        let accept = parse_accept_language(&[b"EN".to_vec()]);
+        assert_eq!(negotiate_language(&accept, &offers), Some(0));
+
+        let accept = parse_accept_language(&[b"*;q=0.1, sv".to_vec()]);
+        assert_eq!(negotiate_language(&accept, &offers), Some(2));
+
+        let accept = parse_accept_language(&[b"*, en;q=0".to_vec()]);
+        assert_eq!(negotiate_language(&accept, &offers), Some(2));
+
+        let accept = parse_accept_language(&[b"de, fr".to_vec()]);
+        assert_eq!(negotiate_language(&accept, &offers), None);
+    }
+
+    #[test]
+    fn sha1_digests() {
+        assert_eq!(base64_encode(&sha1(b"")), "2jmj7l5rSw0yVb/vlWAYkK/YBwk=");
+        assert_eq!(base64_encode(&sha1(b"abc")), "qZk+NkcGgWq6PiVxeFDCbJzQ2J0=");
+        let long = vec![b'a'; 1000];
+        assert_eq!(base64_encode(&sha1(&long)), "KR6abGaZSUm1e6XmUDYemPw2sbo=");
+    }
+
+    #[test]
+    fn sha256_digests() {
+        assert_eq!(base64_encode(&sha256(b"")), "47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=");
+        assert_eq!(base64_encode(&sha256(b"abc")), "ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0=");
+        let long = [b'a'; 1000];
+        assert_eq!(base64_encode(&sha256(&long)), "Qe3s5C1j6Nm/UVqbppMuHCDLyfWl0TRkWttdsblzfqM=");
+    }
+
+    #[test]
+    fn hmac_sha256_signatures() {
+        //Test case 2 from RFC 4231
+        let signature = hmac_sha256(b"Jefe", b"what do ya want for nothing?");
+        assert_eq!(base64_encode(&signature), "W9zBRr9gdU5qBCQmCJV1x1oAPwidJzmDnexYuWTsOEM=");
+
+        //Test case 6 from RFC 4231, with a key that is longer than a block
+        let signature = hmac_sha256(&[0xaa; 131], b"Test Using Larger Than Block-Size Key - Hash Key First");
+        assert_eq!(base64_encode(&signature), "YOQxWR7gtn8Niiaqy/W3f44LxiE3KMUUBUYEDw7jf1Q=");
+    }
+
+    #[test]
+    fn base64_encoding() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn base64_decoding() {
+        assert_eq!(base64_decode(b""), Some(vec![]));
+        assert_eq!(base64_decode(b"Zg=="), Some(b"f".to_vec()));
+        assert_eq!(base64_decode(b"Zm8="), Some(b"fo".to_vec()));
+        assert_eq!(base64_decode(b"Zm9vYmFy"), Some(b"foobar".to_vec()));
+        assert_eq!(base64_decode(b"Zm9"), None);
+        assert_eq!(base64_decode(b"Zg==Zg=="), None);
+        assert_eq!(base64_decode(b"Z=g="), None);
+        assert_eq!(base64_decode(b"Zm9v YmFy"), None);
+    }
+
+    #[test]
+    fn find_charset() {
+        let mime: Mime = "text/plain; charset=UTF-8".parse().unwrap();
+        assert_eq!(mime_charset(&mime), Some("utf-8".to_owned()));
+
+        let mime: Mime = "text/plain; format=flowed; charset=ISO-8859-1".parse().unwrap();
+        assert_eq!(mime_charset(&mime), Some("iso-8859-1".to_owned()));
+
+        let mime: Mime = "application/json".parse().unwrap();
+        assert_eq!(mime_charset(&mime), None);
+    }
+
+    #[test]
+    fn decode_utf8() {
+        let charset = Charset::from_label(" UTF-8").unwrap();
+        assert_eq!(charset.decode(b"abc".to_vec(), TextDecoding::Strict), Some("abc".to_owned()));
+        assert_eq!(charset.decode(b"a\xffc".to_vec(), TextDecoding::Strict), None);
+        assert_eq!(charset.decode(b"a\xffc".to_vec(), TextDecoding::Lossy), Some("a\u{fffd}c".to_owned()));
+    }
+
+    #[cfg(feature = "charsets")]
+    #[test]
+    fn decode_other_charsets() {
+        let charset = Charset::from_label("iso-8859-1").unwrap();
+        assert_eq!(charset.decode(b"r\xe4ksm\xf6rg\xe5s".to_vec(), TextDecoding::Strict), Some("r\u{e4}ksm\u{f6}rg\u{e5}s".to_owned()));
+
+        let form = parse_parameters(b"n%E4me=v%E4lue", None).unwrap();
+        let form = charset.decode_parameters(form);
+        assert_eq!(form.get("n\u{e4}me").map(|value| value.into_owned()), Some("v\u{e4}lue".to_owned()));
+
+        assert!(Charset::from_label("not-a-charset").is_none());
+    }
+
+    #[cfg(not(feature = "charsets"))]
+    #[test]
+    fn reject_other_charsets() {
+        assert!(Charset::from_label("iso-8859-1").is_none());
+    }
 }
@@ -0,0 +1,393 @@
+//!WebSocket connections.
+//!
+//!A handler can take over a connection by accepting a WebSocket upgrade
+//!request, which turns it into a `WebSocket`. This is a simple, blocking,
+//!message channel, where messages are received and sent from the handler's
+//!thread. Requests that are not upgrade requests are given back, so the same
+//!route can be used for both WebSocket clients and regular requests:
+//!
+//!```
+//!use rustful::{Context, Response};
+//!use rustful::websocket::{self, Message};
+//!
+//!fn echo(mut context: Context, response: Response) {
+//!    match websocket::accept(&mut context, response) {
+//!        Ok(mut socket) => {
+//!            while let Ok(Some(message)) = socket.receive() {
+//!                if let Message::Text(text) = message {
+//!                    if socket.send(Message::Text(text)).is_err() {
+//!                        break;
+//!                    }
+//!                }
+//!            }
+//!        },
+//!        Err(response) => response.send("connect using a WebSocket client")
+//!    }
+//!}
+//!```
+//!
+//!The connection is only used by the WebSocket while it's alive, so it will
+//!be closed when the `WebSocket` is dropped. Reading is blocking, but it's
+//!still limited by the server's `read_timeout`, so a timeout error from
+//!`receive` doesn't necessarily mean that the connection is broken.
+
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+
+use hyper::buffer::BufReader;
+use hyper::net::NetworkStream;
+
+use header::{Headers, Connection, ConnectionOption};
+use unicase::UniCase;
+use context::Context;
+use response::Response;
+use {Method, HttpVersion, StatusCode};
+use utils::{sha1, base64_encode};
+
+const GUID: &'static [u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+///A complete WebSocket message.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Message {
+    ///A UTF-8 text message.
+    Text(String),
+
+    ///A binary message.
+    Binary(Vec<u8>)
+}
+
+impl From<String> for Message {
+    fn from(text: String) -> Message {
+        Message::Text(text)
+    }
+}
+
+impl<'a> From<&'a str> for Message {
+    fn from(text: &'a str) -> Message {
+        Message::Text(text.to_owned())
+    }
+}
+
+impl From<Vec<u8>> for Message {
+    fn from(bytes: Vec<u8>) -> Message {
+        Message::Binary(bytes)
+    }
+}
+
+///Check if a request is a WebSocket upgrade request.
+///
+///It has to be an HTTP/1.1 `GET` request with `Upgrade: websocket`,
+///`Connection: upgrade`, `Sec-WebSocket-Version: 13` and a
+///`Sec-WebSocket-Key`.
+pub fn is_upgrade(context: &Context) -> bool {
+    context.method == Method::Get
+        && context.http_version == HttpVersion::Http11
+        && wants_websocket(&context.headers)
+        && header_contains(&context.headers, "Connection", "upgrade")
+        && header_contains(&context.headers, "Sec-WebSocket-Version", "13")
+        && context.headers.get_raw("Sec-WebSocket-Key").map_or(false, |keys| keys.len() == 1)
+}
+
+///Accept a WebSocket upgrade request and switch the connection over to the
+///WebSocket protocol.
+///
+///The response is given back if the request is not an upgrade request, so
+///it can be answered as usual. Its status will be set to `426 Upgrade
+///Required` if the client asked for an unsupported WebSocket version. Any
+///headers that are set on the response before accepting, such as
+///`Sec-WebSocket-Protocol`, are included in the handshake, but the response
+///filters are bypassed.
+pub fn accept<'r, 'a, 'b, 's, 'x, 'y>(context: &'r mut Context<'a, 'b, 's>, mut response: Response<'x, 'y>) -> Result<WebSocket<'r, 'b>, Response<'x, 'y>> {
+    if !is_upgrade(context) {
+        if wants_websocket(&context.headers) && !header_contains(&context.headers, "Sec-WebSocket-Version", "13") {
+            response.set_status(StatusCode::UpgradeRequired);
+            response.headers_mut().set_raw("Sec-WebSocket-Version", vec![b"13".to_vec()]);
+        }
+        return Err(response);
+    }
+
+    let key = context.headers.get_raw("Sec-WebSocket-Key").and_then(|keys| keys.first()).cloned().unwrap_or_else(Vec::new);
+    let max_message_size = context.body.max_size();
+
+    {
+        let headers = response.headers_mut();
+        headers.set_raw("Upgrade", vec![b"websocket".to_vec()]);
+        headers.set(Connection(vec![ConnectionOption::ConnectionHeader(UniCase("Upgrade".to_owned()))]));
+        headers.set_raw("Sec-WebSocket-Accept", vec![accept_key(&key).into_bytes()]);
+    }
+
+    //The connection is useless if the handshake couldn't be sent, so the
+    //socket starts out as closed.
+    let failed = response.switch_protocols().is_err();
+
+    Ok(WebSocket {
+        stream: context.body.raw_stream(),
+        max_message_size: max_message_size,
+        close_sent: failed,
+        closed: failed
+    })
+}
+
+///Compute the `Sec-WebSocket-Accept` value for a `Sec-WebSocket-Key`.
+pub fn accept_key(key: &[u8]) -> String {
+    let mut source = key.to_vec();
+    source.extend_from_slice(GUID);
+    base64_encode(&sha1(&source))
+}
+
+///A WebSocket connection.
+///
+///Messages are received one at a time and pings from the client are
+///answered while waiting for the next message. A close frame is sent to
+///the client, and the connection is shut down, when the `WebSocket` is
+///dropped.
+pub struct WebSocket<'r, 'b: 'r> {
+    stream: &'r mut BufReader<&'b mut NetworkStream>,
+    max_message_size: Option<u64>,
+    close_sent: bool,
+    closed: bool
+}
+
+impl<'r, 'b> WebSocket<'r, 'b> {
+    ///Wait for the next message from the client. `None` is returned when the
+    ///connection has been closed.
+    ///
+    ///Messages that are larger than the server's `max_body_size` are
+    ///rejected, as well as text messages with invalid UTF-8. The connection is
+    ///closed and an error is returned if the client breaks the protocol.
+    pub fn receive(&mut self) -> io::Result<Option<Message>> {
+        let mut message: Option<(u8, Vec<u8>)> = None;
+
+        while !self.closed {
+            let (fin, opcode, payload) = match self.read_frame() {
+                Ok(frame) => frame,
+                Err(Failure::Io(e)) => return Err(e),
+                Err(Failure::Protocol(code, description)) => return Err(self.fail(code, description))
+            };
+
+            match opcode {
+                OP_PING => try!(self.write_frame(OP_PONG, &payload)),
+                OP_PONG => {},
+                OP_CLOSE => {
+                    if !self.close_sent {
+                        let code = if payload.len() >= 2 { &payload[..2] } else { &payload[..0] };
+                        try!(self.write_frame(OP_CLOSE, code));
+                        self.close_sent = true;
+                    }
+                    self.shutdown();
+                    return Ok(None);
+                },
+                OP_CONTINUATION => match message {
+                    Some((_, ref mut buffer)) => buffer.extend_from_slice(&payload),
+                    None => return Err(self.fail(1002, "unexpected continuation frame"))
+                },
+                OP_TEXT | OP_BINARY => if message.is_some() {
+                    return Err(self.fail(1002, "expected a continuation frame"));
+                } else {
+                    message = Some((opcode, payload));
+                },
+                _ => return Err(self.fail(1002, "unknown opcode"))
+            }
+
+            if let (Some(max), Some(&(_, ref buffer))) = (self.max_message_size, message.as_ref()) {
+                if buffer.len() as u64 > max {
+                    return Err(self.fail(1009, "message too large"));
+                }
+            }
+
+            if fin && opcode != OP_PING && opcode != OP_PONG {
+                if let Some((opcode, buffer)) = message.take() {
+                    if opcode == OP_BINARY {
+                        return Ok(Some(Message::Binary(buffer)));
+                    }
+
+                    return match String::from_utf8(buffer) {
+                        Ok(text) => Ok(Some(Message::Text(text))),
+                        Err(_) => Err(self.fail(1007, "invalid UTF-8 in text message"))
+                    };
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    ///Send a message to the client.
+    pub fn send<M: Into<Message>>(&mut self, message: M) -> io::Result<()> {
+        match message.into() {
+            Message::Text(text) => self.write_frame(OP_TEXT, text.as_bytes()),
+            Message::Binary(bytes) => self.write_frame(OP_BINARY, &bytes)
+        }
+    }
+
+    ///Send a ping to the client. The pong will be consumed by `receive`.
+    pub fn ping(&mut self, payload: &[u8]) -> io::Result<()> {
+        if payload.len() > 125 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "ping payloads can't be longer than 125 bytes"));
+        }
+
+        self.write_frame(OP_PING, payload)
+    }
+
+    ///Close the connection with a status code and a reason. The code `1000`
+    ///means that it was closed normally.
+    pub fn close(mut self, code: u16, reason: &str) -> io::Result<()> {
+        self.send_close(code, reason)
+    }
+
+    fn send_close(&mut self, code: u16, reason: &str) -> io::Result<()> {
+        if self.close_sent {
+            return Ok(());
+        }
+
+        self.close_sent = true;
+        let mut payload = vec![(code >> 8) as u8, code as u8];
+        payload.extend_from_slice(&reason.as_bytes()[..::std::cmp::min(reason.len(), 123)]);
+        let result = self.write_frame(OP_CLOSE, &payload);
+        self.shutdown();
+        result
+    }
+
+    fn fail(&mut self, code: u16, description: &'static str) -> io::Error {
+        let _ = self.send_close(code, "");
+        io::Error::new(io::ErrorKind::InvalidData, description)
+    }
+
+    fn shutdown(&mut self) {
+        if !self.closed {
+            self.closed = true;
+            let _ = self.stream.get_mut().close(Shutdown::Both);
+        }
+    }
+
+    fn read_frame(&mut self) -> Result<(bool, u8, Vec<u8>), Failure> {
+        let mut head = [0; 2];
+        try!(self.stream.read_exact(&mut head));
+
+        let fin = head[0] & 0x80 != 0;
+        let opcode = head[0] & 0x0F;
+
+        if head[0] & 0x70 != 0 {
+            return Err(Failure::Protocol(1002, "unexpected reserved bits"));
+        }
+
+        if head[1] & 0x80 == 0 {
+            return Err(Failure::Protocol(1002, "client frames must be masked"));
+        }
+
+        let length = match head[1] & 0x7F {
+            126 => {
+                let mut length = [0; 2];
+                try!(self.stream.read_exact(&mut length));
+                (length[0] as u64) << 8 | length[1] as u64
+            },
+            127 => {
+                let mut length = [0; 8];
+                try!(self.stream.read_exact(&mut length));
+                length.iter().fold(0, |length, &byte| length << 8 | byte as u64)
+            },
+            length => length as u64
+        };
+
+        if opcode & 0x8 != 0 && (length > 125 || !fin) {
+            return Err(Failure::Protocol(1002, "invalid control frame"));
+        }
+
+        if self.max_message_size.map_or(false, |max| length > max) {
+            return Err(Failure::Protocol(1009, "message too large"));
+        }
+
+        let mut mask = [0; 4];
+        try!(self.stream.read_exact(&mut mask));
+
+        let mut payload = Vec::new();
+        try!(self.stream.by_ref().take(length).read_to_end(&mut payload));
+        if (payload.len() as u64) < length {
+            return Err(Failure::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "the connection was closed in the middle of a frame")));
+        }
+
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+
+        Ok((fin, opcode, payload))
+    }
+
+    fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        if self.close_sent && opcode != OP_CLOSE {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "the WebSocket is closed"));
+        }
+
+        let mut frame = Vec::with_capacity(payload.len() + 10);
+        frame.push(0x80 | opcode);
+
+        if payload.len() < 126 {
+            frame.push(payload.len() as u8);
+        } else if payload.len() <= 0xFFFF {
+            frame.push(126);
+            frame.push((payload.len() >> 8) as u8);
+            frame.push(payload.len() as u8);
+        } else {
+            frame.push(127);
+            let length = payload.len() as u64;
+            for i in 0..8 {
+                frame.push((length >> (56 - i * 8)) as u8);
+            }
+        }
+
+        frame.extend_from_slice(payload);
+
+        let stream = self.stream.get_mut();
+        try!(stream.write_all(&frame));
+        stream.flush()
+    }
+}
+
+impl<'r, 'b> Drop for WebSocket<'r, 'b> {
+    fn drop(&mut self) {
+        let _ = self.send_close(1000, "");
+        self.shutdown();
+    }
+}
+
+enum Failure {
+    Io(io::Error),
+    Protocol(u16, &'static str)
+}
+
+impl From<io::Error> for Failure {
+    fn from(err: io::Error) -> Failure {
+        Failure::Io(err)
+    }
+}
+
+fn wants_websocket(headers: &Headers) -> bool {
+    header_contains(headers, "Upgrade", "websocket")
+}
+
+//Check if a comma separated header contains `token`, ignoring case.
+fn header_contains(headers: &Headers, name: &str, token: &str) -> bool {
+    headers.get_raw(name).map_or(false, |values| values.iter().any(|value| {
+        value.split(|&b| b == b',').any(|part| {
+            let part = String::from_utf8_lossy(part);
+            part.trim().to_lowercase() == token
+        })
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::accept_key;
+
+    #[test]
+    fn websocket_accept_key() {
+        assert_eq!(accept_key(b"dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+}